@@ -91,6 +91,17 @@ typedef struct Input Input_t;
 
 #include <stdbool.h>
 
+/** \brief
+ *  Messages of an input were dropped because its queue was full.
+ */
+typedef struct InputDropped {
+    /** <No documentation available> */
+    Vec_uint8_t id;
+
+    /** <No documentation available> */
+    uint64_t count;
+} InputDropped_t;
+
 /** <No documentation available> */
 typedef struct RawEvent {
     /** <No documentation available> */
@@ -104,6 +115,9 @@ typedef struct RawEvent {
 
     /** <No documentation available> */
     Vec_uint8_t error;
+
+    /** <No documentation available> */
+    InputDropped_t * input_dropped;
 } RawEvent_t;
 
 /** <No documentation available> */
@@ -38,6 +38,12 @@ impl PyEvent {
                 if let Some(error) = Self::error(event) {
                     pydict.insert("error", error.to_object(py));
                 }
+                if let Event::InputGap { lost, .. } = event {
+                    pydict.insert("lost", lost.to_object(py));
+                }
+                if let Event::InputDropped { count, .. } = event {
+                    pydict.insert("count", count.to_object(py));
+                }
             }
             MergedEvent::External(event) => {
                 pydict.insert("value", event.clone());
@@ -52,6 +58,8 @@ impl PyEvent {
             Event::Stop => "STOP",
             Event::Input { .. } => "INPUT",
             Event::InputClosed { .. } => "INPUT_CLOSED",
+            Event::InputGap { .. } => "INPUT_GAP",
+            Event::InputDropped { .. } => "INPUT_DROPPED",
             Event::Error(_) => "ERROR",
             _other => "UNKNOWN",
         }
@@ -61,6 +69,8 @@ impl PyEvent {
         match event {
             Event::Input { id, .. } => Some(id),
             Event::InputClosed { id } => Some(id),
+            Event::InputGap { id, .. } => Some(id),
+            Event::InputDropped { id, .. } => Some(id),
             _ => None,
         }
     }
@@ -24,6 +24,19 @@ pub enum Event {
     InputClosed {
         id: DataId,
     },
+    /// Some messages of the input were lost because the connection to the
+    /// machine of the sender was interrupted.
+    InputGap {
+        id: DataId,
+        lost: u64,
+    },
+    /// Messages of the input were dropped because its queue was full.
+    ///
+    /// Only sent for inputs that set `notify_dropped`.
+    InputDropped {
+        id: DataId,
+        count: u64,
+    },
     Error(String),
 }
 
@@ -1,4 +1,8 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 pub use event::{Event, MappedInputData, RawData};
 use futures::{
@@ -13,7 +17,7 @@ use self::{
 };
 use crate::daemon_connection::DaemonChannel;
 use dora_core::{
-    config::NodeId,
+    config::{DataId, NodeId},
     daemon_messages::{
         self, DaemonCommunication, DaemonRequest, DataflowId, NodeEvent, Timestamped,
     },
@@ -28,17 +32,62 @@ mod thread;
 pub struct EventStream {
     node_id: NodeId,
     receiver: flume::r#async::RecvStream<'static, EventItem>,
+    /// Signals the event stream thread that the next event is needed.
+    ///
+    /// Only set in deterministic mode. Declared before the thread handle so that
+    /// the thread sees the disconnect before it is joined on drop.
+    demand: Option<flume::Sender<()>>,
+    /// Whether the next event was requested already through `demand`.
+    demand_pending: bool,
     _thread_handle: EventStreamThreadHandle,
     close_channel: DaemonChannel,
     clock: Arc<uhlc::HLC>,
+    notify_dropped: BTreeSet<DataId>,
+    dropped: BTreeMap<DataId, u64>,
 }
 
 impl EventStream {
+    /// Subscribes read-only to outputs of a running dataflow without being
+    /// declared in its descriptor.
+    ///
+    /// Connects to the local listener of the daemon, which registers the
+    /// subscriber under a generated node ID. Each output is received as an input
+    /// named `<node>/<output>`. If no dataflow ID is given, the only running
+    /// dataflow of the daemon is used.
+    ///
+    /// ```no_run
+    /// use dora_node_api::EventStream;
+    /// use dora_node_api::dora_core::config::{DataId, NodeId};
+    ///
+    /// let outputs = [(NodeId::from("camera".to_string()), DataId::from("image".to_string()))];
+    /// let events = EventStream::subscribe_outputs(None, outputs).expect("Could not subscribe");
+    /// ```
+    pub fn subscribe_outputs(
+        dataflow_id: Option<DataflowId>,
+        outputs: impl IntoIterator<Item = (NodeId, DataId)>,
+    ) -> eyre::Result<Self> {
+        let node_config = crate::node::request_node_config(DaemonRequest::SubscribeOutputs {
+            dataflow_id,
+            outputs: outputs.into_iter().collect(),
+        })?;
+        Self::init(
+            node_config.dataflow_id,
+            &node_config.node_id,
+            &node_config.daemon_communication,
+            BTreeSet::new(),
+            false,
+            Arc::new(uhlc::HLC::default()),
+        )
+        .wrap_err("failed to init event stream")
+    }
+
     #[tracing::instrument(level = "trace", skip(clock))]
     pub(crate) fn init(
         dataflow_id: DataflowId,
         node_id: &NodeId,
         daemon_communication: &DaemonCommunication,
+        notify_dropped: BTreeSet<DataId>,
+        deterministic: bool,
         clock: Arc<uhlc::HLC>,
     ) -> eyre::Result<Self> {
         let channel = match daemon_communication {
@@ -77,7 +126,15 @@ impl EventStream {
             }
         };
 
-        Self::init_on_channel(dataflow_id, node_id, channel, close_channel, clock)
+        Self::init_on_channel(
+            dataflow_id,
+            node_id,
+            channel,
+            close_channel,
+            notify_dropped,
+            deterministic,
+            clock,
+        )
     }
 
     pub(crate) fn init_on_channel(
@@ -85,6 +142,8 @@ impl EventStream {
         node_id: &NodeId,
         mut channel: DaemonChannel,
         mut close_channel: DaemonChannel,
+        notify_dropped: BTreeSet<DataId>,
+        deterministic: bool,
         clock: Arc<uhlc::HLC>,
     ) -> eyre::Result<Self> {
         channel.register(dataflow_id, node_id.clone(), clock.new_timestamp())?;
@@ -107,7 +166,13 @@ impl EventStream {
         close_channel.register(dataflow_id, node_id.clone(), clock.new_timestamp())?;
 
         let (tx, rx) = flume::bounded(0);
-        let thread_handle = thread::init(node_id.clone(), tx, channel, clock.clone())?;
+        let (demand, demand_rx) = if deterministic {
+            let (demand, demand_rx) = flume::bounded(1);
+            (Some(demand), Some(demand_rx))
+        } else {
+            (None, None)
+        };
+        let thread_handle = thread::init(node_id.clone(), tx, channel, clock.clone(), demand_rx)?;
 
         Ok(EventStream {
             node_id: node_id.clone(),
@@ -115,9 +180,21 @@ impl EventStream {
             _thread_handle: thread_handle,
             close_channel,
             clock,
+            notify_dropped,
+            dropped: BTreeMap::new(),
+            demand,
+            demand_pending: false,
         })
     }
 
+    /// Number of messages that were dropped so far because the queue of the
+    /// input was full, by input.
+    ///
+    /// Only includes drops that were reported before the last returned event.
+    pub fn dropped_inputs(&self) -> &BTreeMap<DataId, u64> {
+        &self.dropped
+    }
+
     /// wait for the next event on the events stream.
     pub fn recv(&mut self) -> Option<Event> {
         futures::executor::block_on(self.recv_async())
@@ -129,17 +206,63 @@ impl EventStream {
     }
 
     pub async fn recv_async(&mut self) -> Option<Event> {
-        self.receiver.next().await.map(Self::convert_event_item)
+        loop {
+            self.request_next_event();
+            let item = self.receiver.next().await?;
+            self.demand_pending = false;
+            if let Some(event) = self.handle_event_item(item) {
+                break Some(event);
+            }
+        }
     }
 
     pub async fn recv_async_timeout(&mut self, dur: Duration) -> Option<Event> {
-        let next_event = match select(Delay::new(dur), self.receiver.next()).await {
-            Either::Left((_elapsed, _)) => {
-                Some(EventItem::TimeoutError(eyre!("Receiver timed out")))
+        let deadline = Instant::now() + dur;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            self.request_next_event();
+            let item = match select(Delay::new(remaining), self.receiver.next()).await {
+                Either::Left((_elapsed, _)) => EventItem::TimeoutError(eyre!("Receiver timed out")),
+                Either::Right((item, _)) => {
+                    self.demand_pending = false;
+                    item?
+                }
+            };
+            if let Some(event) = self.handle_event_item(item) {
+                break Some(event);
             }
-            Either::Right((event, _)) => event,
-        };
-        next_event.map(Self::convert_event_item)
+        }
+    }
+
+    /// Lets the event stream thread fetch the next event in deterministic mode.
+    ///
+    /// Requests at most one event at a time, so that the thread never fetches
+    /// events before the node is ready to process them.
+    fn request_next_event(&mut self) {
+        if let Some(demand) = &self.demand {
+            if !self.demand_pending {
+                let _ = demand.try_send(());
+                self.demand_pending = true;
+            }
+        }
+    }
+
+    /// Updates the drop counters and converts the item into an event.
+    ///
+    /// Returns `None` for `InputDropped` events of inputs that did not set
+    /// `notify_dropped`.
+    fn handle_event_item(&mut self, item: EventItem) -> Option<Event> {
+        if let EventItem::NodeEvent {
+            event: NodeEvent::InputDropped { id, count },
+            ..
+        } = &item
+        {
+            *self.dropped.entry(id.clone()).or_default() += count;
+            if !self.notify_dropped.contains(id) {
+                return None;
+            }
+        }
+        Some(Self::convert_event_item(item))
     }
 
     fn convert_event_item(item: EventItem) -> Event {
@@ -148,6 +271,8 @@ impl EventStream {
                 NodeEvent::Stop => Event::Stop,
                 NodeEvent::Reload { operator_id } => Event::Reload { operator_id },
                 NodeEvent::InputClosed { id } => Event::InputClosed { id },
+                NodeEvent::InputGap { id, lost } => Event::InputGap { id, lost },
+                NodeEvent::InputDropped { id, count } => Event::InputDropped { id, count },
                 NodeEvent::Input { id, metadata, data } => {
                     let data = match data {
                         None => Ok(None),
@@ -206,9 +331,20 @@ impl Stream for EventStream {
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        self.receiver
-            .poll_next_unpin(cx)
-            .map(|item| item.map(Self::convert_event_item))
+        loop {
+            self.request_next_event();
+            let item = match self.receiver.poll_next_unpin(cx) {
+                std::task::Poll::Ready(Some(item)) => {
+                    self.demand_pending = false;
+                    item
+                }
+                std::task::Poll::Ready(None) => break std::task::Poll::Ready(None),
+                std::task::Poll::Pending => break std::task::Poll::Pending,
+            };
+            if let Some(event) = self.handle_event_item(item) {
+                break std::task::Poll::Ready(Some(event));
+            }
+        }
     }
 }
 
@@ -17,9 +17,11 @@ pub fn init(
     tx: flume::Sender<EventItem>,
     channel: DaemonChannel,
     clock: Arc<uhlc::HLC>,
+    demand: Option<flume::Receiver<()>>,
 ) -> eyre::Result<EventStreamThreadHandle> {
     let node_id_cloned = node_id.clone();
-    let join_handle = std::thread::spawn(|| event_stream_loop(node_id_cloned, tx, channel, clock));
+    let join_handle =
+        std::thread::spawn(|| event_stream_loop(node_id_cloned, tx, channel, clock, demand));
     Ok(EventStreamThreadHandle::new(node_id, join_handle))
 }
 
@@ -74,22 +76,47 @@ impl Drop for EventStreamThreadHandle {
     }
 }
 
-#[tracing::instrument(skip(tx, channel, clock))]
+/// Fetches events from the daemon and forwards them to the [`EventStream`](super::EventStream).
+///
+/// If a `demand` channel is given (deterministic mode), events are only
+/// requested from the daemon and forwarded once the node asked for them, so that
+/// the daemon knows when the node is idle. Otherwise, the next event is
+/// requested as soon as the previous one was received by the node.
+#[tracing::instrument(skip(tx, channel, clock, demand))]
 fn event_stream_loop(
     node_id: NodeId,
     tx: flume::Sender<EventItem>,
     mut channel: DaemonChannel,
     clock: Arc<uhlc::HLC>,
+    demand: Option<flume::Receiver<()>>,
 ) {
     let mut tx = Some(tx);
     let mut pending_drop_tokens: Vec<(DropToken, flume::Receiver<()>, Instant, u64)> = Vec::new();
     let mut drop_tokens = Vec::new();
+    // whether the node is waiting for an event that we did not forward yet
+    let mut requested = false;
 
     let result = 'outer: loop {
         if let Err(err) = handle_pending_drop_tokens(&mut pending_drop_tokens, &mut drop_tokens) {
             break 'outer Err(err);
         }
 
+        if let Some(demand) = &demand {
+            if tx.is_none() {
+                // all inputs are closed, so don't report the node as idle anymore
+                // and wait until the event stream is dropped
+                while demand.recv().is_ok() {}
+                break Ok(());
+            }
+            if !requested {
+                if demand.recv().is_err() {
+                    // the event stream was dropped
+                    break Ok(());
+                }
+                requested = true;
+            }
+        }
+
         let daemon_request = Timestamped {
             inner: DaemonRequest::NextEvent {
                 drop_tokens: std::mem::take(&mut drop_tokens),
@@ -134,6 +161,12 @@ fn event_stream_loop(
             };
 
             if let Some(tx) = tx.as_ref() {
+                if let Some(demand) = &demand {
+                    // wait until the node asks for the next event of this batch
+                    if !requested && demand.recv().is_err() {
+                        break 'outer Ok(());
+                    }
+                }
                 let (drop_tx, drop_rx) = flume::bounded(0);
                 match tx.send(EventItem::NodeEvent {
                     event: inner,
@@ -153,6 +186,8 @@ fn event_stream_loop(
                 if let Some(token) = drop_token {
                     pending_drop_tokens.push((token, drop_rx, Instant::now(), 1));
                 }
+                // the node received the event, so its request is fulfilled
+                requested = false;
             } else {
                 tracing::warn!("dropping event because event `tx` was already closed: `{inner:?}`");
             }
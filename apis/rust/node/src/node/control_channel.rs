@@ -1,9 +1,11 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use crate::daemon_connection::DaemonChannel;
 use dora_core::{
     config::{DataId, NodeId},
-    daemon_messages::{DaemonCommunication, DaemonRequest, DataMessage, DataflowId, Timestamped},
+    daemon_messages::{
+        DaemonCommunication, DaemonRequest, DataMessage, DataflowId, DropToken, Timestamped,
+    },
     message::{uhlc::HLC, Metadata},
 };
 use eyre::{bail, eyre, Context};
@@ -11,6 +13,7 @@ use eyre::{bail, eyre, Context};
 pub(crate) struct ControlChannel {
     channel: DaemonChannel,
     clock: Arc<HLC>,
+    deterministic: bool,
 }
 
 impl ControlChannel {
@@ -19,6 +22,7 @@ impl ControlChannel {
         dataflow_id: DataflowId,
         node_id: &NodeId,
         daemon_communication: &DaemonCommunication,
+        deterministic: bool,
         clock: Arc<HLC>,
     ) -> eyre::Result<Self> {
         let channel = match daemon_communication {
@@ -36,7 +40,7 @@ impl ControlChannel {
             }
         };
 
-        Self::init_on_channel(dataflow_id, node_id, channel, clock)
+        Self::init_on_channel(dataflow_id, node_id, channel, deterministic, clock)
     }
 
     #[tracing::instrument(skip(channel, clock), level = "trace")]
@@ -44,11 +48,16 @@ impl ControlChannel {
         dataflow_id: DataflowId,
         node_id: &NodeId,
         mut channel: DaemonChannel,
+        deterministic: bool,
         clock: Arc<HLC>,
     ) -> eyre::Result<Self> {
         channel.register(dataflow_id, node_id.clone(), clock.new_timestamp())?;
 
-        Ok(Self { channel, clock })
+        Ok(Self {
+            channel,
+            clock,
+            deterministic,
+        })
     }
 
     pub fn report_outputs_done(&mut self) -> eyre::Result<()> {
@@ -85,6 +94,54 @@ impl ControlChannel {
         Ok(())
     }
 
+    /// Leases a shared memory segment of at least `len` bytes from the daemon.
+    ///
+    /// Returns the ID of the segment and the drop token to send it with.
+    pub fn allocate_shared_memory(&mut self, len: usize) -> eyre::Result<(String, DropToken)> {
+        let reply = self
+            .channel
+            .request(&Timestamped {
+                inner: DaemonRequest::AllocateSharedMemory { len },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to send AllocateSharedMemory request to dora-daemon")?;
+        match reply {
+            dora_core::daemon_messages::DaemonReply::SharedMemory { result } => result
+                .map_err(|e| eyre!(e))
+                .wrap_err("failed to allocate shared memory"),
+            other => bail!("unexpected AllocateSharedMemory reply: {other:?}"),
+        }
+    }
+
+    pub fn report_dropped_inputs(&mut self, counts: BTreeMap<DataId, u64>) -> eyre::Result<()> {
+        let reply = self
+            .channel
+            .request(&Timestamped {
+                inner: DaemonRequest::ReportDroppedInputs { counts },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to report dropped inputs to dora-daemon")?;
+        match reply {
+            dora_core::daemon_messages::DaemonReply::Empty => Ok(()),
+            other => bail!("unexpected ReportDroppedInputs reply: {other:?}"),
+        }
+    }
+
+    /// Returns leased shared memory segments that were not sent to the daemon.
+    pub fn report_unsent_shared_memory(&mut self, drop_tokens: Vec<DropToken>) -> eyre::Result<()> {
+        let reply = self
+            .channel
+            .request(&Timestamped {
+                inner: DaemonRequest::ReportDropTokens { drop_tokens },
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to report unsent shared memory to dora-daemon")?;
+        match reply {
+            dora_core::daemon_messages::DaemonReply::Empty => Ok(()),
+            other => bail!("unexpected ReportDropTokens reply: {other:?}"),
+        }
+    }
+
     pub fn send_message(
         &mut self,
         output_id: DataId,
@@ -104,8 +161,28 @@ impl ControlChannel {
             })
             .wrap_err("failed to send SendMessage request to dora-daemon")?;
         match reply {
-            dora_core::daemon_messages::DaemonReply::Empty => Ok(()),
+            dora_core::daemon_messages::DaemonReply::Empty => {}
             other => bail!("unexpected SendMessage reply: {other:?}"),
         }
+        if self.deterministic {
+            // TCP connections don't reply to `SendMessage`, so wait until the
+            // daemon knows about the output before the node continues
+            self.barrier()?;
+        }
+        Ok(())
+    }
+
+    fn barrier(&mut self) -> eyre::Result<()> {
+        let reply = self
+            .channel
+            .request(&Timestamped {
+                inner: DaemonRequest::Barrier,
+                timestamp: self.clock.new_timestamp(),
+            })
+            .wrap_err("failed to send Barrier request to dora-daemon")?;
+        match reply {
+            dora_core::daemon_messages::DaemonReply::Result(result) => result.map_err(|e| eyre!(e)),
+            other => bail!("unexpected Barrier reply: {other:?}"),
+        }
     }
 }
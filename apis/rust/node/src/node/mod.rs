@@ -18,7 +18,7 @@ use dora_core::{
 use eyre::{bail, WrapErr};
 use shared_memory_extended::{Shmem, ShmemConf};
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
     ops::{Deref, DerefMut},
     sync::Arc,
     time::Duration,
@@ -44,6 +44,9 @@ pub struct DoraNode {
     sent_out_shared_memory: HashMap<DropToken, ShmemHandle>,
     drop_stream: DropStream,
     cache: VecDeque<ShmemHandle>,
+    /// Leased shared memory of samples that were dropped without being sent.
+    unsent_tx: flume::Sender<(ShmemHandle, DropToken)>,
+    unsent_rx: flume::Receiver<(ShmemHandle, DropToken)>,
 
     dataflow_descriptor: Descriptor,
 }
@@ -65,7 +68,7 @@ impl DoraNode {
             serde_yaml::from_str(&raw).context("failed to deserialize operator config")?
         };
         #[cfg(feature = "tracing")]
-        set_up_tracing(&node_config.node_id.to_string())
+        set_up_tracing(node_config.node_id.as_ref())
             .context("failed to set up tracing subscriber")?;
         Self::init(node_config)
     }
@@ -81,27 +84,8 @@ impl DoraNode {
     ///
     pub fn init_from_node_id(node_id: NodeId) -> eyre::Result<(Self, EventStream)> {
         // Make sure that the node is initialized outside of dora start.
-        let daemon_address = (LOCALHOST, DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT).into();
-
-        let mut channel =
-            DaemonChannel::new_tcp(daemon_address).context("Could not connect to the daemon")?;
-        let clock = Arc::new(uhlc::HLC::default());
-
-        let reply = channel
-            .request(&Timestamped {
-                inner: DaemonRequest::NodeConfig { node_id },
-                timestamp: clock.new_timestamp(),
-            })
-            .wrap_err("failed to request node config from daemon")?;
-        match reply {
-            dora_core::daemon_messages::DaemonReply::NodeConfig {
-                result: Ok(node_config),
-            } => Self::init(node_config),
-            dora_core::daemon_messages::DaemonReply::NodeConfig { result: Err(error) } => {
-                bail!("failed to get node config from daemon: {error}")
-            }
-            _ => bail!("unexpected reply from daemon"),
-        }
+        let node_config = request_node_config(DaemonRequest::NodeConfig { node_id })?;
+        Self::init(node_config)
     }
 
     pub fn init_flexible(node_id: NodeId) -> eyre::Result<(Self, EventStream)> {
@@ -122,19 +106,38 @@ impl DoraNode {
             daemon_communication,
             dataflow_descriptor,
             dynamic: _,
+            deterministic,
         } = node_config;
         let clock = Arc::new(uhlc::HLC::default());
 
-        let event_stream =
-            EventStream::init(dataflow_id, &node_id, &daemon_communication, clock.clone())
-                .wrap_err("failed to init event stream")?;
+        let notify_dropped = run_config
+            .inputs
+            .iter()
+            .filter(|(_, input)| input.notify_dropped)
+            .map(|(id, _)| id.clone())
+            .collect();
+        let event_stream = EventStream::init(
+            dataflow_id,
+            &node_id,
+            &daemon_communication,
+            notify_dropped,
+            deterministic,
+            clock.clone(),
+        )
+        .wrap_err("failed to init event stream")?;
         let drop_stream =
             DropStream::init(dataflow_id, &node_id, &daemon_communication, clock.clone())
                 .wrap_err("failed to init drop stream")?;
-        let control_channel =
-            ControlChannel::init(dataflow_id, &node_id, &daemon_communication, clock.clone())
-                .wrap_err("failed to init control channel")?;
-
+        let control_channel = ControlChannel::init(
+            dataflow_id,
+            &node_id,
+            &daemon_communication,
+            deterministic,
+            clock.clone(),
+        )
+        .wrap_err("failed to init control channel")?;
+
+        let (unsent_tx, unsent_rx) = flume::unbounded();
         let node = Self {
             id: node_id,
             dataflow_id,
@@ -144,6 +147,8 @@ impl DoraNode {
             sent_out_shared_memory: HashMap::new(),
             drop_stream,
             cache: VecDeque::new(),
+            unsent_tx,
+            unsent_rx,
             dataflow_descriptor,
         };
         Ok((node, event_stream))
@@ -273,6 +278,14 @@ impl DoraNode {
         Ok(())
     }
 
+    /// Reports inputs that were dropped by the node itself, by input ID.
+    ///
+    /// The daemon adds them to the dropped inputs of the dataflow statistics.
+    /// Used by the dora runtime to report drops in its operator queues.
+    pub fn report_dropped_inputs(&mut self, counts: BTreeMap<DataId, u64>) -> eyre::Result<()> {
+        self.control_channel.report_dropped_inputs(counts)
+    }
+
     pub fn close_outputs(&mut self, outputs: Vec<DataId>) -> eyre::Result<()> {
         for output_id in &outputs {
             if !self.node_config.outputs.remove(output_id) {
@@ -317,15 +330,15 @@ impl DoraNode {
         Ok(data)
     }
 
-    fn allocate_shared_memory(&mut self, data_len: usize) -> eyre::Result<ShmemHandle> {
+    fn allocate_shared_memory(&mut self, data_len: usize) -> eyre::Result<PooledShmem> {
+        // move finished segments to the cache first, as the daemon reuses them
+        self.handle_finished_drop_tokens()?;
+        let (shared_memory_id, drop_token) =
+            self.control_channel.allocate_shared_memory(data_len)?;
         let cache_index = self
             .cache
             .iter()
-            .enumerate()
-            .rev()
-            .filter(|(_, s)| s.len() >= data_len)
-            .min_by_key(|(_, s)| s.len())
-            .map(|(i, _)| i);
+            .position(|s| s.get_os_id() == shared_memory_id);
         let memory = match cache_index {
             Some(i) => {
                 // we know that this index exists, so we can safely unwrap here
@@ -333,18 +346,23 @@ impl DoraNode {
             }
             None => ShmemHandle(Box::new(
                 ShmemConf::new()
-                    .size(data_len)
+                    .os_id(&shared_memory_id)
                     .writable(true)
-                    .create()
-                    .wrap_err("failed to allocate shared memory")?,
+                    .open()
+                    .wrap_err("failed to map shared memory")?,
             )),
         };
         assert!(memory.len() >= data_len);
 
-        Ok(memory)
+        Ok(PooledShmem {
+            handle: Some(memory),
+            drop_token,
+            unsent: self.unsent_tx.clone(),
+        })
     }
 
     fn handle_finished_drop_tokens(&mut self) -> eyre::Result<()> {
+        self.handle_unsent_shared_memory()?;
         loop {
             match self.drop_stream.try_recv() {
                 Ok(token) => match self.sent_out_shared_memory.remove(&token) {
@@ -360,6 +378,20 @@ impl DoraNode {
         Ok(())
     }
 
+    /// Returns the shared memory of dropped samples to the daemon.
+    fn handle_unsent_shared_memory(&mut self) -> eyre::Result<()> {
+        let mut drop_tokens = Vec::new();
+        for (region, drop_token) in self.unsent_rx.try_iter().collect::<Vec<_>>() {
+            self.add_to_cache(region);
+            drop_tokens.push(drop_token);
+        }
+        if !drop_tokens.is_empty() {
+            self.control_channel
+                .report_unsent_shared_memory(drop_tokens)?;
+        }
+        Ok(())
+    }
+
     fn add_to_cache(&mut self, memory: ShmemHandle) {
         const MAX_CACHE_SIZE: usize = 20;
 
@@ -377,9 +409,38 @@ impl DoraNode {
     }
 }
 
+/// Requests a node config from the local listener of the daemon.
+pub(crate) fn request_node_config(request: DaemonRequest) -> eyre::Result<NodeConfig> {
+    let daemon_address = (LOCALHOST, DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT).into();
+
+    let mut channel =
+        DaemonChannel::new_tcp(daemon_address).context("Could not connect to the daemon")?;
+    let clock = Arc::new(uhlc::HLC::default());
+
+    let reply = channel
+        .request(&Timestamped {
+            inner: request,
+            timestamp: clock.new_timestamp(),
+        })
+        .wrap_err("failed to request node config from daemon")?;
+    match reply {
+        dora_core::daemon_messages::DaemonReply::NodeConfig {
+            result: Ok(node_config),
+        } => Ok(node_config),
+        dora_core::daemon_messages::DaemonReply::NodeConfig { result: Err(error) } => {
+            bail!("failed to get node config from daemon: {error}")
+        }
+        _ => bail!("unexpected reply from daemon"),
+    }
+}
+
 impl Drop for DoraNode {
     #[tracing::instrument(skip(self), fields(self.id = %self.id), level = "trace")]
     fn drop(&mut self) {
+        if let Err(err) = self.handle_unsent_shared_memory() {
+            tracing::warn!("{err:?}")
+        }
+
         // close all outputs first to notify subscribers as early as possible
         if let Err(err) = self
             .control_channel
@@ -438,8 +499,9 @@ pub struct DataSample {
 impl DataSample {
     fn finalize(self) -> (Option<DataMessage>, Option<(ShmemHandle, DropToken)>) {
         match self.inner {
-            DataSampleInner::Shmem(shared_memory) => {
-                let drop_token = DropToken::generate();
+            DataSampleInner::Shmem(mut pooled) => {
+                let shared_memory = pooled.handle.take().unwrap();
+                let drop_token = pooled.drop_token;
                 let data = DataMessage::SharedMemory {
                     shared_memory_id: shared_memory.get_os_id().to_owned(),
                     len: self.len,
@@ -457,7 +519,7 @@ impl Deref for DataSample {
 
     fn deref(&self) -> &Self::Target {
         let slice = match &self.inner {
-            DataSampleInner::Shmem(handle) => unsafe { handle.as_slice() },
+            DataSampleInner::Shmem(pooled) => unsafe { pooled.handle().as_slice() },
             DataSampleInner::Vec(data) => data,
         };
         &slice[..self.len]
@@ -467,7 +529,7 @@ impl Deref for DataSample {
 impl DerefMut for DataSample {
     fn deref_mut(&mut self) -> &mut Self::Target {
         let slice = match &mut self.inner {
-            DataSampleInner::Shmem(handle) => unsafe { handle.as_slice_mut() },
+            DataSampleInner::Shmem(pooled) => unsafe { pooled.handle_mut().as_slice_mut() },
             DataSampleInner::Vec(data) => data,
         };
         &mut slice[..self.len]
@@ -497,10 +559,39 @@ impl std::fmt::Debug for DataSample {
 }
 
 enum DataSampleInner {
-    Shmem(ShmemHandle),
+    Shmem(PooledShmem),
     Vec(AVec<u8, ConstAlign<128>>),
 }
 
+/// Shared memory segment that is leased from the daemon.
+///
+/// Returns the segment to the node if it is dropped without being sent.
+struct PooledShmem {
+    handle: Option<ShmemHandle>,
+    drop_token: DropToken,
+    unsent: flume::Sender<(ShmemHandle, DropToken)>,
+}
+
+impl PooledShmem {
+    fn handle(&self) -> &ShmemHandle {
+        self.handle.as_ref().unwrap()
+    }
+
+    fn handle_mut(&mut self) -> &mut ShmemHandle {
+        self.handle.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledShmem {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            // the node might be dropped already, in which case the daemon
+            // reclaims the segment when the node exits
+            let _ = self.unsent.send((handle, self.drop_token));
+        }
+    }
+}
+
 struct ShmemHandle(Box<Shmem>);
 
 impl Deref for ShmemHandle {
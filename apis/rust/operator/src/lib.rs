@@ -32,9 +32,24 @@ pub mod raw;
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum Event<'a> {
-    Input { id: &'a str, data: ArrowData },
-    InputParseError { id: &'a str, error: String },
-    InputClosed { id: &'a str },
+    Input {
+        id: &'a str,
+        data: ArrowData,
+    },
+    InputParseError {
+        id: &'a str,
+        error: String,
+    },
+    InputClosed {
+        id: &'a str,
+    },
+    /// Messages of the input were dropped because its queue was full.
+    ///
+    /// Only sent for inputs that set `notify_dropped`.
+    InputDropped {
+        id: &'a str,
+        count: u64,
+    },
     Stop,
 }
 
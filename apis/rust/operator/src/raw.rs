@@ -58,6 +58,11 @@ pub unsafe fn dora_on_event<O: DoraOperator>(
         }
     } else if let Some(input_id) = &event.input_closed {
         Event::InputClosed { id: input_id }
+    } else if let Some(dropped) = &event.input_dropped {
+        Event::InputDropped {
+            id: &dropped.id,
+            count: dropped.count,
+        }
     } else if event.stop {
         Event::Stop
     } else {
@@ -78,3 +83,55 @@ pub unsafe fn dora_on_event<O: DoraOperator>(
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_operator_api_types::{safer_ffi::closure::ArcDynFn1, InputDropped};
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct RecordingOperator {
+        events: Vec<String>,
+    }
+
+    impl DoraOperator for RecordingOperator {
+        fn on_event(
+            &mut self,
+            event: &Event,
+            _output_sender: &mut DoraOutputSender,
+        ) -> Result<DoraStatus, String> {
+            self.events.push(format!("{event:?}"));
+            Ok(DoraStatus::Continue)
+        }
+    }
+
+    #[test]
+    fn input_dropped_events_are_forwarded() {
+        let send_output = SendOutput {
+            send_output: ArcDynFn1::new(Arc::new(|_| DoraResult { error: None })),
+        };
+        let mut operator = RecordingOperator::default();
+        let mut event = RawEvent {
+            input: None,
+            input_closed: None,
+            stop: false,
+            error: None,
+            input_dropped: Some(
+                Box::new(InputDropped {
+                    id: String::from("camera").into(),
+                    count: 3,
+                })
+                .into(),
+            ),
+        };
+        let context: *mut RecordingOperator = &mut operator;
+        let result =
+            unsafe { dora_on_event::<RecordingOperator>(&mut event, &send_output, context.cast()) };
+        assert!(result.result.error.is_none());
+        assert_eq!(
+            operator.events,
+            [r#"InputDropped { id: "camera", count: 3 }"#]
+        );
+    }
+}
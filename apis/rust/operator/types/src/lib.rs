@@ -99,6 +99,17 @@ pub struct RawEvent {
     pub input_closed: Option<safer_ffi::String>,
     pub stop: bool,
     pub error: Option<safer_ffi::String>,
+    pub input_dropped: Option<safer_ffi::boxed::Box<InputDropped>>,
+}
+
+/// Messages of an input were dropped because its queue was full.
+#[derive_ReprC]
+#[ffi_export]
+#[repr(C)]
+#[derive(Debug)]
+pub struct InputDropped {
+    pub id: safer_ffi::String,
+    pub count: u64,
 }
 
 #[derive_ReprC]
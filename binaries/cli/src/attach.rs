@@ -1,23 +1,26 @@
 use colored::Colorize;
-use communication_layer_request_reply::{TcpConnection, TcpRequestReplyConnection};
+use communication_layer_request_reply::TcpRequestReplyConnection;
 use dora_core::{
     coordinator_messages::LogMessage,
     descriptor::{resolve_path, CoreNodeKind, Descriptor},
-    topics::{ControlRequest, ControlRequestReply},
+    security::SecurityConfig,
+    topics::{ControlRequest, ControlRequestReply, EventMessage, LifecycleEvent},
 };
+use dora_daemon::ctrlc_handler::subscribe_ctrlc;
 use eyre::Context;
 use notify::event::ModifyKind;
 use notify::{Config, Event as NotifyEvent, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use std::{
-    collections::HashMap,
-    net::{SocketAddr, TcpStream},
-};
+use std::{collections::HashMap, net::SocketAddr};
 use std::{path::PathBuf, sync::mpsc, time::Duration};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use crate::handle_dataflow_result;
+use crate::{
+    connect_to_coordinator_stream, handle_dataflow_result,
+    output::{self, OutputFormat},
+};
 
+#[allow(clippy::too_many_arguments)]
 pub fn attach_dataflow(
     dataflow: Descriptor,
     dataflow_path: PathBuf,
@@ -25,7 +28,9 @@ pub fn attach_dataflow(
     session: &mut TcpRequestReplyConnection,
     hot_reload: bool,
     coordinator_socket: SocketAddr,
+    security: &SecurityConfig,
     log_level: log::LevelFilter,
+    format: OutputFormat,
 ) -> Result<(), eyre::ErrReport> {
     let (tx, rx) = mpsc::sync_channel(2);
 
@@ -105,31 +110,46 @@ pub fn attach_dataflow(
     };
 
     // Setup Ctrlc Watcher to stop dataflow after ctrlc
-    let ctrlc_tx = tx.clone();
-    let mut ctrlc_sent = false;
-    ctrlc::set_handler(move || {
-        if ctrlc_sent {
-            std::process::abort();
-        } else {
-            if ctrlc_tx
-                .send(AttachEvent::Control(ControlRequest::Stop {
-                    dataflow_uuid: dataflow_id,
-                    grace_duration: None,
-                }))
-                .is_err()
-            {
-                // bail!("failed to report ctrl-c event to dora-daemon");
+    let ctrlc_sender = tx.clone();
+    let _ctrlc_subscription = subscribe_ctrlc(move || {
+        ctrlc_sender
+            .send(AttachEvent::Control(ControlRequest::Stop {
+                dataflow_uuid: dataflow_id,
+                grace_duration: None,
+            }))
+            .map_err(|_| eyre::eyre!("failed to send stop request for dataflow {dataflow_id}"))
+    })?;
+
+    // subscribe to lifecycle events to get notified when the dataflow finishes
+    let mut event_session = connect_to_coordinator_stream(coordinator_socket, security)
+        .wrap_err("failed to connect to dora coordinator")?;
+    event_session
+        .send(
+            &serde_json::to_vec(&ControlRequest::SubscribeEvents {
+                dataflow_id: Some(dataflow_id),
+            })
+            .wrap_err("failed to serialize message")?,
+        )
+        .wrap_err("failed to send event subscribe request to coordinator")?;
+    let events_tx = tx.clone();
+    std::thread::spawn(move || {
+        while let Ok(raw) = event_session.receive() {
+            match serde_json::from_slice(&raw) {
+                Ok(EventMessage::Lifecycle(event)) => {
+                    if events_tx.send(AttachEvent::Lifecycle(event)).is_err() {
+                        return;
+                    }
+                }
+                Ok(EventMessage::Log(_)) => {}
+                Err(err) => tracing::warn!("failed to parse lifecycle event: {err}"),
             }
-            ctrlc_sent = true;
         }
-    })
-    .wrap_err("failed to set ctrl-c handler")?;
+        let _ = events_tx.send(AttachEvent::EventsClosed);
+    });
 
     // subscribe to log messages
-    let mut log_session = TcpConnection {
-        stream: TcpStream::connect(coordinator_socket)
-            .wrap_err("failed to connect to dora coordinator")?,
-    };
+    let mut log_session = connect_to_coordinator_stream(coordinator_socket, security)
+        .wrap_err("failed to connect to dora coordinator")?;
     log_session
         .send(
             &serde_json::to_vec(&ControlRequest::LogSubscribe {
@@ -149,12 +169,36 @@ pub fn attach_dataflow(
         }
     });
 
+    let mut events_closed = false;
     loop {
         let control_request = match rx.recv_timeout(Duration::from_secs(1)) {
-            Err(_err) => ControlRequest::Check {
+            // fall back to polling if the event stream was closed unexpectedly
+            Err(_err) if events_closed => ControlRequest::Check {
                 dataflow_uuid: dataflow_id,
             },
+            Err(_err) => continue,
             Ok(AttachEvent::Control(control_request)) => control_request,
+            Ok(AttachEvent::Lifecycle(event)) => match event {
+                LifecycleEvent::DataflowFinished { dataflow, result } => {
+                    info!("dataflow {} stopped", dataflow.uuid);
+                    break handle_dataflow_result(result, Some(dataflow.uuid));
+                }
+                LifecycleEvent::NodeRestarted { node, machine, .. } => {
+                    info!("node `{node}` was restarted on machine `{machine}`");
+                    continue;
+                }
+                LifecycleEvent::DaemonLost { machine } => {
+                    warn!("daemon on machine `{machine}` stopped responding");
+                    continue;
+                }
+                _ => continue,
+            },
+            Ok(AttachEvent::EventsClosed) => {
+                events_closed = true;
+                ControlRequest::Check {
+                    dataflow_uuid: dataflow_id,
+                }
+            }
             Ok(AttachEvent::Log(Ok(log_message))) => {
                 let LogMessage {
                     dataflow_id: _,
@@ -181,7 +225,7 @@ pub fn attach_dataflow(
                     None => "".normal(),
                 };
 
-                println!("{level}{node}{target}: {message}");
+                output::print_status(format, &format!("{level}{node}{target}: {message}"));
                 continue;
             }
             Ok(AttachEvent::Log(Err(err))) => {
@@ -212,4 +256,7 @@ pub fn attach_dataflow(
 enum AttachEvent {
     Control(ControlRequest),
     Log(eyre::Result<LogMessage>),
+    Lifecycle(LifecycleEvent),
+    /// The lifecycle event stream was closed.
+    EventsClosed,
 }
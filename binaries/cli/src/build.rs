@@ -1,11 +1,41 @@
 use dora_core::{
-    config::OperatorId,
-    descriptor::{Descriptor, SINGLE_OPERATOR_DEFAULT_ID},
+    config::{NodeId, OperatorId},
+    descriptor::{
+        resolve_path, Descriptor, DYNAMIC_SOURCE, SHELL_SOURCE, SINGLE_OPERATOR_DEFAULT_ID,
+    },
 };
-use eyre::{eyre, Context};
-use std::{path::Path, process::Command};
+use eyre::{bail, eyre, Context};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the file that records the last successful build of every node.
+///
+/// The file is placed in the `out` folder next to the dataflow descriptor.
+const BUILD_STATE_FILE: &str = "build.lock";
 
-pub fn build(dataflow: &Path) -> eyre::Result<()> {
+/// Directories that contain build outputs or caches instead of source files.
+const IGNORED_SOURCE_DIRS: &[&str] = &["target", "out", "node_modules", "__pycache__"];
+
+pub struct BuildOptions {
+    /// Maximum number of build commands that run concurrently.
+    pub jobs: NonZeroUsize,
+    /// Only build the given nodes. All nodes are built if empty.
+    pub nodes: BTreeSet<NodeId>,
+    /// Ignore the build state file and rebuild all selected nodes.
+    pub force: bool,
+}
+
+pub fn build(dataflow: &Path, options: BuildOptions) -> eyre::Result<()> {
     let descriptor = Descriptor::blocking_read(dataflow)?;
     let dataflow_absolute = if dataflow.is_relative() {
         std::env::current_dir().unwrap().join(dataflow)
@@ -14,68 +44,478 @@ pub fn build(dataflow: &Path) -> eyre::Result<()> {
     };
     let working_dir = dataflow_absolute.parent().unwrap();
 
-    let default_op_id = OperatorId::from(SINGLE_OPERATOR_DEFAULT_ID.to_string());
+    for node_id in &options.nodes {
+        if !descriptor.nodes.iter().any(|n| &n.id == node_id) {
+            bail!("no node with ID `{node_id}` in dataflow");
+        }
+    }
+
+    let state_path = working_dir.join("out").join(BUILD_STATE_FILE);
+    let mut state = BuildState::load(&state_path)?;
+
+    let mut targets = Vec::new();
+    for node in &descriptor.nodes {
+        if !options.nodes.is_empty() && !options.nodes.contains(&node.id) {
+            continue;
+        }
+        targets.extend(BuildTarget::collect(node)?);
+    }
+
+    let mut pending = Vec::new();
+    for target in targets {
+        let fingerprint = target.fingerprint(working_dir);
+        if !options.force && state.is_up_to_date(&target.key(), &fingerprint) {
+            println!("{} is up to date", target.description);
+            continue;
+        }
+        pending.push(target);
+    }
+
+    let results = run_parallel(&pending, options.jobs, working_dir);
 
-    for node in descriptor.nodes {
-        match node.kind()? {
-            dora_core::descriptor::NodeKind::Standard(_) => {
-                run_build_command(node.build.as_deref(), working_dir).with_context(|| {
-                    format!("build command failed for standard node `{}`", node.id)
-                })?
+    let mut failures = Vec::new();
+    for (target, result) in pending.iter().zip(results) {
+        match result {
+            Ok(()) => {
+                // the build command might have (re)created the artifact, so we
+                // need to compute the fingerprint again
+                state.record(target.key(), target.fingerprint(working_dir));
+            }
+            Err(err) => {
+                state.remove(&target.key());
+                failures.push((&target.description, err));
             }
-            dora_core::descriptor::NodeKind::Runtime(runtime_node) => {
-                for operator in &runtime_node.operators {
-                    run_build_command(operator.config.build.as_deref(), working_dir).with_context(
-                        || {
-                            format!(
-                                "build command failed for operator `{}/{}`",
-                                node.id, operator.id
-                            )
-                        },
-                    )?;
+        }
+    }
+
+    state
+        .store(&state_path)
+        .wrap_err("failed to write build state file")?;
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        let total = failures.len();
+        let mut message = String::new();
+        for (description, err) in failures {
+            message.push_str(&format!("\n  - {description}: {err:#}"));
+        }
+        Err(eyre!("{total} build command(s) failed:{message}"))
+    }
+}
+
+/// Runs the build commands of the given targets using up to `jobs` threads.
+///
+/// All commands are run, even if some of them fail. The returned results are
+/// in the same order as the given targets.
+fn run_parallel(
+    targets: &[BuildTarget],
+    jobs: NonZeroUsize,
+    working_dir: &Path,
+) -> Vec<eyre::Result<()>> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<eyre::Result<()>>>> =
+        Mutex::new(targets.iter().map(|_| None).collect());
+
+    std::thread::scope(|s| {
+        for _ in 0..jobs.get().min(targets.len()) {
+            s.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(target) = targets.get(index) else {
+                    break;
+                };
+                let result = run_build_command(&target.build, working_dir);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.unwrap_or_else(|| Err(eyre!("build command was not run"))))
+        .collect()
+}
+
+/// A single build command of a node or operator.
+struct BuildTarget {
+    node_id: NodeId,
+    operator_id: Option<OperatorId>,
+    description: String,
+    build: String,
+    /// Path of the executable or library produced by the build command, if known.
+    source: Option<String>,
+    /// Serialized node definition, used to detect changes to inputs and outputs.
+    definition: String,
+}
+
+impl BuildTarget {
+    fn collect(node: &dora_core::descriptor::Node) -> eyre::Result<Vec<Self>> {
+        let default_op_id = OperatorId::from(SINGLE_OPERATOR_DEFAULT_ID.to_string());
+        let definition =
+            serde_yaml::to_string(node).context("failed to serialize node definition")?;
+
+        let target = |operator_id: Option<&OperatorId>,
+                      kind: &str,
+                      build: Option<&String>,
+                      source: Option<String>| {
+            build.map(|build| {
+                let description = match operator_id {
+                    Some(op) => format!("operator `{}/{op}`", node.id),
+                    None => format!("{kind} node `{}`", node.id),
+                };
+                Self {
+                    node_id: node.id.clone(),
+                    operator_id: operator_id.cloned(),
+                    description,
+                    build: build.clone(),
+                    source,
+                    definition: definition.clone(),
                 }
+            })
+        };
+
+        let targets = match node.kind()? {
+            dora_core::descriptor::NodeKind::Standard(path) => {
+                target(None, "standard", node.build.as_ref(), node_source(path))
+                    .into_iter()
+                    .collect()
             }
-            dora_core::descriptor::NodeKind::Custom(custom_node) => {
-                run_build_command(custom_node.build.as_deref(), working_dir).with_context(|| {
-                    format!("build command failed for custom node `{}`", node.id)
-                })?
+            dora_core::descriptor::NodeKind::Runtime(runtime_node) => runtime_node
+                .operators
+                .iter()
+                .filter_map(|operator| {
+                    target(
+                        Some(&operator.id),
+                        "runtime",
+                        operator.config.build.as_ref(),
+                        operator_source(&operator.config.source),
+                    )
+                })
+                .collect(),
+            dora_core::descriptor::NodeKind::Custom(custom_node) => target(
+                None,
+                "custom",
+                custom_node.build.as_ref(),
+                node_source(&custom_node.source),
+            )
+            .into_iter()
+            .collect(),
+            dora_core::descriptor::NodeKind::Operator(operator) => target(
+                Some(operator.id.as_ref().unwrap_or(&default_op_id)),
+                "operator",
+                operator.config.build.as_ref(),
+                operator_source(&operator.config.source),
+            )
+            .into_iter()
+            .collect(),
+        };
+        Ok(targets)
+    }
+
+    fn key(&self) -> String {
+        match &self.operator_id {
+            Some(op) => format!("{}/{op}", self.node_id),
+            None => self.node_id.to_string(),
+        }
+    }
+
+    /// Hashes the build command, the node definition, the source files, and
+    /// the modification time of the build artifact.
+    ///
+    /// The source files are the files in the directory of the artifact if it
+    /// lies inside the dataflow directory, e.g. for Python nodes. Otherwise,
+    /// e.g. for compiled nodes whose artifact is in a `target` directory, all
+    /// files in the dataflow directory are considered sources. Files are
+    /// compared by path, size, and modification time.
+    fn fingerprint(&self, working_dir: &Path) -> String {
+        let mut hasher = Sha256::new();
+        hash_str(&mut hasher, &self.build);
+        hash_str(&mut hasher, &self.definition);
+
+        let artifact = self
+            .source
+            .as_ref()
+            .and_then(|source| artifact_path(source, working_dir));
+        if self.source.is_some() {
+            let modified = artifact
+                .as_ref()
+                .and_then(|path| std::fs::metadata(path).ok())
+                .and_then(|metadata| metadata.modified().ok());
+            match modified {
+                Some(modified) => hash_time(&mut hasher, modified),
+                // artifact is missing, so the target is never up to date
+                None => hash_time(&mut hasher, SystemTime::now()),
             }
-            dora_core::descriptor::NodeKind::Operator(operator) => {
-                run_build_command(operator.config.build.as_deref(), working_dir).with_context(
-                    || {
-                        format!(
-                            "build command failed for operator `{}/{}`",
-                            node.id,
-                            operator.id.as_ref().unwrap_or(&default_op_id)
-                        )
-                    },
-                )?
+        }
+
+        let source_dir = artifact
+            .as_deref()
+            .and_then(|path| source_dir_of_artifact(path, working_dir))
+            .unwrap_or_else(|| working_dir.to_owned());
+        hash_source_files(&mut hasher, &source_dir, &source_dir);
+
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// Returns the directory that contains the artifact if it is a source
+/// directory inside the dataflow directory.
+fn source_dir_of_artifact(artifact: &Path, working_dir: &Path) -> Option<PathBuf> {
+    let working_dir = working_dir.canonicalize().ok()?;
+    let dir = artifact.parent()?;
+    let relative = dir.strip_prefix(&working_dir).ok()?;
+    let is_output_dir = relative
+        .components()
+        .any(|c| is_ignored_source_dir(c.as_os_str().to_str().unwrap_or_default()));
+    (!is_output_dir).then(|| dir.to_owned())
+}
+
+fn is_ignored_source_dir(name: &str) -> bool {
+    name.starts_with('.') || IGNORED_SOURCE_DIRS.contains(&name)
+}
+
+/// Hashes the relative path, size, and modification time of all files in
+/// the given directory, recursively and in a stable order.
+fn hash_source_files(hasher: &mut Sha256, dir: &Path, root: &Path) {
+    let mut entries: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).collect(),
+        Err(err) => {
+            tracing::debug!("failed to read source dir `{}`: {err}", dir.display());
+            return;
+        }
+    };
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            if !is_ignored_source_dir(&entry.file_name().to_string_lossy()) {
+                hash_source_files(hasher, &path, root);
+            }
+        } else if !entry.file_name().to_string_lossy().starts_with('.') {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            hash_str(hasher, &relative.to_string_lossy());
+            hasher.update(metadata.len().to_le_bytes());
+            if let Ok(modified) = metadata.modified() {
+                hash_time(hasher, modified);
             }
         }
     }
+}
+
+fn hash_str(hasher: &mut Sha256, value: &str) {
+    // prefix with the length to keep the boundaries between fields unambiguous
+    hasher.update((value.len() as u64).to_le_bytes());
+    hasher.update(value.as_bytes());
+}
+
+fn hash_time(hasher: &mut Sha256, time: SystemTime) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    hasher.update(since_epoch.as_nanos().to_le_bytes());
+}
+
+fn node_source(source: &str) -> Option<String> {
+    match source {
+        SHELL_SOURCE | DYNAMIC_SOURCE => None,
+        source => Some(source.to_owned()),
+    }
+}
 
-    Ok(())
-}
-
-fn run_build_command(build: Option<&str>, working_dir: &Path) -> eyre::Result<()> {
-    if let Some(build) = build {
-        let mut split = build.split_whitespace();
-        let mut cmd = Command::new(
-            split
-                .next()
-                .ok_or_else(|| eyre!("build command is empty"))?,
-        );
-        cmd.args(split);
-        cmd.current_dir(working_dir);
-        let exit_status = cmd
-            .status()
-            .wrap_err_with(|| format!("failed to run `{}`", build))?;
-        if exit_status.success() {
-            Ok(())
-        } else {
-            Err(eyre!("build command returned an error code"))
+fn operator_source(source: &dora_core::descriptor::OperatorSource) -> Option<String> {
+    match source {
+        dora_core::descriptor::OperatorSource::SharedLibrary(path) => {
+            dora_core::adjust_shared_library_path(Path::new(path))
+                .ok()
+                .map(|p| p.to_string_lossy().into_owned())
         }
+        dora_core::descriptor::OperatorSource::Python(python) => Some(python.source.clone()),
+        dora_core::descriptor::OperatorSource::Wasm(path) => Some(path.clone()),
+    }
+}
+
+fn artifact_path(source: &str, working_dir: &Path) -> Option<PathBuf> {
+    if dora_core::descriptor::source_is_url(source) {
+        return None;
+    }
+    let path = Path::new(source);
+    if path.extension().is_some() {
+        working_dir.join(path).canonicalize().ok()
     } else {
+        resolve_path(source, working_dir).ok()
+    }
+}
+
+/// Records the fingerprints of successfully built nodes and operators.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct BuildState {
+    #[serde(default)]
+    targets: BTreeMap<String, String>,
+}
+
+impl BuildState {
+    fn load(path: &Path) -> eyre::Result<Self> {
+        match std::fs::read(path) {
+            Ok(raw) => Ok(serde_yaml::from_slice(&raw).unwrap_or_else(|err| {
+                tracing::warn!("ignoring invalid build state file: {err}");
+                Self::default()
+            })),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).wrap_err_with(|| format!("failed to read `{}`", path.display())),
+        }
+    }
+
+    fn store(&self, path: &Path) -> eyre::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err_with(|| format!("failed to create `{}`", parent.display()))?;
+        }
+        let serialized = serde_yaml::to_string(self)?;
+        std::fs::write(path, serialized)
+            .wrap_err_with(|| format!("failed to write `{}`", path.display()))
+    }
+
+    fn is_up_to_date(&self, key: &str, fingerprint: &str) -> bool {
+        self.targets.get(key).map(String::as_str) == Some(fingerprint)
+    }
+
+    fn record(&mut self, key: String, fingerprint: String) {
+        self.targets.insert(key, fingerprint);
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.targets.remove(key);
+    }
+}
+
+fn run_build_command(build: &str, working_dir: &Path) -> eyre::Result<()> {
+    let mut split = build.split_whitespace();
+    let mut cmd = Command::new(
+        split
+            .next()
+            .ok_or_else(|| eyre!("build command is empty"))?,
+    );
+    cmd.args(split);
+    cmd.current_dir(working_dir);
+    let exit_status = cmd
+        .status()
+        .wrap_err_with(|| format!("failed to run `{}`", build))?;
+    if exit_status.success() {
         Ok(())
+    } else {
+        Err(eyre!("build command returned an error code"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dataflow_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("node")).unwrap();
+        std::fs::write(dir.path().join("node").join("main.py"), "print('hello')").unwrap();
+        dir
+    }
+
+    fn python_target() -> BuildTarget {
+        BuildTarget {
+            node_id: NodeId::from("node".to_string()),
+            operator_id: None,
+            description: "custom node `node`".into(),
+            build: "pip install -r requirements.txt".into(),
+            source: Some("node/main.py".into()),
+            definition: "id: node".into(),
+        }
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        let temp_dir = temp_dataflow_dir();
+        let dir = temp_dir.path();
+        let target = python_target();
+        let fingerprint = target.fingerprint(dir);
+        assert_eq!(fingerprint.len(), 64);
+        assert_eq!(fingerprint, target.fingerprint(dir));
+    }
+
+    #[test]
+    fn fingerprint_changes_with_build_command() {
+        let temp_dir = temp_dataflow_dir();
+        let dir = temp_dir.path();
+        let target = python_target();
+        let other = BuildTarget {
+            build: "pip install .".into(),
+            ..python_target()
+        };
+        assert_ne!(target.fingerprint(dir), other.fingerprint(dir));
+    }
+
+    #[test]
+    fn fingerprint_changes_with_source_files() {
+        let temp_dir = temp_dataflow_dir();
+        let dir = temp_dir.path();
+        let target = python_target();
+        let before = target.fingerprint(dir);
+
+        std::fs::write(dir.join("node").join("util.py"), "X = 1").unwrap();
+        let after = target.fingerprint(dir);
+        assert_ne!(before, after);
+
+        // files outside of the source directory of the node are ignored
+        std::fs::write(dir.join("README.md"), "docs").unwrap();
+        assert_eq!(after, target.fingerprint(dir));
+    }
+
+    #[test]
+    fn fingerprint_ignores_build_outputs() {
+        let temp_dir = temp_dataflow_dir();
+        let dir = temp_dir.path();
+        let target = BuildTarget {
+            source: None,
+            ..python_target()
+        };
+        let before = target.fingerprint(dir);
+
+        std::fs::create_dir_all(dir.join("target").join("debug")).unwrap();
+        std::fs::write(dir.join("target").join("debug").join("node"), "binary").unwrap();
+        std::fs::create_dir_all(dir.join("node").join("__pycache__")).unwrap();
+        std::fs::write(dir.join("node").join("__pycache__").join("main.pyc"), "").unwrap();
+        assert_eq!(before, target.fingerprint(dir));
+
+        std::fs::write(dir.join("dataflow.yml"), "nodes: []").unwrap();
+        assert_ne!(before, target.fingerprint(dir));
+    }
+
+    #[test]
+    fn build_state_roundtrip() {
+        let temp_dir = temp_dataflow_dir();
+        let dir = temp_dir.path();
+        let path = dir.join("out").join(BUILD_STATE_FILE);
+        let mut state = BuildState::load(&path).unwrap();
+        assert!(!state.is_up_to_date("node", "abc"));
+
+        state.record("node".into(), "abc".into());
+        state.store(&path).unwrap();
+
+        let mut state = BuildState::load(&path).unwrap();
+        assert!(state.is_up_to_date("node", "abc"));
+        assert!(!state.is_up_to_date("node", "def"));
+        state.remove("node");
+        assert!(!state.is_up_to_date("node", "abc"));
+    }
+
+    #[test]
+    fn invalid_build_state_is_ignored() {
+        let temp_dir = temp_dataflow_dir();
+        let dir = temp_dir.path();
+        let path = dir.join(BUILD_STATE_FILE);
+        // fingerprints of older versions were integers
+        std::fs::write(&path, "targets:\n  node: 42\n  other: [1]\n").unwrap();
+        let state = BuildState::load(&path).unwrap();
+        assert!(state.targets.is_empty());
     }
 }
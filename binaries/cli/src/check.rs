@@ -1,6 +1,12 @@
-use crate::connect_to_coordinator;
+use crate::{
+    connect_to_coordinator,
+    output::{self, CheckFailed, CheckStatus, Content, OutputFormat},
+};
 use communication_layer_request_reply::TcpRequestReplyConnection;
-use dora_core::topics::{ControlRequest, ControlRequestReply};
+use dora_core::{
+    security::SecurityConfig,
+    topics::{ControlRequest, ControlRequestReply},
+};
 use eyre::{bail, Context};
 use std::{
     io::{IsTerminal, Write},
@@ -8,9 +14,35 @@ use std::{
 };
 use termcolor::{Color, ColorChoice, ColorSpec, WriteColor};
 
-pub fn check_environment(coordinator_addr: SocketAddr) -> eyre::Result<()> {
-    let mut error_occurred = false;
+pub fn check_environment(
+    coordinator_addr: SocketAddr,
+    security: &SecurityConfig,
+    format: OutputFormat,
+) -> eyre::Result<()> {
+    let mut session = connect_to_coordinator(coordinator_addr, security).ok();
+    let status = CheckStatus {
+        coordinator_running: session.is_some(),
+        daemon_running: session
+            .as_deref_mut()
+            .map(daemon_running)
+            .transpose()?
+            .unwrap_or(false),
+    };
+
+    if format == OutputFormat::Text {
+        print_status(&status)?;
+    } else if status.is_ok() {
+        output::print(format, Content::Check(&status))?;
+    }
 
+    if !status.is_ok() {
+        return Err(CheckFailed(status).into());
+    }
+
+    Ok(())
+}
+
+fn print_status(status: &CheckStatus) -> eyre::Result<()> {
     let color_choice = if std::io::stdout().is_terminal() {
         ColorChoice::Auto
     } else {
@@ -18,47 +50,24 @@ pub fn check_environment(coordinator_addr: SocketAddr) -> eyre::Result<()> {
     };
     let mut stdout = termcolor::StandardStream::stdout(color_choice);
 
-    // check whether coordinator is running
     write!(stdout, "Dora Coordinator: ")?;
-    let mut session = match connect_to_coordinator(coordinator_addr) {
-        Ok(session) => {
-            let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)));
-            writeln!(stdout, "ok")?;
-            Some(session)
-        }
-        Err(_) => {
-            let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
-            writeln!(stdout, "not running")?;
-            error_occurred = true;
-            None
-        }
-    };
+    print_running(&mut stdout, status.coordinator_running)?;
+    write!(stdout, "Dora Daemon: ")?;
+    print_running(&mut stdout, status.daemon_running)?;
+    writeln!(stdout)?;
 
-    let _ = stdout.reset();
+    Ok(())
+}
 
-    // check whether daemon is running
-    write!(stdout, "Dora Daemon: ")?;
-    if session
-        .as_deref_mut()
-        .map(daemon_running)
-        .transpose()?
-        .unwrap_or(false)
-    {
+fn print_running(stdout: &mut termcolor::StandardStream, running: bool) -> eyre::Result<()> {
+    if running {
         let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)));
         writeln!(stdout, "ok")?;
     } else {
         let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)));
         writeln!(stdout, "not running")?;
-        error_occurred = true;
     }
     let _ = stdout.reset();
-
-    writeln!(stdout)?;
-
-    if error_occurred {
-        bail!("Environment check failed.");
-    }
-
     Ok(())
 }
 
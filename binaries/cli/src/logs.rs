@@ -1,3 +1,4 @@
+use crate::output::{self, Content, OutputFormat};
 use communication_layer_request_reply::TcpRequestReplyConnection;
 use dora_core::topics::{ControlRequest, ControlRequestReply};
 use eyre::{bail, Context, Result};
@@ -10,6 +11,8 @@ pub fn logs(
     uuid: Option<Uuid>,
     name: Option<String>,
     node: String,
+    level: Option<log::LevelFilter>,
+    format: OutputFormat,
 ) -> Result<()> {
     let logs = {
         let reply_raw = session
@@ -18,6 +21,7 @@ pub fn logs(
                     uuid,
                     name,
                     node: node.clone(),
+                    level,
                 })
                 .wrap_err("")?,
             )
@@ -30,6 +34,17 @@ pub fn logs(
         }
     };
 
+    if format != OutputFormat::Text {
+        let content = String::from_utf8_lossy(&logs).into_owned();
+        return output::print(
+            format,
+            Content::Logs {
+                node: &node,
+                content,
+            },
+        );
+    }
+
     PrettyPrinter::new()
         .header(false)
         .grid(false)
@@ -1,23 +1,31 @@
 use attach::attach_dataflow;
 use clap::Parser;
 use colored::Colorize;
-use communication_layer_request_reply::{RequestReplyLayer, TcpLayer, TcpRequestReplyConnection};
-use dora_coordinator::Event;
+use communication_layer_request_reply::{TcpConnection, TcpRequestReplyConnection};
+use dora_coordinator::{Event, HeartbeatConfig, LogConfig, StateConfig};
 use dora_core::{
+    config::NodeId,
     descriptor::Descriptor,
+    security::{tls::TlsConnector, SecurityConfig},
     topics::{
         ControlRequest, ControlRequestReply, DataflowList, DORA_COORDINATOR_PORT_CONTROL_DEFAULT,
         DORA_COORDINATOR_PORT_DEFAULT, DORA_DAEMON_LOCAL_LISTEN_PORT_DEFAULT,
     },
 };
-use dora_daemon::Daemon;
+use dora_daemon::{Daemon, DeterministicOptions, InterDaemonConfig, RunDataflowOptions};
 #[cfg(feature = "tracing")]
 use dora_tracing::set_up_tracing;
 use dora_tracing::set_up_tracing_opts;
+#[cfg(feature = "tracing")]
+use dora_tracing::set_up_tracing_stderr;
 use duration_str::parse;
-use eyre::{bail, Context};
-use formatting::FormatDataflowError;
-use std::{io::Write, net::SocketAddr};
+use eyre::{bail, eyre, Context};
+use output::{Content, DataflowFailed, ErrorReport, OutputFormat};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    num::NonZeroUsize,
+};
 use std::{
     net::{IpAddr, Ipv4Addr},
     path::PathBuf,
@@ -33,7 +41,13 @@ mod check;
 mod formatting;
 mod graph;
 mod logs;
+mod node;
+mod output;
+mod run;
+mod stats;
 mod template;
+#[cfg(feature = "testing")]
+mod test;
 mod up;
 
 const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
@@ -44,6 +58,12 @@ const LISTEN_WILDCARD: IpAddr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
 struct Args {
     #[clap(subcommand)]
     command: Command,
+    /// Output format of `check`, `list`, `logs`, `start`, `stop` and of errors
+    #[clap(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// Path to a YAML file with TLS and access token settings
+    #[clap(long, global = true, value_name = "PATH")]
+    security_config: Option<PathBuf>,
 }
 
 /// dora-rs cli client
@@ -74,10 +94,22 @@ enum Command {
         open: bool,
     },
     /// Run build commands provided in the given dataflow.
+    ///
+    /// Nodes whose definition and build output did not change since the last
+    /// successful build are skipped. Use `--force` to rebuild them anyway.
     Build {
         /// Path to the dataflow descriptor file
         #[clap(value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
         dataflow: PathBuf,
+        /// Number of build commands to run in parallel
+        #[clap(long, short, value_name = "N", default_value = "1")]
+        jobs: NonZeroUsize,
+        /// Only build the given node (can be specified multiple times)
+        #[clap(long = "node", value_name = "ID")]
+        nodes: Vec<NodeId>,
+        /// Rebuild all selected nodes, even if they are up to date
+        #[clap(long, action)]
+        force: bool,
     },
     /// Generate a new project or node. Choose the language between Rust, Python, C or C++.
     New {
@@ -103,6 +135,9 @@ enum Command {
         /// Port number of the coordinator control server
         #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
         coordinator_port: u16,
+        /// Remove shared memory segments that were leaked by crashed daemons on this machine
+        #[clap(long)]
+        cleanup: bool,
     },
     /// Start the given dataflow path. Attach a name to the running dataflow by using --name.
     Start {
@@ -127,6 +162,48 @@ enum Command {
         /// Enable hot reloading (Python only)
         #[clap(long, action)]
         hot_reload: bool,
+        /// Only show on which machines the nodes would run, without starting the dataflow
+        #[clap(long, action)]
+        dry_run: bool,
+    },
+    /// Run the given dataflow locally, without connecting to a coordinator.
+    ///
+    /// The output of all nodes is printed with a node ID prefix. Exits with a
+    /// non-zero exit code if any node fails.
+    Run {
+        /// Path to the dataflow descriptor file
+        #[clap(value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+        dataflow: PathBuf,
+        /// Stop the dataflow after the given duration (e.g. `10s`)
+        #[clap(long, value_name = "DURATION")]
+        #[arg(value_parser = parse)]
+        timeout: Option<Duration>,
+        /// Stop the dataflow once the given node exits
+        #[clap(long, value_name = "NODE")]
+        stop_after: Option<NodeId>,
+        /// Don't print the output of the nodes
+        #[clap(long)]
+        quiet: bool,
+        /// Deliver events in a reproducible order, driven by a virtual clock
+        ///
+        /// The `--timeout` is measured in virtual time in this mode.
+        #[clap(long)]
+        deterministic: bool,
+        /// Seed for ordering simultaneous events in deterministic mode
+        #[clap(long, value_name = "SEED", requires = "deterministic")]
+        seed: Option<u64>,
+    },
+    /// Run dataflow test specs locally and check the captured outputs.
+    ///
+    /// Exits with a non-zero exit code if any test fails.
+    #[cfg(feature = "testing")]
+    Test {
+        /// Paths to the test spec files
+        #[clap(value_name = "PATH", value_hint = clap::ValueHint::FilePath, required = true)]
+        specs: Vec<PathBuf>,
+        /// Write the results as JUnit XML to the given file
+        #[clap(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+        junit: Option<PathBuf>,
     },
     /// Stop the given dataflow UUID. If no id is provided, you will be able to choose between the running dataflows.
     Stop {
@@ -166,6 +243,11 @@ enum Command {
         /// Show logs for the given node
         #[clap(value_name = "NAME")]
         node: String,
+        /// Only show structured log records of this level or more severe
+        ///
+        /// Lines that nodes did not print as JSON log records are always shown.
+        #[clap(long, value_name = "LEVEL")]
+        level: Option<log::LevelFilter>,
         /// Address of the dora coordinator
         #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
         coordinator_addr: IpAddr,
@@ -174,7 +256,23 @@ enum Command {
         coordinator_port: u16,
     },
     // Metrics,
-    // Stats,
+    /// Show statistics that the daemons collected about a running dataflow.
+    Stats {
+        /// Identifier of the dataflow
+        #[clap(value_name = "UUID_OR_NAME")]
+        dataflow: Option<String>,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
+    /// Add nodes to or remove nodes from a running dataflow.
+    Node {
+        #[clap(subcommand)]
+        command: NodeCommand,
+    },
     // Get,
     // Upgrade,
     /// Run daemon
@@ -193,6 +291,18 @@ enum Command {
         coordinator_addr: SocketAddr,
         #[clap(long, hide = true)]
         run_dataflow: Option<PathBuf>,
+        /// Label of this machine, used for placing nodes (e.g. `camera=true`)
+        ///
+        /// Can be given multiple times. The `arch`, `os`, and `cpus` labels are
+        /// detected automatically.
+        #[clap(long = "label", value_name = "KEY=VALUE", value_parser = parse_label)]
+        labels: Vec<(String, String)>,
+        /// Number of outputs that are buffered per remote machine while it is unreachable
+        ///
+        /// Further outputs are dropped and the receivers are notified about the
+        /// gap. The daemon never waits for remote machines.
+        #[clap(long, default_value_t = 100)]
+        inter_daemon_buffer: usize,
         /// Suppresses all log output to stdout.
         #[clap(long)]
         quiet: bool,
@@ -213,12 +323,89 @@ enum Command {
         /// Port number to bind to for control communication
         #[clap(long, default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
         control_port: u16,
+        /// Serve the HTTP/WebSocket API on the given address, e.g. `0.0.0.0:6014`
+        #[clap(long, value_name = "ADDR")]
+        http_addr: Option<SocketAddr>,
+        /// Directory for persisting the coordinator state across restarts.
+        ///
+        /// If set, running and finished dataflows are restored on startup.
+        #[clap(long, value_name = "PATH")]
+        state_dir: Option<PathBuf>,
+        /// Maximum number of finished dataflows to keep in the state directory.
+        #[clap(long, value_name = "COUNT", default_value_t = 100)]
+        state_max_finished: usize,
+        /// Consider a machine as lost if its daemon sends no heartbeat for the given duration.
+        ///
+        /// Daemons send a heartbeat every 5 seconds.
+        #[clap(long, value_name = "DURATION", default_value = "30s")]
+        #[arg(value_parser = parse)]
+        heartbeat_timeout: Duration,
+        /// Stop dataflows with nodes on a lost machine, instead of only marking
+        /// the lost nodes as failed.
+        #[clap(long)]
+        stop_on_machine_lost: bool,
+        /// Directory for storing the logs of all nodes.
+        ///
+        /// Defaults to the `logs` subdirectory of the state directory.
+        #[clap(long, value_name = "PATH")]
+        log_dir: Option<PathBuf>,
+        /// Remove the logs of the oldest finished dataflows once all logs
+        /// exceed the given size, e.g. `500M` or `2G`
+        #[clap(long, value_name = "SIZE", default_value = "1G", value_parser = parse_size)]
+        log_max_size: u64,
+        /// Remove the logs of finished dataflows after the given duration.
+        #[clap(long, value_name = "DURATION", default_value = "7d")]
+        #[arg(value_parser = parse)]
+        log_max_age: Duration,
         /// Suppresses all log output to stdout.
         #[clap(long)]
         quiet: bool,
     },
 }
 
+#[derive(Debug, clap::Subcommand)]
+enum NodeCommand {
+    /// Add the nodes of a descriptor fragment to a running dataflow.
+    ///
+    /// Source paths of the nodes are resolved relative to the working
+    /// directory of the dataflow.
+    Add {
+        /// Identifier of the dataflow
+        #[clap(value_name = "UUID_OR_NAME")]
+        dataflow: String,
+        /// Path to a YAML file with a `nodes` list
+        #[clap(value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+        nodes: PathBuf,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
+    /// Stop nodes of a running dataflow and remove them.
+    ///
+    /// Inputs that are fed by the removed nodes are closed.
+    Remove {
+        /// Identifier of the dataflow
+        #[clap(value_name = "UUID_OR_NAME")]
+        dataflow: String,
+        /// IDs of the nodes that should be removed
+        #[clap(value_name = "NODE", required = true)]
+        nodes: Vec<NodeId>,
+        /// Kill the nodes if they don't stop after the given duration
+        #[clap(long, value_name = "DURATION")]
+        #[arg(value_parser = parse)]
+        grace_duration: Option<Duration>,
+        /// Address of the dora coordinator
+        #[clap(long, value_name = "IP", default_value_t = LOCALHOST)]
+        coordinator_addr: IpAddr,
+        /// Port number of the coordinator control server
+        #[clap(long, value_name = "PORT", default_value_t = DORA_COORDINATOR_PORT_CONTROL_DEFAULT)]
+        coordinator_port: u16,
+    },
+}
+
 #[derive(Debug, clap::Args)]
 pub struct CommandNew {
     /// The entity that should be created
@@ -249,15 +436,24 @@ enum Lang {
 }
 
 fn main() {
-    if let Err(err) = run() {
-        eprintln!("\n\n{}", "[ERROR]".bold().red());
-        eprintln!("{err:#}");
+    let args = Args::parse();
+    let format = args.format;
+    if let Err(err) = run(args) {
+        let printed = match format {
+            OutputFormat::Text => false,
+            format => output::print(format, Content::Error(ErrorReport::new(&err))).is_ok(),
+        };
+        if !printed {
+            eprintln!("\n\n{}", "[ERROR]".bold().red());
+            eprintln!("{err:#}");
+        }
         std::process::exit(1);
     }
 }
 
-fn run() -> eyre::Result<()> {
-    let args = Args::parse();
+fn run(args: Args) -> eyre::Result<()> {
+    let format = args.format;
+    let security = SecurityConfig::load(args.security_config.as_deref())?;
 
     #[cfg(feature = "tracing")]
     match &args.command {
@@ -280,6 +476,10 @@ fn run() -> eyre::Result<()> {
             set_up_tracing_opts(name, !quiet, Some(name))
                 .context("failed to set up tracing subscriber")?;
         }
+        _ if format != OutputFormat::Text => {
+            // keep stdout free for the machine-readable output
+            set_up_tracing_stderr("dora-cli").context("failed to set up tracing subscriber")?;
+        }
         _ => {
             set_up_tracing("dora-cli").context("failed to set up tracing subscriber")?;
         }
@@ -305,9 +505,17 @@ fn run() -> eyre::Result<()> {
                     .ok_or_else(|| eyre::eyre!("dataflow path has no parent dir"))?
                     .to_owned();
                 Descriptor::blocking_read(&dataflow)?.check(&working_dir)?;
-                check::check_environment((coordinator_addr, coordinator_port).into())?
+                check::check_environment(
+                    (coordinator_addr, coordinator_port).into(),
+                    &security,
+                    format,
+                )?
             }
-            None => check::check_environment((coordinator_addr, coordinator_port).into())?,
+            None => check::check_environment(
+                (coordinator_addr, coordinator_port).into(),
+                &security,
+                format,
+            )?,
         },
         Command::Graph {
             dataflow,
@@ -316,30 +524,46 @@ fn run() -> eyre::Result<()> {
         } => {
             graph::create(dataflow, mermaid, open)?;
         }
-        Command::Build { dataflow } => {
-            build::build(&dataflow)?;
+        Command::Build {
+            dataflow,
+            jobs,
+            nodes,
+            force,
+        } => {
+            let options = build::BuildOptions {
+                jobs,
+                nodes: nodes.into_iter().collect(),
+                force,
+            };
+            build::build(&dataflow, options)?;
         }
         Command::New {
             args,
             internal_create_with_path_dependencies,
         } => template::create(args, internal_create_with_path_dependencies)?,
         Command::Up { config } => {
-            up::up(config.as_deref())?;
+            up::up(
+                config.as_deref(),
+                args.security_config.as_deref(),
+                &security,
+            )?;
         }
         Command::Logs {
             dataflow,
             node,
+            level,
             coordinator_addr,
             coordinator_port,
         } => {
-            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
-                .wrap_err("failed to connect to dora coordinator")?;
+            let mut session =
+                connect_to_coordinator((coordinator_addr, coordinator_port).into(), &security)
+                    .wrap_err("failed to connect to dora coordinator")?;
             let list = query_running_dataflows(&mut *session)
                 .wrap_err("failed to query running dataflows")?;
             if let Some(dataflow) = dataflow {
                 let uuid = Uuid::parse_str(&dataflow).ok();
                 let name = if uuid.is_some() { None } else { Some(dataflow) };
-                logs::logs(&mut *session, uuid, name, node)?
+                logs::logs(&mut *session, uuid, name, node, level, format)?
             } else {
                 let active = list.get_active();
                 let uuid = match &active[..] {
@@ -347,7 +571,31 @@ fn run() -> eyre::Result<()> {
                     [uuid] => uuid.clone(),
                     _ => inquire::Select::new("Choose dataflow to show logs:", active).prompt()?,
                 };
-                logs::logs(&mut *session, Some(uuid.uuid), None, node)?
+                logs::logs(&mut *session, Some(uuid.uuid), None, node, level, format)?
+            }
+        }
+        Command::Stats {
+            dataflow,
+            coordinator_addr,
+            coordinator_port,
+        } => {
+            let mut session =
+                connect_to_coordinator((coordinator_addr, coordinator_port).into(), &security)
+                    .wrap_err("failed to connect to dora coordinator")?;
+            if let Some(dataflow) = dataflow {
+                let uuid = Uuid::parse_str(&dataflow).ok();
+                let name = if uuid.is_some() { None } else { Some(dataflow) };
+                stats::stats(&mut *session, uuid, name, format)?
+            } else {
+                let list = query_running_dataflows(&mut *session)
+                    .wrap_err("failed to query running dataflows")?;
+                let active = list.get_active();
+                let uuid = match &active[..] {
+                    [] => bail!("No dataflows are running"),
+                    [uuid] => uuid.clone(),
+                    _ => inquire::Select::new("Choose dataflow to show stats:", active).prompt()?,
+                };
+                stats::stats(&mut *session, Some(uuid.uuid), None, format)?
             }
         }
         Command::Start {
@@ -358,6 +606,7 @@ fn run() -> eyre::Result<()> {
             attach,
             detach,
             hot_reload,
+            dry_run,
         } => {
             let dataflow_descriptor =
                 Descriptor::blocking_read(&dataflow).wrap_err("Failed to read yaml dataflow")?;
@@ -376,21 +625,33 @@ fn run() -> eyre::Result<()> {
             }
 
             let coordinator_socket = (coordinator_addr, coordinator_port).into();
-            let mut session = connect_to_coordinator(coordinator_socket)
+            let mut session = connect_to_coordinator(coordinator_socket, &security)
                 .wrap_err("failed to connect to dora coordinator")?;
+            if dry_run {
+                return plan_dataflow(dataflow_descriptor, working_dir, &mut *session, format);
+            }
             let dataflow_id = start_dataflow(
                 dataflow_descriptor.clone(),
                 name,
                 working_dir,
                 &mut *session,
             )?;
+            match format {
+                OutputFormat::Text => eprintln!("{dataflow_id}"),
+                format => output::print(
+                    format,
+                    Content::Reply(&ControlRequestReply::DataflowStarted { uuid: dataflow_id }),
+                )?,
+            }
 
             let attach = match (attach, detach) {
                 (true, true) => eyre::bail!("both `--attach` and `--detach` are given"),
                 (true, false) => true,
                 (false, true) => false,
                 (false, false) => {
-                    println!("attaching to dataflow (use `--detach` to run in background)");
+                    if format == OutputFormat::Text {
+                        println!("attaching to dataflow (use `--detach` to run in background)");
+                    }
                     true
                 }
             };
@@ -403,15 +664,39 @@ fn run() -> eyre::Result<()> {
                     &mut *session,
                     hot_reload,
                     coordinator_socket,
+                    &security,
                     log_level,
+                    format,
                 )?
             }
         }
+        Command::Run {
+            dataflow,
+            timeout,
+            stop_after,
+            quiet,
+            deterministic,
+            seed,
+        } => {
+            let options = RunDataflowOptions {
+                timeout,
+                stop_after,
+                print_node_output: !quiet,
+                deterministic: deterministic.then(|| DeterministicOptions {
+                    seed: seed.unwrap_or_default(),
+                }),
+                ..Default::default()
+            };
+            run::run(&dataflow, options)?
+        }
+        #[cfg(feature = "testing")]
+        Command::Test { specs, junit } => test::run_tests(&specs, junit.as_deref())?,
         Command::List {
             coordinator_addr,
             coordinator_port,
-        } => match connect_to_coordinator((coordinator_addr, coordinator_port).into()) {
-            Ok(mut session) => list(&mut *session)?,
+        } => match connect_to_coordinator((coordinator_addr, coordinator_port).into(), &security) {
+            Ok(mut session) => list(&mut *session, format)?,
+            Err(err) if is_auth_error(&err) => return Err(err),
             Err(_) => {
                 bail!("No dora coordinator seems to be running.");
             }
@@ -423,27 +708,84 @@ fn run() -> eyre::Result<()> {
             coordinator_addr,
             coordinator_port,
         } => {
-            let mut session = connect_to_coordinator((coordinator_addr, coordinator_port).into())
-                .wrap_err("could not connect to dora coordinator")?;
+            let mut session =
+                connect_to_coordinator((coordinator_addr, coordinator_port).into(), &security)
+                    .wrap_err("could not connect to dora coordinator")?;
             match (uuid, name) {
-                (Some(uuid), _) => stop_dataflow(uuid, grace_duration, &mut *session)?,
-                (None, Some(name)) => stop_dataflow_by_name(name, grace_duration, &mut *session)?,
-                (None, None) => stop_dataflow_interactive(grace_duration, &mut *session)?,
+                (Some(uuid), _) => stop_dataflow(uuid, grace_duration, &mut *session, format)?,
+                (None, Some(name)) => {
+                    stop_dataflow_by_name(name, grace_duration, &mut *session, format)?
+                }
+                (None, None) => stop_dataflow_interactive(grace_duration, &mut *session, format)?,
             }
         }
+        Command::Node { command } => match command {
+            NodeCommand::Add {
+                dataflow,
+                nodes,
+                coordinator_addr,
+                coordinator_port,
+            } => {
+                let mut session =
+                    connect_to_coordinator((coordinator_addr, coordinator_port).into(), &security)
+                        .wrap_err("failed to connect to dora coordinator")?;
+                node::add(&mut *session, dataflow, &nodes, format)?
+            }
+            NodeCommand::Remove {
+                dataflow,
+                nodes,
+                grace_duration,
+                coordinator_addr,
+                coordinator_port,
+            } => {
+                let mut session =
+                    connect_to_coordinator((coordinator_addr, coordinator_port).into(), &security)
+                        .wrap_err("failed to connect to dora coordinator")?;
+                node::remove(
+                    &mut *session,
+                    dataflow,
+                    nodes.into_iter().collect(),
+                    grace_duration,
+                    format,
+                )?
+            }
+        },
         Command::Destroy {
             config,
             coordinator_addr,
             coordinator_port,
-        } => up::destroy(
-            config.as_deref(),
-            (coordinator_addr, coordinator_port).into(),
-        )?,
+            cleanup,
+        } => {
+            let destroyed = up::destroy(
+                config.as_deref(),
+                (coordinator_addr, coordinator_port).into(),
+                &security,
+            );
+            // leaked segments can be removed even if the coordinator is not reachable
+            if cleanup {
+                match dora_daemon::cleanup_shared_memory()
+                    .context("failed to clean up shared memory")
+                {
+                    Ok(removed) => println!("Removed {removed} leaked shared memory segments"),
+                    Err(err) if destroyed.is_err() => eprintln!("{err:?}"),
+                    Err(err) => return Err(err),
+                }
+            }
+            destroyed?;
+        }
         Command::Coordinator {
             interface,
             port,
             control_interface,
             control_port,
+            http_addr,
+            state_dir,
+            state_max_finished,
+            heartbeat_timeout,
+            stop_on_machine_lost,
+            log_dir,
+            log_max_size,
+            log_max_age,
             quiet,
         } => {
             let rt = Builder::new_multi_thread()
@@ -453,9 +795,27 @@ fn run() -> eyre::Result<()> {
             rt.block_on(async {
                 let bind = SocketAddr::new(interface, port);
                 let bind_control = SocketAddr::new(control_interface, control_port);
-                let (port, task) =
-                    dora_coordinator::start(bind, bind_control, futures::stream::empty::<Event>())
-                        .await?;
+                let (port, task) = dora_coordinator::start(
+                    bind,
+                    bind_control,
+                    http_addr,
+                    StateConfig {
+                        dir: state_dir,
+                        max_finished: state_max_finished,
+                    },
+                    security,
+                    HeartbeatConfig {
+                        timeout: heartbeat_timeout,
+                        stop_on_machine_lost,
+                    },
+                    LogConfig {
+                        dir: log_dir,
+                        max_size: log_max_size,
+                        max_age: log_max_age,
+                    },
+                    futures::stream::empty::<Event>(),
+                )
+                .await?;
                 if !quiet {
                     println!("Listening for incoming daemon connection on {port}");
                 }
@@ -469,6 +829,8 @@ fn run() -> eyre::Result<()> {
             local_listen_port,
             machine_id,
             run_dataflow,
+            labels,
+            inter_daemon_buffer,
             quiet: _,
         } => {
             let rt = Builder::new_multi_thread()
@@ -486,14 +848,18 @@ fn run() -> eyre::Result<()> {
                             );
                         }
 
-                        let result = Daemon::run_dataflow(&dataflow_path).await?;
+                        let result =
+                            Daemon::run_dataflow(&dataflow_path, Default::default()).await?;
                         handle_dataflow_result(result, None)
                     }
                     None => {
                         if coordinator_addr.ip() == LOCALHOST {
                             tracing::info!("Starting in local mode");
                         }
-                        Daemon::run(coordinator_addr, machine_id.unwrap_or_default(), inter_daemon_addr, local_listen_port).await
+                        let inter_daemon = InterDaemonConfig {
+                            buffer_size: inter_daemon_buffer,
+                        };
+                        Daemon::run(coordinator_addr, machine_id.unwrap_or_default(), inter_daemon_addr, local_listen_port, labels.into_iter().collect(), security, inter_daemon).await
                     }
                 }
             })
@@ -505,6 +871,32 @@ fn run() -> eyre::Result<()> {
     Ok(())
 }
 
+/// Parses a `KEY=VALUE` machine label.
+fn parse_label(label: &str) -> eyre::Result<(String, String)> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => bail!("invalid label `{label}`, expected `KEY=VALUE`"),
+    }
+}
+
+/// Parses a size in bytes with an optional `K`, `M`, or `G` suffix.
+fn parse_size(size: &str) -> eyre::Result<u64> {
+    let trimmed = size.trim().trim_end_matches(['B', 'b']);
+    let (number, factor) = match trimmed.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&trimmed[..i], 1024),
+        Some((i, 'M' | 'm')) => (&trimmed[..i], 1024 * 1024),
+        Some((i, 'G' | 'g')) => (&trimmed[..i], 1024 * 1024 * 1024),
+        _ => (trimmed, 1),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .map_err(|_| eyre!("invalid size `{size}`, expected e.g. `500M` or `2G`"))?;
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| eyre!("size `{size}` is too large"))
+}
+
 fn start_dataflow(
     dataflow: Descriptor,
     name: Option<String>,
@@ -517,6 +909,7 @@ fn start_dataflow(
                 dataflow,
                 name,
                 local_working_dir,
+                dry_run: false,
             })
             .unwrap(),
         )
@@ -525,18 +918,58 @@ fn start_dataflow(
     let result: ControlRequestReply =
         serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
     match result {
-        ControlRequestReply::DataflowStarted { uuid } => {
-            eprintln!("{uuid}");
-            Ok(uuid)
-        }
+        ControlRequestReply::DataflowStarted { uuid } => Ok(uuid),
         ControlRequestReply::Error(err) => bail!("{err}"),
         other => bail!("unexpected start dataflow reply: {other:?}"),
     }
 }
 
+/// Prints the machines that the coordinator would place the nodes on.
+fn plan_dataflow(
+    dataflow: Descriptor,
+    local_working_dir: PathBuf,
+    session: &mut TcpRequestReplyConnection,
+    format: OutputFormat,
+) -> eyre::Result<()> {
+    let reply_raw = session
+        .request(
+            &serde_json::to_vec(&ControlRequest::Start {
+                dataflow,
+                name: None,
+                local_working_dir,
+                dry_run: true,
+            })
+            .unwrap(),
+        )
+        .wrap_err("failed to send start dataflow message")?;
+
+    let reply: ControlRequestReply =
+        serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
+    let nodes = match &reply {
+        ControlRequestReply::DataflowPlan { nodes } => nodes,
+        ControlRequestReply::Error(err) => bail!("{err}"),
+        other => bail!("unexpected start dataflow reply: {other:?}"),
+    };
+    if format != OutputFormat::Text {
+        return output::print(format, Content::Reply(&reply));
+    }
+
+    let mut tw = TabWriter::new(vec![]);
+    tw.write_all(b"Node\tMachine\n")?;
+    for node in nodes {
+        tw.write_all(format!("{}\t{}\n", node.id, node.machine).as_bytes())?;
+    }
+    tw.flush()?;
+    let formatted = String::from_utf8(tw.into_inner()?)?;
+
+    print!("{formatted}");
+    Ok(())
+}
+
 fn stop_dataflow_interactive(
     grace_duration: Option<Duration>,
     session: &mut TcpRequestReplyConnection,
+    format: OutputFormat,
 ) -> eyre::Result<()> {
     let list = query_running_dataflows(session).wrap_err("failed to query running dataflows")?;
     let active = list.get_active();
@@ -544,7 +977,7 @@ fn stop_dataflow_interactive(
         eprintln!("No dataflows are running");
     } else {
         let selection = inquire::Select::new("Choose dataflow to stop:", active).prompt()?;
-        stop_dataflow(selection.uuid, grace_duration, session)?;
+        stop_dataflow(selection.uuid, grace_duration, session, format)?;
     }
 
     Ok(())
@@ -554,6 +987,7 @@ fn stop_dataflow(
     uuid: Uuid,
     grace_duration: Option<Duration>,
     session: &mut TcpRequestReplyConnection,
+    format: OutputFormat,
 ) -> Result<(), eyre::ErrReport> {
     let reply_raw = session
         .request(
@@ -564,11 +998,22 @@ fn stop_dataflow(
             .unwrap(),
         )
         .wrap_err("failed to send dataflow stop message")?;
-    let result: ControlRequestReply =
+    let reply: ControlRequestReply =
         serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
-    match result {
+    handle_stop_reply(reply, format)
+}
+
+fn handle_stop_reply(reply: ControlRequestReply, format: OutputFormat) -> eyre::Result<()> {
+    match reply {
         ControlRequestReply::DataflowStopped { uuid, result } => {
-            handle_dataflow_result(result, Some(uuid))
+            if result.is_ok() {
+                output::print(
+                    format,
+                    Content::Reply(&ControlRequestReply::DataflowStopped { uuid, result }),
+                )
+            } else {
+                handle_dataflow_result(result, Some(uuid))
+            }
         }
         ControlRequestReply::Error(err) => bail!("{err}"),
         other => bail!("unexpected stop dataflow reply: {other:?}"),
@@ -582,14 +1027,7 @@ fn handle_dataflow_result(
     if result.is_ok() {
         Ok(())
     } else {
-        Err(match uuid {
-            Some(uuid) => {
-                eyre::eyre!("Dataflow {uuid} failed:\n{}", FormatDataflowError(&result))
-            }
-            None => {
-                eyre::eyre!("Dataflow failed:\n{}", FormatDataflowError(&result))
-            }
-        })
+        Err(DataflowFailed { uuid, result }.into())
     }
 }
 
@@ -597,6 +1035,7 @@ fn stop_dataflow_by_name(
     name: String,
     grace_duration: Option<Duration>,
     session: &mut TcpRequestReplyConnection,
+    format: OutputFormat,
 ) -> Result<(), eyre::ErrReport> {
     let reply_raw = session
         .request(
@@ -607,19 +1046,22 @@ fn stop_dataflow_by_name(
             .unwrap(),
         )
         .wrap_err("failed to send dataflow stop_by_name message")?;
-    let result: ControlRequestReply =
+    let reply: ControlRequestReply =
         serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
-    match result {
-        ControlRequestReply::DataflowStopped { uuid, result } => {
-            handle_dataflow_result(result, Some(uuid))
-        }
-        ControlRequestReply::Error(err) => bail!("{err}"),
-        other => bail!("unexpected stop dataflow reply: {other:?}"),
-    }
+    handle_stop_reply(reply, format)
 }
 
-fn list(session: &mut TcpRequestReplyConnection) -> Result<(), eyre::ErrReport> {
+fn list(
+    session: &mut TcpRequestReplyConnection,
+    format: OutputFormat,
+) -> Result<(), eyre::ErrReport> {
     let list = query_running_dataflows(session)?;
+    if format != OutputFormat::Text {
+        return output::print(
+            format,
+            Content::Reply(&ControlRequestReply::DataflowList(list)),
+        );
+    }
 
     let mut tw = TabWriter::new(vec![]);
     tw.write_all(b"UUID\tName\tStatus\n")?;
@@ -656,8 +1098,61 @@ fn query_running_dataflows(session: &mut TcpRequestReplyConnection) -> eyre::Res
     Ok(ids)
 }
 
+/// A blocking connection to the coordinator, which is encrypted if TLS is enabled.
+trait CoordinatorStream: Read + Write + Send + Sync {}
+
+impl<S: Read + Write + Send + Sync> CoordinatorStream for S {}
+
+/// Error that is returned if the coordinator rejects the access token.
+#[derive(Debug)]
+struct AuthenticationFailed(String);
+
+impl std::fmt::Display for AuthenticationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to authenticate at dora coordinator: {}", self.0)
+    }
+}
+
+impl std::error::Error for AuthenticationFailed {}
+
+fn is_auth_error(err: &eyre::Report) -> bool {
+    err.chain()
+        .any(|e| e.downcast_ref::<AuthenticationFailed>().is_some())
+}
+
+/// Opens a control connection to the coordinator and authenticates if a
+/// token is configured.
+fn connect_to_coordinator_stream(
+    coordinator_addr: SocketAddr,
+    security: &SecurityConfig,
+) -> eyre::Result<TcpConnection<Box<dyn CoordinatorStream>>> {
+    let stream = TcpStream::connect(coordinator_addr)?;
+    let stream: Box<dyn CoordinatorStream> = match &security.tls {
+        Some(tls) => Box::new(TlsConnector::new(tls)?.connect_blocking(stream)?),
+        None => Box::new(stream),
+    };
+    let mut connection = TcpConnection { stream };
+    if let Some(token) = &security.token {
+        connection.send(&serde_json::to_vec(&ControlRequest::Authenticate {
+            token: token.clone(),
+        })?)?;
+        let reply: ControlRequestReply = serde_json::from_slice(&connection.receive()?)
+            .wrap_err("failed to parse authentication reply")?;
+        match reply {
+            ControlRequestReply::Authenticated { .. } => {}
+            ControlRequestReply::Error(err) => return Err(AuthenticationFailed(err).into()),
+            other => bail!("unexpected authentication reply: {other:?}"),
+        }
+    }
+    Ok(connection)
+}
+
 fn connect_to_coordinator(
     coordinator_addr: SocketAddr,
-) -> std::io::Result<Box<TcpRequestReplyConnection>> {
-    TcpLayer::new().connect(coordinator_addr)
+    security: &SecurityConfig,
+) -> eyre::Result<Box<TcpRequestReplyConnection>> {
+    Ok(Box::new(connect_to_coordinator_stream(
+        coordinator_addr,
+        security,
+    )?))
 }
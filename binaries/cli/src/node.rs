@@ -0,0 +1,83 @@
+use crate::output::{self, Content, OutputFormat};
+use communication_layer_request_reply::TcpRequestReplyConnection;
+use dora_core::{
+    config::NodeId,
+    descriptor::Descriptor,
+    topics::{ControlRequest, ControlRequestReply},
+};
+use eyre::{bail, Context, Result};
+use std::{collections::BTreeSet, path::Path, time::Duration};
+use uuid::Uuid;
+
+pub fn add(
+    session: &mut TcpRequestReplyConnection,
+    dataflow: String,
+    path: &Path,
+    format: OutputFormat,
+) -> Result<()> {
+    let nodes = Descriptor::blocking_read(path)
+        .wrap_err_with(|| format!("failed to read nodes from `{}`", path.display()))?;
+    let (uuid, name) = parse_dataflow(dataflow);
+    let reply = request(session, &ControlRequest::AddNodes { uuid, name, nodes })?;
+    match &reply {
+        ControlRequestReply::NodesAdded { nodes, .. } => {
+            if format == OutputFormat::Text {
+                for node in nodes {
+                    println!("added node `{}` on machine `{}`", node.id, node.machine);
+                }
+            }
+        }
+        ControlRequestReply::Error(err) => bail!("{err}"),
+        other => bail!("unexpected reply to add nodes request: {other:?}"),
+    }
+    output::print(format, Content::Reply(&reply))
+}
+
+pub fn remove(
+    session: &mut TcpRequestReplyConnection,
+    dataflow: String,
+    nodes: BTreeSet<NodeId>,
+    grace_duration: Option<Duration>,
+    format: OutputFormat,
+) -> Result<()> {
+    let (uuid, name) = parse_dataflow(dataflow);
+    let request_nodes = nodes.clone();
+    let reply = request(
+        session,
+        &ControlRequest::RemoveNodes {
+            uuid,
+            name,
+            nodes: request_nodes,
+            grace_duration,
+        },
+    )?;
+    match &reply {
+        ControlRequestReply::NodesRemoved { .. } => {
+            if format == OutputFormat::Text {
+                for node in &nodes {
+                    println!("removed node `{node}`");
+                }
+            }
+        }
+        ControlRequestReply::Error(err) => bail!("{err}"),
+        other => bail!("unexpected reply to remove nodes request: {other:?}"),
+    }
+    output::print(format, Content::Reply(&reply))
+}
+
+fn parse_dataflow(dataflow: String) -> (Option<Uuid>, Option<String>) {
+    match Uuid::parse_str(&dataflow) {
+        Ok(uuid) => (Some(uuid), None),
+        Err(_) => (None, Some(dataflow)),
+    }
+}
+
+fn request(
+    session: &mut TcpRequestReplyConnection,
+    request: &ControlRequest,
+) -> Result<ControlRequestReply> {
+    let reply_raw = session
+        .request(&serde_json::to_vec(request).unwrap())
+        .wrap_err("failed to send request to coordinator")?;
+    serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")
+}
@@ -0,0 +1,212 @@
+use crate::formatting::FormatDataflowError;
+use dora_core::topics::{ControlRequestReply, DataflowResult};
+use serde::Serialize;
+use std::fmt;
+use uuid::Uuid;
+
+/// Version of the machine-readable output documents.
+///
+/// Must be increased on every breaking change of the document layout.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    Text,
+    Json,
+    Yaml,
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    content: Content<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Content<'a> {
+    /// Reply of the coordinator to a control request.
+    Reply(&'a ControlRequestReply),
+    Logs {
+        node: &'a str,
+        content: String,
+    },
+    Check(&'a CheckStatus),
+    Error(ErrorReport<'a>),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckStatus {
+    pub coordinator_running: bool,
+    pub daemon_running: bool,
+}
+
+impl CheckStatus {
+    pub fn is_ok(&self) -> bool {
+        self.coordinator_running && self.daemon_running
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorReport<'a> {
+    message: String,
+    causes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dataflow_result: Option<&'a DataflowResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    check: Option<&'a CheckStatus>,
+}
+
+impl<'a> ErrorReport<'a> {
+    pub fn new(err: &'a eyre::Report) -> Self {
+        Self {
+            message: err.to_string(),
+            causes: err.chain().skip(1).map(|cause| cause.to_string()).collect(),
+            dataflow_result: err
+                .chain()
+                .find_map(|e| e.downcast_ref::<DataflowFailed>())
+                .map(|e| &e.result),
+            check: err
+                .chain()
+                .find_map(|e| e.downcast_ref::<CheckFailed>())
+                .map(|e| &e.0),
+        }
+    }
+}
+
+/// Prints the given content as a JSON or YAML document.
+///
+/// Does nothing for [`OutputFormat::Text`], as commands print their text
+/// output themselves.
+pub fn print(format: OutputFormat, content: Content) -> eyre::Result<()> {
+    if let Some(rendered) = render(format, content)? {
+        print!("{rendered}");
+    }
+    Ok(())
+}
+
+/// Renders the given content as a JSON or YAML document.
+///
+/// Returns `None` for [`OutputFormat::Text`].
+fn render(format: OutputFormat, content: Content) -> eyre::Result<Option<String>> {
+    let document = Document {
+        schema_version: SCHEMA_VERSION,
+        content,
+    };
+    let rendered = match format {
+        OutputFormat::Text => return Ok(None),
+        OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(&document)?),
+        OutputFormat::Yaml => {
+            // represent enums as maps, the same way as in JSON
+            let mut yaml = Vec::new();
+            let mut serializer = serde_yaml::Serializer::new(&mut yaml);
+            serde_yaml::with::singleton_map_recursive::serialize(&document, &mut serializer)?;
+            String::from_utf8(yaml)?
+        }
+    };
+    Ok(Some(rendered))
+}
+
+/// Prints a line that is not part of the command's data, e.g. a log message
+/// of an attached dataflow.
+///
+/// The line goes to stderr if a machine-readable format is selected, so that
+/// stdout only contains the documents printed by [`print`].
+pub fn print_status(format: OutputFormat, line: &str) {
+    match format {
+        OutputFormat::Text => println!("{line}"),
+        OutputFormat::Json | OutputFormat::Yaml => eprintln!("{line}"),
+    }
+}
+
+/// Error that is returned when a dataflow finished with failed nodes.
+#[derive(Debug)]
+pub struct DataflowFailed {
+    pub uuid: Option<Uuid>,
+    pub result: DataflowResult,
+}
+
+impl fmt::Display for DataflowFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.uuid {
+            Some(uuid) => write!(
+                f,
+                "Dataflow {uuid} failed:\n{}",
+                FormatDataflowError(&self.result)
+            ),
+            None => write!(f, "Dataflow failed:\n{}", FormatDataflowError(&self.result)),
+        }
+    }
+}
+
+impl std::error::Error for DataflowFailed {}
+
+/// Error that is returned by `dora check` if the coordinator or daemon are not running.
+#[derive(Debug)]
+pub struct CheckFailed(pub CheckStatus);
+
+impl fmt::Display for CheckFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Environment check failed.")
+    }
+}
+
+impl std::error::Error for CheckFailed {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::message::uhlc::HLC;
+
+    #[test]
+    fn text_is_not_rendered() {
+        let status = CheckStatus {
+            coordinator_running: true,
+            daemon_running: true,
+        };
+        assert!(render(OutputFormat::Text, Content::Check(&status))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn documents_contain_schema_version() {
+        let uuid = Uuid::nil();
+        let reply = ControlRequestReply::DataflowStarted { uuid };
+
+        let json = render(OutputFormat::Json, Content::Reply(&reply))
+            .unwrap()
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        assert_eq!(value["reply"]["DataflowStarted"]["uuid"], uuid.to_string());
+
+        let yaml = render(OutputFormat::Yaml, Content::Reply(&reply))
+            .unwrap()
+            .unwrap();
+        let value: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        assert_eq!(
+            value["reply"]["DataflowStarted"]["uuid"].as_str(),
+            Some(uuid.to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn errors_include_causes_and_dataflow_result() {
+        let result = DataflowResult::ok_empty(Uuid::nil(), HLC::default().new_timestamp());
+        let err = eyre::Report::new(DataflowFailed { uuid: None, result })
+            .wrap_err("failed to run dataflow");
+
+        let json = render(OutputFormat::Json, Content::Error(ErrorReport::new(&err)))
+            .unwrap()
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["error"]["message"], "failed to run dataflow");
+        assert_eq!(value["error"]["causes"].as_array().unwrap().len(), 1);
+        assert!(value["error"]["dataflow_result"].is_object());
+        assert!(value["error"].get("check").is_none());
+    }
+}
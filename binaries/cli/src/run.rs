@@ -0,0 +1,43 @@
+use crate::handle_dataflow_result;
+use dora_core::topics::DataflowResult;
+use dora_daemon::{Daemon, RunDataflowOptions};
+use eyre::Context;
+use std::{io::Write, path::Path};
+use tabwriter::TabWriter;
+use tokio::runtime::Builder;
+
+pub fn run(dataflow: &Path, options: RunDataflowOptions) -> eyre::Result<()> {
+    let rt = Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("tokio runtime failed")?;
+    let result = rt.block_on(Daemon::run_dataflow(dataflow, options))?;
+
+    if !result.is_ok() {
+        print_node_results(&result)?;
+    }
+    handle_dataflow_result(result, None)
+}
+
+/// Prints a table with the exit status of every node to stderr.
+fn print_node_results(result: &DataflowResult) -> eyre::Result<()> {
+    let mut tw = TabWriter::new(vec![]);
+    tw.write_all(b"NODE\tSTATUS\tDETAILS\n")?;
+    for (node_id, node_result) in &result.node_results {
+        let (status, details) = match node_result {
+            Ok(()) => ("succeeded", String::new()),
+            Err(err) => {
+                let details = err.to_string();
+                let first_line = details.lines().next().unwrap_or_default().to_owned();
+                ("failed", first_line)
+            }
+        };
+        tw.write_all(format!("{node_id}\t{status}\t{details}\n").as_bytes())?;
+    }
+    tw.flush()?;
+    let formatted = String::from_utf8(tw.into_inner()?)?;
+
+    eprintln!("\n{formatted}");
+
+    Ok(())
+}
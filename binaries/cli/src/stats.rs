@@ -0,0 +1,68 @@
+use crate::output::{self, Content, OutputFormat};
+use communication_layer_request_reply::TcpRequestReplyConnection;
+use dora_core::topics::{ControlRequest, ControlRequestReply};
+use eyre::{bail, Context, Result};
+use std::io::Write;
+use tabwriter::TabWriter;
+use uuid::Uuid;
+
+pub fn stats(
+    session: &mut TcpRequestReplyConnection,
+    uuid: Option<Uuid>,
+    name: Option<String>,
+    format: OutputFormat,
+) -> Result<()> {
+    let reply_raw = session
+        .request(&serde_json::to_vec(&ControlRequest::Stats { uuid, name }).unwrap())
+        .wrap_err("failed to send Stats request message")?;
+    let reply: ControlRequestReply =
+        serde_json::from_slice(&reply_raw).wrap_err("failed to parse reply")?;
+    let stats = match &reply {
+        ControlRequestReply::DataflowStats(stats) => stats,
+        ControlRequestReply::Error(err) => bail!("{err}"),
+        other => bail!("unexpected reply to stats request: {other:?}"),
+    };
+    if format != OutputFormat::Text {
+        return output::print(format, Content::Reply(&reply));
+    }
+
+    let mut tw = TabWriter::new(vec![]);
+    tw.write_all(
+        b"Machine\tOutput\tCompressed\tRatio\tCompression Time\tDecompressed\tDecompression Time\n",
+    )?;
+    for (machine, machine_stats) in stats {
+        for (output, compression) in &machine_stats.compression {
+            let ratio = compression
+                .ratio()
+                .map(|ratio| format!("{ratio:.2}"))
+                .unwrap_or_else(|| "-".into());
+            tw.write_all(
+                format!(
+                    "{machine}\t{output}\t{}\t{ratio}\t{:?}\t{}\t{:?}\n",
+                    compression.compressed_messages,
+                    compression.compression_time,
+                    compression.decompressed_messages,
+                    compression.decompression_time,
+                )
+                .as_bytes(),
+            )?;
+        }
+    }
+    tw.flush()?;
+    let formatted = String::from_utf8(tw.into_inner()?)?;
+    print!("{formatted}");
+
+    if stats.values().any(|s| !s.dropped_inputs.is_empty()) {
+        let mut tw = TabWriter::new(vec![]);
+        tw.write_all(b"\nMachine\tInput\tDropped\n")?;
+        for (machine, machine_stats) in stats {
+            for (input, dropped) in &machine_stats.dropped_inputs {
+                tw.write_all(format!("{machine}\t{input}\t{dropped}\n").as_bytes())?;
+            }
+        }
+        tw.flush()?;
+        let formatted = String::from_utf8(tw.into_inner()?)?;
+        print!("{formatted}");
+    }
+    Ok(())
+}
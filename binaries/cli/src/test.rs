@@ -0,0 +1,60 @@
+use colored::Colorize;
+use dora_daemon::testing::{run_test_spec, write_junit, TestCase, TestReport};
+use eyre::{bail, Context};
+use std::{path::Path, time::Duration};
+use tokio::runtime::Builder;
+
+pub fn run_tests(specs: &[impl AsRef<Path>], junit: Option<&Path>) -> eyre::Result<()> {
+    let rt = Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("tokio runtime failed")?;
+
+    let mut reports = Vec::new();
+    for spec in specs {
+        let spec = spec.as_ref();
+        println!("running dataflow test `{}`", spec.display());
+        let report = match rt.block_on(run_test_spec(spec)) {
+            Ok(report) => report,
+            // still report specs that failed to run
+            Err(err) => TestReport {
+                name: spec.display().to_string(),
+                duration: Duration::ZERO,
+                cases: vec![TestCase {
+                    name: "dataflow".into(),
+                    failure: Some(format!("{err:?}")),
+                }],
+            },
+        };
+        print_report(&report);
+        reports.push(report);
+    }
+
+    if let Some(path) = junit {
+        let file = std::fs::File::create(path)
+            .wrap_err_with(|| format!("failed to create `{}`", path.display()))?;
+        write_junit(&reports, std::io::BufWriter::new(file))
+            .wrap_err("failed to write JUnit report")?;
+    }
+
+    let failed = reports.iter().filter(|r| !r.is_ok()).count();
+    if failed > 0 {
+        bail!("{failed} of {} dataflow tests failed", reports.len());
+    }
+    Ok(())
+}
+
+fn print_report(report: &TestReport) {
+    for case in &report.cases {
+        match &case.failure {
+            None => println!("  {} ... {}", case.name, "ok".green()),
+            Some(failure) => {
+                println!("  {} ... {}", case.name, "FAILED".red());
+                for line in failure.lines() {
+                    println!("      {line}");
+                }
+            }
+        }
+    }
+    println!();
+}
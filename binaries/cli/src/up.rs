@@ -1,20 +1,30 @@
 use crate::{check::daemon_running, connect_to_coordinator, LOCALHOST};
-use dora_core::topics::{ControlRequest, DORA_COORDINATOR_PORT_CONTROL_DEFAULT};
+use dora_core::{
+    security::SecurityConfig,
+    topics::{ControlRequest, DORA_COORDINATOR_PORT_CONTROL_DEFAULT},
+};
 use eyre::Context;
 use std::{fs, net::SocketAddr, path::Path, process::Command, time::Duration};
 #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
 struct UpConfig {}
 
-pub(crate) fn up(config_path: Option<&Path>) -> eyre::Result<()> {
+/// Starts a coordinator and a daemon on the local machine if they are not running yet.
+///
+/// The security config is passed on to the started processes.
+pub(crate) fn up(
+    config_path: Option<&Path>,
+    security_config_path: Option<&Path>,
+    security: &SecurityConfig,
+) -> eyre::Result<()> {
     let UpConfig {} = parse_dora_config(config_path)?;
     let coordinator_addr = (LOCALHOST, DORA_COORDINATOR_PORT_CONTROL_DEFAULT).into();
-    let mut session = match connect_to_coordinator(coordinator_addr) {
+    let mut session = match connect_to_coordinator(coordinator_addr, security) {
         Ok(session) => session,
         Err(_) => {
-            start_coordinator().wrap_err("failed to start dora-coordinator")?;
+            start_coordinator(security_config_path).wrap_err("failed to start dora-coordinator")?;
 
             loop {
-                match connect_to_coordinator(coordinator_addr) {
+                match connect_to_coordinator(coordinator_addr, security) {
                     Ok(session) => break session,
                     Err(_) => {
                         // sleep a bit until the coordinator accepts connections
@@ -26,7 +36,7 @@ pub(crate) fn up(config_path: Option<&Path>) -> eyre::Result<()> {
     };
 
     if !daemon_running(&mut *session)? {
-        start_daemon().wrap_err("failed to start dora-daemon")?;
+        start_daemon(security_config_path).wrap_err("failed to start dora-daemon")?;
 
         // wait a bit until daemon is connected
         let mut i = 0;
@@ -49,9 +59,10 @@ pub(crate) fn up(config_path: Option<&Path>) -> eyre::Result<()> {
 pub(crate) fn destroy(
     config_path: Option<&Path>,
     coordinator_addr: SocketAddr,
+    security: &SecurityConfig,
 ) -> Result<(), eyre::ErrReport> {
     let UpConfig {} = parse_dora_config(config_path)?;
-    match connect_to_coordinator(coordinator_addr) {
+    match connect_to_coordinator(coordinator_addr, security) {
         Ok(mut session) => {
             // send destroy command to dora-coordinator
             session
@@ -81,11 +92,14 @@ fn parse_dora_config(config_path: Option<&Path>) -> Result<UpConfig, eyre::ErrRe
     Ok(config)
 }
 
-fn start_coordinator() -> eyre::Result<()> {
+fn start_coordinator(security_config_path: Option<&Path>) -> eyre::Result<()> {
     let mut cmd =
         Command::new(std::env::current_exe().wrap_err("failed to get current executable path")?);
     cmd.arg("coordinator");
     cmd.arg("--quiet");
+    if let Some(path) = security_config_path {
+        cmd.arg("--security-config").arg(path);
+    }
     cmd.spawn().wrap_err("failed to run `dora coordinator`")?;
 
     println!("started dora coordinator");
@@ -93,11 +107,14 @@ fn start_coordinator() -> eyre::Result<()> {
     Ok(())
 }
 
-fn start_daemon() -> eyre::Result<()> {
+fn start_daemon(security_config_path: Option<&Path>) -> eyre::Result<()> {
     let mut cmd =
         Command::new(std::env::current_exe().wrap_err("failed to get current executable path")?);
     cmd.arg("daemon");
     cmd.arg("--quiet");
+    if let Some(path) = security_config_path {
+        cmd.arg("--security-config").arg(path);
+    }
     cmd.spawn().wrap_err("failed to run `dora daemon`")?;
 
     println!("started dora daemon");
@@ -1,16 +1,22 @@
 use crate::{
+    event_subscriber::EVENT_BUFFER_SIZE,
+    http,
+    security::Security,
     tcp_utils::{tcp_receive, tcp_send},
     Event,
 };
-use dora_core::topics::{ControlRequest, ControlRequestReply};
-use eyre::{eyre, Context};
+use dora_core::{
+    security::{tls::Connection, Role},
+    topics::{ControlRequest, ControlRequestReply},
+};
+use eyre::{bail, eyre, Context};
 use futures::{
     future::{self, Either},
     stream::FuturesUnordered,
     FutureExt, Stream, StreamExt,
 };
 use futures_concurrency::future::Race;
-use std::{io::ErrorKind, net::SocketAddr};
+use std::{io::ErrorKind, net::SocketAddr, sync::Arc};
 use tokio::{
     net::{TcpListener, TcpStream},
     sync::{mpsc, oneshot},
@@ -21,12 +27,30 @@ use uuid::Uuid;
 
 pub(crate) async fn control_events(
     control_listen_addr: SocketAddr,
+    http_listen_addr: Option<SocketAddr>,
     tasks: &FuturesUnordered<JoinHandle<()>>,
+    security: Arc<Security>,
 ) -> eyre::Result<impl Stream<Item = Event>> {
     let (tx, rx) = mpsc::channel(10);
 
+    if let Some(addr) = http_listen_addr {
+        let listener = TcpListener::bind(addr)
+            .await
+            .wrap_err("failed to listen for HTTP requests")?;
+        tasks.push(tokio::spawn(http::serve(
+            listener,
+            tx.clone(),
+            security.clone(),
+        )));
+    }
+
     let (finish_tx, mut finish_rx) = mpsc::channel(1);
-    tasks.push(tokio::spawn(listen(control_listen_addr, tx, finish_tx)));
+    tasks.push(tokio::spawn(listen(
+        control_listen_addr,
+        tx,
+        finish_tx,
+        security,
+    )));
     tasks.push(tokio::spawn(async move {
         while let Some(()) = finish_rx.recv().await {}
     }));
@@ -38,6 +62,7 @@ async fn listen(
     control_listen_addr: SocketAddr,
     tx: mpsc::Sender<ControlEvent>,
     _finish_tx: mpsc::Sender<()>,
+    security: Arc<Security>,
 ) {
     let result = TcpListener::bind(control_listen_addr)
         .await
@@ -63,7 +88,12 @@ async fn listen(
         match connection.wrap_err("failed to connect") {
             Ok((connection, _)) => {
                 let tx = tx.clone();
-                tokio::spawn(handle_requests(connection, tx, _finish_tx.clone()));
+                tokio::spawn(handle_requests(
+                    connection,
+                    tx,
+                    _finish_tx.clone(),
+                    security.clone(),
+                ));
             }
             Err(err) => {
                 if tx.blocking_send(err.into()).is_err() {
@@ -75,10 +105,20 @@ async fn listen(
 }
 
 async fn handle_requests(
-    mut connection: TcpStream,
+    connection: TcpStream,
     tx: mpsc::Sender<ControlEvent>,
     _finish_tx: mpsc::Sender<()>,
+    security: Arc<Security>,
 ) {
+    let mut connection = match security.accept(connection).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            tracing::warn!("failed to accept control connection: {err}");
+            return;
+        }
+    };
+    let mut role = (!security.requires_token()).then_some(Role::Admin);
+
     loop {
         let next_request = tcp_receive(&mut connection).map(Either::Left);
         let coordinator_stopped = tx.closed().map(Either::Right);
@@ -100,8 +140,12 @@ async fn handle_requests(
             },
         };
 
-        let request =
-            serde_json::from_slice(&raw).wrap_err("failed to deserialize incoming message");
+        let request = serde_json::from_slice(&raw)
+            .wrap_err("failed to deserialize incoming message")
+            .and_then(|request| {
+                check_permission(role, &request)?;
+                Ok(request)
+            });
 
         if let Ok(ControlRequest::LogSubscribe { dataflow_id, level }) = request {
             let _ = tx
@@ -114,7 +158,28 @@ async fn handle_requests(
             break;
         }
 
+        if let Ok(ControlRequest::SubscribeEvents { dataflow_id }) = request {
+            // only lifecycle events, no log messages
+            if let Some(mut receiver) =
+                subscribe_events(&tx, dataflow_id, log::LevelFilter::Off).await
+            {
+                while let Some(message) = receiver.recv().await {
+                    if let Err(err) = tcp_send(&mut connection, message.as_bytes()).await {
+                        tracing::debug!("failed to send event to subscriber: {err}");
+                        break;
+                    }
+                }
+            }
+            break;
+        }
+
         let result = match request {
+            Ok(ControlRequest::Authenticate { token }) => {
+                security.authenticate(Some(&token)).map(|new_role| {
+                    role = Some(new_role);
+                    ControlRequestReply::Authenticated { role: new_role }
+                })
+            }
             Ok(request) => handle_request(request, &tx).await,
             Err(err) => Err(err),
         };
@@ -149,7 +214,79 @@ async fn handle_requests(
     }
 }
 
-async fn handle_request(
+pub(crate) fn check_permission(role: Option<Role>, request: &ControlRequest) -> eyre::Result<()> {
+    match role {
+        _ if matches!(request, ControlRequest::Authenticate { .. }) => Ok(()),
+        None => bail!("authentication required"),
+        Some(Role::ReadOnly) if !request.is_read_only() => {
+            bail!("permission denied: request requires the `admin` role")
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Subscribes to the lifecycle events of all dataflows, or only of the given
+/// one, and to log messages up to the given level.
+///
+/// This is shared by the `SubscribeEvents` control request and the WebSocket
+/// endpoint of the HTTP API. Returns `None` if the coordinator stopped.
+pub(crate) async fn subscribe_events(
+    tx: &mpsc::Sender<ControlEvent>,
+    dataflow_id: Option<Uuid>,
+    level: log::LevelFilter,
+) -> Option<mpsc::Receiver<String>> {
+    let (sender, receiver) = mpsc::channel(EVENT_BUFFER_SIZE);
+    let subscribe = ControlEvent::EventSubscribe {
+        dataflow_id,
+        level,
+        sender,
+    };
+    tx.send(subscribe).await.ok().map(|()| receiver)
+}
+
+/// Error that is caused by the control request itself, e.g. because it
+/// references an unknown dataflow.
+///
+/// All other errors are treated as internal errors of the coordinator.
+#[derive(Debug)]
+pub(crate) enum RequestError {
+    /// The referenced dataflow, node, or machine does not exist.
+    NotFound(String),
+    /// The request is invalid, e.g. because of an invalid dataflow descriptor.
+    Invalid(String),
+}
+
+impl RequestError {
+    /// Finds the request error in the chain of the given error, if any.
+    pub fn find(err: &eyre::Report) -> Option<&Self> {
+        err.chain().find_map(|err| err.downcast_ref())
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::NotFound(message) | RequestError::Invalid(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Creates an error for a request that references a missing dataflow or node.
+pub(crate) fn not_found(message: impl std::fmt::Display) -> eyre::Report {
+    RequestError::NotFound(message.to_string()).into()
+}
+
+/// Creates an error for an invalid request, keeping the full error chain of
+/// the given message.
+pub(crate) fn invalid_request(message: impl std::fmt::Display) -> eyre::Report {
+    RequestError::Invalid(format!("{message:#}")).into()
+}
+
+pub(crate) async fn handle_request(
     request: ControlRequest,
     tx: &mpsc::Sender<ControlEvent>,
 ) -> eyre::Result<ControlRequestReply> {
@@ -177,7 +314,16 @@ pub enum ControlEvent {
     LogSubscribe {
         dataflow_id: Uuid,
         level: log::LevelFilter,
-        connection: TcpStream,
+        connection: Connection,
+    },
+    /// Subscribes to log messages and lifecycle events of all dataflows, or only
+    /// of the given one.
+    ///
+    /// The messages are sent as serialized JSON.
+    EventSubscribe {
+        dataflow_id: Option<Uuid>,
+        level: log::LevelFilter,
+        sender: mpsc::Sender<String>,
     },
     Error(eyre::Report),
 }
@@ -187,3 +333,42 @@ impl From<eyre::Report> for ControlEvent {
         ControlEvent::Error(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop() -> ControlRequest {
+        ControlRequest::Stop {
+            dataflow_uuid: Uuid::now_v7(),
+            grace_duration: None,
+        }
+    }
+
+    #[test]
+    fn unauthenticated_connections_can_only_authenticate() {
+        assert!(check_permission(None, &ControlRequest::List).is_err());
+        assert!(check_permission(None, &stop()).is_err());
+        let authenticate = ControlRequest::Authenticate {
+            token: "secret".into(),
+        };
+        assert!(check_permission(None, &authenticate).is_ok());
+    }
+
+    #[test]
+    fn read_only_role_cannot_modify_dataflows() {
+        let role = Some(Role::ReadOnly);
+        assert!(check_permission(role, &ControlRequest::List).is_ok());
+        assert!(check_permission(role, &ControlRequest::ConnectedMachines).is_ok());
+        assert!(check_permission(role, &stop()).is_err());
+        assert!(check_permission(role, &ControlRequest::Destroy).is_err());
+    }
+
+    #[test]
+    fn admin_role_can_send_all_requests() {
+        let role = Some(Role::Admin);
+        assert!(check_permission(role, &ControlRequest::List).is_ok());
+        assert!(check_permission(role, &stop()).is_ok());
+        assert!(check_permission(role, &ControlRequest::Destroy).is_ok());
+    }
+}
@@ -0,0 +1,108 @@
+use dora_core::{coordinator_messages::LogMessage, topics::LifecycleEvent};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Number of buffered messages per subscriber before it is disconnected.
+pub const EVENT_BUFFER_SIZE: usize = 100;
+
+/// Borrowed version of [`dora_core::topics::EventMessage`], to avoid cloning
+/// messages before serializing them.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SubscriberMessage<'a> {
+    Log(&'a LogMessage),
+    Lifecycle(&'a LifecycleEvent),
+}
+
+/// Subscriber to log messages and lifecycle events, e.g. a WebSocket client.
+struct EventSubscriber {
+    /// Only forward messages of this dataflow, if set.
+    dataflow_id: Option<Uuid>,
+    level: log::LevelFilter,
+    sender: mpsc::Sender<String>,
+}
+
+#[derive(Default)]
+pub struct EventSubscribers {
+    subscribers: Vec<EventSubscriber>,
+}
+
+impl EventSubscribers {
+    pub fn add(
+        &mut self,
+        dataflow_id: Option<Uuid>,
+        level: log::LevelFilter,
+        sender: mpsc::Sender<String>,
+    ) {
+        self.subscribers.push(EventSubscriber {
+            dataflow_id,
+            level,
+            sender,
+        });
+    }
+
+    pub fn send_log(&mut self, message: &LogMessage) {
+        self.send(
+            Some(message.dataflow_id),
+            Some(message.level),
+            SubscriberMessage::Log(message),
+        );
+    }
+
+    pub fn send_lifecycle(&mut self, event: &LifecycleEvent) {
+        self.send(
+            event.dataflow_uuid(),
+            None,
+            SubscriberMessage::Lifecycle(event),
+        );
+    }
+
+    /// Closes all subscribers that are limited to the given dataflow.
+    pub fn dataflow_finished(&mut self, dataflow_id: Uuid) {
+        self.subscribers
+            .retain(|s| s.dataflow_id != Some(dataflow_id));
+    }
+
+    /// Sends the given event to a subscriber that is not added, e.g. because
+    /// the dataflow it subscribed to finished already.
+    pub fn send_once(sender: &mpsc::Sender<String>, event: &LifecycleEvent) {
+        match serde_json::to_string(&SubscriberMessage::Lifecycle(event)) {
+            Ok(message) => {
+                let _ = sender.try_send(message);
+            }
+            Err(err) => tracing::warn!("failed to serialize subscriber message: {err}"),
+        }
+    }
+
+    /// Messages that belong to no dataflow are sent to all subscribers.
+    fn send(
+        &mut self,
+        dataflow_id: Option<Uuid>,
+        level: Option<log::Level>,
+        message: SubscriberMessage,
+    ) {
+        let mut serialized = None;
+        self.subscribers.retain(|subscriber| {
+            if subscriber
+                .dataflow_id
+                .zip(dataflow_id)
+                .is_some_and(|(subscribed, id)| subscribed != id)
+                || level.is_some_and(|level| level > subscriber.level)
+            {
+                return true;
+            }
+            let message = match &serialized {
+                Some(message) => message,
+                None => match serde_json::to_string(&message) {
+                    Ok(message) => serialized.insert(message),
+                    Err(err) => {
+                        tracing::warn!("failed to serialize subscriber message: {err}");
+                        return true;
+                    }
+                },
+            };
+            // close subscribers that don't keep up instead of blocking the coordinator
+            subscriber.sender.try_send(message.clone()).is_ok()
+        });
+    }
+}
@@ -0,0 +1,631 @@
+//! HTTP API of the coordinator.
+//!
+//! Provides REST endpoints for the most important control requests and a
+//! WebSocket endpoint that streams log messages and dataflow lifecycle events:
+//!
+//! | Method | Path                                       | Description                |
+//! |--------|--------------------------------------------|----------------------------|
+//! | GET    | `/api/v1/dataflows`                        | list dataflows             |
+//! | POST   | `/api/v1/dataflows`                        | start a dataflow           |
+//! | GET    | `/api/v1/dataflows/{dataflow}`             | inspect a dataflow         |
+//! | POST   | `/api/v1/dataflows/{dataflow}/stop`        | stop a dataflow            |
+//! | GET    | `/api/v1/dataflows/{dataflow}/logs/{node}` | get the logs of a node     |
+//! | GET    | `/api/v1/dataflows/{dataflow}/stats`       | get dataflow statistics    |
+//! | GET    | `/api/v1/machines`                         | list connected machines    |
+//! | GET    | `/api/v1/events`                           | WebSocket stream of events |
+//!
+//! The WebSocket endpoint provides the same stream as the `SubscribeEvents`
+//! control request, with additional log messages up to the `level` query
+//! parameter (default `info`). Like for `SubscribeEvents`, a stream that is
+//! limited to a single `dataflow` is closed after the dataflow finished.
+//!
+//! The logs endpoint accepts a `level` query parameter to only return structured
+//! log records of that level or more severe.
+//!
+//! Errors that are caused by the request, e.g. an unknown dataflow, result in
+//! a `4xx` status code, all other errors in `500 Internal Server Error`.
+//!
+//! Dataflows can be referenced by UUID or by name. If the coordinator requires
+//! token authentication, the token must be passed as `Authorization: Bearer`
+//! header, or as `token` query parameter for the WebSocket endpoint.
+
+use crate::{
+    control::{self, ControlEvent, RequestError},
+    security::Security,
+};
+use axum::{
+    body::Bytes,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use dora_core::{
+    descriptor::Descriptor,
+    security::{tls::Connection, Role},
+    topics::{ControlRequest, ControlRequestReply},
+};
+use futures::{future::Either, FutureExt};
+use futures_concurrency::future::Race;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+struct HttpState {
+    control_tx: mpsc::Sender<ControlEvent>,
+    security: Arc<Security>,
+}
+
+pub(crate) async fn serve(
+    listener: TcpListener,
+    control_tx: mpsc::Sender<ControlEvent>,
+    security: Arc<Security>,
+) {
+    let (connections_tx, connections_rx) = mpsc::channel(10);
+    tokio::spawn(accept_connections(
+        listener,
+        connections_tx,
+        security.clone(),
+    ));
+
+    let coordinator_stopped = control_tx.clone();
+    let app = Router::new()
+        .route("/api/v1/dataflows", get(list).post(start))
+        .route("/api/v1/dataflows/:dataflow", get(inspect))
+        .route("/api/v1/dataflows/:dataflow/stop", post(stop))
+        .route("/api/v1/dataflows/:dataflow/logs/:node", get(logs))
+        .route("/api/v1/dataflows/:dataflow/stats", get(stats))
+        .route("/api/v1/machines", get(machines))
+        .route("/api/v1/events", get(events))
+        .with_state(Arc::new(HttpState {
+            control_tx,
+            security,
+        }));
+    let incoming = hyper::server::accept::from_stream(ReceiverStream::new(connections_rx));
+    let result = axum::Server::builder(incoming)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async move { coordinator_stopped.closed().await })
+        .await;
+    if let Err(err) = result {
+        tracing::error!("HTTP server failed: {err}");
+    }
+}
+
+/// Accepts incoming connections and performs the TLS handshake, if enabled.
+async fn accept_connections(
+    listener: TcpListener,
+    connections: mpsc::Sender<std::io::Result<Connection>>,
+    security: Arc<Security>,
+) {
+    loop {
+        let new_connection = listener.accept().map(Either::Left);
+        let server_stopped = connections.closed().map(Either::Right);
+        let stream = match (new_connection, server_stopped).race().await {
+            Either::Left(Ok((stream, _))) => stream,
+            Either::Left(Err(err)) => {
+                tracing::warn!("failed to accept HTTP connection: {err}");
+                continue;
+            }
+            Either::Right(()) => break,
+        };
+        let security = security.clone();
+        let connections = connections.clone();
+        tokio::spawn(async move {
+            match security.accept(stream).await {
+                Ok(connection) => {
+                    let _ = connections.send(Ok(connection)).await;
+                }
+                Err(err) => tracing::warn!("failed to accept HTTP connection: {err}"),
+            }
+        });
+    }
+}
+
+impl HttpState {
+    fn authenticate(&self, headers: &HeaderMap, token: Option<&str>) -> Result<Role, ApiError> {
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        self.security
+            .authenticate(bearer.or(token))
+            .map_err(|err| ApiError::new(StatusCode::UNAUTHORIZED, err))
+    }
+
+    async fn request(
+        &self,
+        headers: &HeaderMap,
+        request: ControlRequest,
+    ) -> Result<ControlRequestReply, ApiError> {
+        let role = self.authenticate(headers, None)?;
+        control::check_permission(Some(role), &request)
+            .map_err(|err| ApiError::new(StatusCode::FORBIDDEN, err))?;
+        match control::handle_request(request, &self.control_tx).await {
+            Ok(ControlRequestReply::Error(err)) => {
+                Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err))
+            }
+            Ok(ControlRequestReply::CoordinatorStopped) => Err(ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "coordinator stopped",
+            )),
+            Ok(reply) => Ok(reply),
+            Err(err) => Err(ApiError::from_report(&err)),
+        }
+    }
+}
+
+/// Dataflow reference from the request path, either a UUID or a name.
+fn parse_dataflow(dataflow: String) -> (Option<Uuid>, Option<String>) {
+    match Uuid::parse_str(&dataflow) {
+        Ok(uuid) => (Some(uuid), None),
+        Err(_) => (None, Some(dataflow)),
+    }
+}
+
+async fn list(
+    State(state): State<Arc<HttpState>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    match state.request(&headers, ControlRequest::List).await? {
+        ControlRequestReply::DataflowList(list) => Ok(json(StatusCode::OK, &list.0)),
+        other => Err(ApiError::unexpected(other)),
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StartRequest {
+    dataflow: Descriptor,
+    name: Option<String>,
+    /// Directory on the daemon machines that relative paths are resolved against.
+    working_dir: PathBuf,
+    /// Only return the node placement, without starting the dataflow.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+async fn start(
+    State(state): State<Arc<HttpState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let StartRequest {
+        dataflow,
+        name,
+        working_dir,
+        dry_run,
+    } = serde_json::from_slice(&body).map_err(|err| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("invalid start request: {err}"),
+        )
+    })?;
+    let request = ControlRequest::Start {
+        dataflow,
+        name,
+        local_working_dir: working_dir,
+        dry_run,
+    };
+    match state.request(&headers, request).await? {
+        ControlRequestReply::DataflowStarted { uuid } => Ok(json(
+            StatusCode::CREATED,
+            &serde_json::json!({ "uuid": uuid }),
+        )),
+        ControlRequestReply::DataflowPlan { nodes } => {
+            Ok(json(StatusCode::OK, &serde_json::json!({ "nodes": nodes })))
+        }
+        other => Err(ApiError::unexpected(other)),
+    }
+}
+
+async fn inspect(
+    State(state): State<Arc<HttpState>>,
+    Path(dataflow): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let (uuid, name) = parse_dataflow(dataflow);
+    match state
+        .request(&headers, ControlRequest::Inspect { uuid, name })
+        .await?
+    {
+        ControlRequestReply::DataflowInfo(info) => Ok(json(StatusCode::OK, &info)),
+        other => Err(ApiError::unexpected(other)),
+    }
+}
+
+async fn stats(
+    State(state): State<Arc<HttpState>>,
+    Path(dataflow): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let (uuid, name) = parse_dataflow(dataflow);
+    match state
+        .request(&headers, ControlRequest::Stats { uuid, name })
+        .await?
+    {
+        ControlRequestReply::DataflowStats(stats) => Ok(json(StatusCode::OK, &stats)),
+        other => Err(ApiError::unexpected(other)),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct StopParams {
+    /// Grace duration in seconds before the nodes are killed.
+    grace_duration: Option<f64>,
+}
+
+async fn stop(
+    State(state): State<Arc<HttpState>>,
+    Path(dataflow): Path<String>,
+    Query(params): Query<StopParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let grace_duration = params
+        .grace_duration
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err))?;
+    let request = match parse_dataflow(dataflow) {
+        (Some(dataflow_uuid), _) => ControlRequest::Stop {
+            dataflow_uuid,
+            grace_duration,
+        },
+        (None, name) => ControlRequest::StopByName {
+            name: name.unwrap_or_default(),
+            grace_duration,
+        },
+    };
+    match state.request(&headers, request).await? {
+        ControlRequestReply::DataflowStopped { result, .. } => Ok(json(StatusCode::OK, &result)),
+        other => Err(ApiError::unexpected(other)),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct LogsParams {
+    /// Maximum level of returned structured log records.
+    level: Option<log::LevelFilter>,
+}
+
+async fn logs(
+    State(state): State<Arc<HttpState>>,
+    Path((dataflow, node)): Path<(String, String)>,
+    Query(params): Query<LogsParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let (uuid, name) = parse_dataflow(dataflow);
+    match state
+        .request(
+            &headers,
+            ControlRequest::Logs {
+                uuid,
+                name,
+                node,
+                level: params.level,
+            },
+        )
+        .await?
+    {
+        ControlRequestReply::Logs(logs) => {
+            Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], logs).into_response())
+        }
+        other => Err(ApiError::unexpected(other)),
+    }
+}
+
+async fn machines(
+    State(state): State<Arc<HttpState>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    match state
+        .request(&headers, ControlRequest::ConnectedMachines)
+        .await?
+    {
+        ControlRequestReply::ConnectedMachines(machines) => Ok(json(StatusCode::OK, &machines)),
+        other => Err(ApiError::unexpected(other)),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct EventsParams {
+    /// Only stream events of the given dataflow.
+    dataflow: Option<Uuid>,
+    /// Maximum level of forwarded log messages.
+    level: Option<log::LevelFilter>,
+    token: Option<String>,
+}
+
+async fn events(
+    State(state): State<Arc<HttpState>>,
+    Query(params): Query<EventsParams>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    // all roles are allowed to subscribe, same as for `LogSubscribe`
+    state.authenticate(&headers, params.token.as_deref())?;
+
+    let level = params.level.unwrap_or(log::LevelFilter::Info);
+    let Some(receiver) = control::subscribe_events(&state.control_tx, params.dataflow, level).await
+    else {
+        return Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "coordinator stopped",
+        ));
+    };
+    Ok(ws.on_upgrade(move |socket| forward_events(socket, receiver)))
+}
+
+async fn forward_events(mut socket: WebSocket, mut events: mpsc::Receiver<String>) {
+    loop {
+        let next_event = events.recv().map(Either::Left);
+        let incoming = socket.recv().map(Either::Right);
+        match (next_event, incoming).race().await {
+            Either::Left(Some(event)) => {
+                if socket.send(Message::Text(event)).await.is_err() {
+                    break;
+                }
+            }
+            Either::Left(None) => {
+                // the subscription was closed, e.g. because the dataflow finished
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+            // ignore messages from the client, pings are answered automatically
+            Either::Right(Some(Ok(_))) => {}
+            Either::Right(_) => break,
+        }
+    }
+}
+
+fn json(status: StatusCode, value: &impl serde::Serialize) -> Response {
+    match serde_json::to_vec(value) {
+        Ok(body) => (
+            status,
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )],
+            body,
+        )
+            .into_response(),
+        Err(err) => ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to serialize response: {err}"),
+        )
+        .into_response(),
+    }
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    /// Errors caused by the request are client errors, all other errors are
+    /// internal errors of the coordinator.
+    fn from_report(err: &eyre::Report) -> Self {
+        let status = match RequestError::find(err) {
+            Some(RequestError::NotFound(_)) => StatusCode::NOT_FOUND,
+            Some(RequestError::Invalid(_)) => StatusCode::BAD_REQUEST,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(status, format!("{err:#}"))
+    }
+
+    fn unexpected(reply: ControlRequestReply) -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unexpected reply from coordinator: {reply:?}"),
+        )
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        json(self.status, &serde_json::json!({ "error": self.message }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::{invalid_request, not_found};
+    use dora_core::{
+        security::{AccessToken, SecurityConfig},
+        topics::DataflowList,
+    };
+    use eyre::{eyre, WrapErr};
+    use std::net::SocketAddr;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    /// Starts the HTTP server with a fake coordinator that answers `List` and
+    /// `Logs` requests and fails `Inspect` requests depending on the dataflow
+    /// name.
+    async fn start_server() -> (SocketAddr, mpsc::Sender<ControlEvent>) {
+        let security = Security::new(SecurityConfig {
+            access_tokens: vec![
+                AccessToken {
+                    token: "admin".into(),
+                    role: Role::Admin,
+                },
+                AccessToken {
+                    token: "reader".into(),
+                    role: Role::ReadOnly,
+                },
+            ],
+            ..Default::default()
+        })
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (control_tx, mut control_rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            while let Some(event) = control_rx.recv().await {
+                let ControlEvent::IncomingRequest {
+                    request,
+                    reply_sender,
+                } = event
+                else {
+                    continue;
+                };
+                let reply = match request {
+                    ControlRequest::List => {
+                        Ok(ControlRequestReply::DataflowList(DataflowList(Vec::new())))
+                    }
+                    ControlRequest::Inspect { name, .. } => match name.as_deref() {
+                        Some("missing") => Err(not_found("no dataflow with name `missing`")),
+                        Some("invalid") => {
+                            Err(invalid_request("multiple dataflows found with name"))
+                        }
+                        _ => Err(eyre!("connection to daemon lost")),
+                    },
+                    ControlRequest::Logs { node, level, .. } => Ok(ControlRequestReply::Logs(
+                        format!("{node} {level:?}").into_bytes(),
+                    )),
+                    other => Err(eyre!("unexpected request {other:?}")),
+                };
+                let _ = reply_sender.send(reply);
+            }
+        });
+        tokio::spawn(serve(listener, control_tx.clone(), Arc::new(security)));
+        (addr, control_tx)
+    }
+
+    async fn http_request(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+    ) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n");
+        if let Some(token) = token {
+            request.push_str(&format!("Authorization: Bearer {token}\r\n"));
+        }
+        request.push_str("Content-Length: 0\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .unwrap();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_owned())
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn errors_are_mapped_to_status_codes() {
+        let (addr, _control_tx) = start_server().await;
+        let admin = Some("admin");
+
+        let (status, body) = http_request(addr, "GET", "/api/v1/dataflows", admin).await;
+        assert_eq!((status, body.as_str()), (200, "[]"));
+
+        let (status, body) = http_request(addr, "GET", "/api/v1/dataflows/missing", admin).await;
+        assert_eq!(status, 404);
+        assert!(body.contains("no dataflow with name `missing`"), "{body}");
+
+        let (status, _) = http_request(addr, "GET", "/api/v1/dataflows/invalid", admin).await;
+        assert_eq!(status, 400);
+
+        let (status, body) = http_request(addr, "GET", "/api/v1/dataflows/other", admin).await;
+        assert_eq!(status, 500);
+        assert!(body.contains("connection to daemon lost"), "{body}");
+
+        let (status, _) = http_request(
+            addr,
+            "POST",
+            "/api/v1/dataflows/other/stop?grace_duration=-1",
+            admin,
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn tokens_and_roles_are_checked() {
+        let (addr, _control_tx) = start_server().await;
+
+        let (status, _) = http_request(addr, "GET", "/api/v1/dataflows", None).await;
+        assert_eq!(status, 401);
+        let (status, _) = http_request(addr, "GET", "/api/v1/dataflows", Some("wrong")).await;
+        assert_eq!(status, 401);
+        let (status, _) = http_request(addr, "GET", "/api/v1/dataflows", Some("reader")).await;
+        assert_eq!(status, 200);
+        let (status, _) =
+            http_request(addr, "POST", "/api/v1/dataflows/other/stop", Some("reader")).await;
+        assert_eq!(status, 403);
+    }
+
+    #[tokio::test]
+    async fn log_level_is_passed_to_the_coordinator() {
+        let (addr, _control_tx) = start_server().await;
+        let admin = Some("admin");
+
+        let (status, body) =
+            http_request(addr, "GET", "/api/v1/dataflows/camera/logs/node", admin).await;
+        assert_eq!((status, body.as_str()), (200, "node None"));
+
+        let (status, body) = http_request(
+            addr,
+            "GET",
+            "/api/v1/dataflows/camera/logs/node?level=warn",
+            admin,
+        )
+        .await;
+        assert_eq!((status, body.as_str()), (200, "node Some(Warn)"));
+
+        let (status, _) = http_request(
+            addr,
+            "GET",
+            "/api/v1/dataflows/camera/logs/node?level=loud",
+            admin,
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn wrapped_request_errors_keep_their_status() {
+        let err = Err::<(), _>(not_found("no node `foo`"))
+            .wrap_err("failed to get logs")
+            .unwrap_err();
+        let api_error = ApiError::from_report(&err);
+        assert_eq!(api_error.status, StatusCode::NOT_FOUND);
+        assert_eq!(api_error.message, "failed to get logs: no node `foo`");
+
+        let api_error = ApiError::from_report(&eyre!("failed to serialize"));
+        assert_eq!(api_error.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn dataflows_are_referenced_by_uuid_or_name() {
+        let uuid = Uuid::now_v7();
+        assert_eq!(parse_dataflow(uuid.to_string()), (Some(uuid), None));
+        assert_eq!(
+            parse_dataflow("camera".into()),
+            (None, Some("camera".into()))
+        );
+    }
+}
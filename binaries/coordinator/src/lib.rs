@@ -1,5 +1,6 @@
 use crate::{
-    run::spawn_dataflow,
+    control::{invalid_request, not_found},
+    run::{plan_added_nodes, plan_dataflow, spawn_dataflow},
     tcp_utils::{tcp_receive, tcp_send},
 };
 pub use control::ControlEvent;
@@ -9,38 +10,68 @@ use dora_core::{
     daemon_messages::{DaemonCoordinatorEvent, DaemonCoordinatorReply, Timestamped},
     descriptor::{Descriptor, ResolvedNode},
     message::uhlc::{self, HLC},
+    security::{tls::Connection, SecurityConfig},
     topics::{
-        ControlRequest, ControlRequestReply, DataflowDaemonResult, DataflowId, DataflowListEntry,
-        DataflowResult,
+        ControlRequest, ControlRequestReply, DataflowDaemonResult, DataflowDaemonStats, DataflowId,
+        DataflowInfo, DataflowListEntry, DataflowResult, DataflowStatus, LifecycleEvent, NodeError,
+        NodeErrorCause, NodeExitStatus, NodeInfo,
     },
 };
+use event_subscriber::EventSubscribers;
 use eyre::{bail, eyre, ContextCompat, WrapErr};
 use futures::{stream::FuturesUnordered, Future, Stream, StreamExt};
 use futures_concurrency::stream::Merge;
+pub use log_store::LogConfig;
+use log_store::LogStore;
 use log_subscriber::LogSubscriber;
 use run::SpawnedDataflow;
+use security::Security;
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{hash_map, BTreeMap, BTreeSet, HashMap},
     net::SocketAddr,
     path::PathBuf,
     sync::Arc,
     time::{Duration, Instant},
 };
+use store::{Store, StoredDataflow};
 use tokio::{net::TcpStream, sync::mpsc, task::JoinHandle};
 use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
 use uuid::Uuid;
 
 mod control;
+mod event_subscriber;
+mod http;
 mod listener;
+mod log_store;
 mod log_subscriber;
 mod run;
+mod security;
+mod store;
 mod tcp_utils;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start(
     bind: SocketAddr,
     bind_control: SocketAddr,
+    bind_http: Option<SocketAddr>,
+    state: StateConfig,
+    security: SecurityConfig,
+    heartbeat: HeartbeatConfig,
+    logs: LogConfig,
     external_events: impl Stream<Item = Event> + Unpin,
 ) -> Result<(u16, impl Future<Output = eyre::Result<()>>), eyre::ErrReport> {
+    let security = Arc::new(Security::new(security)?);
+    let log_dir = logs
+        .dir
+        .clone()
+        .or_else(|| state.dir.as_ref().map(|dir| dir.join("logs")))
+        .unwrap_or_else(|| std::env::temp_dir().join("dora-coordinator").join("logs"));
+    let log_store = LogStore::open(log_dir, &logs).wrap_err("failed to open log store")?;
+    let store = state
+        .dir
+        .map(|dir| Store::open(&dir, state.max_finished))
+        .transpose()
+        .wrap_err("failed to open coordinator state store")?;
     let listener = listener::create_listener(bind).await?;
     let port = listener
         .local_addr()
@@ -53,7 +84,7 @@ pub async fn start(
     });
 
     let mut tasks = FuturesUnordered::new();
-    let control_events = control::control_events(bind_control, &tasks)
+    let control_events = control::control_events(bind_control, bind_http, &tasks, security.clone())
         .await
         .wrap_err("failed to create control events")?;
 
@@ -69,7 +100,7 @@ pub async fn start(
         .merge();
 
     let future = async move {
-        start_inner(events, &tasks).await?;
+        start_inner(events, &tasks, store, log_store, security, heartbeat).await?;
 
         tracing::debug!("coordinator main loop finished, waiting on spawned tasks");
         while let Some(join_result) = tasks.next().await {
@@ -83,6 +114,47 @@ pub async fn start(
     Ok((port, future))
 }
 
+/// Settings for persisting the coordinator state across restarts.
+#[derive(Debug, Clone)]
+pub struct StateConfig {
+    /// Directory to store the state in. The state is not persisted if not set.
+    pub dir: Option<PathBuf>,
+    /// Maximum number of finished dataflows to keep in the state directory.
+    ///
+    /// The oldest finished dataflows are removed first.
+    pub max_finished: usize,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_finished: 100,
+        }
+    }
+}
+
+/// Settings for detecting machines whose daemon stopped responding.
+#[derive(Debug, Clone)]
+pub struct HeartbeatConfig {
+    /// Consider a machine as lost if its daemon sent no heartbeat for this duration.
+    pub timeout: Duration,
+    /// Stop the dataflows that have nodes on a lost machine.
+    ///
+    /// Otherwise, only the nodes on the lost machine are marked as failed and
+    /// the remaining nodes keep running.
+    pub stop_on_machine_lost: bool,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            stop_on_machine_lost: false,
+        }
+    }
+}
+
 // Resolve the dataflow name.
 fn resolve_name(
     name: String,
@@ -104,23 +176,29 @@ fn resolve_name(
 
     if uuids.is_empty() {
         if archived_uuids.is_empty() {
-            bail!("no dataflow with name `{name}`");
+            Err(not_found(format!("no dataflow with name `{name}`")))
         } else if let [uuid] = archived_uuids.as_slice() {
             Ok(*uuid)
         } else {
             // TODO: Index the archived dataflows in order to return logs based on the index.
-            bail!("multiple archived dataflows found with name `{name}`, Please provide the UUID instead.");
+            Err(invalid_request(format!("multiple archived dataflows found with name `{name}`, Please provide the UUID instead.")))
         }
     } else if let [uuid] = uuids.as_slice() {
         Ok(*uuid)
     } else {
-        bail!("multiple dataflows found with name `{name}`");
+        Err(invalid_request(format!(
+            "multiple dataflows found with name `{name}`"
+        )))
     }
 }
 
 async fn start_inner(
     events: impl Stream<Item = Event> + Unpin,
     tasks: &FuturesUnordered<JoinHandle<()>>,
+    store: Option<Store>,
+    log_store: LogStore,
+    security: Arc<Security>,
+    heartbeat: HeartbeatConfig,
 ) -> eyre::Result<()> {
     let clock = Arc::new(HLC::default());
 
@@ -131,10 +209,14 @@ async fn start_inner(
     let daemon_heartbeat_interval =
         tokio_stream::wrappers::IntervalStream::new(tokio::time::interval(Duration::from_secs(3)))
             .map(|_| Event::DaemonHeartbeatInterval);
+    let log_retention_interval =
+        tokio_stream::wrappers::IntervalStream::new(tokio::time::interval(Duration::from_secs(60)))
+            .map(|_| Event::LogRetentionInterval);
 
     // events that should be aborted on `dora destroy`
-    let (abortable_events, abort_handle) =
-        futures::stream::abortable((events, daemon_heartbeat_interval).merge());
+    let (abortable_events, abort_handle) = futures::stream::abortable(
+        (events, daemon_heartbeat_interval, log_retention_interval).merge(),
+    );
 
     let mut events = (abortable_events, daemon_events).merge();
 
@@ -143,6 +225,30 @@ async fn start_inner(
         HashMap::new();
     let mut archived_dataflows: HashMap<Uuid, ArchivedDataflow> = HashMap::new();
     let mut daemon_connections: HashMap<_, DaemonConnection> = HashMap::new();
+    let mut event_subscribers = EventSubscribers::default();
+
+    if let Some(store) = &store {
+        for stored in store
+            .load_dataflows()
+            .wrap_err("failed to load stored dataflows")?
+        {
+            restore_dataflow(
+                stored,
+                &mut running_dataflows,
+                &mut dataflow_results,
+                &mut archived_dataflows,
+            );
+        }
+        tracing::info!(
+            "restored {} dataflows from state store ({} still running)",
+            archived_dataflows
+                .keys()
+                .chain(running_dataflows.keys())
+                .collect::<BTreeSet<_>>()
+                .len(),
+            running_dataflows.len(),
+        );
+    }
 
     while let Some(event) = events.next().await {
         if event.log() {
@@ -157,6 +263,7 @@ async fn start_inner(
                         connection,
                         events_tx,
                         clock.clone(),
+                        security.clone(),
                     ));
                     tasks.push(task);
                 } else {
@@ -174,6 +281,8 @@ async fn start_inner(
                     mut connection,
                     dora_version: daemon_version,
                     listen_port,
+                    running_dataflows: dataflows_on_daemon,
+                    labels,
                 } => {
                     let coordinator_version: &&str = &env!("CARGO_PKG_VERSION");
                     let version_check = if &daemon_version == coordinator_version {
@@ -206,6 +315,7 @@ async fn start_inner(
                                     stream: connection,
                                     listen_socket: (ip, listen_port).into(),
                                     last_heartbeat: Instant::now(),
+                                    labels,
                                 },
                             );
                             if let Some(_previous) = previous {
@@ -213,6 +323,19 @@ async fn start_inner(
                                     "closing previous connection `{machine_id}` on new register"
                                 );
                             }
+                            event_subscribers.send_lifecycle(&LifecycleEvent::DaemonConnected {
+                                machine: machine_id.clone(),
+                            });
+                            reconcile_daemon_dataflows(
+                                &machine_id,
+                                &dataflows_on_daemon,
+                                &mut running_dataflows,
+                                &mut dataflow_results,
+                                &mut archived_dataflows,
+                                &mut event_subscribers,
+                                store.as_ref(),
+                                &clock,
+                            );
                         }
                         (Err(err), _) => {
                             tracing::warn!("failed to register daemon connection for machine `{machine_id}`: {err}");
@@ -228,84 +351,52 @@ async fn start_inner(
                     machine_id,
                     exited_before_subscribe,
                 } => {
-                    match running_dataflows.entry(uuid) {
-                        std::collections::hash_map::Entry::Occupied(mut entry) => {
-                            let dataflow = entry.get_mut();
-                            dataflow.pending_machines.remove(&machine_id);
-                            dataflow
-                                .exited_before_subscribe
-                                .extend(exited_before_subscribe);
-                            if dataflow.pending_machines.is_empty() {
-                                let message = serde_json::to_vec(&Timestamped {
-                                    inner: DaemonCoordinatorEvent::AllNodesReady {
-                                        dataflow_id: uuid,
-                                        exited_before_subscribe: dataflow
-                                            .exited_before_subscribe
-                                            .clone(),
-                                    },
-                                    timestamp: clock.new_timestamp(),
-                                })
-                                .wrap_err("failed to serialize AllNodesReady message")?;
-
-                                // notify all machines that run parts of the dataflow
-                                for machine_id in &dataflow.machines {
-                                    let Some(connection) = daemon_connections.get_mut(machine_id)
-                                    else {
-                                        tracing::warn!(
-                                            "no daemon connection found for machine `{machine_id}`"
-                                        );
-                                        continue;
-                                    };
-                                    tcp_send(&mut connection.stream, &message)
-                                        .await
-                                        .wrap_err_with(|| {
-                                            format!(
-                                                "failed to send AllNodesReady({uuid}) message \
-                                            to machine {machine_id}"
-                                            )
-                                        })?;
-                                }
-                            }
-                        }
-                        std::collections::hash_map::Entry::Vacant(_) => {
-                            tracing::warn!("dataflow not running on ReadyOnMachine");
-                        }
+                    ready_on_machine(
+                        uuid,
+                        &machine_id,
+                        exited_before_subscribe,
+                        &mut running_dataflows,
+                        &mut daemon_connections,
+                        &clock,
+                    )
+                    .await?;
+                }
+                DataflowEvent::NodeReady { node_id } => {
+                    if let Some(dataflow) = running_dataflows.get(&uuid) {
+                        event_subscribers.send_lifecycle(&LifecycleEvent::NodeReady {
+                            dataflow: DataflowId {
+                                uuid,
+                                name: dataflow.name.clone(),
+                            },
+                            node: node_id,
+                        });
                     }
                 }
-                DataflowEvent::DataflowFinishedOnMachine { machine_id, result } => {
-                    match running_dataflows.entry(uuid) {
-                        std::collections::hash_map::Entry::Occupied(mut entry) => {
-                            // Archive finished dataflow
-                            if archived_dataflows.get(&uuid).is_none() {
-                                archived_dataflows
-                                    .insert(uuid, ArchivedDataflow::from(entry.get()));
-                            }
-                            entry.get_mut().machines.remove(&machine_id);
-                            dataflow_results
-                                .entry(uuid)
-                                .or_default()
-                                .insert(machine_id, result);
-                            if entry.get_mut().machines.is_empty() {
-                                let finished_dataflow = entry.remove();
-                                let reply = ControlRequestReply::DataflowStopped {
-                                    uuid,
-                                    result: dataflow_results
-                                        .get(&uuid)
-                                        .map(|r| dataflow_result(r, uuid, &clock))
-                                        .unwrap_or_else(|| {
-                                            DataflowResult::ok_empty(uuid, clock.new_timestamp())
-                                        }),
-                                };
-                                for sender in finished_dataflow.reply_senders {
-                                    let _ = sender.send(Ok(reply.clone()));
-                                }
-                            }
-                        }
-                        std::collections::hash_map::Entry::Vacant(_) => {
-                            tracing::warn!("dataflow not running on DataflowFinishedOnMachine");
-                        }
+                DataflowEvent::NodeExited { node_id, result } => {
+                    if let Some(dataflow) = running_dataflows.get(&uuid) {
+                        event_subscribers.send_lifecycle(&LifecycleEvent::NodeExited {
+                            dataflow: DataflowId {
+                                uuid,
+                                name: dataflow.name.clone(),
+                            },
+                            node: node_id,
+                            result,
+                        });
                     }
                 }
+                DataflowEvent::DataflowFinishedOnMachine { machine_id, result } => {
+                    dataflow_finished_on_machine(
+                        uuid,
+                        machine_id,
+                        result,
+                        &mut running_dataflows,
+                        &mut dataflow_results,
+                        &mut archived_dataflows,
+                        &mut event_subscribers,
+                        store.as_ref(),
+                        &clock,
+                    );
+                }
             },
 
             Event::Control(event) => match event {
@@ -314,10 +405,37 @@ async fn start_inner(
                     reply_sender,
                 } => {
                     match request {
+                        ControlRequest::Start {
+                            dataflow,
+                            name: _,
+                            local_working_dir,
+                            dry_run: true,
+                        } => {
+                            let reply = plan_dataflow(
+                                dataflow,
+                                &local_working_dir,
+                                &daemon_connections,
+                                &reserved_cpus(&running_dataflows, None),
+                            )
+                            .map(|plan| {
+                                ControlRequestReply::DataflowPlan {
+                                    nodes: plan
+                                        .nodes
+                                        .into_iter()
+                                        .map(|node| NodeInfo {
+                                            id: node.id,
+                                            machine: node.deploy.machine,
+                                        })
+                                        .collect(),
+                                }
+                            });
+                            let _ = reply_sender.send(reply);
+                        }
                         ControlRequest::Start {
                             dataflow,
                             name,
                             local_working_dir,
+                            dry_run: false,
                         } => {
                             let name = name.or_else(|| names::Generator::default().next());
 
@@ -328,14 +446,16 @@ async fn start_inner(
                                         .values()
                                         .any(|d: &RunningDataflow| d.name.as_deref() == Some(name))
                                     {
-                                        bail!("there is already a running dataflow with name `{name}`");
+                                        return Err(invalid_request(format!("there is already a running dataflow with name `{name}`")));
                                     }
                                 }
+                                let reserved_cpus = reserved_cpus(&running_dataflows, None);
                                 let dataflow = start_dataflow(
                                     dataflow,
                                     local_working_dir,
                                     name,
                                     &mut daemon_connections,
+                                    &reserved_cpus,
                                     &clock,
                                 )
                                 .await?;
@@ -343,6 +463,25 @@ async fn start_inner(
                             };
                             let reply = inner.await.map(|dataflow| {
                                 let uuid = dataflow.uuid;
+                                persist_dataflow(store.as_ref(), &dataflow, None);
+                                let id = DataflowId {
+                                    uuid,
+                                    name: dataflow.name.clone(),
+                                };
+                                event_subscribers.send_lifecycle(
+                                    &LifecycleEvent::DataflowStarted {
+                                        dataflow: id.clone(),
+                                    },
+                                );
+                                for node in &dataflow.nodes {
+                                    event_subscribers.send_lifecycle(
+                                        &LifecycleEvent::NodeSpawned {
+                                            dataflow: id.clone(),
+                                            node: node.id.clone(),
+                                            machine: node.deploy.machine.clone(),
+                                        },
+                                    );
+                                }
                                 running_dataflows.insert(uuid, dataflow);
                                 ControlRequestReply::DataflowStarted { uuid }
                             });
@@ -430,27 +569,157 @@ async fn start_inner(
                                 let _ = reply_sender.send(Err(err));
                             }
                         },
-                        ControlRequest::Logs { uuid, name, node } => {
-                            let dataflow_uuid = if let Some(uuid) = uuid {
-                                uuid
-                            } else if let Some(name) = name {
-                                resolve_name(name, &running_dataflows, &archived_dataflows)?
-                            } else {
-                                bail!("No uuid")
-                            };
+                        ControlRequest::Logs {
+                            uuid,
+                            name,
+                            node,
+                            level,
+                        } => {
+                            let reply = async {
+                                // an invalid request must not stop the coordinator
+                                let dataflow_uuid = match (uuid, name) {
+                                    (Some(uuid), _) => uuid,
+                                    (None, Some(name)) => {
+                                        resolve_name(name, &running_dataflows, &archived_dataflows)?
+                                    }
+                                    (None, None) => return Err(invalid_request("No uuid")),
+                                };
 
-                            let reply = retrieve_logs(
-                                &running_dataflows,
-                                &archived_dataflows,
-                                dataflow_uuid,
-                                node.into(),
-                                &mut daemon_connections,
-                                clock.new_timestamp(),
-                            )
+                                let node_id = NodeId::from(node);
+                                retrieve_logs(
+                                    &running_dataflows,
+                                    &archived_dataflows,
+                                    dataflow_uuid,
+                                    node_id.clone(),
+                                    &mut daemon_connections,
+                                    &log_store,
+                                    clock.new_timestamp(),
+                                )
+                                .await
+                                .map(|logs| match level {
+                                    Some(level) => {
+                                        filter_logs(&logs, dataflow_uuid, &node_id, level)
+                                    }
+                                    None => logs,
+                                })
+                            }
                             .await
                             .map(ControlRequestReply::Logs);
                             let _ = reply_sender.send(reply);
                         }
+                        ControlRequest::Stats { uuid, name } => {
+                            let reply = async {
+                                let dataflow_uuid = match (uuid, name) {
+                                    (Some(uuid), _) => uuid,
+                                    (None, Some(name)) => {
+                                        resolve_name(name, &running_dataflows, &archived_dataflows)?
+                                    }
+                                    (None, None) => return Err(invalid_request("No uuid")),
+                                };
+                                retrieve_stats(
+                                    &running_dataflows,
+                                    dataflow_uuid,
+                                    &mut daemon_connections,
+                                    clock.new_timestamp(),
+                                )
+                                .await
+                            }
+                            .await
+                            .map(ControlRequestReply::DataflowStats);
+                            let _ = reply_sender.send(reply);
+                        }
+                        ControlRequest::AddNodes { uuid, name, nodes } => {
+                            let reply = async {
+                                let dataflow_uuid = match (uuid, name) {
+                                    (Some(uuid), _) => uuid,
+                                    (None, Some(name)) => {
+                                        resolve_name(name, &running_dataflows, &archived_dataflows)?
+                                    }
+                                    (None, None) => return Err(invalid_request("No uuid")),
+                                };
+                                let reserved_cpus =
+                                    reserved_cpus(&running_dataflows, Some(dataflow_uuid));
+                                let dataflow =
+                                    running_dataflows.get_mut(&dataflow_uuid).ok_or_else(|| {
+                                        not_found(format!(
+                                            "no running dataflow with UUID `{dataflow_uuid}`"
+                                        ))
+                                    })?;
+                                // removed nodes stay in the descriptor
+                                let previous_nodes: BTreeSet<_> = dataflow
+                                    .descriptor
+                                    .nodes
+                                    .iter()
+                                    .map(|n| n.id.clone())
+                                    .collect();
+                                let added = add_nodes(
+                                    dataflow,
+                                    nodes,
+                                    &mut daemon_connections,
+                                    &reserved_cpus,
+                                    clock.new_timestamp(),
+                                )
+                                .await?;
+                                persist_dataflow(store.as_ref(), dataflow, None);
+                                let id = DataflowId {
+                                    uuid: dataflow_uuid,
+                                    name: dataflow.name.clone(),
+                                };
+                                for node in &added {
+                                    event_subscribers.send_lifecycle(&added_node_event(
+                                        id.clone(),
+                                        node,
+                                        &previous_nodes,
+                                    ));
+                                }
+                                Ok(ControlRequestReply::NodesAdded {
+                                    uuid: dataflow_uuid,
+                                    nodes: added
+                                        .into_iter()
+                                        .map(|node| NodeInfo {
+                                            id: node.id,
+                                            machine: node.deploy.machine,
+                                        })
+                                        .collect(),
+                                })
+                            };
+                            let _ = reply_sender.send(reply.await);
+                        }
+                        ControlRequest::RemoveNodes {
+                            uuid,
+                            name,
+                            nodes,
+                            grace_duration,
+                        } => {
+                            let reply = async {
+                                let dataflow_uuid = match (uuid, name) {
+                                    (Some(uuid), _) => uuid,
+                                    (None, Some(name)) => {
+                                        resolve_name(name, &running_dataflows, &archived_dataflows)?
+                                    }
+                                    (None, None) => return Err(invalid_request("No uuid")),
+                                };
+                                let dataflow =
+                                    running_dataflows.get_mut(&dataflow_uuid).ok_or_else(|| {
+                                        not_found(format!(
+                                            "no running dataflow with UUID `{dataflow_uuid}`"
+                                        ))
+                                    })?;
+                                remove_nodes(
+                                    dataflow,
+                                    nodes,
+                                    grace_duration,
+                                    &mut daemon_connections,
+                                    clock.new_timestamp(),
+                                )
+                                .await?;
+                                persist_dataflow(store.as_ref(), dataflow, None);
+                                Ok(ControlRequestReply::NodesRemoved {
+                                    uuid: dataflow_uuid,
+                                })
+                            };
+                            let _ = reply_sender.send(reply.await);
+                        }
                         ControlRequest::Destroy => {
                             tracing::info!("Received destroy command");
 
@@ -507,11 +776,43 @@ async fn start_inner(
                             ));
                             let _ = reply_sender.send(reply);
                         }
+                        ControlRequest::Inspect { uuid, name } => {
+                            let reply = match (uuid, name) {
+                                (Some(uuid), _) => Ok(uuid),
+                                (None, Some(name)) => {
+                                    resolve_name(name, &running_dataflows, &archived_dataflows)
+                                }
+                                (None, None) => {
+                                    Err(invalid_request("no dataflow UUID or name given"))
+                                }
+                            }
+                            .and_then(|uuid| {
+                                inspect_dataflow(
+                                    uuid,
+                                    &running_dataflows,
+                                    &dataflow_results,
+                                    &archived_dataflows,
+                                    &clock,
+                                )
+                            })
+                            .map(ControlRequestReply::DataflowInfo);
+                            let _ = reply_sender.send(reply);
+                        }
                         ControlRequest::LogSubscribe { .. } => {
                             let _ = reply_sender.send(Err(eyre::eyre!(
                                 "LogSubscribe request should be handled separately"
                             )));
                         }
+                        ControlRequest::SubscribeEvents { .. } => {
+                            let _ = reply_sender.send(Err(eyre::eyre!(
+                                "SubscribeEvents request should be handled separately"
+                            )));
+                        }
+                        ControlRequest::Authenticate { .. } => {
+                            let _ = reply_sender.send(Err(eyre::eyre!(
+                                "Authenticate request should be handled separately"
+                            )));
+                        }
                     }
                 }
                 ControlEvent::Error(err) => tracing::error!("{err:?}"),
@@ -526,17 +827,43 @@ async fn start_inner(
                             .push(LogSubscriber::new(level, connection));
                     }
                 }
+                ControlEvent::EventSubscribe {
+                    dataflow_id,
+                    level,
+                    sender,
+                } => {
+                    // like log subscribers, subscribers of a single dataflow are
+                    // only accepted while the dataflow is running
+                    match dataflow_id {
+                        Some(id) if !running_dataflows.contains_key(&id) => {
+                            // report the result if the dataflow finished already
+                            if let Some(results) = dataflow_results.get(&id) {
+                                let event = LifecycleEvent::DataflowFinished {
+                                    dataflow: DataflowId {
+                                        uuid: id,
+                                        name: archived_dataflows
+                                            .get(&id)
+                                            .and_then(|d| d.name.clone()),
+                                    },
+                                    result: dataflow_result(results, id, &clock),
+                                };
+                                EventSubscribers::send_once(&sender, &event);
+                            }
+                        }
+                        _ => event_subscribers.add(dataflow_id, level, sender),
+                    }
+                }
             },
             Event::DaemonHeartbeatInterval => {
                 let mut disconnected = BTreeSet::new();
                 for (machine_id, connection) in &mut daemon_connections {
-                    if connection.last_heartbeat.elapsed() > Duration::from_secs(15) {
+                    if connection.last_heartbeat.elapsed() > heartbeat.timeout / 2 {
                         tracing::warn!(
                             "no heartbeat message from machine `{machine_id}` since {:?}",
                             connection.last_heartbeat.elapsed()
                         )
                     }
-                    if connection.last_heartbeat.elapsed() > Duration::from_secs(30) {
+                    if connection.last_heartbeat.elapsed() > heartbeat.timeout {
                         disconnected.insert(machine_id.clone());
                         continue;
                     }
@@ -559,6 +886,21 @@ async fn start_inner(
                     tracing::error!("Disconnecting daemons that failed watchdog: {disconnected:?}");
                     for machine_id in disconnected {
                         daemon_connections.remove(&machine_id);
+                        event_subscribers.send_lifecycle(&LifecycleEvent::DaemonLost {
+                            machine: machine_id.clone(),
+                        });
+                        machine_lost(
+                            &machine_id,
+                            &heartbeat,
+                            &mut running_dataflows,
+                            &mut dataflow_results,
+                            &mut archived_dataflows,
+                            &mut event_subscribers,
+                            &mut daemon_connections,
+                            store.as_ref(),
+                            &clock,
+                        )
+                        .await;
                     }
                 }
             }
@@ -578,7 +920,18 @@ async fn start_inner(
                     connection.last_heartbeat = Instant::now();
                 }
             }
+            Event::NodeLogs {
+                dataflow_id,
+                node_id,
+                output,
+            } => {
+                log_store.append(dataflow_id, node_id, output);
+            }
+            Event::LogRetentionInterval => {
+                log_store.apply_retention(running_dataflows.keys().copied().collect());
+            }
             Event::Log(message) => {
+                event_subscribers.send_log(&message);
                 if let Some(dataflow) = running_dataflows.get_mut(&message.dataflow_id) {
                     for subscriber in &mut dataflow.log_subscribers {
                         let send_result = tokio::time::timeout(
@@ -601,6 +954,179 @@ async fn start_inner(
     Ok(())
 }
 
+/// Marks the given machine as ready for the dataflow and notifies all machines
+/// once the dataflow is ready everywhere.
+async fn ready_on_machine(
+    uuid: Uuid,
+    machine_id: &str,
+    exited_before_subscribe: Vec<NodeId>,
+    running_dataflows: &mut HashMap<Uuid, RunningDataflow>,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    clock: &HLC,
+) -> eyre::Result<()> {
+    let Some(dataflow) = running_dataflows.get_mut(&uuid) else {
+        tracing::warn!("dataflow not running on ReadyOnMachine");
+        return Ok(());
+    };
+    dataflow.pending_machines.remove(machine_id);
+    dataflow
+        .exited_before_subscribe
+        .extend(exited_before_subscribe);
+    if dataflow.pending_machines.is_empty() {
+        let message = serde_json::to_vec(&Timestamped {
+            inner: DaemonCoordinatorEvent::AllNodesReady {
+                dataflow_id: uuid,
+                exited_before_subscribe: dataflow.exited_before_subscribe.clone(),
+            },
+            timestamp: clock.new_timestamp(),
+        })
+        .wrap_err("failed to serialize AllNodesReady message")?;
+
+        // notify all machines that run parts of the dataflow
+        for machine_id in &dataflow.machines {
+            let Some(connection) = daemon_connections.get_mut(machine_id) else {
+                tracing::warn!("no daemon connection found for machine `{machine_id}`");
+                continue;
+            };
+            tcp_send(&mut connection.stream, &message)
+                .await
+                .wrap_err_with(|| {
+                    format!(
+                        "failed to send AllNodesReady({uuid}) message \
+                    to machine {machine_id}"
+                    )
+                })?;
+        }
+    }
+    Ok(())
+}
+
+/// Handles a machine whose daemon stopped responding to heartbeat messages.
+///
+/// The nodes on the lost machine are marked as failed and the other machines
+/// are notified, so that they close the inputs fed by the lost nodes.
+#[allow(clippy::too_many_arguments)]
+async fn machine_lost(
+    machine_id: &str,
+    heartbeat: &HeartbeatConfig,
+    running_dataflows: &mut HashMap<Uuid, RunningDataflow>,
+    dataflow_results: &mut HashMap<Uuid, BTreeMap<String, DataflowDaemonResult>>,
+    archived_dataflows: &mut HashMap<Uuid, ArchivedDataflow>,
+    event_subscribers: &mut EventSubscribers,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    store: Option<&Store>,
+    clock: &HLC,
+) {
+    let affected: Vec<_> = running_dataflows
+        .values()
+        .filter(|d| d.machines.contains(machine_id))
+        .map(|d| d.uuid)
+        .collect();
+    for uuid in affected {
+        tracing::warn!("machine `{machine_id}` of dataflow `{uuid}` was lost");
+        let dataflow = &running_dataflows[&uuid];
+        let lost_nodes: BTreeSet<NodeId> = dataflow
+            .nodes
+            .iter()
+            .filter(|node| node.deploy.machine == machine_id)
+            .map(|node| node.id.clone())
+            .collect();
+
+        let notify = async {
+            let message = serde_json::to_vec(&Timestamped {
+                inner: DaemonCoordinatorEvent::MachineLost {
+                    dataflow_id: uuid,
+                    machine_id: machine_id.to_owned(),
+                    nodes: lost_nodes.clone(),
+                },
+                timestamp: clock.new_timestamp(),
+            })?;
+            for other in dataflow.machines.iter().filter(|m| *m != machine_id) {
+                let Some(connection) = daemon_connections.get_mut(other) else {
+                    continue;
+                };
+                tcp_send(&mut connection.stream, &message)
+                    .await
+                    .wrap_err_with(|| format!("failed to notify machine `{other}`"))?;
+            }
+            Result::<_, eyre::Report>::Ok(())
+        };
+        if let Err(err) = notify.await {
+            tracing::warn!("{:?}", err.wrap_err("failed to send MachineLost message"));
+        }
+
+        // the lost nodes will never subscribe, so don't wait for them
+        if dataflow.pending_machines.contains(machine_id) {
+            let result = ready_on_machine(
+                uuid,
+                machine_id,
+                lost_nodes.iter().cloned().collect(),
+                running_dataflows,
+                daemon_connections,
+                clock,
+            )
+            .await;
+            if let Err(err) = result {
+                tracing::warn!("{err:?}");
+            }
+        }
+
+        let timestamp = clock.new_timestamp();
+        let dataflow_id = DataflowId {
+            uuid,
+            name: running_dataflows.get(&uuid).and_then(|d| d.name.clone()),
+        };
+        let node_results = lost_nodes
+            .into_iter()
+            .map(|node_id| {
+                let result = Err(NodeError {
+                    timestamp,
+                    cause: NodeErrorCause::MachineLost {
+                        machine_id: machine_id.to_owned(),
+                    },
+                    exit_status: NodeExitStatus::Unknown,
+                });
+                event_subscribers.send_lifecycle(&LifecycleEvent::NodeExited {
+                    dataflow: dataflow_id.clone(),
+                    node: node_id.clone(),
+                    result: result.clone(),
+                });
+                (node_id, result)
+            })
+            .collect();
+        dataflow_finished_on_machine(
+            uuid,
+            machine_id.to_owned(),
+            DataflowDaemonResult {
+                timestamp,
+                node_results,
+            },
+            running_dataflows,
+            dataflow_results,
+            archived_dataflows,
+            event_subscribers,
+            store,
+            clock,
+        );
+
+        if heartbeat.stop_on_machine_lost {
+            if let Some(dataflow) = running_dataflows.get(&uuid) {
+                let result = stop_dataflow(
+                    dataflow,
+                    uuid,
+                    daemon_connections,
+                    clock.new_timestamp(),
+                    None,
+                )
+                .await;
+                if let Err(err) = result {
+                    tracing::warn!("{:?}", err.wrap_err("failed to stop dataflow"));
+                }
+            }
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn stop_dataflow_by_uuid(
     running_dataflows: &mut HashMap<Uuid, RunningDataflow>,
@@ -621,7 +1147,9 @@ async fn stop_dataflow_by_uuid(
             let _ = reply_sender.send(Ok(reply));
             return Ok(());
         }
-        bail!("no known dataflow found with UUID `{dataflow_uuid}`")
+        return Err(not_found(format!(
+            "no known dataflow found with UUID `{dataflow_uuid}`"
+        )));
     };
     let stop = async {
         stop_dataflow(
@@ -645,13 +1173,300 @@ async fn stop_dataflow_by_uuid(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
+fn dataflow_finished_on_machine(
+    uuid: Uuid,
+    machine_id: String,
+    result: DataflowDaemonResult,
+    running_dataflows: &mut HashMap<Uuid, RunningDataflow>,
+    dataflow_results: &mut HashMap<Uuid, BTreeMap<String, DataflowDaemonResult>>,
+    archived_dataflows: &mut HashMap<Uuid, ArchivedDataflow>,
+    event_subscribers: &mut EventSubscribers,
+    store: Option<&Store>,
+    clock: &HLC,
+) {
+    match running_dataflows.entry(uuid) {
+        hash_map::Entry::Occupied(mut entry) => {
+            // Archive finished dataflow
+            if archived_dataflows.get(&uuid).is_none() {
+                archived_dataflows.insert(uuid, ArchivedDataflow::from(entry.get()));
+            }
+            entry.get_mut().machines.remove(&machine_id);
+            let results = dataflow_results.entry(uuid).or_default();
+            results.insert(machine_id, result);
+            persist_dataflow(store, entry.get(), Some(results));
+            if entry.get_mut().machines.is_empty() {
+                let finished_dataflow = entry.remove();
+                let result = dataflow_result(results, uuid, clock);
+                event_subscribers.send_lifecycle(&LifecycleEvent::DataflowFinished {
+                    dataflow: DataflowId {
+                        uuid,
+                        name: finished_dataflow.name.clone(),
+                    },
+                    result: result.clone(),
+                });
+                event_subscribers.dataflow_finished(uuid);
+                let reply = ControlRequestReply::DataflowStopped { uuid, result };
+                for sender in finished_dataflow.reply_senders {
+                    let _ = sender.send(Ok(reply.clone()));
+                }
+            }
+        }
+        hash_map::Entry::Vacant(_) => {
+            tracing::warn!("dataflow not running on DataflowFinishedOnMachine");
+        }
+    }
+}
+
+/// Compares the dataflows that a newly registered daemon reports as running with
+/// the coordinator state.
+///
+/// Dataflows that the coordinator assumes to be running on the machine, but that
+/// the daemon doesn't know about, are marked as finished with an error. This is
+/// the case if the daemon was restarted while the coordinator was down.
+#[allow(clippy::too_many_arguments)]
+fn reconcile_daemon_dataflows(
+    machine_id: &str,
+    dataflows_on_daemon: &BTreeSet<Uuid>,
+    running_dataflows: &mut HashMap<Uuid, RunningDataflow>,
+    dataflow_results: &mut HashMap<Uuid, BTreeMap<String, DataflowDaemonResult>>,
+    archived_dataflows: &mut HashMap<Uuid, ArchivedDataflow>,
+    event_subscribers: &mut EventSubscribers,
+    store: Option<&Store>,
+    clock: &HLC,
+) {
+    for uuid in dataflows_on_daemon {
+        if !running_dataflows.contains_key(uuid) {
+            tracing::warn!("machine `{machine_id}` reports unknown running dataflow `{uuid}`");
+        }
+    }
+
+    let lost: Vec<_> = running_dataflows
+        .values()
+        .filter(|d| d.machines.contains(machine_id) && !dataflows_on_daemon.contains(&d.uuid))
+        .map(|d| d.uuid)
+        .collect();
+    for uuid in lost {
+        tracing::warn!("dataflow `{uuid}` is no longer running on machine `{machine_id}`");
+        let timestamp = clock.new_timestamp();
+        let node_results = running_dataflows[&uuid]
+            .nodes
+            .iter()
+            .filter(|node| node.deploy.machine == machine_id)
+            .map(|node| {
+                let error = NodeError {
+                    timestamp,
+                    cause: NodeErrorCause::Other {
+                        stderr: format!(
+                            "node was lost because machine `{machine_id}` \
+                            no longer runs the dataflow\n"
+                        ),
+                    },
+                    exit_status: NodeExitStatus::Unknown,
+                };
+                (node.id.clone(), Err(error))
+            })
+            .collect();
+        dataflow_finished_on_machine(
+            uuid,
+            machine_id.to_owned(),
+            DataflowDaemonResult {
+                timestamp,
+                node_results,
+            },
+            running_dataflows,
+            dataflow_results,
+            archived_dataflows,
+            event_subscribers,
+            store,
+            clock,
+        );
+    }
+}
+
+fn inspect_dataflow(
+    uuid: Uuid,
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+    dataflow_results: &HashMap<Uuid, BTreeMap<String, DataflowDaemonResult>>,
+    archived_dataflows: &HashMap<Uuid, ArchivedDataflow>,
+    clock: &HLC,
+) -> eyre::Result<DataflowInfo> {
+    let (name, nodes) = if let Some(dataflow) = running_dataflows.get(&uuid) {
+        (&dataflow.name, &dataflow.nodes)
+    } else if let Some(dataflow) = archived_dataflows.get(&uuid) {
+        (&dataflow.name, &dataflow.nodes)
+    } else {
+        return Err(not_found(format!("no dataflow with UUID `{uuid}`")));
+    };
+    let result = match dataflow_results.get(&uuid) {
+        Some(results) if !running_dataflows.contains_key(&uuid) => {
+            Some(dataflow_result(results, uuid, clock))
+        }
+        _ => None,
+    };
+    let status = match &result {
+        None => DataflowStatus::Running,
+        Some(result) if result.is_ok() => DataflowStatus::Finished,
+        Some(_) => DataflowStatus::Failed,
+    };
+    Ok(DataflowInfo {
+        id: DataflowId {
+            uuid,
+            name: name.clone(),
+        },
+        status,
+        nodes: nodes
+            .iter()
+            .map(|node| NodeInfo {
+                id: node.id.clone(),
+                machine: node.deploy.machine.clone(),
+            })
+            .collect(),
+        result,
+    })
+}
+
+/// Writes the current state of the given dataflow to the store, if any.
+fn persist_dataflow(
+    store: Option<&Store>,
+    dataflow: &RunningDataflow,
+    results: Option<&BTreeMap<String, DataflowDaemonResult>>,
+) {
+    let Some(store) = store else { return };
+    let results = results.cloned().unwrap_or_default();
+    let stored = StoredDataflow {
+        uuid: dataflow.uuid,
+        name: dataflow.name.clone(),
+        descriptor: dataflow.descriptor.clone(),
+        working_dir: dataflow.working_dir.clone(),
+        nodes: dataflow.nodes.clone(),
+        // finished machines are removed from `machines`, so we add them back
+        machines: dataflow
+            .machines
+            .iter()
+            .chain(results.keys())
+            .cloned()
+            .collect(),
+        results,
+    };
+    if let Err(err) = store.save_dataflow(&stored) {
+        tracing::warn!(
+            "{:?}",
+            err.wrap_err(format!("failed to persist dataflow `{}`", dataflow.uuid))
+        );
+    }
+}
+
+fn restore_dataflow(
+    stored: StoredDataflow,
+    running_dataflows: &mut HashMap<Uuid, RunningDataflow>,
+    dataflow_results: &mut HashMap<Uuid, BTreeMap<String, DataflowDaemonResult>>,
+    archived_dataflows: &mut HashMap<Uuid, ArchivedDataflow>,
+) {
+    let finished = stored.is_finished();
+    let StoredDataflow {
+        uuid,
+        name,
+        descriptor,
+        working_dir,
+        nodes,
+        machines,
+        results,
+    } = stored;
+
+    if finished || !results.is_empty() {
+        archived_dataflows.insert(
+            uuid,
+            ArchivedDataflow {
+                name: name.clone(),
+                nodes: nodes.clone(),
+            },
+        );
+    }
+    if !finished {
+        let machines = machines
+            .into_iter()
+            .filter(|m| !results.contains_key(m))
+            .collect();
+        running_dataflows.insert(
+            uuid,
+            RunningDataflow {
+                name,
+                uuid,
+                descriptor,
+                working_dir,
+                machines,
+                // the dataflow was already started before the restart
+                pending_machines: BTreeSet::new(),
+                exited_before_subscribe: Vec::new(),
+                nodes,
+                reply_senders: Vec::new(),
+                log_subscribers: Vec::new(),
+            },
+        );
+    }
+    if !results.is_empty() {
+        dataflow_results.insert(uuid, results);
+    }
+}
+
+/// Returns the lifecycle event for a node that was added to a running dataflow.
+///
+/// Nodes that replace a removed node with the same ID are reported as restarted.
+fn added_node_event(
+    dataflow: DataflowId,
+    node: &ResolvedNode,
+    previous_nodes: &BTreeSet<NodeId>,
+) -> LifecycleEvent {
+    let node_id = node.id.clone();
+    let machine = node.deploy.machine.clone();
+    if previous_nodes.contains(&node.id) {
+        LifecycleEvent::NodeRestarted {
+            dataflow,
+            node: node_id,
+            machine,
+        }
+    } else {
+        LifecycleEvent::NodeSpawned {
+            dataflow,
+            node: node_id,
+            machine,
+        }
+    }
+}
+
+/// Returns the CPUs per machine that are reserved by the running nodes of all
+/// dataflows, except for the given one.
+fn reserved_cpus(
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+    except: Option<Uuid>,
+) -> BTreeMap<String, u32> {
+    let mut reserved = BTreeMap::new();
+    for dataflow in running_dataflows.values() {
+        if Some(dataflow.uuid) == except {
+            continue;
+        }
+        for node in &dataflow.descriptor.nodes {
+            let Some(cpus) = node.deploy.cpus else {
+                continue;
+            };
+            // removed nodes are still part of the descriptor
+            let Some(running) = dataflow.nodes.iter().find(|n| n.id == node.id) else {
+                continue;
+            };
+            *reserved.entry(running.deploy.machine.clone()).or_default() += cpus;
+        }
+    }
+    reserved
+}
+
 fn dataflow_result(
     results: &BTreeMap<String, DataflowDaemonResult>,
     dataflow_uuid: Uuid,
     clock: &uhlc::HLC,
 ) -> DataflowResult {
     let mut node_results = BTreeMap::new();
-    for (_machine, result) in results {
+    for result in results.values() {
         node_results.extend(result.node_results.clone());
         if let Err(err) = clock.update_with_timestamp(&result.timestamp) {
             tracing::warn!("failed to update HLC: {err}");
@@ -666,9 +1481,11 @@ fn dataflow_result(
 }
 
 struct DaemonConnection {
-    stream: TcpStream,
+    stream: Connection,
     listen_socket: SocketAddr,
     last_heartbeat: Instant,
+    /// Labels that the daemon registered with, used for placing nodes.
+    labels: BTreeMap<String, String>,
 }
 
 async fn handle_destroy(
@@ -695,7 +1512,7 @@ async fn handle_destroy(
 }
 
 async fn send_heartbeat_message(
-    connection: &mut TcpStream,
+    connection: &mut Connection,
     timestamp: uhlc::Timestamp,
 ) -> eyre::Result<()> {
     let message = serde_json::to_vec(&Timestamped {
@@ -712,6 +1529,8 @@ async fn send_heartbeat_message(
 struct RunningDataflow {
     name: Option<String>,
     uuid: Uuid,
+    descriptor: Descriptor,
+    working_dir: PathBuf,
     /// The IDs of the machines that the dataflow is running on.
     machines: BTreeSet<String>,
     /// IDs of machines that are waiting until all nodes are started.
@@ -796,7 +1615,9 @@ async fn reload_dataflow(
     timestamp: uhlc::Timestamp,
 ) -> eyre::Result<()> {
     let Some(dataflow) = running_dataflows.get(&dataflow_id) else {
-        bail!("No running dataflow found with UUID `{dataflow_id}`")
+        return Err(not_found(format!(
+            "No running dataflow found with UUID `{dataflow_id}`"
+        )));
     };
     let message = serde_json::to_vec(&Timestamped {
         inner: DaemonCoordinatorEvent::ReloadDataflow {
@@ -833,20 +1654,244 @@ async fn reload_dataflow(
     Ok(())
 }
 
+/// Adds the nodes of the given descriptor fragment to a running dataflow.
+///
+/// Returns the added nodes.
+async fn add_nodes(
+    dataflow: &mut RunningDataflow,
+    fragment: Descriptor,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    reserved_cpus: &BTreeMap<String, u32>,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<Vec<ResolvedNode>> {
+    let dataflow_id = dataflow.uuid;
+    if !dataflow.pending_machines.is_empty() {
+        return Err(invalid_request(format!(
+            "dataflow `{dataflow_id}` is still starting"
+        )));
+    }
+    let (plan, added) = plan_added_nodes(
+        &dataflow.descriptor,
+        &dataflow.nodes,
+        &dataflow.machines,
+        fragment,
+        &dataflow.working_dir,
+        daemon_connections,
+        reserved_cpus,
+    )?;
+    let added_nodes: Vec<_> = plan
+        .nodes
+        .iter()
+        .filter(|node| added.contains(&node.id))
+        .cloned()
+        .collect();
+    let target_machines: BTreeSet<_> = added_nodes
+        .iter()
+        .map(|node| node.deploy.machine.clone())
+        .collect();
+    if let Some(machine) = target_machines
+        .iter()
+        .find(|m| !dataflow.machines.contains(*m))
+    {
+        return Err(invalid_request(format!(
+            "dataflow `{dataflow_id}` is not running on machine `{machine}`"
+        )));
+    }
+
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::AddNodes {
+            dataflow_id,
+            nodes: plan.nodes.clone(),
+            added: added.clone(),
+            dataflow_descriptor: plan.descriptor.clone(),
+        },
+        timestamp,
+    })?;
+
+    // update the other machines first, so that they know the new inputs
+    // before the added nodes start sending
+    let (targets, others): (Vec<_>, Vec<_>) = dataflow
+        .machines
+        .iter()
+        .partition(|m| target_machines.contains(*m));
+    let mut updated = Vec::new();
+    for machine_id in others.into_iter().chain(targets) {
+        if let Err(err) = add_nodes_on_machine(daemon_connections, machine_id, &message).await {
+            // the failed daemon undoes its changes itself
+            let rollback = serde_json::to_vec(&Timestamped {
+                inner: DaemonCoordinatorEvent::RemoveNodes {
+                    dataflow_id,
+                    nodes: added,
+                    grace_duration: None,
+                },
+                timestamp,
+            })?;
+            for machine_id in updated {
+                if let Err(err) =
+                    remove_nodes_on_machine(daemon_connections, machine_id, &rollback).await
+                {
+                    tracing::warn!(
+                        "failed to roll back added nodes on machine `{machine_id}`: {err:?}"
+                    );
+                }
+            }
+            return Err(err);
+        }
+        updated.push(machine_id);
+    }
+    tracing::info!("successfully added nodes to dataflow `{dataflow_id}`");
+
+    dataflow.descriptor = plan.descriptor;
+    dataflow.nodes = plan.nodes;
+    Ok(added_nodes)
+}
+
+/// Stops the given nodes of a running dataflow and removes them.
+async fn remove_nodes(
+    dataflow: &mut RunningDataflow,
+    nodes: BTreeSet<NodeId>,
+    grace_duration: Option<Duration>,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<()> {
+    let dataflow_id = dataflow.uuid;
+    if let Some(missing) = nodes
+        .iter()
+        .find(|id| !dataflow.nodes.iter().any(|n| &n.id == *id))
+    {
+        return Err(not_found(format!(
+            "dataflow `{dataflow_id}` has no node `{missing}`"
+        )));
+    }
+
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::RemoveNodes {
+            dataflow_id,
+            nodes: nodes.clone(),
+            grace_duration,
+        },
+        timestamp,
+    })?;
+    // send to all machines, so that a failing machine doesn't keep the nodes
+    // on the other machines running
+    let mut failed = BTreeMap::new();
+    for machine_id in &dataflow.machines {
+        if let Err(err) = remove_nodes_on_machine(daemon_connections, machine_id, &message).await {
+            failed.insert(machine_id.clone(), err);
+        }
+    }
+
+    // removed nodes stay in the descriptor because the inputs of other nodes
+    // might still reference them
+    dataflow
+        .nodes
+        .retain(|node| !nodes.contains(&node.id) || failed.contains_key(&node.deploy.machine));
+    if failed.is_empty() {
+        tracing::info!("successfully removed nodes from dataflow `{dataflow_id}`");
+        Ok(())
+    } else {
+        let errors: Vec<_> = failed.values().map(|err| format!("{err:?}")).collect();
+        bail!(
+            "failed to remove nodes on {} of {} machines:\n\n{}",
+            failed.len(),
+            dataflow.machines.len(),
+            errors.join("\n\n")
+        )
+    }
+}
+
+async fn add_nodes_on_machine(
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    machine_id: &str,
+    message: &[u8],
+) -> eyre::Result<()> {
+    let daemon_connection = daemon_connections
+        .get_mut(machine_id)
+        .wrap_err_with(|| format!("no daemon connection for machine `{machine_id}`"))?;
+    tcp_send(&mut daemon_connection.stream, message)
+        .await
+        .wrap_err("failed to send add nodes message to daemon")?;
+
+    // wait for reply
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive add nodes reply from daemon")?;
+    match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize add nodes reply from daemon")?
+    {
+        DaemonCoordinatorReply::AddNodesResult(result) => result
+            .map_err(|e| eyre!(e))
+            .wrap_err_with(|| format!("failed to add nodes on machine `{machine_id}`")),
+        other => bail!("unexpected reply after sending add nodes: {other:?}"),
+    }
+}
+
+async fn remove_nodes_on_machine(
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    machine_id: &str,
+    message: &[u8],
+) -> eyre::Result<()> {
+    let daemon_connection = daemon_connections
+        .get_mut(machine_id)
+        .wrap_err_with(|| format!("no daemon connection for machine `{machine_id}`"))?;
+    tcp_send(&mut daemon_connection.stream, message)
+        .await
+        .wrap_err("failed to send remove nodes message to daemon")?;
+
+    // wait for reply
+    let reply_raw = tcp_receive(&mut daemon_connection.stream)
+        .await
+        .wrap_err("failed to receive remove nodes reply from daemon")?;
+    match serde_json::from_slice(&reply_raw)
+        .wrap_err("failed to deserialize remove nodes reply from daemon")?
+    {
+        DaemonCoordinatorReply::RemoveNodesResult(result) => result
+            .map_err(|e| eyre!(e))
+            .wrap_err_with(|| format!("failed to remove nodes on machine `{machine_id}`")),
+        other => bail!("unexpected reply after sending remove nodes: {other:?}"),
+    }
+}
+
+/// Removes structured log records that are less severe than the given level.
+fn filter_logs(
+    logs: &[u8],
+    dataflow_id: Uuid,
+    node_id: &NodeId,
+    level: log::LevelFilter,
+) -> Vec<u8> {
+    String::from_utf8_lossy(logs)
+        .split_inclusive('\n')
+        .filter(|line| {
+            LogMessage::from_json_line(dataflow_id, Some(node_id.clone()), line)
+                .map_or(true, |message| message.level <= level)
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
 async fn retrieve_logs(
     running_dataflows: &HashMap<Uuid, RunningDataflow>,
     archived_dataflows: &HashMap<Uuid, ArchivedDataflow>,
     dataflow_id: Uuid,
     node_id: NodeId,
     daemon_connections: &mut HashMap<String, DaemonConnection>,
+    log_store: &LogStore,
     timestamp: uhlc::Timestamp,
 ) -> eyre::Result<Vec<u8>> {
+    // logs that were sent to the coordinator are available even if the
+    // dataflow is no longer known or its machine is offline
+    if let Some(logs) = log_store.read(dataflow_id, &node_id).await? {
+        return Ok(logs);
+    }
+
     let nodes = if let Some(dataflow) = archived_dataflows.get(&dataflow_id) {
         dataflow.nodes.clone()
     } else if let Some(dataflow) = running_dataflows.get(&dataflow_id) {
         dataflow.nodes.clone()
     } else {
-        bail!("No dataflow found with UUID `{dataflow_id}`")
+        return Err(not_found(format!(
+            "No dataflow found with UUID `{dataflow_id}`"
+        )));
     };
 
     let message = serde_json::to_vec(&Timestamped {
@@ -866,7 +1911,10 @@ async fn retrieve_logs(
     let machine_id = if let [machine_id] = &machine_ids[..] {
         machine_id
     } else if machine_ids.is_empty() {
-        bail!("No machine contains {}/{}", dataflow_id, node_id)
+        return Err(not_found(format!(
+            "No machine contains {}/{}",
+            dataflow_id, node_id
+        )));
     } else {
         bail!(
             "More than one machine contains {}/{}. However, it should only be present on one.",
@@ -897,21 +1945,74 @@ async fn retrieve_logs(
     reply_logs.map_err(|err| eyre!(err))
 }
 
+/// Requests the statistics of the given dataflow from all of its machines.
+async fn retrieve_stats(
+    running_dataflows: &HashMap<Uuid, RunningDataflow>,
+    dataflow_id: Uuid,
+    daemon_connections: &mut HashMap<String, DaemonConnection>,
+    timestamp: uhlc::Timestamp,
+) -> eyre::Result<BTreeMap<String, DataflowDaemonStats>> {
+    let Some(dataflow) = running_dataflows.get(&dataflow_id) else {
+        return Err(not_found(format!(
+            "No running dataflow found with UUID `{dataflow_id}`"
+        )));
+    };
+    let message = serde_json::to_vec(&Timestamped {
+        inner: DaemonCoordinatorEvent::Stats { dataflow_id },
+        timestamp,
+    })?;
+
+    let mut stats = BTreeMap::new();
+    for machine_id in &dataflow.machines {
+        let Some(daemon_connection) = daemon_connections.get_mut(machine_id.as_str()) else {
+            tracing::warn!("no daemon connection to machine `{machine_id}`, skipping its stats");
+            continue;
+        };
+        tcp_send(&mut daemon_connection.stream, &message)
+            .await
+            .wrap_err("failed to send stats message to daemon")?;
+        let reply_raw = tcp_receive(&mut daemon_connection.stream)
+            .await
+            .wrap_err("failed to retrieve stats reply from daemon")?;
+        match serde_json::from_slice(&reply_raw)
+            .wrap_err("failed to deserialize stats reply from daemon")?
+        {
+            DaemonCoordinatorReply::Stats(result) => {
+                let machine_stats = result.map_err(|err| eyre!(err))?;
+                stats.insert(machine_id.clone(), machine_stats);
+            }
+            other => bail!("unexpected reply after sending stats: {other:?}"),
+        }
+    }
+    Ok(stats)
+}
+
 async fn start_dataflow(
     dataflow: Descriptor,
     working_dir: PathBuf,
     name: Option<String>,
     daemon_connections: &mut HashMap<String, DaemonConnection>,
+    reserved_cpus: &BTreeMap<String, u32>,
     clock: &HLC,
 ) -> eyre::Result<RunningDataflow> {
     let SpawnedDataflow {
         uuid,
+        descriptor,
         machines,
         nodes,
-    } = spawn_dataflow(dataflow, working_dir, daemon_connections, clock).await?;
+    } = spawn_dataflow(
+        dataflow,
+        working_dir.clone(),
+        daemon_connections,
+        reserved_cpus,
+        clock,
+    )
+    .await?;
     Ok(RunningDataflow {
         uuid,
         name,
+        descriptor,
+        working_dir,
         pending_machines: if machines.len() > 1 {
             machines.clone()
         } else {
@@ -962,13 +2063,25 @@ async fn destroy_daemons(
 pub enum Event {
     NewDaemonConnection(TcpStream),
     DaemonConnectError(eyre::Report),
-    DaemonHeartbeat { machine_id: String },
-    Dataflow { uuid: Uuid, event: DataflowEvent },
+    DaemonHeartbeat {
+        machine_id: String,
+    },
+    Dataflow {
+        uuid: Uuid,
+        event: DataflowEvent,
+    },
     Control(ControlEvent),
     Daemon(DaemonEvent),
     DaemonHeartbeatInterval,
     CtrlC,
     Log(LogMessage),
+    /// Output of a node, which is appended to the stored logs.
+    NodeLogs {
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        output: String,
+    },
+    LogRetentionInterval,
 }
 
 impl Event {
@@ -976,7 +2089,9 @@ impl Event {
     #[allow(clippy::match_like_matches_macro)]
     pub fn log(&self) -> bool {
         match self {
-            Event::DaemonHeartbeatInterval => false,
+            Event::DaemonHeartbeatInterval
+            | Event::NodeLogs { .. }
+            | Event::LogRetentionInterval => false,
             _ => true,
         }
     }
@@ -992,6 +2107,13 @@ pub enum DataflowEvent {
         machine_id: String,
         exited_before_subscribe: Vec<NodeId>,
     },
+    NodeReady {
+        node_id: NodeId,
+    },
+    NodeExited {
+        node_id: NodeId,
+        result: Result<(), NodeError>,
+    },
 }
 
 #[derive(Debug)]
@@ -999,8 +2121,10 @@ pub enum DaemonEvent {
     Register {
         dora_version: String,
         machine_id: String,
-        connection: TcpStream,
+        connection: Connection,
         listen_port: u16,
+        running_dataflows: BTreeSet<Uuid>,
+        labels: BTreeMap<String, String>,
     },
 }
 
@@ -1025,3 +2149,352 @@ fn set_up_ctrlc_handler() -> Result<impl Stream<Item = Event>, eyre::ErrReport>
 
     Ok(ReceiverStream::new(ctrlc_rx))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a running dataflow with one node per given machine.
+    fn running_dataflow(machines: &[&str]) -> RunningDataflow {
+        let nodes: Vec<_> = machines
+            .iter()
+            .map(|machine| {
+                serde_json::json!({
+                    "id": format!("node-{machine}"),
+                    "path": "dynamic",
+                    "_unstable_deploy": { "machine": machine },
+                })
+            })
+            .collect();
+        let descriptor: Descriptor =
+            serde_json::from_value(serde_json::json!({ "nodes": nodes })).unwrap();
+        let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+        let machines: BTreeSet<_> = machines.iter().map(|m| m.to_string()).collect();
+        RunningDataflow {
+            name: None,
+            uuid: Uuid::now_v7(),
+            descriptor,
+            working_dir: PathBuf::new(),
+            pending_machines: machines.clone(),
+            machines,
+            exited_before_subscribe: Vec::new(),
+            nodes,
+            reply_senders: Vec::new(),
+            log_subscribers: Vec::new(),
+        }
+    }
+
+    struct State {
+        running_dataflows: HashMap<Uuid, RunningDataflow>,
+        dataflow_results: HashMap<Uuid, BTreeMap<String, DataflowDaemonResult>>,
+        archived_dataflows: HashMap<Uuid, ArchivedDataflow>,
+        event_subscribers: EventSubscribers,
+        clock: HLC,
+    }
+
+    impl State {
+        fn new(dataflow: RunningDataflow) -> Self {
+            Self {
+                running_dataflows: [(dataflow.uuid, dataflow)].into_iter().collect(),
+                dataflow_results: HashMap::new(),
+                archived_dataflows: HashMap::new(),
+                event_subscribers: EventSubscribers::default(),
+                clock: HLC::default(),
+            }
+        }
+
+        async fn machine_lost(&mut self, machine_id: &str, heartbeat: HeartbeatConfig) {
+            machine_lost(
+                machine_id,
+                &heartbeat,
+                &mut self.running_dataflows,
+                &mut self.dataflow_results,
+                &mut self.archived_dataflows,
+                &mut self.event_subscribers,
+                &mut HashMap::new(),
+                None,
+                &self.clock,
+            )
+            .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn lost_machine_fails_its_nodes() {
+        let dataflow = running_dataflow(&["A", "B"]);
+        let uuid = dataflow.uuid;
+        let mut state = State::new(dataflow);
+
+        state.machine_lost("A", HeartbeatConfig::default()).await;
+
+        let dataflow = &state.running_dataflows[&uuid];
+        assert_eq!(dataflow.machines, ["B".to_string()].into());
+        assert_eq!(dataflow.pending_machines, ["B".to_string()].into());
+        assert_eq!(
+            dataflow.exited_before_subscribe,
+            vec![NodeId::from("node-A".to_string())]
+        );
+
+        let results = &state.dataflow_results[&uuid]["A"];
+        let error = results.node_results[&NodeId::from("node-A".to_string())]
+            .as_ref()
+            .unwrap_err();
+        assert!(matches!(
+            &error.cause,
+            NodeErrorCause::MachineLost { machine_id } if machine_id == "A"
+        ));
+        assert!(!state.dataflow_results[&uuid].contains_key("B"));
+    }
+
+    #[tokio::test]
+    async fn dataflow_finishes_when_its_only_machine_is_lost() {
+        let dataflow = running_dataflow(&["A"]);
+        let uuid = dataflow.uuid;
+        let mut state = State::new(dataflow);
+        let (sender, mut receiver) = mpsc::channel(10);
+        state
+            .event_subscribers
+            .add(Some(uuid), log::LevelFilter::Off, sender);
+
+        state.machine_lost("A", HeartbeatConfig::default()).await;
+
+        assert!(state.running_dataflows.is_empty());
+        assert!(state.archived_dataflows.contains_key(&uuid));
+        let mut finished = false;
+        while let Ok(message) = receiver.try_recv() {
+            finished |= message.contains("dataflow_finished") && message.contains("MachineLost");
+        }
+        assert!(finished, "no failed DataflowFinished event");
+    }
+
+    #[test]
+    fn reserved_cpus_only_count_running_nodes_of_other_dataflows() {
+        let mut first = running_dataflow(&["A", "B"]);
+        for node in &mut first.descriptor.nodes {
+            node.deploy.cpus = Some(2);
+        }
+        // removed nodes stay in the descriptor
+        first.nodes.retain(|n| n.deploy.machine != "B");
+        let mut second = running_dataflow(&["A"]);
+        second.descriptor.nodes[0].deploy.cpus = Some(3);
+        let (first_id, second_id) = (first.uuid, second.uuid);
+        let running: HashMap<_, _> = [(first_id, first), (second_id, second)].into();
+
+        assert_eq!(reserved_cpus(&running, None), [("A".to_string(), 5)].into());
+        assert_eq!(
+            reserved_cpus(&running, Some(second_id)),
+            [("A".to_string(), 2)].into()
+        );
+    }
+
+    #[test]
+    fn re_added_nodes_are_reported_as_restarted() {
+        let dataflow = running_dataflow(&["A", "B"]);
+        let id = DataflowId {
+            uuid: dataflow.uuid,
+            name: None,
+        };
+        let previous_nodes = [NodeId::from("node-A".to_string())].into();
+
+        let event = added_node_event(id.clone(), &dataflow.nodes[0], &previous_nodes);
+        assert!(matches!(
+            event,
+            LifecycleEvent::NodeRestarted { node, machine, .. }
+                if node.to_string() == "node-A" && machine == "A"
+        ));
+        let event = added_node_event(id, &dataflow.nodes[1], &previous_nodes);
+        assert!(matches!(
+            event,
+            LifecycleEvent::NodeSpawned { node, .. } if node.to_string() == "node-B"
+        ));
+    }
+
+    /// Connects a fake daemon for every given machine that answers `AddNodes`
+    /// and `RemoveNodes` requests with the given result.
+    ///
+    /// The returned tasks finish with the received events once the daemon
+    /// connections are dropped.
+    async fn fake_daemons(
+        machines: &[(&str, Result<(), String>)],
+    ) -> (
+        HashMap<String, DaemonConnection>,
+        Vec<JoinHandle<Vec<&'static str>>>,
+    ) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut connections = HashMap::new();
+        let mut daemons = Vec::new();
+        for (machine_id, result) in machines {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let (mut daemon, _) = listener.accept().await.unwrap();
+            let result = result.clone();
+            daemons.push(tokio::spawn(async move {
+                let mut received = Vec::new();
+                while let Ok(raw) = tcp_receive(&mut daemon).await {
+                    let event: Timestamped<DaemonCoordinatorEvent> =
+                        serde_json::from_slice(&raw).unwrap();
+                    let reply = match event.inner {
+                        DaemonCoordinatorEvent::AddNodes { .. } => {
+                            received.push("add");
+                            DaemonCoordinatorReply::AddNodesResult(result.clone())
+                        }
+                        DaemonCoordinatorEvent::RemoveNodes { .. } => {
+                            received.push("remove");
+                            DaemonCoordinatorReply::RemoveNodesResult(result.clone())
+                        }
+                        other => panic!("unexpected event {other:?}"),
+                    };
+                    tcp_send(&mut daemon, &serde_json::to_vec(&reply).unwrap())
+                        .await
+                        .unwrap();
+                }
+                received
+            }));
+            let connection = DaemonConnection {
+                stream: Connection::Tcp(stream),
+                listen_socket: addr,
+                last_heartbeat: Instant::now(),
+                labels: [("camera".to_string(), "true".to_string())].into(),
+            };
+            connections.insert(machine_id.to_string(), connection);
+        }
+        (connections, daemons)
+    }
+
+    fn fragment(nodes: serde_json::Value) -> Descriptor {
+        serde_json::from_value(serde_json::json!({ "nodes": nodes })).unwrap()
+    }
+
+    async fn received_events(
+        connections: HashMap<String, DaemonConnection>,
+        daemons: Vec<JoinHandle<Vec<&'static str>>>,
+    ) -> Vec<Vec<&'static str>> {
+        drop(connections);
+        let mut received = Vec::new();
+        for daemon in daemons {
+            received.push(daemon.await.unwrap());
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn failed_add_nodes_is_rolled_back() {
+        let mut dataflow = running_dataflow(&["A", "B"]);
+        dataflow.pending_machines.clear();
+        let (mut connections, daemons) =
+            fake_daemons(&[("A", Ok(())), ("B", Err("spawn failed".into()))]).await;
+        let nodes = fragment(serde_json::json!([
+            { "id": "added", "path": "dynamic", "_unstable_deploy": { "machine": "B" } },
+        ]));
+
+        let err = add_nodes(
+            &mut dataflow,
+            nodes,
+            &mut connections,
+            &BTreeMap::new(),
+            HLC::default().new_timestamp(),
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{err:?}").contains("spawn failed"), "{err:?}");
+        assert_eq!(dataflow.nodes.len(), 2);
+        assert!(!dataflow
+            .descriptor
+            .nodes
+            .iter()
+            .any(|n| n.id.as_ref() == "added"));
+
+        // the failed daemon undoes its changes itself
+        let received = received_events(connections, daemons).await;
+        assert_eq!(received, vec![vec!["add", "remove"], vec!["add"]]);
+    }
+
+    #[tokio::test]
+    async fn added_nodes_are_placed_on_machines_of_the_dataflow() {
+        let mut dataflow = running_dataflow(&["A", "B"]);
+        dataflow.pending_machines.clear();
+        // `C` runs no nodes yet, so it would be preferred otherwise
+        let (mut connections, daemons) =
+            fake_daemons(&[("A", Ok(())), ("B", Ok(())), ("C", Ok(()))]).await;
+        let nodes = fragment(serde_json::json!([
+            { "id": "camera", "path": "dynamic", "_unstable_deploy": { "selector": { "camera": true } } },
+        ]));
+
+        let added = add_nodes(
+            &mut dataflow,
+            nodes,
+            &mut connections,
+            &BTreeMap::new(),
+            HLC::default().new_timestamp(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(added.len(), 1);
+        assert!(dataflow.machines.contains(&added[0].deploy.machine));
+        assert_eq!(dataflow.nodes.len(), 3);
+
+        let received = received_events(connections, daemons).await;
+        assert_eq!(received, vec![vec!["add"], vec!["add"], vec![]]);
+    }
+
+    #[tokio::test]
+    async fn remove_nodes_continues_after_failed_machines() {
+        let mut dataflow = running_dataflow(&["A", "B"]);
+        let (mut connections, daemons) =
+            fake_daemons(&[("A", Err("daemon busy".into())), ("B", Ok(()))]).await;
+        let nodes = dataflow.nodes.iter().map(|n| n.id.clone()).collect();
+
+        let err = remove_nodes(
+            &mut dataflow,
+            nodes,
+            None,
+            &mut connections,
+            HLC::default().new_timestamp(),
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{err:?}").contains("daemon busy"), "{err:?}");
+        // only the nodes of the failed machine are kept
+        let remaining: Vec<_> = dataflow.nodes.iter().map(|n| n.id.to_string()).collect();
+        assert_eq!(remaining, vec!["node-A"]);
+
+        let received = received_events(connections, daemons).await;
+        assert_eq!(received, vec![vec!["remove"], vec!["remove"]]);
+    }
+
+    #[tokio::test]
+    async fn other_machines_are_not_affected() {
+        let dataflow = running_dataflow(&["A"]);
+        let uuid = dataflow.uuid;
+        let mut state = State::new(dataflow);
+
+        state.machine_lost("B", HeartbeatConfig::default()).await;
+
+        assert!(state.running_dataflows.contains_key(&uuid));
+        assert!(state.dataflow_results.is_empty());
+    }
+
+    #[test]
+    fn filter_logs_keeps_unstructured_lines() {
+        let logs = concat!(
+            "plain output\n",
+            "{\"level\":\"DEBUG\",\"fields\":{\"message\":\"details\"}}\n",
+            "{\"levelname\":\"WARNING\",\"msg\":\"slow\"}\n",
+            "{\"level\":\"ERROR\",\"fields\":{\"message\":\"failed\"}}",
+        );
+        let filtered = filter_logs(
+            logs.as_bytes(),
+            Uuid::now_v7(),
+            &"node".to_string().into(),
+            log::LevelFilter::Warn,
+        );
+        assert_eq!(
+            String::from_utf8(filtered).unwrap(),
+            concat!(
+                "plain output\n",
+                "{\"levelname\":\"WARNING\",\"msg\":\"slow\"}\n",
+                "{\"level\":\"ERROR\",\"fields\":{\"message\":\"failed\"}}",
+            )
+        );
+    }
+}
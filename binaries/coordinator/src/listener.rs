@@ -1,5 +1,13 @@
-use crate::{tcp_utils::tcp_receive, DaemonEvent, DataflowEvent, Event};
-use dora_core::{coordinator_messages, daemon_messages::Timestamped, message::uhlc::HLC};
+use crate::{
+    security::Security,
+    tcp_utils::{tcp_receive, tcp_send},
+    DaemonEvent, DataflowEvent, Event,
+};
+use dora_core::{
+    coordinator_messages::{self, RegisterResult},
+    daemon_messages::Timestamped,
+    message::uhlc::HLC,
+};
 use eyre::Context;
 use std::{io::ErrorKind, net::SocketAddr, sync::Arc};
 use tokio::{
@@ -18,10 +26,21 @@ pub async fn create_listener(bind: SocketAddr) -> eyre::Result<TcpListener> {
 }
 
 pub async fn handle_connection(
-    mut connection: TcpStream,
+    connection: TcpStream,
     events_tx: mpsc::Sender<Event>,
     clock: Arc<HLC>,
+    security: Arc<Security>,
 ) {
+    let mut connection = match security.accept(connection).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            tracing::warn!("failed to accept daemon connection: {err}");
+            return;
+        }
+    };
+    // event connections need to authenticate before sending any events
+    let mut authenticated = !security.requires_token();
+
     loop {
         // receive the next message and parse it
         let raw = match tcp_receive(&mut connection).await {
@@ -31,7 +50,7 @@ pub async fn handle_connection(
             }
             Err(err) => {
                 tracing::error!("{err:?}");
-                continue;
+                break;
             }
         };
         let message: Timestamped<coordinator_messages::CoordinatorRequest> =
@@ -53,16 +72,47 @@ pub async fn handle_connection(
                 machine_id,
                 dora_version,
                 listen_port,
+                running_dataflows,
+                token,
+                labels,
             } => {
+                if let Err(err) = security.authenticate_daemon(token.as_deref()) {
+                    tracing::warn!("rejected registration of machine `{machine_id}`: {err}");
+                    let reply = Timestamped {
+                        inner: RegisterResult::Err(format!("authentication failed: {err}")),
+                        timestamp: clock.new_timestamp(),
+                    };
+                    if let Ok(reply) = serde_json::to_vec(&reply) {
+                        let _ = tcp_send(&mut connection, &reply).await;
+                    }
+                    break;
+                }
                 let event = DaemonEvent::Register {
                     dora_version,
                     machine_id,
                     connection,
                     listen_port,
+                    running_dataflows,
+                    labels,
                 };
                 let _ = events_tx.send(Event::Daemon(event)).await;
                 break;
             }
+            coordinator_messages::CoordinatorRequest::Authenticate { token } => {
+                match security.authenticate_daemon(Some(&token)) {
+                    Ok(()) => authenticated = true,
+                    Err(err) => {
+                        tracing::warn!("rejected daemon event connection: {err}");
+                        break;
+                    }
+                }
+            }
+            coordinator_messages::CoordinatorRequest::Event { machine_id, .. }
+                if !authenticated =>
+            {
+                tracing::warn!("closing unauthenticated event connection of `{machine_id}`");
+                break;
+            }
             coordinator_messages::CoordinatorRequest::Event { machine_id, event } => match event {
                 coordinator_messages::DaemonEvent::AllNodesReady {
                     dataflow_id,
@@ -91,6 +141,31 @@ pub async fn handle_connection(
                         break;
                     }
                 }
+                coordinator_messages::DaemonEvent::NodeReady {
+                    dataflow_id,
+                    node_id,
+                } => {
+                    let event = Event::Dataflow {
+                        uuid: dataflow_id,
+                        event: DataflowEvent::NodeReady { node_id },
+                    };
+                    if events_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+                coordinator_messages::DaemonEvent::NodeExited {
+                    dataflow_id,
+                    node_id,
+                    result,
+                } => {
+                    let event = Event::Dataflow {
+                        uuid: dataflow_id,
+                        event: DataflowEvent::NodeExited { node_id, result },
+                    };
+                    if events_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
                 coordinator_messages::DaemonEvent::Heartbeat => {
                     let event = Event::DaemonHeartbeat { machine_id };
                     if events_tx.send(event).await.is_err() {
@@ -103,6 +178,20 @@ pub async fn handle_connection(
                         break;
                     }
                 }
+                coordinator_messages::DaemonEvent::NodeLogs {
+                    dataflow_id,
+                    node_id,
+                    output,
+                } => {
+                    let event = Event::NodeLogs {
+                        dataflow_id,
+                        node_id,
+                        output,
+                    };
+                    if events_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
             },
         };
     }
@@ -0,0 +1,380 @@
+//! File-based storage of the stdout and stderr output of nodes.
+//!
+//! Daemons send the output of their nodes incrementally to the coordinator,
+//! which appends it to a separate file per node, stored in a directory per
+//! dataflow. This way, logs are still available after a dataflow finished or
+//! after its machines went offline.
+//!
+//! Old logs are removed based on their age and on the total size of all logs.
+//! Logs of running dataflows are never removed.
+//!
+//! Output that a node produces while its daemon is disconnected from the
+//! coordinator is not sent again after reconnecting, so it is missing from the
+//! stored logs. It is still available in the log file on the daemon machine.
+//!
+//! All file operations run on a separate thread to avoid blocking the event
+//! loop of the coordinator.
+
+use dora_core::config::NodeId;
+use eyre::{bail, Context};
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+use uuid::Uuid;
+
+/// Number of pending file operations before new output is dropped.
+const QUEUE_SIZE: usize = 1024;
+
+/// Settings for storing node logs on the coordinator.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Directory to store the logs in.
+    ///
+    /// Defaults to the `logs` subdirectory of the state directory, or to a
+    /// directory in the system's temporary directory.
+    pub dir: Option<PathBuf>,
+    /// Remove the logs of the oldest finished dataflows once the logs exceed
+    /// the given total size (in bytes).
+    pub max_size: u64,
+    /// Remove the logs of finished dataflows after the given duration.
+    pub max_age: Duration,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_size: 1024 * 1024 * 1024,
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Handle to the log store, which performs the file operations on a separate
+/// thread.
+pub struct LogStore {
+    requests: mpsc::Sender<Request>,
+}
+
+enum Request {
+    Append {
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        data: String,
+    },
+    Read {
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        reply: oneshot::Sender<eyre::Result<Option<Vec<u8>>>>,
+    },
+    ApplyRetention {
+        running: HashSet<Uuid>,
+    },
+}
+
+impl LogStore {
+    pub fn open(dir: PathBuf, config: &LogConfig) -> eyre::Result<Self> {
+        let files = LogFiles::open(dir, config)?;
+        let (requests, mut receiver) = mpsc::channel(QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name("dora-coordinator-log-store".into())
+            .spawn(move || {
+                while let Some(request) = receiver.blocking_recv() {
+                    files.handle(request);
+                }
+            })
+            .wrap_err("failed to spawn log store thread")?;
+        Ok(Self { requests })
+    }
+
+    /// Appends the given output of a node to its log file.
+    ///
+    /// The output is dropped if the log store can't keep up.
+    pub fn append(&self, dataflow_id: Uuid, node_id: NodeId, data: String) {
+        let request = Request::Append {
+            dataflow_id,
+            node_id,
+            data,
+        };
+        if let Err(TrySendError::Full(request) | TrySendError::Closed(request)) =
+            self.requests.try_send(request)
+        {
+            if let Request::Append { node_id, .. } = request {
+                tracing::warn!(
+                    "log store is overloaded, dropping output of node \
+                    `{dataflow_id}/{node_id}`"
+                );
+            }
+        }
+    }
+
+    /// Reads the stored logs of the given node, if there are any.
+    pub async fn read(&self, dataflow_id: Uuid, node_id: &NodeId) -> eyre::Result<Option<Vec<u8>>> {
+        let (reply, result) = oneshot::channel();
+        let request = Request::Read {
+            dataflow_id,
+            node_id: node_id.clone(),
+            reply,
+        };
+        self.requests
+            .send(request)
+            .await
+            .map_err(|_| eyre::eyre!("log store thread stopped"))?;
+        result.await.wrap_err("log store thread stopped")?
+    }
+
+    /// Removes the logs of finished dataflows that are too old or that exceed
+    /// the size limit.
+    pub fn apply_retention(&self, running: HashSet<Uuid>) {
+        if self
+            .requests
+            .try_send(Request::ApplyRetention { running })
+            .is_err()
+        {
+            tracing::debug!("log store is busy, skipping log retention");
+        }
+    }
+}
+
+/// Synchronous file operations of the log store.
+struct LogFiles {
+    dir: PathBuf,
+    max_size: u64,
+    max_age: Duration,
+}
+
+impl LogFiles {
+    fn open(dir: PathBuf, config: &LogConfig) -> eyre::Result<Self> {
+        fs::create_dir_all(&dir)
+            .wrap_err_with(|| format!("failed to create log dir `{}`", dir.display()))?;
+        Ok(Self {
+            dir,
+            max_size: config.max_size,
+            max_age: config.max_age,
+        })
+    }
+
+    fn handle(&self, request: Request) {
+        match request {
+            Request::Append {
+                dataflow_id,
+                node_id,
+                data,
+            } => {
+                if let Err(err) = self.append(dataflow_id, &node_id, &data) {
+                    tracing::warn!("{:?}", err.wrap_err("failed to store node logs"));
+                }
+            }
+            Request::Read {
+                dataflow_id,
+                node_id,
+                reply,
+            } => {
+                let _ = reply.send(self.read(dataflow_id, &node_id));
+            }
+            Request::ApplyRetention { running } => {
+                if let Err(err) = self.apply_retention(&running) {
+                    tracing::warn!("{:?}", err.wrap_err("failed to remove old logs"));
+                }
+            }
+        }
+    }
+
+    /// Appends the given output of a node to its log file.
+    fn append(&self, dataflow_id: Uuid, node_id: &NodeId, data: &str) -> eyre::Result<()> {
+        let dataflow_dir = self.dir.join(dataflow_id.to_string());
+        let path = log_path(&dataflow_dir, node_id)?;
+        fs::create_dir_all(&dataflow_dir)
+            .wrap_err_with(|| format!("failed to create `{}`", dataflow_dir.display()))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .wrap_err_with(|| format!("failed to open `{}`", path.display()))?;
+        file.write_all(data.as_bytes())
+            .wrap_err_with(|| format!("failed to write to `{}`", path.display()))
+    }
+
+    /// Reads the stored logs of the given node, if there are any.
+    fn read(&self, dataflow_id: Uuid, node_id: &NodeId) -> eyre::Result<Option<Vec<u8>>> {
+        let path = log_path(&self.dir.join(dataflow_id.to_string()), node_id)?;
+        match fs::read(&path) {
+            Ok(logs) => Ok(Some(logs)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).wrap_err_with(|| format!("failed to read `{}`", path.display())),
+        }
+    }
+
+    /// Removes the logs of finished dataflows that are too old or that exceed
+    /// the size limit.
+    fn apply_retention(&self, running: &HashSet<Uuid>) -> eyre::Result<()> {
+        let mut dataflows = Vec::new();
+        let mut total_size = 0;
+        let entries = fs::read_dir(&self.dir)
+            .wrap_err_with(|| format!("failed to read `{}`", self.dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            let Some(uuid) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| Uuid::parse_str(name).ok())
+            else {
+                continue;
+            };
+            let (size, modified) = dir_stats(&path)?;
+            total_size += size;
+            if !running.contains(&uuid) {
+                dataflows.push((modified, size, uuid, path));
+            }
+        }
+
+        // remove the least recently written logs first
+        dataflows.sort();
+        let now = SystemTime::now();
+        for (modified, size, uuid, path) in dataflows {
+            let too_old = now
+                .duration_since(modified)
+                .is_ok_and(|age| age > self.max_age);
+            if !too_old && total_size <= self.max_size {
+                break;
+            }
+            tracing::debug!("removing logs of dataflow `{uuid}`");
+            fs::remove_dir_all(&path)
+                .wrap_err_with(|| format!("failed to remove `{}`", path.display()))?;
+            total_size -= size;
+        }
+        if total_size > self.max_size {
+            tracing::warn!(
+                "logs of running dataflows exceed the maximum log size ({total_size} > {} bytes)",
+                self.max_size
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Returns the path of the log file of the given node.
+///
+/// Node IDs are received from daemons and control clients, so IDs that could
+/// refer to a file outside of the dataflow directory are rejected.
+fn log_path(dataflow_dir: &Path, node_id: &NodeId) -> eyre::Result<PathBuf> {
+    let id = node_id.as_ref();
+    if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\', '\0']) {
+        bail!("invalid node ID `{id}`");
+    }
+    Ok(dataflow_dir.join(format!("{id}.log")))
+}
+
+/// Returns the total size and the latest modification time of the files in the given directory.
+fn dir_stats(dir: &Path) -> eyre::Result<(u64, SystemTime)> {
+    let mut size = 0;
+    let mut modified = SystemTime::UNIX_EPOCH;
+    let entries =
+        fs::read_dir(dir).wrap_err_with(|| format!("failed to read `{}`", dir.display()))?;
+    for entry in entries {
+        let metadata = entry?.metadata()?;
+        size += metadata.len();
+        modified = modified.max(metadata.modified()?);
+    }
+    Ok((size, modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str) -> NodeId {
+        NodeId::from(id.to_string())
+    }
+
+    #[tokio::test]
+    async fn appended_output_can_be_read() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let store = LogStore::open(dir.to_owned(), &LogConfig::default()).unwrap();
+        let dataflow_id = Uuid::now_v7();
+
+        assert_eq!(
+            store.read(dataflow_id, &node("camera")).await.unwrap(),
+            None
+        );
+        store.append(dataflow_id, node("camera"), "first\n".into());
+        store.append(dataflow_id, node("camera"), "second\n".into());
+        store.append(dataflow_id, node("plot"), "other\n".into());
+
+        // requests are handled in order, so the appends are done before the read
+        let logs = store.read(dataflow_id, &node("camera")).await.unwrap();
+        assert_eq!(logs.as_deref(), Some(&b"first\nsecond\n"[..]));
+    }
+
+    #[tokio::test]
+    async fn node_ids_cannot_escape_the_log_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let store = LogStore::open(dir.to_owned(), &LogConfig::default()).unwrap();
+        let dataflow_id = Uuid::now_v7();
+
+        for id in ["../escape", "..", "a/b", "a\\b", "", ".hidden"] {
+            store.append(dataflow_id, node(id), "data".into());
+            assert!(store.read(dataflow_id, &node(id)).await.is_err(), "{id:?}");
+        }
+        assert!(!dir.join("escape.log").exists());
+        assert!(!dir.join(dataflow_id.to_string()).exists());
+    }
+
+    #[test]
+    fn retention_keeps_running_dataflows() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let config = LogConfig {
+            dir: None,
+            max_size: 10,
+            max_age: Duration::from_secs(3600),
+        };
+        let files = LogFiles::open(dir.to_owned(), &config).unwrap();
+        let finished = Uuid::now_v7();
+        let running = Uuid::now_v7();
+        let recent = Uuid::now_v7();
+        files.append(finished, &node("a"), "0123456789").unwrap();
+        files.append(running, &node("a"), "0123456789").unwrap();
+        files.append(recent, &node("a"), "01234").unwrap();
+
+        files
+            .apply_retention(&[running, recent].into_iter().collect())
+            .unwrap();
+        assert_eq!(files.read(finished, &node("a")).unwrap(), None);
+        assert!(files.read(running, &node("a")).unwrap().is_some());
+
+        // below the size limit and not too old
+        files
+            .apply_retention(&[running].into_iter().collect())
+            .unwrap();
+        assert!(files.read(running, &node("a")).unwrap().is_some());
+    }
+
+    #[test]
+    fn old_logs_are_removed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let config = LogConfig {
+            dir: None,
+            max_size: u64::MAX,
+            max_age: Duration::ZERO,
+        };
+        let files = LogFiles::open(dir.to_owned(), &config).unwrap();
+        let dataflow_id = Uuid::now_v7();
+        files.append(dataflow_id, &node("a"), "data").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+
+        files.apply_retention(&HashSet::new()).unwrap();
+        assert_eq!(files.read(dataflow_id, &node("a")).unwrap(), None);
+    }
+}
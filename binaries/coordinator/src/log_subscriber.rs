@@ -1,15 +1,15 @@
-use dora_core::coordinator_messages::LogMessage;
+use dora_core::{coordinator_messages::LogMessage, security::tls::Connection};
 use eyre::{Context, ContextCompat};
 
 use crate::tcp_utils::tcp_send;
 
 pub struct LogSubscriber {
     pub level: log::LevelFilter,
-    connection: Option<tokio::net::TcpStream>,
+    connection: Option<Connection>,
 }
 
 impl LogSubscriber {
-    pub fn new(level: log::LevelFilter, connection: tokio::net::TcpStream) -> Self {
+    pub fn new(level: log::LevelFilter, connection: Connection) -> Self {
         Self {
             level,
             connection: Some(connection),
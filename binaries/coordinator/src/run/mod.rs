@@ -1,9 +1,11 @@
 use crate::{
+    control::invalid_request,
     tcp_utils::{tcp_receive, tcp_send},
     DaemonConnection,
 };
 
 use dora_core::{
+    config::NodeId,
     daemon_messages::{
         DaemonCoordinatorEvent, DaemonCoordinatorReply, SpawnDataflowNodes, Timestamped,
     },
@@ -13,33 +15,27 @@ use dora_core::{
 use eyre::{bail, eyre, ContextCompat, WrapErr};
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 use uuid::{NoContext, Timestamp, Uuid};
 
-#[tracing::instrument(skip(daemon_connections, clock))]
+mod placement;
+
+#[tracing::instrument(skip(daemon_connections, reserved_cpus, clock))]
 pub(super) async fn spawn_dataflow(
     dataflow: Descriptor,
     working_dir: PathBuf,
     daemon_connections: &mut HashMap<String, DaemonConnection>,
+    reserved_cpus: &BTreeMap<String, u32>,
     clock: &HLC,
 ) -> eyre::Result<SpawnedDataflow> {
-    let remote_machine_id: Vec<_> = daemon_connections
-        .iter()
-        .filter_map(|(id, c)| {
-            if !c.listen_socket.ip().is_loopback() {
-                Some(id.as_str())
-            } else {
-                None
-            }
-        })
-        .collect();
-    dataflow.check_in_daemon(&working_dir, &remote_machine_id, false)?;
-
-    let nodes = dataflow.resolve_aliases_and_set_defaults()?;
+    let DataflowPlan {
+        descriptor: dataflow,
+        nodes,
+        machines,
+    } = plan_dataflow(dataflow, &working_dir, daemon_connections, reserved_cpus)?;
     let uuid = Uuid::new_v7(Timestamp::now(NoContext));
 
-    let machines: BTreeSet<_> = nodes.iter().map(|n| n.deploy.machine.clone()).collect();
     let machine_listen_ports = machines
         .iter()
         .map(|m| {
@@ -55,7 +51,7 @@ pub(super) async fn spawn_dataflow(
         working_dir,
         nodes: nodes.clone(),
         machine_listen_ports,
-        dataflow_descriptor: dataflow,
+        dataflow_descriptor: dataflow.clone(),
     };
     let message = serde_json::to_vec(&Timestamped {
         inner: DaemonCoordinatorEvent::Spawn(spawn_command),
@@ -73,11 +69,140 @@ pub(super) async fn spawn_dataflow(
 
     Ok(SpawnedDataflow {
         uuid,
+        descriptor: dataflow,
         machines,
         nodes,
     })
 }
 
+/// Assigns the nodes of the given dataflow to machines and validates the
+/// dataflow, without spawning it.
+///
+/// The `reserved_cpus` are the CPUs per machine that are used by the nodes of
+/// other running dataflows.
+pub(super) fn plan_dataflow(
+    dataflow: Descriptor,
+    working_dir: &Path,
+    daemon_connections: &HashMap<String, DaemonConnection>,
+    reserved_cpus: &BTreeMap<String, u32>,
+) -> eyre::Result<DataflowPlan> {
+    plan_dataflow_on(
+        dataflow,
+        working_dir,
+        daemon_connections,
+        reserved_cpus,
+        None,
+    )
+}
+
+/// Like [`plan_dataflow`], but only places nodes on the `allowed_machines`, if given.
+fn plan_dataflow_on(
+    mut dataflow: Descriptor,
+    working_dir: &Path,
+    daemon_connections: &HashMap<String, DaemonConnection>,
+    reserved_cpus: &BTreeMap<String, u32>,
+    allowed_machines: Option<&BTreeSet<String>>,
+) -> eyre::Result<DataflowPlan> {
+    placement::place_nodes(
+        &mut dataflow,
+        daemon_connections,
+        reserved_cpus,
+        allowed_machines,
+    )
+    .map_err(invalid_request)?;
+
+    let remote_machine_id: Vec<_> = daemon_connections
+        .iter()
+        .filter_map(|(id, c)| {
+            if !c.listen_socket.ip().is_loopback() {
+                Some(id.as_str())
+            } else {
+                None
+            }
+        })
+        .collect();
+    dataflow
+        .check_in_daemon(working_dir, &remote_machine_id, false)
+        .map_err(invalid_request)?;
+
+    let nodes = dataflow
+        .resolve_aliases_and_set_defaults()
+        .map_err(invalid_request)?;
+    let machines: BTreeSet<_> = nodes.iter().map(|n| n.deploy.machine.clone()).collect();
+    if let Some(missing) = machines
+        .iter()
+        .find(|m| !daemon_connections.contains_key(*m))
+    {
+        return Err(invalid_request(format!(
+            "no daemon connected for machine `{missing}`"
+        )));
+    }
+
+    Ok(DataflowPlan {
+        descriptor: dataflow,
+        nodes,
+        machines,
+    })
+}
+
+/// Adds the nodes of the given descriptor fragment to the descriptor of a
+/// running dataflow and validates the result.
+///
+/// Nodes that were removed from the dataflow before are still part of the
+/// descriptor, so that inputs that reference them stay valid. Added nodes
+/// replace removed nodes with the same ID.
+///
+/// Added nodes are only placed on the given `machines` of the dataflow. The
+/// returned plan only contains the running and the added nodes.
+pub(super) fn plan_added_nodes(
+    descriptor: &Descriptor,
+    running_nodes: &[ResolvedNode],
+    machines: &BTreeSet<String>,
+    fragment: Descriptor,
+    working_dir: &Path,
+    daemon_connections: &HashMap<String, DaemonConnection>,
+    reserved_cpus: &BTreeMap<String, u32>,
+) -> eyre::Result<(DataflowPlan, BTreeSet<NodeId>)> {
+    let mut added = BTreeSet::new();
+    for node in &fragment.nodes {
+        if !added.insert(node.id.clone()) {
+            return Err(invalid_request(format!(
+                "node `{}` is defined multiple times",
+                node.id
+            )));
+        }
+        if running_nodes.iter().any(|n| n.id == node.id) {
+            return Err(invalid_request(format!(
+                "dataflow already has a node `{}`",
+                node.id
+            )));
+        }
+    }
+    if added.is_empty() {
+        return Err(invalid_request("no nodes to add"));
+    }
+
+    let mut merged = descriptor.clone();
+    merged.nodes.retain(|node| !added.contains(&node.id));
+    merged.nodes.extend(fragment.nodes);
+
+    let mut plan = plan_dataflow_on(
+        merged,
+        working_dir,
+        daemon_connections,
+        reserved_cpus,
+        Some(machines),
+    )?;
+    plan.nodes
+        .retain(|node| added.contains(&node.id) || running_nodes.iter().any(|n| n.id == node.id));
+    plan.machines = plan
+        .nodes
+        .iter()
+        .map(|n| n.deploy.machine.clone())
+        .collect();
+    Ok((plan, added))
+}
+
 async fn spawn_dataflow_on_machine(
     daemon_connections: &mut HashMap<String, DaemonConnection>,
     machine: &str,
@@ -103,8 +228,16 @@ async fn spawn_dataflow_on_machine(
     Ok(())
 }
 
+pub struct DataflowPlan {
+    /// The dataflow descriptor, with the chosen machines filled in.
+    pub descriptor: Descriptor,
+    pub nodes: Vec<ResolvedNode>,
+    pub machines: BTreeSet<String>,
+}
+
 pub struct SpawnedDataflow {
     pub uuid: Uuid,
+    pub descriptor: Descriptor,
     pub machines: BTreeSet<String>,
     pub nodes: Vec<ResolvedNode>,
 }
@@ -0,0 +1,359 @@
+//! Automatic placement of nodes on machines, based on the labels that the
+//! daemons register with.
+
+use crate::DaemonConnection;
+use dora_core::descriptor::{Deploy, Descriptor};
+use eyre::bail;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+};
+
+/// Label that specifies the number of CPUs of a machine.
+const CPUS_LABEL: &str = "cpus";
+
+struct Machine<'a> {
+    labels: &'a BTreeMap<String, String>,
+    /// Remaining CPUs, if the machine has a valid `cpus` label.
+    free_cpus: Option<u32>,
+    /// Number of nodes that are assigned to the machine.
+    nodes: usize,
+}
+
+/// Assigns a machine to every node that has deploy constraints instead of an
+/// explicit machine.
+///
+/// The chosen machine is written to the `machine` field of the node, together
+/// with the CPUs that the node reserves on it.
+///
+/// The `reserved_cpus` are the CPUs per machine that are already used by the
+/// nodes of other running dataflows. If `allowed_machines` is given, nodes are
+/// only placed on these machines.
+pub fn place_nodes(
+    dataflow: &mut Descriptor,
+    daemon_connections: &HashMap<String, DaemonConnection>,
+    reserved_cpus: &BTreeMap<String, u32>,
+    allowed_machines: Option<&BTreeSet<String>>,
+) -> eyre::Result<()> {
+    let mut machines: BTreeMap<&str, Machine> = daemon_connections
+        .iter()
+        .filter(|(id, _)| allowed_machines.map_or(true, |allowed| allowed.contains(*id)))
+        .map(|(id, connection)| {
+            let reserved = reserved_cpus.get(id).copied().unwrap_or_default();
+            let machine = Machine {
+                labels: &connection.labels,
+                free_cpus: connection
+                    .labels
+                    .get(CPUS_LABEL)
+                    .and_then(|cpus| cpus.parse::<u32>().ok())
+                    .map(|cpus| cpus.saturating_sub(reserved)),
+                nodes: 0,
+            };
+            (id.as_str(), machine)
+        })
+        .collect();
+
+    let mut pending = Vec::new();
+    for (index, node) in dataflow.nodes.iter().enumerate() {
+        match constraints(&node.deploy, &dataflow.deploy) {
+            Some(constraints) => pending.push((index, constraints)),
+            None => {
+                // nodes with an explicit machine still use up its capacity
+                let machine_id = node
+                    .deploy
+                    .machine
+                    .as_deref()
+                    .or(dataflow.deploy.machine.as_deref())
+                    .unwrap_or_default();
+                if let Some(machine) = machines.get_mut(machine_id) {
+                    machine.assign(node.deploy.cpus);
+                }
+            }
+        }
+    }
+
+    // place the most demanding nodes first to avoid fragmentation
+    pending.sort_by_key(|(_, constraints)| Reverse(constraints.cpus));
+
+    for (index, constraints) in pending {
+        let node = &mut dataflow.nodes[index];
+        let chosen = machines
+            .iter()
+            .filter(|(_, machine)| machine.fits(&constraints))
+            .min_by_key(|(id, machine)| {
+                let preferred = constraints
+                    .prefer
+                    .iter()
+                    .filter(|(key, value)| machine.labels.get(*key) == Some(value))
+                    .count();
+                // spread nodes over the machines that match equally well
+                (Reverse(preferred), machine.nodes, **id)
+            })
+            .map(|(id, _)| id.to_string());
+        let Some(machine_id) = chosen else {
+            bail!(
+                "no machine satisfies the deploy constraints of node `{}` ({})\n\n{}",
+                node.id,
+                format_constraints(&constraints),
+                format_machines(&machines, &constraints)
+            );
+        };
+        tracing::debug!("placing node `{}` on machine `{machine_id}`", node.id);
+        if let Some(machine) = machines.get_mut(machine_id.as_str()) {
+            machine.assign(constraints.cpus);
+        }
+        node.deploy.machine = Some(machine_id);
+        // keep the reservation, e.g. for placing the nodes of other dataflows
+        node.deploy.cpus = constraints.cpus;
+    }
+
+    Ok(())
+}
+
+/// Returns the constraints of the given node if it needs to be placed.
+///
+/// Nodes inherit the constraints of the dataflow-level deploy config, unless
+/// they specify a machine or constraints themselves.
+fn constraints(node: &Deploy, dataflow: &Deploy) -> Option<Deploy> {
+    if node.needs_placement() {
+        let mut constraints = node.clone();
+        for (key, value) in &dataflow.selector {
+            constraints
+                .selector
+                .entry(key.clone())
+                .or_insert(value.clone());
+        }
+        for (key, value) in &dataflow.prefer {
+            constraints
+                .prefer
+                .entry(key.clone())
+                .or_insert(value.clone());
+        }
+        Some(constraints)
+    } else if node.machine.is_none() && dataflow.needs_placement() {
+        Some(dataflow.clone())
+    } else {
+        None
+    }
+}
+
+impl Machine<'_> {
+    fn matches(&self, constraints: &Deploy) -> bool {
+        constraints
+            .selector
+            .iter()
+            .all(|(key, value)| self.labels.get(key) == Some(value))
+    }
+
+    fn fits(&self, constraints: &Deploy) -> bool {
+        let enough_cpus = match constraints.cpus {
+            Some(required) => self.free_cpus.is_some_and(|free| free >= required),
+            None => true,
+        };
+        self.matches(constraints) && enough_cpus
+    }
+
+    fn assign(&mut self, cpus: Option<u32>) {
+        self.nodes += 1;
+        if let (Some(free), Some(cpus)) = (&mut self.free_cpus, cpus) {
+            *free = free.saturating_sub(cpus);
+        }
+    }
+}
+
+fn format_constraints(constraints: &Deploy) -> String {
+    let mut parts = Vec::new();
+    if !constraints.selector.is_empty() {
+        parts.push(format!(
+            "selector: {}",
+            format_labels(&constraints.selector)
+        ));
+    }
+    if let Some(cpus) = constraints.cpus {
+        parts.push(format!("cpus: {cpus}"));
+    }
+    if parts.is_empty() {
+        parts.push("no required labels".into());
+    }
+    parts.join(", ")
+}
+
+fn format_machines(machines: &BTreeMap<&str, Machine>, constraints: &Deploy) -> String {
+    if machines.is_empty() {
+        return "no daemon is connected to the coordinator".into();
+    }
+    let mut output = String::from("connected machines:");
+    for (id, machine) in machines {
+        let _ = write!(output, "\n  - `{id}`: {}", format_labels(machine.labels));
+        if machine.matches(constraints) {
+            let _ = match machine.free_cpus {
+                Some(free) => write!(output, " (matching, but only {free} free cpus)"),
+                None => write!(output, " (matching, but the number of cpus is unknown)"),
+            };
+        }
+    }
+    output
+}
+
+fn format_labels(labels: &BTreeMap<String, String>) -> String {
+    if labels.is_empty() {
+        return "no labels".into();
+    }
+    labels
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::security::tls::Connection;
+    use std::time::Instant;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn daemon_connections(
+        machines: &[(&str, &[(&str, &str)])],
+    ) -> HashMap<String, DaemonConnection> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut connections = HashMap::new();
+        for (id, labels) in machines {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let connection = DaemonConnection {
+                stream: Connection::Tcp(stream),
+                listen_socket: addr,
+                last_heartbeat: Instant::now(),
+                labels: labels
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            };
+            connections.insert(id.to_string(), connection);
+        }
+        connections
+    }
+
+    fn descriptor(nodes: serde_json::Value) -> Descriptor {
+        serde_json::from_value(serde_json::json!({ "nodes": nodes })).unwrap()
+    }
+
+    fn machines(dataflow: &Descriptor) -> Vec<(String, Option<String>, Option<u32>)> {
+        dataflow
+            .nodes
+            .iter()
+            .map(|n| (n.id.to_string(), n.deploy.machine.clone(), n.deploy.cpus))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn nodes_are_placed_on_matching_machines() {
+        let connections = daemon_connections(&[
+            ("a", &[("arch", "x86_64")]),
+            ("b", &[("arch", "aarch64"), ("camera", "true")]),
+            ("c", &[("arch", "aarch64")]),
+        ])
+        .await;
+        let mut dataflow = descriptor(serde_json::json!([
+            { "id": "camera", "path": "x", "deploy": { "selector": { "camera": true } } },
+            { "id": "arm", "path": "x", "deploy": { "selector": { "arch": "aarch64" }, "prefer": { "camera": true } } },
+            { "id": "fixed", "path": "x", "deploy": { "machine": "a" } },
+        ]));
+        place_nodes(&mut dataflow, &connections, &BTreeMap::new(), None).unwrap();
+        assert_eq!(
+            machines(&dataflow),
+            vec![
+                ("camera".into(), Some("b".into()), None),
+                ("arm".into(), Some("b".into()), None),
+                ("fixed".into(), Some("a".into()), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unsatisfiable_constraints_are_rejected() {
+        let connections = daemon_connections(&[("a", &[("arch", "x86_64")])]).await;
+        let mut dataflow = descriptor(serde_json::json!([
+            { "id": "gpu", "path": "x", "deploy": { "selector": { "gpu": "true" } } },
+        ]));
+        let err = place_nodes(&mut dataflow, &connections, &BTreeMap::new(), None).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("node `gpu`"), "{message}");
+        assert!(message.contains("`a`: arch=x86_64"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn cpus_are_reserved_across_nodes() {
+        let connections =
+            daemon_connections(&[("a", &[("cpus", "4")]), ("b", &[("cpus", "2")])]).await;
+        let mut dataflow = descriptor(serde_json::json!([
+            { "id": "big", "path": "x", "deploy": { "cpus": 3 } },
+            { "id": "small", "path": "x", "deploy": { "cpus": 2 } },
+            { "id": "tiny", "path": "x", "deploy": { "cpus": 1 } },
+        ]));
+        place_nodes(&mut dataflow, &connections, &BTreeMap::new(), None).unwrap();
+        assert_eq!(
+            machines(&dataflow),
+            vec![
+                ("big".into(), Some("a".into()), Some(3)),
+                ("small".into(), Some("b".into()), Some(2)),
+                ("tiny".into(), Some("a".into()), Some(1)),
+            ]
+        );
+
+        let mut too_large = descriptor(serde_json::json!([
+            { "id": "huge", "path": "x", "deploy": { "cpus": 5 } },
+        ]));
+        assert!(place_nodes(&mut too_large, &connections, &BTreeMap::new(), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn cpus_of_other_dataflows_are_taken_into_account() {
+        let connections =
+            daemon_connections(&[("a", &[("cpus", "4")]), ("b", &[("cpus", "2")])]).await;
+        let node = serde_json::json!([
+            { "id": "node", "path": "x", "deploy": { "cpus": 3 } },
+        ]);
+
+        let mut dataflow = descriptor(node.clone());
+        place_nodes(&mut dataflow, &connections, &BTreeMap::new(), None).unwrap();
+        assert_eq!(dataflow.nodes[0].deploy.machine.as_deref(), Some("a"));
+
+        let reserved = [("a".to_string(), 2)].into();
+        let mut dataflow = descriptor(node);
+        let err = place_nodes(&mut dataflow, &connections, &reserved, None).unwrap_err();
+        assert!(err.to_string().contains("only 2 free cpus"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn placement_can_be_restricted_to_some_machines() {
+        let connections =
+            daemon_connections(&[("a", &[("camera", "true")]), ("b", &[("camera", "true")])]).await;
+        let node = serde_json::json!([
+            { "id": "camera", "path": "x", "deploy": { "selector": { "camera": true } } },
+        ]);
+
+        let allowed = BTreeSet::from(["b".to_string()]);
+        let mut dataflow = descriptor(node.clone());
+        place_nodes(
+            &mut dataflow,
+            &connections,
+            &BTreeMap::new(),
+            Some(&allowed),
+        )
+        .unwrap();
+        assert_eq!(dataflow.nodes[0].deploy.machine.as_deref(), Some("b"));
+
+        let allowed = BTreeSet::from(["c".to_string()]);
+        let mut dataflow = descriptor(node);
+        assert!(place_nodes(
+            &mut dataflow,
+            &connections,
+            &BTreeMap::new(),
+            Some(&allowed)
+        )
+        .is_err());
+    }
+}
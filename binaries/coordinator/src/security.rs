@@ -0,0 +1,82 @@
+use dora_core::security::{
+    tls::{Connection, TlsAcceptor},
+    Role, SecurityConfig,
+};
+use eyre::{bail, Context};
+use tokio::net::TcpStream;
+
+/// Authentication and encryption of incoming connections.
+pub struct Security {
+    config: SecurityConfig,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Security {
+    pub fn new(config: SecurityConfig) -> eyre::Result<Self> {
+        let tls = config
+            .tls
+            .as_ref()
+            .map(TlsAcceptor::new)
+            .transpose()
+            .wrap_err("failed to set up TLS")?;
+        Ok(Self { config, tls })
+    }
+
+    /// Performs the TLS handshake, if TLS is enabled.
+    pub async fn accept(&self, stream: TcpStream) -> std::io::Result<Connection> {
+        match &self.tls {
+            Some(acceptor) => acceptor.accept(stream).await,
+            None => Ok(Connection::Tcp(stream)),
+        }
+    }
+
+    pub fn requires_token(&self) -> bool {
+        self.config.requires_token()
+    }
+
+    pub fn authenticate(&self, token: Option<&str>) -> eyre::Result<Role> {
+        self.config.authenticate(token)
+    }
+
+    /// Daemons can spawn arbitrary nodes, so they need full access.
+    pub fn authenticate_daemon(&self, token: Option<&str>) -> eyre::Result<()> {
+        match self.authenticate(token)? {
+            Role::Admin => Ok(()),
+            role => bail!("daemons cannot use access tokens with role `{role}`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::security::AccessToken;
+
+    #[test]
+    fn daemons_need_admin_tokens() {
+        let security = Security::new(SecurityConfig {
+            access_tokens: vec![
+                AccessToken {
+                    token: "admin".into(),
+                    role: Role::Admin,
+                },
+                AccessToken {
+                    token: "reader".into(),
+                    role: Role::ReadOnly,
+                },
+            ],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(security.authenticate_daemon(Some("admin")).is_ok());
+        assert!(security.authenticate_daemon(Some("reader")).is_err());
+        assert!(security.authenticate_daemon(None).is_err());
+    }
+
+    #[test]
+    fn daemons_are_accepted_without_access_tokens() {
+        let security = Security::new(SecurityConfig::default()).unwrap();
+        assert!(!security.requires_token());
+        assert!(security.authenticate_daemon(None).is_ok());
+    }
+}
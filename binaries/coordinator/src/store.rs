@@ -0,0 +1,258 @@
+//! File-based store that persists the coordinator state across restarts.
+//!
+//! Every dataflow is stored as a separate JSON file in the `dataflows`
+//! subdirectory of the state directory. Files are replaced atomically, so a
+//! crash during a write never leaves a corrupted file behind.
+//!
+//! Only the most recent finished dataflows are kept. Older ones are removed
+//! from the store when another dataflow finishes.
+
+use dora_core::{
+    descriptor::{Descriptor, ResolvedNode},
+    topics::DataflowDaemonResult,
+};
+use eyre::{Context, ContextCompat};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+pub struct Store {
+    dataflows_dir: PathBuf,
+    /// Maximum number of finished dataflows to keep.
+    max_finished: usize,
+    /// UUIDs of the stored finished dataflows.
+    ///
+    /// The UUIDs are v7 UUIDs, so they are ordered by their creation time.
+    finished: Mutex<BTreeSet<Uuid>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct StoredDataflow {
+    pub uuid: Uuid,
+    pub name: Option<String>,
+    pub descriptor: Descriptor,
+    pub working_dir: PathBuf,
+    pub nodes: Vec<ResolvedNode>,
+    /// The IDs of the machines that the dataflow was spawned on.
+    pub machines: BTreeSet<String>,
+    /// Results of the machines on which the dataflow already finished.
+    pub results: BTreeMap<String, DataflowDaemonResult>,
+}
+
+impl StoredDataflow {
+    pub fn is_finished(&self) -> bool {
+        self.machines.iter().all(|m| self.results.contains_key(m))
+    }
+}
+
+impl Store {
+    pub fn open(state_dir: &Path, max_finished: usize) -> eyre::Result<Self> {
+        let dataflows_dir = state_dir.join("dataflows");
+        fs::create_dir_all(&dataflows_dir).wrap_err_with(|| {
+            format!(
+                "failed to create coordinator state dir `{}`",
+                dataflows_dir.display()
+            )
+        })?;
+        Ok(Self {
+            dataflows_dir,
+            max_finished,
+            finished: Default::default(),
+        })
+    }
+
+    /// Loads all stored dataflows, skipping (and logging) unreadable files.
+    ///
+    /// Removes the oldest finished dataflows if more than the maximum number
+    /// are stored.
+    pub fn load_dataflows(&self) -> eyre::Result<Vec<StoredDataflow>> {
+        let mut dataflows = Vec::new();
+        let entries = fs::read_dir(&self.dataflows_dir)
+            .wrap_err_with(|| format!("failed to read `{}`", self.dataflows_dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_dataflow(&path) {
+                Ok(dataflow) => dataflows.push(dataflow),
+                Err(err) => tracing::warn!("skipping stored dataflow: {err:?}"),
+            }
+        }
+        dataflows.sort_by_key(|d| d.uuid);
+
+        self.lock_finished()
+            .extend(dataflows.iter().filter(|d| d.is_finished()).map(|d| d.uuid));
+        let removed = self.prune_finished();
+        dataflows.retain(|d| !removed.contains(&d.uuid));
+        Ok(dataflows)
+    }
+
+    pub fn save_dataflow(&self, dataflow: &StoredDataflow) -> eyre::Result<()> {
+        let path = self.dataflows_dir.join(format!("{}.json", dataflow.uuid));
+        let tmp_path = path.with_extension("json.tmp");
+        let data = serde_json::to_vec_pretty(dataflow).context("failed to serialize dataflow")?;
+        fs::write(&tmp_path, data)
+            .wrap_err_with(|| format!("failed to write `{}`", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .wrap_err_with(|| format!("failed to replace `{}`", path.display()))?;
+
+        if dataflow.is_finished() {
+            self.lock_finished().insert(dataflow.uuid);
+            self.prune_finished();
+        }
+        Ok(())
+    }
+
+    /// Removes the oldest finished dataflows until at most `max_finished` are
+    /// left. Returns the UUIDs of the removed dataflows.
+    fn prune_finished(&self) -> BTreeSet<Uuid> {
+        let mut finished = self.lock_finished();
+        let mut removed = BTreeSet::new();
+        while finished.len() > self.max_finished {
+            let Some(uuid) = finished.pop_first() else {
+                break;
+            };
+            let path = self.dataflows_dir.join(format!("{uuid}.json"));
+            if let Err(err) = fs::remove_file(&path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("failed to remove `{}`: {err}", path.display());
+                }
+            }
+            removed.insert(uuid);
+        }
+        removed
+    }
+
+    fn lock_finished(&self) -> std::sync::MutexGuard<'_, BTreeSet<Uuid>> {
+        self.finished
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn read_dataflow(path: &Path) -> eyre::Result<StoredDataflow> {
+    let data = fs::read(path).wrap_err_with(|| format!("failed to read `{}`", path.display()))?;
+    let dataflow: StoredDataflow = serde_json::from_slice(&data)
+        .wrap_err_with(|| format!("failed to parse `{}`", path.display()))?;
+    let file_stem = path.file_stem().context("no file stem")?;
+    if file_stem.to_str() != Some(dataflow.uuid.to_string().as_str()) {
+        eyre::bail!(
+            "file name of `{}` does not match dataflow UUID {}",
+            path.display(),
+            dataflow.uuid
+        );
+    }
+    Ok(dataflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::message::uhlc::HLC;
+    use uuid::{NoContext, Timestamp};
+
+    /// Creates a dataflow that was started `secs` seconds after the epoch.
+    fn dataflow(secs: u64, finished: bool) -> StoredDataflow {
+        let mut results = BTreeMap::new();
+        if finished {
+            results.insert(
+                "A".to_owned(),
+                DataflowDaemonResult {
+                    timestamp: HLC::default().new_timestamp(),
+                    node_results: BTreeMap::new(),
+                },
+            );
+        }
+        StoredDataflow {
+            uuid: Uuid::new_v7(Timestamp::from_unix(NoContext, secs, 0)),
+            name: Some(format!("dataflow-{secs}")),
+            descriptor: serde_json::from_str(r#"{"nodes": []}"#).unwrap(),
+            working_dir: PathBuf::from("/tmp"),
+            nodes: Vec::new(),
+            machines: ["A".to_owned()].into(),
+            results,
+        }
+    }
+
+    fn stored_uuids(store: &Store) -> Vec<Uuid> {
+        store
+            .load_dataflows()
+            .unwrap()
+            .into_iter()
+            .map(|d| d.uuid)
+            .collect()
+    }
+
+    #[test]
+    fn save_and_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let store = Store::open(dir, 10).unwrap();
+        let running = dataflow(1, false);
+        let finished = dataflow(2, true);
+        store.save_dataflow(&running).unwrap();
+        store.save_dataflow(&finished).unwrap();
+
+        let loaded = Store::open(dir, 10).unwrap().load_dataflows().unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].uuid, running.uuid);
+        assert!(!loaded[0].is_finished());
+        assert_eq!(loaded[1].name, finished.name);
+        assert!(loaded[1].is_finished());
+    }
+
+    #[test]
+    fn oldest_finished_dataflows_are_pruned() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let store = Store::open(dir, 2).unwrap();
+        let running = dataflow(1, false);
+        store.save_dataflow(&running).unwrap();
+        let finished: Vec<_> = (2..6).map(|secs| dataflow(secs, true)).collect();
+        for dataflow in &finished {
+            store.save_dataflow(dataflow).unwrap();
+        }
+
+        // running dataflows are never pruned
+        assert_eq!(
+            stored_uuids(&store),
+            vec![running.uuid, finished[2].uuid, finished[3].uuid]
+        );
+    }
+
+    #[test]
+    fn pruned_on_load_with_lower_limit() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let store = Store::open(dir, 10).unwrap();
+        let finished: Vec<_> = (1..4).map(|secs| dataflow(secs, true)).collect();
+        for dataflow in &finished {
+            store.save_dataflow(dataflow).unwrap();
+        }
+
+        let store = Store::open(dir, 1).unwrap();
+        assert_eq!(stored_uuids(&store), vec![finished[2].uuid]);
+        assert_eq!(fs::read_dir(dir.join("dataflows")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn invalid_files_are_skipped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let store = Store::open(dir, 10).unwrap();
+        let valid = dataflow(1, true);
+        store.save_dataflow(&valid).unwrap();
+        fs::write(dir.join("dataflows").join("garbage.json"), "{").unwrap();
+        // the file name must match the UUID
+        let mismatched = serde_json::to_vec(&dataflow(2, true)).unwrap();
+        let path = dir.join("dataflows").join(format!("{}.json", Uuid::nil()));
+        fs::write(path, mismatched).unwrap();
+
+        assert_eq!(stored_uuids(&store), vec![valid.uuid]);
+    }
+}
@@ -0,0 +1,158 @@
+//! Compression of outputs that are sent to other machines.
+
+use aligned_vec::{AVec, ConstAlign};
+use dora_core::{config::Compression, message::ArrowTypeInfo, topics::CompressionStats};
+use eyre::{bail, Context};
+use std::time::Instant;
+
+/// Compresses the data of an output with the given type info and records the
+/// result in `stats`.
+pub fn compress(
+    compression: Compression,
+    data: &[u8],
+    type_info: &ArrowTypeInfo,
+    stats: &mut CompressionStats,
+) -> eyre::Result<AVec<u8, ConstAlign<128>>> {
+    // bytes after the last buffer are never read, so they are not sent
+    let data = &data[..data.len().min(data_len(type_info))];
+    let start = Instant::now();
+    let compressed = match compression {
+        Compression::Lz4 => lz4_flex::compress_prepend_size(data),
+        Compression::Zstd(level) => {
+            zstd::bulk::compress(data, level).wrap_err("failed to compress with zstd")?
+        }
+    };
+    stats.compression_time += start.elapsed();
+    stats.compressed_messages += 1;
+    stats.uncompressed_bytes += data.len() as u64;
+    stats.compressed_bytes += compressed.len() as u64;
+    Ok(AVec::from_slice(128, &compressed))
+}
+
+/// Decompresses data that was compressed by [`compress`] and records the
+/// time in `stats`.
+///
+/// Fails if the data decompresses to more bytes than the given type info
+/// describes.
+pub fn decompress(
+    compression: Compression,
+    data: &[u8],
+    type_info: &ArrowTypeInfo,
+    stats: &mut CompressionStats,
+) -> eyre::Result<AVec<u8, ConstAlign<128>>> {
+    let max_len = data_len(type_info);
+    let start = Instant::now();
+    let decompressed = match compression {
+        Compression::Lz4 => {
+            let (len, compressed) = lz4_flex::block::uncompressed_size(data)
+                .wrap_err("failed to read size of lz4 data")?;
+            if len > max_len {
+                bail!(
+                    "lz4 data claims to be {len} bytes long, \
+                    but the output has only {max_len} bytes"
+                );
+            }
+            lz4_flex::decompress(compressed, len).wrap_err("failed to decompress lz4 data")?
+        }
+        Compression::Zstd(_) => {
+            zstd::bulk::decompress(data, max_len).wrap_err("failed to decompress zstd data")?
+        }
+    };
+    stats.decompression_time += start.elapsed();
+    stats.decompressed_messages += 1;
+    Ok(AVec::from_slice(128, &decompressed))
+}
+
+/// Number of bytes that are needed for the buffers of the given type info.
+fn data_len(type_info: &ArrowTypeInfo) -> usize {
+    let buffers = type_info
+        .buffer_offsets
+        .iter()
+        .map(|buffer| buffer.offset.saturating_add(buffer.len));
+    let children = type_info.child_data.iter().map(data_len);
+    buffers.chain(children).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> Vec<u8> {
+        (0..10_000u32)
+            .flat_map(|i| (i % 100).to_le_bytes())
+            .collect()
+    }
+
+    fn round_trip(compression: Compression) {
+        let data = data();
+        let type_info = ArrowTypeInfo::byte_array(data.len());
+        let mut stats = CompressionStats::default();
+        let compressed = compress(compression, &data, &type_info, &mut stats).unwrap();
+        assert!(compressed.len() < data.len());
+        let decompressed = decompress(compression, &compressed, &type_info, &mut stats).unwrap();
+        assert_eq!(&decompressed[..], &data[..]);
+    }
+
+    #[test]
+    fn lz4_round_trip() {
+        round_trip(Compression::Lz4);
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        round_trip(Compression::Zstd(3));
+    }
+
+    #[test]
+    fn stats_are_accumulated() {
+        let data = data();
+        let type_info = ArrowTypeInfo::byte_array(data.len());
+        let mut stats = CompressionStats::default();
+        let mut compressed_bytes = 0;
+        for compression in [Compression::Lz4, Compression::Zstd(1)] {
+            let compressed = compress(compression, &data, &type_info, &mut stats).unwrap();
+            compressed_bytes += compressed.len() as u64;
+            decompress(compression, &compressed, &type_info, &mut stats).unwrap();
+        }
+        assert_eq!(stats.compressed_messages, 2);
+        assert_eq!(stats.decompressed_messages, 2);
+        assert_eq!(stats.uncompressed_bytes, 2 * data.len() as u64);
+        assert_eq!(stats.compressed_bytes, compressed_bytes);
+    }
+
+    #[test]
+    fn unused_trailing_bytes_are_not_sent() {
+        let data = data();
+        let type_info = ArrowTypeInfo::byte_array(100);
+        let mut stats = CompressionStats::default();
+        let compressed = compress(Compression::Lz4, &data, &type_info, &mut stats).unwrap();
+        assert_eq!(stats.uncompressed_bytes, 100);
+        let decompressed =
+            decompress(Compression::Lz4, &compressed, &type_info, &mut stats).unwrap();
+        assert_eq!(&decompressed[..], &data[..100]);
+    }
+
+    #[test]
+    fn size_is_limited_by_type_info() {
+        let data = data();
+        let mut stats = CompressionStats::default();
+        for compression in [Compression::Lz4, Compression::Zstd(3)] {
+            let compressed = compress(
+                compression,
+                &data,
+                &ArrowTypeInfo::byte_array(data.len()),
+                &mut stats,
+            )
+            .unwrap();
+            let smaller = ArrowTypeInfo::byte_array(data.len() - 1);
+            assert!(decompress(compression, &compressed, &smaller, &mut stats).is_err());
+        }
+
+        // the size prefix is checked before allocating
+        let mut forged = u32::MAX.to_le_bytes().to_vec();
+        forged.extend_from_slice(&[0; 16]);
+        let type_info = ArrowTypeInfo::byte_array(data.len());
+        let err = decompress(Compression::Lz4, &forged, &type_info, &mut stats).unwrap_err();
+        assert!(err.to_string().contains("claims to be"));
+    }
+}
@@ -3,41 +3,102 @@ use crate::{
     DaemonCoordinatorEvent,
 };
 use dora_core::{
-    coordinator_messages::{CoordinatorRequest, RegisterResult},
-    daemon_messages::{DaemonCoordinatorReply, Timestamped},
+    coordinator_messages::{CoordinatorRequest, DaemonEvent, RegisterResult},
+    daemon_messages::{DaemonCoordinatorReply, DataflowId, Timestamped},
     message::uhlc::HLC,
+    security::tls::{self, Connection, TlsConnector},
 };
 use eyre::{eyre, Context};
-use std::{io::ErrorKind, net::SocketAddr};
-use tokio::{
-    net::TcpStream,
-    sync::{mpsc, oneshot},
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
 };
+use tokio::sync::{mpsc, oneshot};
 use tokio_stream::{wrappers::ReceiverStream, Stream};
 
+const RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Maximum time for registering at the coordinator after reconnecting.
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
 pub struct CoordinatorEvent {
     pub event: DaemonCoordinatorEvent,
     pub reply_tx: oneshot::Sender<Option<DaemonCoordinatorReply>>,
 }
 
-pub async fn register(
+pub async fn connect(addr: SocketAddr, tls: Option<&TlsConnector>) -> eyre::Result<Connection> {
+    tls::connect(addr, tls)
+        .await
+        .wrap_err("failed to connect to dora-coordinator")
+}
+
+/// Tries to connect to the coordinator until it succeeds, using exponential backoff.
+async fn connect_with_backoff(addr: SocketAddr, tls: Option<TlsConnector>) -> Connection {
+    let mut backoff = RECONNECT_INITIAL_BACKOFF;
+    loop {
+        tokio::time::sleep(backoff).await;
+        match connect(addr, tls.as_ref()).await {
+            Ok(stream) => break stream,
+            Err(err) => {
+                tracing::debug!("{err:?}");
+                backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+                tracing::info!("failed to reconnect to dora-coordinator, retrying in {backoff:?}");
+            }
+        }
+    }
+}
+
+/// Opens a connection for sending events to the coordinator and authenticates
+/// it if needed.
+async fn open_event_stream(
     addr: SocketAddr,
+    tls: Option<&TlsConnector>,
+    token: Option<&str>,
+    clock: &HLC,
+) -> eyre::Result<Connection> {
+    let mut stream = connect(addr, tls).await?;
+    if let Some(token) = token {
+        let message = serde_json::to_vec(&Timestamped {
+            inner: CoordinatorRequest::Authenticate {
+                token: token.to_owned(),
+            },
+            timestamp: clock.new_timestamp(),
+        })?;
+        socket_stream_send(&mut stream, &message)
+            .await
+            .wrap_err("failed to authenticate at dora-coordinator")?;
+    }
+    Ok(stream)
+}
+
+/// Registers the daemon at the coordinator through the given connection.
+///
+/// The `running_dataflows` are used by the coordinator to reconcile its state
+/// when the daemon re-registers after a connection loss.
+pub async fn register(
+    mut stream: Connection,
     machine_id: String,
     listen_port: u16,
+    running_dataflows: BTreeSet<DataflowId>,
+    token: Option<String>,
+    labels: BTreeMap<String, String>,
     clock: &HLC,
 ) -> eyre::Result<impl Stream<Item = Timestamped<CoordinatorEvent>>> {
-    let mut stream = TcpStream::connect(addr)
-        .await
-        .wrap_err("failed to connect to dora-coordinator")?;
-    stream
-        .set_nodelay(true)
-        .wrap_err("failed to set TCP_NODELAY")?;
+    let addr = stream.peer_addr()?;
     let register = serde_json::to_vec(&Timestamped {
         inner: CoordinatorRequest::Register {
             dora_version: env!("CARGO_PKG_VERSION").to_owned(),
             machine_id,
             listen_port,
+            running_dataflows,
+            token,
+            labels,
         },
         timestamp: clock.new_timestamp(),
     })?;
@@ -69,11 +130,12 @@ pub async fn register(
                         continue;
                     }
                 },
-                Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
                 Err(err) => {
+                    // the connection is unusable after an I/O error
                     let err = eyre!(err).wrap_err("failed to receive incoming event");
                     tracing::warn!("{err:?}");
-                    continue;
+                    break;
                 }
             };
             let Timestamped {
@@ -125,3 +187,328 @@ pub async fn register(
 
     Ok(ReceiverStream::new(rx))
 }
+
+/// Result of [`CoordinatorConnection::reconnect`].
+pub struct Reconnected {
+    /// Events sent by the coordinator after registering again.
+    pub events: Pin<Box<dyn Stream<Item = Timestamped<CoordinatorEvent>> + Send>>,
+    /// New connection for sending events to the coordinator.
+    pub event_stream: Connection,
+}
+
+impl fmt::Debug for Reconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reconnected").finish_non_exhaustive()
+    }
+}
+
+/// Connection for sending events to the coordinator.
+///
+/// Events that must not get lost, such as dataflow results, are queued while
+/// the coordinator is unreachable and sent again after reconnecting.
+pub struct CoordinatorConnection {
+    addr: SocketAddr,
+    /// Port of the inter-daemon listener, which is sent on register.
+    listen_port: u16,
+    tls: Option<TlsConnector>,
+    /// Token for authenticating at the coordinator, if required.
+    token: Option<String>,
+    /// Machine labels, which are sent on register.
+    labels: BTreeMap<String, String>,
+    clock: Arc<HLC>,
+    stream: Option<Connection>,
+    queued: Vec<Timestamped<CoordinatorRequest>>,
+    /// Incremented on every reconnect to detect outdated disconnect events.
+    generation: u64,
+    reconnecting: bool,
+}
+
+impl CoordinatorConnection {
+    pub async fn connect(
+        addr: SocketAddr,
+        listen_port: u16,
+        tls: Option<TlsConnector>,
+        token: Option<String>,
+        labels: BTreeMap<String, String>,
+        clock: Arc<HLC>,
+    ) -> eyre::Result<Self> {
+        let mut connection = Self {
+            addr,
+            listen_port,
+            tls,
+            token,
+            labels,
+            clock,
+            stream: None,
+            queued: Vec::new(),
+            generation: 0,
+            reconnecting: false,
+        };
+        connection.stream = Some(connection.open_event_stream().await?);
+        Ok(connection)
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub fn is_reconnecting(&self) -> bool {
+        self.reconnecting
+    }
+
+    /// Drops the current connection and marks the connection as reconnecting.
+    ///
+    /// Returns `false` if a reconnect is already in progress.
+    pub fn start_reconnect(&mut self) -> bool {
+        self.stream = None;
+        !std::mem::replace(&mut self.reconnecting, true)
+    }
+
+    /// Aborts the current reconnect attempt, e.g. because registering failed.
+    pub fn reconnect_failed(&mut self) {
+        self.reconnecting = false;
+    }
+
+    /// Dataflows that finished while the coordinator was unreachable.
+    ///
+    /// They are still reported as running on register because their results
+    /// were not sent to the coordinator yet.
+    pub fn unreported_dataflows(&self) -> impl Iterator<Item = DataflowId> + '_ {
+        self.queued
+            .iter()
+            .filter_map(|request| match &request.inner {
+                CoordinatorRequest::Event {
+                    event: DaemonEvent::AllNodesFinished { dataflow_id, .. },
+                    ..
+                } => Some(*dataflow_id),
+                _ => None,
+            })
+    }
+
+    /// Returns a future that reconnects to the coordinator with exponential
+    /// backoff and registers the daemon again, retrying until it succeeds.
+    ///
+    /// The future doesn't borrow the connection, so that it can run in a
+    /// separate task without blocking the event loop of the daemon.
+    pub fn reconnect(
+        &self,
+        machine_id: String,
+        running_dataflows: BTreeSet<DataflowId>,
+    ) -> impl Future<Output = Reconnected> + Send + 'static {
+        let addr = self.addr;
+        let listen_port = self.listen_port;
+        let tls = self.tls.clone();
+        let token = self.token.clone();
+        let labels = self.labels.clone();
+        let clock = self.clock.clone();
+        async move {
+            loop {
+                let stream = connect_with_backoff(addr, tls.clone()).await;
+                let register = async {
+                    let events = register(
+                        stream,
+                        machine_id.clone(),
+                        listen_port,
+                        running_dataflows.clone(),
+                        token.clone(),
+                        labels.clone(),
+                        &clock,
+                    )
+                    .await?;
+                    let event_stream =
+                        open_event_stream(addr, tls.as_ref(), token.as_deref(), &clock).await?;
+                    eyre::Ok(Reconnected {
+                        events: Box::pin(events),
+                        event_stream,
+                    })
+                };
+                match tokio::time::timeout(REGISTER_TIMEOUT, register)
+                    .await
+                    .wrap_err("timeout")
+                    .and_then(|r| r)
+                {
+                    Ok(reconnected) => break reconnected,
+                    Err(err) => tracing::warn!(
+                        "{:?}",
+                        err.wrap_err("failed to register at dora-coordinator")
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Switches to the event connection of a successful reconnect and sends
+    /// all queued events.
+    pub async fn reconnected(&mut self, mut stream: Connection) -> eyre::Result<()> {
+        for request in &self.queued {
+            let message = serde_json::to_vec(request)?;
+            socket_stream_send(&mut stream, &message)
+                .await
+                .wrap_err("failed to send queued event to dora-coordinator")?;
+        }
+        self.queued.clear();
+        self.stream = Some(stream);
+        self.generation += 1;
+        self.reconnecting = false;
+        Ok(())
+    }
+
+    /// Connects to the coordinator and authenticates the connection if needed.
+    async fn open_event_stream(&self) -> eyre::Result<Connection> {
+        open_event_stream(
+            self.addr,
+            self.tls.as_ref(),
+            self.token.as_deref(),
+            &self.clock,
+        )
+        .await
+    }
+
+    /// Sends the given request to the coordinator.
+    ///
+    /// Connection errors are not returned. Instead, the connection is marked as
+    /// disconnected and important events are queued for a later reconnect.
+    pub async fn send(&mut self, request: Timestamped<CoordinatorRequest>) -> eyre::Result<()> {
+        let message = serde_json::to_vec(&request)?;
+        if let Some(stream) = &mut self.stream {
+            match socket_stream_send(stream, &message).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    tracing::warn!("failed to send message to dora-coordinator: {err}");
+                    self.stream = None;
+                }
+            }
+        }
+        let important = match &request.inner {
+            CoordinatorRequest::Event { event, .. } => matches!(
+                event,
+                DaemonEvent::AllNodesReady { .. } | DaemonEvent::AllNodesFinished { .. }
+            ),
+            CoordinatorRequest::Register { .. } | CoordinatorRequest::Authenticate { .. } => false,
+        };
+        if important {
+            self.queued.push(request);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn receive_request(stream: &mut TcpStream) -> CoordinatorRequest {
+        let raw = socket_stream_receive(stream).await.unwrap();
+        serde_json::from_slice::<Timestamped<CoordinatorRequest>>(&raw)
+            .unwrap()
+            .inner
+    }
+
+    async fn reply_register(stream: &mut TcpStream, result: RegisterResult, clock: &HLC) {
+        let reply = serde_json::to_vec(&Timestamped {
+            inner: result,
+            timestamp: clock.new_timestamp(),
+        })
+        .unwrap();
+        socket_stream_send(stream, &reply).await.unwrap();
+    }
+
+    fn all_nodes_ready(dataflow_id: DataflowId, clock: &HLC) -> Timestamped<CoordinatorRequest> {
+        Timestamped {
+            inner: CoordinatorRequest::Event {
+                machine_id: "A".into(),
+                event: DaemonEvent::AllNodesReady {
+                    dataflow_id,
+                    exited_before_subscribe: Vec::new(),
+                },
+            },
+            timestamp: clock.new_timestamp(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_retries_and_sends_queued_events() {
+        let clock = Arc::new(HLC::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut connection =
+            CoordinatorConnection::connect(addr, 1234, None, None, BTreeMap::new(), clock.clone())
+                .await
+                .unwrap();
+        let (initial_event_stream, _) = listener.accept().await.unwrap();
+        drop(initial_event_stream);
+
+        assert!(connection.start_reconnect());
+        assert!(
+            !connection.start_reconnect(),
+            "reconnect already in progress"
+        );
+        assert!(!connection.is_connected());
+
+        let dataflow_id = DataflowId::now_v7();
+        connection
+            .send(all_nodes_ready(dataflow_id, &clock))
+            .await
+            .unwrap();
+
+        let reconnect =
+            tokio::spawn(connection.reconnect("A".into(), [dataflow_id].into_iter().collect()));
+
+        // the first register attempt is rejected, so the daemon needs to retry
+        let (mut register_stream, _) = listener.accept().await.unwrap();
+        assert!(matches!(
+            receive_request(&mut register_stream).await,
+            CoordinatorRequest::Register { .. }
+        ));
+        reply_register(
+            &mut register_stream,
+            RegisterResult::Err("busy".into()),
+            &clock,
+        )
+        .await;
+
+        let (mut register_stream, _) = listener.accept().await.unwrap();
+        match receive_request(&mut register_stream).await {
+            CoordinatorRequest::Register {
+                machine_id,
+                listen_port,
+                running_dataflows,
+                ..
+            } => {
+                assert_eq!(machine_id, "A");
+                assert_eq!(listen_port, 1234);
+                assert!(running_dataflows.contains(&dataflow_id));
+            }
+            other => panic!("unexpected request {other:?}"),
+        }
+        reply_register(&mut register_stream, RegisterResult::Ok, &clock).await;
+        let (mut event_stream, _) = listener.accept().await.unwrap();
+
+        let reconnected = reconnect.await.unwrap();
+        connection
+            .reconnected(reconnected.event_stream)
+            .await
+            .unwrap();
+        assert!(connection.is_connected());
+        assert!(!connection.is_reconnecting());
+        assert_eq!(connection.generation(), 1);
+        assert_eq!(connection.unreported_dataflows().count(), 0);
+
+        match receive_request(&mut event_stream).await {
+            CoordinatorRequest::Event {
+                event:
+                    DaemonEvent::AllNodesReady {
+                        dataflow_id: id, ..
+                    },
+                ..
+            } => assert_eq!(id, dataflow_id),
+            other => panic!("unexpected request {other:?}"),
+        }
+    }
+}
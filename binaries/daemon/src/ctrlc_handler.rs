@@ -0,0 +1,174 @@
+//! Process-wide handling of ctrl-c signals.
+//!
+//! The `ctrlc` crate allows to set the signal handler only once per process.
+//! This module installs it on the first subscription and forwards each signal
+//! to all current subscribers, so that multiple dataflows can be run or
+//! attached to from the same process.
+
+use eyre::{eyre, Context};
+use std::{collections::BTreeMap, sync::Mutex};
+
+static SUBSCRIBERS: Subscribers = Subscribers::new();
+
+/// Calls `on_ctrlc` on the next ctrl-c signal, until the returned subscription
+/// is dropped.
+///
+/// A signal that arrives when all subscribers were notified already, or when
+/// there are no subscribers, aborts the process.
+pub fn subscribe_ctrlc(
+    on_ctrlc: impl FnMut() -> eyre::Result<()> + Send + 'static,
+) -> eyre::Result<CtrlcSubscription> {
+    SUBSCRIBERS.set_handler()?;
+    Ok(SUBSCRIBERS.subscribe(Box::new(on_ctrlc)))
+}
+
+/// Unsubscribes from ctrl-c signals when dropped.
+#[must_use]
+pub struct CtrlcSubscription {
+    subscribers: &'static Subscribers,
+    id: u64,
+}
+
+impl Drop for CtrlcSubscription {
+    fn drop(&mut self) {
+        self.subscribers.lock().subscribers.remove(&self.id);
+    }
+}
+
+type Callback = Box<dyn FnMut() -> eyre::Result<()> + Send>;
+
+struct Subscribers {
+    state: Mutex<State>,
+}
+
+struct State {
+    handler_set: bool,
+    next_id: u64,
+    subscribers: BTreeMap<u64, Subscriber>,
+}
+
+struct Subscriber {
+    on_ctrlc: Callback,
+    notified: bool,
+}
+
+impl Subscribers {
+    const fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                handler_set: false,
+                next_id: 0,
+                subscribers: BTreeMap::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn set_handler(&'static self) -> eyre::Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| eyre!("ctrl-c handler state is poisoned"))?;
+        if !state.handler_set {
+            ctrlc::set_handler(|| self.handle_ctrlc()).wrap_err("failed to set ctrl-c handler")?;
+            state.handler_set = true;
+        }
+        Ok(())
+    }
+
+    fn subscribe(&'static self, on_ctrlc: Callback) -> CtrlcSubscription {
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.subscribers.insert(
+            id,
+            Subscriber {
+                on_ctrlc,
+                notified: false,
+            },
+        );
+        CtrlcSubscription {
+            subscribers: self,
+            id,
+        }
+    }
+
+    fn handle_ctrlc(&self) {
+        let mut state = self.lock();
+        if state.subscribers.is_empty() {
+            tracing::warn!("received ctrlc signal while no dataflow is running -> aborting");
+            std::process::abort();
+        }
+        let mut notified_any = false;
+        for subscriber in state.subscribers.values_mut().filter(|s| !s.notified) {
+            subscriber.notified = true;
+            notified_any = true;
+            if let Err(err) = (subscriber.on_ctrlc)() {
+                tracing::error!("failed to handle ctrl-c signal: {err:?}");
+            }
+        }
+        if notified_any {
+            tracing::info!("received ctrlc signal");
+        } else {
+            tracing::warn!("received second ctrlc signal -> aborting immediately");
+            std::process::abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn counting(subscribers: &'static Subscribers) -> (CtrlcSubscription, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let subscription = subscribers.subscribe(Box::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }));
+        (subscription, count)
+    }
+
+    #[test]
+    fn signals_are_forwarded_to_all_subscribers() {
+        // a separate instance, so that the test doesn't interfere with the
+        // process-wide handler
+        let subscribers: &'static Subscribers = Box::leak(Box::new(Subscribers::new()));
+        let (first, first_count) = counting(subscribers);
+        let (_second, second_count) = counting(subscribers);
+
+        subscribers.handle_ctrlc();
+        assert_eq!(first_count.load(Ordering::SeqCst), 1);
+        assert_eq!(second_count.load(Ordering::SeqCst), 1);
+
+        // dropped subscriptions are not notified anymore
+        drop(first);
+        assert_eq!(subscribers.lock().subscribers.len(), 1);
+
+        // the next signal goes to subscribers that were not notified yet
+        let (_third, third_count) = counting(subscribers);
+        subscribers.handle_ctrlc();
+        assert_eq!(second_count.load(Ordering::SeqCst), 1);
+        assert_eq!(third_count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn failing_subscribers_dont_affect_others() {
+        let subscribers: &'static Subscribers = Box::leak(Box::new(Subscribers::new()));
+        let _failing = subscribers.subscribe(Box::new(|| Err(eyre!("receiver is gone"))));
+        let (_subscription, count) = counting(subscribers);
+
+        subscribers.handle_ctrlc();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
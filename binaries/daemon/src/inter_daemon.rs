@@ -1,65 +1,281 @@
+//! Connections between the daemons of different machines.
+//!
+//! Every connection starts with a handshake frame that contains the ID of the
+//! sending [`InterDaemonConnection`]. All following frames are prefixed with a
+//! sequence number. A frame that was interrupted by a connection error is
+//! resent after reconnecting, so the receiver discards frames whose sequence
+//! number it has already seen.
+
 use crate::socket_stream_utils::{socket_stream_receive, socket_stream_send};
-use dora_core::daemon_messages::{InterDaemonEvent, Timestamped};
+use dora_core::{
+    config::{DataId, NodeId},
+    daemon_messages::{DataflowId, InterDaemonEvent, Timestamped},
+    message::uhlc::HLC,
+};
 use eyre::{Context, ContextCompat};
-use std::{collections::BTreeMap, io::ErrorKind, net::SocketAddr};
-use tokio::net::{TcpListener, TcpStream};
+use futures::FutureExt;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Notify,
+};
+use uuid::Uuid;
+
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Settings for the connections to the daemons of other machines.
+#[derive(Debug, Clone, Copy)]
+pub struct InterDaemonConfig {
+    /// Maximum number of outputs that are buffered per machine while it is
+    /// unreachable. With Zenoh, it limits the outputs that are queued for
+    /// publishing per dataflow instead.
+    ///
+    /// Further outputs are dropped. The receiving nodes are notified about the
+    /// number of lost messages once the connection works again. The daemon
+    /// never waits for a remote machine.
+    pub buffer_size: usize,
+}
+
+impl Default for InterDaemonConfig {
+    fn default() -> Self {
+        Self { buffer_size: 100 }
+    }
+}
 
+/// Number of lost messages, by dataflow and output.
+type LostMessages = BTreeMap<(DataflowId, NodeId, DataId), u64>;
+
+/// Messages that wait for being sent to a machine.
+#[derive(Default)]
+struct SendQueue {
+    messages: VecDeque<(Arc<Vec<u8>>, bool)>,
+    /// Number of queued messages that belong to an output.
+    queued_outputs: usize,
+    /// Outputs that were dropped because the queue was full.
+    lost: LostMessages,
+}
+
+/// Connection to the daemon of another machine.
+///
+/// Messages are sent by a background task, which reconnects with an
+/// exponential backoff if the connection breaks.
 pub struct InterDaemonConnection {
     socket: SocketAddr,
-    connection: Option<TcpStream>,
+    queue: Arc<Mutex<SendQueue>>,
+    notify: Arc<Notify>,
+    /// Maximum number of queued outputs.
+    output_limit: usize,
+    /// Cancels the background task on drop.
+    _task: futures::future::RemoteHandle<()>,
 }
 
 impl InterDaemonConnection {
-    pub fn new(socket: SocketAddr) -> Self {
+    pub fn new(
+        machine_id: String,
+        socket: SocketAddr,
+        config: InterDaemonConfig,
+        clock: Arc<HLC>,
+    ) -> Self {
+        let queue = Arc::new(Mutex::new(SendQueue::default()));
+        let notify = Arc::new(Notify::new());
+        let (task, handle) =
+            send_loop(machine_id, socket, queue.clone(), notify.clone(), clock).remote_handle();
+        tokio::spawn(task);
         Self {
             socket,
-            connection: None,
+            queue,
+            notify,
+            output_limit: config.buffer_size.max(1),
+            _task: handle,
         }
     }
 
-    #[tracing::instrument(skip(self), fields(%self.socket))]
-    async fn connect(&mut self) -> eyre::Result<&mut TcpStream> {
-        match &mut self.connection {
-            Some(c) => Ok(c),
-            entry @ None => {
-                let connection = TcpStream::connect(self.socket)
-                    .await
-                    .wrap_err("failed to connect")?;
-                connection
-                    .set_nodelay(true)
-                    .wrap_err("failed to set nodelay")?;
-                Ok(entry.insert(connection))
+    pub fn socket(&self) -> SocketAddr {
+        self.socket
+    }
+
+    /// Queues the given message for sending, without waiting.
+    ///
+    /// Messages that belong to an output are dropped if too many outputs are
+    /// queued already. Other messages are never dropped.
+    fn send(&self, message: Arc<Vec<u8>>, output: Option<(DataflowId, NodeId, DataId)>) {
+        let mut queue = self.queue.lock().unwrap();
+        match output {
+            Some(output) if queue.queued_outputs >= self.output_limit => {
+                tracing::trace!("send buffer for `{}` is full, dropping output", self.socket);
+                *queue.lost.entry(output).or_default() += 1;
+            }
+            output => {
+                queue.queued_outputs += usize::from(output.is_some());
+                queue.messages.push_back((message, output.is_some()));
             }
         }
+        drop(queue);
+        self.notify.notify_one();
     }
+}
 
-    pub fn socket(&self) -> SocketAddr {
-        self.socket
+async fn send_loop(
+    machine_id: String,
+    socket: SocketAddr,
+    queue: Arc<Mutex<SendQueue>>,
+    notify: Arc<Notify>,
+    clock: Arc<HLC>,
+) {
+    let id = Uuid::now_v7();
+    let mut connection = None;
+    let mut reconnect_delay = INITIAL_RECONNECT_DELAY;
+    let mut next_seq = 1;
+    // frame that is currently sent, which is kept until it was written completely
+    let mut in_flight: Option<(u64, Arc<Vec<u8>>)> = None;
+    loop {
+        let (seq, message) = match &in_flight {
+            Some(frame) => frame.clone(),
+            None => {
+                let next = {
+                    let mut queue = queue.lock().unwrap();
+                    // report lost messages before the next message
+                    let lost = std::mem::take(&mut queue.lost);
+                    if !lost.is_empty() {
+                        let event = Timestamped {
+                            inner: InterDaemonEvent::ConnectionGap { lost },
+                            timestamp: clock.new_timestamp(),
+                        };
+                        match bincode::serialize(&event) {
+                            Ok(gap) => Some(Arc::new(gap)),
+                            Err(err) => {
+                                tracing::warn!("failed to serialize ConnectionGap: {err}");
+                                None
+                            }
+                        }
+                    } else {
+                        queue.messages.pop_front().map(|(message, is_output)| {
+                            queue.queued_outputs -= usize::from(is_output);
+                            message
+                        })
+                    }
+                };
+                match next {
+                    Some(message) => {
+                        let frame = (next_seq, message);
+                        next_seq += 1;
+                        in_flight.insert(frame).clone()
+                    }
+                    None => {
+                        notify.notified().await;
+                        continue;
+                    }
+                }
+            }
+        };
+        let stream = match &mut connection {
+            Some(stream) => stream,
+            None => match connect(socket, id).await {
+                Ok(stream) => {
+                    if reconnect_delay > INITIAL_RECONNECT_DELAY {
+                        tracing::info!("reconnected to machine `{machine_id}`");
+                    }
+                    reconnect_delay = INITIAL_RECONNECT_DELAY;
+                    connection.insert(stream)
+                }
+                Err(err) => {
+                    if reconnect_delay == INITIAL_RECONNECT_DELAY {
+                        tracing::warn!(
+                            "{:?}",
+                            err.wrap_err(format!("failed to connect to machine `{machine_id}`"))
+                        );
+                    }
+                    tokio::time::sleep(reconnect_delay).await;
+                    reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                    continue;
+                }
+            },
+        };
+
+        match send_frame(stream, seq, &message).await {
+            Ok(()) => in_flight = None,
+            Err(err) => {
+                tracing::warn!(
+                    "{:?}",
+                    eyre::Report::new(err)
+                        .wrap_err(format!("connection to machine `{machine_id}` broke"))
+                );
+                // the frame is resent after reconnecting; the receiver ignores
+                // it if it was received already
+                connection = None;
+            }
+        }
     }
 }
 
+/// Connects to the given socket and sends the handshake frame.
+async fn connect(socket: SocketAddr, id: Uuid) -> eyre::Result<TcpStream> {
+    let mut connection = TcpStream::connect(socket)
+        .await
+        .wrap_err("failed to connect")?;
+    connection
+        .set_nodelay(true)
+        .wrap_err("failed to set nodelay")?;
+    socket_stream_send(&mut connection, id.as_bytes())
+        .await
+        .wrap_err("failed to send handshake")?;
+    Ok(connection)
+}
+
+async fn send_frame(
+    connection: &mut (impl AsyncWrite + Unpin),
+    seq: u64,
+    message: &[u8],
+) -> std::io::Result<()> {
+    let len_raw = (message.len() as u64 + 8).to_le_bytes();
+    connection.write_all(&len_raw).await?;
+    connection.write_all(&seq.to_le_bytes()).await?;
+    connection.write_all(message).await?;
+    connection.flush().await?;
+    Ok(())
+}
+
+/// Queues the given event for all target machines.
+///
+/// This function never waits for the remote machines.
 #[tracing::instrument(skip(inter_daemon_connections))]
-pub async fn send_inter_daemon_event(
+pub fn send_inter_daemon_event(
     target_machines: &[String],
     inter_daemon_connections: &mut BTreeMap<String, InterDaemonConnection>,
     event: &Timestamped<InterDaemonEvent>,
 ) -> eyre::Result<()> {
-    let message = bincode::serialize(event).wrap_err("failed to serialize InterDaemonEvent")?;
+    let message =
+        Arc::new(bincode::serialize(event).wrap_err("failed to serialize InterDaemonEvent")?);
+    let output = match &event.inner {
+        InterDaemonEvent::Output {
+            dataflow_id,
+            node_id,
+            output_id,
+            ..
+        } => Some((*dataflow_id, node_id.clone(), output_id.clone())),
+        _ => None,
+    };
     for target_machine in target_machines {
-        let connection = inter_daemon_connections
-            .get_mut(target_machine)
+        inter_daemon_connections
+            .get(target_machine)
             .wrap_err_with(|| format!("unknown target machine `{target_machine}`"))?
-            .connect()
-            .await
-            .wrap_err_with(|| format!("failed to connect to machine `{target_machine}`"))?;
-        socket_stream_send(connection, &message)
-            .await
-            .wrap_err_with(|| format!("failed to send event to machine `{target_machine}`"))?;
+            .send(message.clone(), output.clone());
     }
 
     Ok(())
 }
 
+/// Last received sequence number, by sending connection.
+type ReceivedFrames = Arc<Mutex<HashMap<Uuid, u64>>>;
+
 pub async fn spawn_listener_loop(
     bind: SocketAddr,
     machine_id: String,
@@ -88,6 +304,7 @@ async fn listener_loop(
     listener: TcpListener,
     events_tx: flume::Sender<Timestamped<InterDaemonEvent>>,
 ) {
+    let received = ReceivedFrames::default();
     loop {
         match listener
             .accept()
@@ -98,7 +315,11 @@ async fn listener_loop(
                 tracing::info!("{err}");
             }
             Ok((connection, _)) => {
-                tokio::spawn(handle_connection_loop(connection, events_tx.clone()));
+                tokio::spawn(handle_connection_loop(
+                    connection,
+                    events_tx.clone(),
+                    received.clone(),
+                ));
             }
         }
     }
@@ -107,14 +328,41 @@ async fn listener_loop(
 async fn handle_connection_loop(
     mut connection: TcpStream,
     events_tx: flume::Sender<Timestamped<InterDaemonEvent>>,
+    received: ReceivedFrames,
 ) {
     if let Err(err) = connection.set_nodelay(true) {
         tracing::warn!("failed to set nodelay for connection: {err}");
     }
+    let sender = match receive_handshake(&mut connection).await {
+        Ok(Some(sender)) => sender,
+        Ok(None) => return,
+        Err(err) => {
+            tracing::warn!("{err:?}");
+            return;
+        }
+    };
 
     loop {
         match receive_message(&mut connection).await {
-            Ok(Some(message)) => {
+            Ok(Some((seq, message))) => {
+                {
+                    let mut received = received.lock().unwrap();
+                    let last = received.entry(sender).or_default();
+                    if seq <= *last {
+                        // resent after a reconnect, or an outdated frame of a
+                        // previous connection
+                        tracing::trace!("ignoring duplicate inter-daemon frame {seq}");
+                        continue;
+                    }
+                    *last = seq;
+                }
+                let message = match bincode::deserialize(&message) {
+                    Ok(message) => message,
+                    Err(err) => {
+                        tracing::warn!("failed to deserialize InterDaemonEvent: {err}");
+                        continue;
+                    }
+                };
                 if events_tx.send_async(message).await.is_err() {
                     break;
                 }
@@ -128,22 +376,272 @@ async fn handle_connection_loop(
     }
 }
 
-async fn receive_message(
-    connection: &mut TcpStream,
-) -> eyre::Result<Option<Timestamped<InterDaemonEvent>>> {
-    let raw = match socket_stream_receive(connection).await {
-        Ok(raw) => raw,
+async fn receive_handshake(connection: &mut TcpStream) -> eyre::Result<Option<Uuid>> {
+    let Some(raw) = receive_frame(connection).await? else {
+        return Ok(None);
+    };
+    Uuid::from_slice(&raw)
+        .wrap_err("invalid inter-daemon handshake")
+        .map(Some)
+}
+
+/// Receives the next frame and splits off its sequence number.
+///
+/// Incomplete frames of broken connections are discarded.
+async fn receive_message(connection: &mut TcpStream) -> eyre::Result<Option<(u64, Vec<u8>)>> {
+    let Some(mut raw) = receive_frame(connection).await? else {
+        return Ok(None);
+    };
+    if raw.len() < 8 {
+        eyre::bail!("inter-daemon frame without sequence number");
+    }
+    let message = raw.split_off(8);
+    let seq = u64::from_le_bytes(raw.try_into().unwrap());
+    Ok(Some((seq, message)))
+}
+
+async fn receive_frame(connection: &mut TcpStream) -> eyre::Result<Option<Vec<u8>>> {
+    match socket_stream_receive(connection).await {
+        Ok(raw) => Ok(Some(raw)),
         Err(err) => match err.kind() {
             ErrorKind::UnexpectedEof
             | ErrorKind::ConnectionAborted
-            | ErrorKind::ConnectionReset => return Ok(None),
+            | ErrorKind::ConnectionReset => Ok(None),
             _other => {
-                return Err(err)
-                    .context("unexpected I/O error while trying to receive InterDaemonEvent")
+                Err(err).context("unexpected I/O error while trying to receive InterDaemonEvent")
             }
         },
-    };
-    bincode::deserialize(&raw)
-        .wrap_err("failed to deserialize DaemonRequest")
-        .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aligned_vec::AVec;
+    use dora_core::message::{ArrowTypeInfo, Metadata};
+    use tokio::net::TcpSocket;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn addr(port: u16) -> SocketAddr {
+        ([127, 0, 0, 1], port).into()
+    }
+
+    /// Binds a socket to the given port, or to a free one for port `0`.
+    ///
+    /// Connection attempts are refused until the socket starts to listen.
+    fn bind(port: u16) -> TcpSocket {
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.set_reuseaddr(true).unwrap();
+        socket.bind(addr(port)).unwrap();
+        socket
+    }
+
+    fn port(socket: &TcpSocket) -> u16 {
+        socket.local_addr().unwrap().port()
+    }
+
+    fn output(dataflow_id: DataflowId, index: u64, clock: &HLC) -> Timestamped<InterDaemonEvent> {
+        Timestamped {
+            inner: InterDaemonEvent::Output {
+                dataflow_id,
+                node_id: "source".to_owned().into(),
+                output_id: "out".to_owned().into(),
+                metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty()),
+                data: Some(AVec::from_slice(128, &index.to_le_bytes())),
+                compression: None,
+            },
+            timestamp: clock.new_timestamp(),
+        }
+    }
+
+    fn output_index(event: &InterDaemonEvent) -> Option<u64> {
+        match event {
+            InterDaemonEvent::Output {
+                data: Some(data), ..
+            } => Some(u64::from_le_bytes(data[..].try_into().unwrap())),
+            _ => None,
+        }
+    }
+
+    fn connections(
+        port: u16,
+        config: InterDaemonConfig,
+        clock: &Arc<HLC>,
+    ) -> BTreeMap<String, InterDaemonConnection> {
+        let connection =
+            InterDaemonConnection::new("remote".into(), addr(port), config, clock.clone());
+        [("remote".to_owned(), connection)].into()
+    }
+
+    fn listen(socket: TcpSocket) -> flume::Receiver<Timestamped<InterDaemonEvent>> {
+        let (events_tx, events_rx) = flume::unbounded();
+        let listener = socket.listen(1024).unwrap();
+        tokio::spawn(listener_loop(listener, events_tx));
+        events_rx
+    }
+
+    async fn receive(events: &flume::Receiver<Timestamped<InterDaemonEvent>>) -> InterDaemonEvent {
+        tokio::time::timeout(TIMEOUT, events.recv_async())
+            .await
+            .expect("no event received")
+            .unwrap()
+            .inner
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lost_outputs_are_reported_on_reconnect() {
+        let clock = Arc::new(HLC::default());
+        let dataflow_id = Uuid::now_v7();
+        let socket = bind(0);
+        let port = port(&socket);
+        let config = InterDaemonConfig { buffer_size: 2 };
+        let mut connections = connections(port, config, &clock);
+        for i in 0..6 {
+            send_inter_daemon_event(
+                &["remote".into()],
+                &mut connections,
+                &output(dataflow_id, i, &clock),
+            )
+            .unwrap();
+        }
+
+        // the gap is reported after reconnecting, without any further output
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let events = listen(socket);
+        let mut received = Vec::new();
+        let mut lost = 0;
+        while received.len() as u64 + lost < 6 {
+            match receive(&events).await {
+                InterDaemonEvent::ConnectionGap { lost: gap } => {
+                    assert_eq!(gap.len(), 1);
+                    lost += gap.values().sum::<u64>();
+                }
+                event => received.push(output_index(&event).unwrap()),
+            }
+        }
+        assert!(lost >= 3, "only {lost} outputs were lost");
+        assert!(received.windows(2).all(|w| w[0] < w[1]), "{received:?}");
+        assert_eq!(received[0], 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn inputs_closed_events_are_never_dropped() {
+        let clock = Arc::new(HLC::default());
+        let dataflow_id = Uuid::now_v7();
+        let socket = bind(0);
+        let port = port(&socket);
+        let config = InterDaemonConfig { buffer_size: 2 };
+        let mut connections = connections(port, config, &clock);
+        for i in 0..10 {
+            send_inter_daemon_event(
+                &["remote".into()],
+                &mut connections,
+                &output(dataflow_id, i, &clock),
+            )
+            .unwrap();
+        }
+        let closed = Timestamped {
+            inner: InterDaemonEvent::InputsClosed {
+                dataflow_id,
+                inputs: Default::default(),
+            },
+            timestamp: clock.new_timestamp(),
+        };
+        send_inter_daemon_event(&["remote".into()], &mut connections, &closed).unwrap();
+
+        // let the first connection attempts fail
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let events = listen(socket);
+        // outputs are dropped, but the InputsClosed event must arrive
+        while !matches!(
+            receive(&events).await,
+            InterDaemonEvent::InputsClosed { .. }
+        ) {}
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reconnects_after_connection_broke() {
+        let clock = Arc::new(HLC::default());
+        let dataflow_id = Uuid::now_v7();
+        let socket = bind(0);
+        let port = port(&socket);
+        let mut connections = connections(port, InterDaemonConfig::default(), &clock);
+
+        // receive the first output, then close the connection
+        let listener = socket.listen(1024).unwrap();
+        send_inter_daemon_event(
+            &["remote".into()],
+            &mut connections,
+            &output(dataflow_id, 0, &clock),
+        )
+        .unwrap();
+        let (mut connection, _) = tokio::time::timeout(TIMEOUT, listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        let sender = receive_handshake(&mut connection).await.unwrap().unwrap();
+        let (seq, message) = receive_message(&mut connection).await.unwrap().unwrap();
+        assert_eq!(seq, 1);
+        let event: Timestamped<InterDaemonEvent> = bincode::deserialize(&message).unwrap();
+        assert_eq!(output_index(&event.inner), Some(0));
+        drop(connection);
+        drop(listener);
+
+        // outputs that are sent while the connection breaks might be lost, but
+        // the connection must recover
+        let events = listen(bind(port));
+        let mut i = 1;
+        let received = tokio::time::timeout(TIMEOUT, async {
+            loop {
+                send_inter_daemon_event(
+                    &["remote".into()],
+                    &mut connections,
+                    &output(dataflow_id, i, &clock),
+                )
+                .unwrap();
+                i += 1;
+                if let Ok(event) =
+                    tokio::time::timeout(Duration::from_millis(50), events.recv_async()).await
+                {
+                    break event.unwrap().inner;
+                }
+            }
+        })
+        .await
+        .expect("connection was not restored");
+        assert!(output_index(&received).unwrap() >= 1);
+        assert_ne!(sender, Uuid::nil());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resent_frames_are_ignored() {
+        let clock = HLC::default();
+        let dataflow_id = Uuid::now_v7();
+        let (events_tx, events) = flume::unbounded();
+        let port = spawn_listener_loop(addr(0), "local".into(), events_tx)
+            .await
+            .unwrap();
+        let sender = Uuid::now_v7();
+        let frame = |i| bincode::serialize(&output(dataflow_id, i, &clock)).unwrap();
+
+        let mut first = TcpStream::connect(addr(port)).await.unwrap();
+        socket_stream_send(&mut first, sender.as_bytes())
+            .await
+            .unwrap();
+        send_frame(&mut first, 1, &frame(0)).await.unwrap();
+        send_frame(&mut first, 2, &frame(1)).await.unwrap();
+        assert_eq!(output_index(&receive(&events).await), Some(0));
+        assert_eq!(output_index(&receive(&events).await), Some(1));
+
+        // the second frame is resent on a new connection
+        let mut second = TcpStream::connect(addr(port)).await.unwrap();
+        socket_stream_send(&mut second, sender.as_bytes())
+            .await
+            .unwrap();
+        send_frame(&mut second, 2, &frame(1)).await.unwrap();
+        send_frame(&mut second, 3, &frame(2)).await.unwrap();
+        assert_eq!(output_index(&receive(&events).await), Some(2));
+        assert!(events.is_empty());
+    }
 }
@@ -1,7 +1,10 @@
 use aligned_vec::{AVec, ConstAlign};
-use coordinator::CoordinatorEvent;
+use coordinator::{CoordinatorConnection, CoordinatorEvent};
 use crossbeam::queue::ArrayQueue;
-use dora_core::config::{Input, OperatorId};
+use dora_core::config::{
+    Compression, Input, LocalCommunicationConfig, NodeRunConfig, OperatorId,
+    RemoteCommunicationConfig, UserInputMapping,
+};
 use dora_core::coordinator_messages::{CoordinatorRequest, Level, LogMessage};
 use dora_core::daemon_messages::{
     DataMessage, DynamicNodeEvent, InterDaemonEvent, NodeConfig, Timestamped,
@@ -9,9 +12,11 @@ use dora_core::daemon_messages::{
 use dora_core::descriptor::runtime_node_inputs;
 use dora_core::message::uhlc::{self, HLC};
 use dora_core::message::{ArrowTypeInfo, Metadata, MetadataParameters};
+use dora_core::security::{tls::TlsConnector, SecurityConfig};
 use dora_core::topics::LOCALHOST;
 use dora_core::topics::{
-    DataflowDaemonResult, DataflowResult, NodeError, NodeErrorCause, NodeExitStatus,
+    CompressionStats, DataflowDaemonResult, DataflowDaemonStats, DataflowResult, NodeError,
+    NodeErrorCause, NodeExitStatus,
 };
 use dora_core::{
     config::{DataId, InputMapping, NodeId},
@@ -23,18 +28,26 @@ use dora_core::{
     descriptor::{CoreNodeKind, Descriptor, ResolvedNode},
 };
 
+use dora_node_api::arrow::array::ArrayData;
+use dora_node_api::arrow_utils::{copy_array_into_sample, required_data_size};
 use eyre::{bail, eyre, Context, ContextCompat, Result};
 use futures::{future, stream, FutureExt, TryFutureExt};
 use futures_concurrency::stream::Merge;
+pub use inter_daemon::InterDaemonConfig;
 use inter_daemon::InterDaemonConnection;
 use local_listener::DynamicNodeEventWrapper;
+use lockstep::{Lockstep, LockstepEvent};
 use pending::PendingNodes;
 use shared_memory_server::ShmemConf;
-use socket_stream_utils::socket_stream_send;
+use shmem_pool::ShmemPool;
+pub use shmem_registry::cleanup_shared_memory;
+use shmem_registry::ShmemRegistry;
+use simulated_clock::SimulatedClock;
+use std::sync::atomic::{self, AtomicU64};
 use std::sync::Arc;
 use std::time::Instant;
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     net::SocketAddr,
     path::{Path, PathBuf},
     time::Duration,
@@ -42,22 +55,31 @@ use std::{
 use sysinfo::Pid;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot::Sender;
 use tokio::sync::{mpsc, oneshot};
 use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tracing::{error, warn};
 use uuid::{NoContext, Timestamp, Uuid};
+use zenoh_communication::ZenohConnection;
 
+mod compression;
 mod coordinator;
+pub mod ctrlc_handler;
 mod inter_daemon;
 mod local_listener;
+mod lockstep;
 mod log;
 mod node_communication;
 mod pending;
+mod shmem_pool;
+mod shmem_registry;
+mod simulated_clock;
 mod socket_stream_utils;
 mod spawn;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod zenoh_communication;
 
 #[cfg(feature = "telemetry")]
 use dora_tracing::telemetry::serialize_context;
@@ -67,6 +89,8 @@ use tracing_opentelemetry::OpenTelemetrySpanExt;
 use crate::pending::DataflowStatus;
 
 const STDERR_LOG_LINES: usize = 10;
+/// Time after which ad-hoc subscribers that did not connect are removed again.
+const AD_HOC_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct Daemon {
     running: HashMap<DataflowId, RunningDataflow>,
@@ -74,29 +98,113 @@ pub struct Daemon {
 
     events_tx: mpsc::Sender<Timestamped<Event>>,
 
-    coordinator_connection: Option<TcpStream>,
+    coordinator_connection: Option<CoordinatorConnection>,
     last_coordinator_heartbeat: Instant,
     inter_daemon_connections: BTreeMap<String, InterDaemonConnection>,
+    inter_daemon_config: InterDaemonConfig,
+    /// zenoh sessions of finished dataflows that still publish their queued messages
+    closing_zenoh_sessions: Vec<tokio::task::JoinHandle<()>>,
+    /// records the shared memory segments of this daemon for cleaning them up after crashes
+    shmem_registry: ShmemRegistry,
     machine_id: String,
 
     /// used for testing and examples
     exit_when_done: Option<BTreeSet<(Uuid, NodeId)>>,
     /// used to record dataflow results when `exit_when_done` is used
     dataflow_node_results: BTreeMap<Uuid, BTreeMap<NodeId, Result<(), NodeError>>>,
+    /// stop all dataflows when the given node exits (used by `run_dataflow`)
+    stop_after: Option<NodeId>,
+    /// print the stdout and stderr of all spawned nodes, prefixed with the node ID
+    print_node_output: bool,
+    /// inputs to inject into and outputs to capture from spawned dataflows
+    taps: DataflowTaps,
+    /// run spawned dataflows in deterministic lockstep mode
+    deterministic: Option<DeterministicOptions>,
+    /// virtual time after which deterministic dataflows are stopped
+    virtual_timeout: Option<Duration>,
 
     clock: Arc<uhlc::HLC>,
 }
 
 type DaemonRunResult = BTreeMap<Uuid, BTreeMap<NodeId, Result<(), NodeError>>>;
 
+/// Options for running a dataflow locally through [`Daemon::run_dataflow`].
+#[derive(Debug, Clone, Default)]
+pub struct RunDataflowOptions {
+    /// Stop the dataflow after the given duration.
+    pub timeout: Option<Duration>,
+    /// Stop the dataflow as soon as the given node exits.
+    pub stop_after: Option<NodeId>,
+    /// Print the stdout and stderr of all nodes, prefixed with the node ID.
+    pub print_node_output: bool,
+    /// Inject inputs into and capture outputs from the dataflow.
+    pub taps: DataflowTaps,
+    /// Run the dataflow in deterministic lockstep mode.
+    ///
+    /// The `timeout` is measured in virtual time in this mode.
+    pub deterministic: Option<DeterministicOptions>,
+}
+
+/// Options for running a dataflow in deterministic lockstep mode.
+///
+/// Events are delivered one at a time, in a total order, once all nodes are
+/// idle. Timers and injected inputs use a virtual clock that starts at zero and
+/// only advances when no other events are pending. So the same inputs and seed
+/// lead to the same outputs on every run.
+///
+/// Nodes must only send outputs in response to events for this to hold. Runtime
+/// nodes report to be idle as soon as their operators received an event, so
+/// dataflows with operators are rejected. The `send_stdout_as` option is not
+/// deterministic either.
+#[derive(Debug, Clone, Default)]
+pub struct DeterministicOptions {
+    /// Seed for ordering events of different sources that have the same timestamp.
+    pub seed: u64,
+}
+
+/// Taps that feed node inputs and observe node outputs of a locally run dataflow.
+///
+/// Used by the [`testing`] harness.
+#[derive(Debug, Clone, Default)]
+pub struct DataflowTaps {
+    /// Messages to send to the given node inputs.
+    ///
+    /// Injected inputs are disconnected from their mapped source. They are
+    /// closed after the last message was sent.
+    pub inputs: BTreeMap<(NodeId, DataId), Vec<InjectedMessage>>,
+    /// Channels that receive a copy of every message sent on the given outputs.
+    pub outputs: BTreeMap<(NodeId, DataId), UnboundedSender<CapturedOutput>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InjectedMessage {
+    /// Send the message this long after the dataflow was started.
+    pub offset: Duration,
+    pub data: ArrayData,
+}
+
+#[derive(Debug)]
+pub struct CapturedOutput {
+    pub metadata: Metadata,
+    pub data: Option<AVec<u8, ConstAlign<128>>>,
+    /// Time at which the daemon received the output.
+    pub received: uhlc::Timestamp,
+}
+
 impl Daemon {
     pub async fn run(
         coordinator_addr: SocketAddr,
         machine_id: String,
         inter_daemon_addr: SocketAddr,
         local_listen_port: u16,
+        labels: BTreeMap<String, String>,
+        security: SecurityConfig,
+        inter_daemon: InterDaemonConfig,
     ) -> eyre::Result<()> {
         let clock = Arc::new(HLC::default());
+        // explicitly given labels take precedence over the detected ones
+        let labels: BTreeMap<_, _> = default_labels().into_iter().chain(labels).collect();
+        let tls = security.tls.as_ref().map(TlsConnector::new).transpose()?;
 
         let ctrlc_events = set_up_ctrlc_handler(clock.clone())?;
 
@@ -111,19 +219,27 @@ impl Daemon {
         });
 
         // connect to the coordinator
-        let coordinator_events =
-            coordinator::register(coordinator_addr, machine_id.clone(), listen_port, &clock)
-                .await
-                .wrap_err("failed to connect to dora-coordinator")?
-                .map(
-                    |Timestamped {
-                         inner: event,
-                         timestamp,
-                     }| Timestamped {
-                        inner: Event::Coordinator(event),
-                        timestamp,
-                    },
-                );
+        let coordinator_events = coordinator::register(
+            coordinator::connect(coordinator_addr, tls.as_ref()).await?,
+            machine_id.clone(),
+            listen_port,
+            BTreeSet::new(),
+            security.token.clone(),
+            labels.clone(),
+            &clock,
+        )
+        .await
+        .wrap_err("failed to connect to dora-coordinator")?;
+        let coordinator_events = coordinator_event_stream(coordinator_events, 0, clock.clone());
+        let coordinator_connection = CoordinatorConnection::connect(
+            coordinator_addr,
+            listen_port,
+            tls,
+            security.token,
+            labels,
+            clock.clone(),
+        )
+        .await?;
 
         // Spawn local listener loop
         let (events_tx, events_rx) = flume::bounded(10);
@@ -145,16 +261,24 @@ impl Daemon {
                 dynamic_node_events,
             )
                 .merge(),
-            Some(coordinator_addr),
+            Some(coordinator_connection),
             machine_id,
             None,
+            RunDataflowOptions::default(),
+            inter_daemon,
             clock,
         )
         .await
         .map(|_| ())
     }
 
-    pub async fn run_dataflow(dataflow_path: &Path) -> eyre::Result<DataflowResult> {
+    /// Runs the given dataflow locally, without connecting to a coordinator.
+    ///
+    /// Returns once all nodes of the dataflow have exited.
+    pub async fn run_dataflow(
+        dataflow_path: &Path,
+        options: RunDataflowOptions,
+    ) -> eyre::Result<DataflowResult> {
         let working_dir = dataflow_path
             .canonicalize()
             .context("failed to canoncialize dataflow path")?
@@ -164,7 +288,33 @@ impl Daemon {
 
         let descriptor = Descriptor::read(dataflow_path).await?;
         descriptor.check(&working_dir)?;
+        if options.deterministic.is_some() {
+            descriptor.check_deterministic()?;
+        }
         let nodes = descriptor.resolve_aliases_and_set_defaults()?;
+        if let Some(stop_after) = &options.stop_after {
+            if !nodes.iter().any(|n| &n.id == stop_after) {
+                bail!("no node with ID `{stop_after}` in dataflow");
+            }
+        }
+        for (node_id, input_id) in options.taps.inputs.keys() {
+            let node = nodes
+                .iter()
+                .find(|n| &n.id == node_id)
+                .ok_or_else(|| eyre!("cannot inject input: no node with ID `{node_id}`"))?;
+            if !node_inputs(node).contains_key(input_id) {
+                bail!("cannot inject input: node `{node_id}` has no input `{input_id}`");
+            }
+        }
+        for (node_id, output_id) in options.taps.outputs.keys() {
+            let node = nodes
+                .iter()
+                .find(|n| &n.id == node_id)
+                .ok_or_else(|| eyre!("cannot capture output: no node with ID `{node_id}`"))?;
+            if !node.kind.run_config().outputs.contains(output_id) {
+                bail!("cannot capture output: node `{node_id}` has no output `{output_id}`");
+            }
+        }
 
         let dataflow_id = Uuid::new_v7(Timestamp::now(NoContext));
         let spawn_command = SpawnDataflowNodes {
@@ -177,6 +327,8 @@ impl Daemon {
 
         let clock = Arc::new(HLC::default());
 
+        let ctrlc_events = set_up_ctrlc_handler(clock.clone())?;
+
         let exit_when_done = spawn_command
             .nodes
             .iter()
@@ -194,10 +346,12 @@ impl Daemon {
             }
         });
         let run_result = Self::run_general(
-            Box::pin(coordinator_events),
+            (Box::pin(coordinator_events), ctrlc_events).merge(),
             None,
             "".to_string(),
             Some(exit_when_done),
+            options,
+            InterDaemonConfig::default(),
             clock.clone(),
         );
 
@@ -225,25 +379,16 @@ impl Daemon {
 
     async fn run_general(
         external_events: impl Stream<Item = Timestamped<Event>> + Unpin,
-        coordinator_addr: Option<SocketAddr>,
+        coordinator_connection: Option<CoordinatorConnection>,
         machine_id: String,
         exit_when_done: Option<BTreeSet<(Uuid, NodeId)>>,
+        options: RunDataflowOptions,
+        inter_daemon_config: InterDaemonConfig,
         clock: Arc<HLC>,
     ) -> eyre::Result<DaemonRunResult> {
-        let coordinator_connection = match coordinator_addr {
-            Some(addr) => {
-                let stream = TcpStream::connect(addr)
-                    .await
-                    .wrap_err("failed to connect to dora-coordinator")?;
-                stream
-                    .set_nodelay(true)
-                    .wrap_err("failed to set TCP_NODELAY")?;
-                Some(stream)
-            }
-            None => None,
-        };
-
         let (dora_events_tx, dora_events_rx) = mpsc::channel(5);
+        let shmem_registry =
+            ShmemRegistry::open().wrap_err("failed to open shared memory registry")?;
         let daemon = Self {
             running: HashMap::new(),
             working_dir: HashMap::new(),
@@ -251,9 +396,17 @@ impl Daemon {
             coordinator_connection,
             last_coordinator_heartbeat: Instant::now(),
             inter_daemon_connections: BTreeMap::new(),
+            inter_daemon_config,
+            closing_zenoh_sessions: Vec::new(),
+            shmem_registry,
             machine_id,
             exit_when_done,
             dataflow_node_results: BTreeMap::new(),
+            stop_after: options.stop_after,
+            print_node_output: options.print_node_output,
+            taps: options.taps,
+            deterministic: options.deterministic.clone(),
+            virtual_timeout: options.timeout.filter(|_| options.deterministic.is_some()),
             clock,
         };
 
@@ -266,7 +419,26 @@ impl Daemon {
             inner: Event::HeartbeatInterval,
             timestamp: watchdog_clock.new_timestamp(),
         });
-        let events = (external_events, dora_events, watchdog_interval).merge();
+        let timeout_clock = daemon.clock.clone();
+        // the timeout is applied in virtual time in deterministic mode
+        let wall_clock_timeout = options.timeout.filter(|_| options.deterministic.is_none());
+        let timeout = stream::iter(wall_clock_timeout).then(move |timeout| {
+            let clock = timeout_clock.clone();
+            async move {
+                tokio::time::sleep(timeout).await;
+                Timestamped {
+                    inner: Event::Timeout,
+                    timestamp: clock.new_timestamp(),
+                }
+            }
+        });
+        let events = (
+            external_events,
+            dora_events,
+            watchdog_interval,
+            Box::pin(timeout),
+        )
+            .merge();
         daemon.run_inner(events).await
     }
 
@@ -307,22 +479,43 @@ impl Daemon {
                 Event::DynamicNode(event) => self.handle_dynamic_node_event(event).await?,
                 Event::HeartbeatInterval => {
                     if let Some(connection) = &mut self.coordinator_connection {
-                        let msg = serde_json::to_vec(&Timestamped {
-                            inner: CoordinatorRequest::Event {
-                                machine_id: self.machine_id.clone(),
-                                event: DaemonEvent::Heartbeat,
-                            },
-                            timestamp: self.clock.new_timestamp(),
-                        })?;
-                        socket_stream_send(connection, &msg)
+                        if connection.is_reconnecting() {
+                            continue;
+                        }
+                        connection
+                            .send(Timestamped {
+                                inner: CoordinatorRequest::Event {
+                                    machine_id: self.machine_id.clone(),
+                                    event: DaemonEvent::Heartbeat,
+                                },
+                                timestamp: self.clock.new_timestamp(),
+                            })
                             .await
                             .wrap_err("failed to send watchdog message to dora-coordinator")?;
 
-                        if self.last_coordinator_heartbeat.elapsed() > Duration::from_secs(20) {
-                            bail!("lost connection to coordinator")
+                        if !connection.is_connected() {
+                            self.reconnect_to_coordinator();
+                        } else if self.last_coordinator_heartbeat.elapsed()
+                            > Duration::from_secs(20)
+                        {
+                            tracing::warn!("no heartbeat from dora-coordinator for 20s");
+                            self.reconnect_to_coordinator();
                         }
                     }
                 }
+                Event::CoordinatorDisconnected { generation } => {
+                    let Some(connection) = &self.coordinator_connection else {
+                        continue;
+                    };
+                    // ignore disconnects of connections that were replaced already
+                    if connection.generation() == generation && !connection.is_reconnecting() {
+                        tracing::warn!("lost connection to dora-coordinator");
+                        self.reconnect_to_coordinator();
+                    }
+                }
+                Event::CoordinatorReconnected(reconnected) => {
+                    self.handle_coordinator_reconnected(reconnected).await;
+                }
                 Event::CtrlC => {
                     for dataflow in self.running.values_mut() {
                         dataflow
@@ -330,30 +523,242 @@ impl Daemon {
                             .await?;
                     }
                 }
+                Event::Timeout => {
+                    tracing::info!("timeout reached -> stopping all dataflows");
+                    for dataflow in self.running.values_mut() {
+                        dataflow
+                            .stop_all(&mut self.coordinator_connection, &self.clock, None)
+                            .await?;
+                    }
+                }
+            }
+
+            self.advance_lockstep().await?;
+        }
+
+        for dataflow in self.running.values_mut() {
+            if let Some(zenoh) = dataflow.zenoh.take() {
+                self.closing_zenoh_sessions
+                    .push(tokio::spawn(zenoh.close()));
             }
         }
+        for task in self.closing_zenoh_sessions.drain(..) {
+            let _ = task.await;
+        }
 
         Ok(self.dataflow_node_results)
     }
 
+    /// Closes the zenoh session of a finished dataflow in the background.
+    ///
+    /// The daemon waits for the sessions to be closed before exiting.
+    fn close_zenoh(&mut self, zenoh: ZenohConnection) {
+        self.closing_zenoh_sessions
+            .retain(|task| !task.is_finished());
+        self.closing_zenoh_sessions
+            .push(tokio::spawn(zenoh.close()));
+    }
+
+    /// Delivers the next queued events of deterministic dataflows whose nodes
+    /// are all idle.
+    ///
+    /// Stops a dataflow once no events are left or its virtual timeout is reached.
+    async fn advance_lockstep(&mut self) -> eyre::Result<()> {
+        let dataflow_ids: Vec<_> = self.running.keys().copied().collect();
+        for dataflow_id in dataflow_ids {
+            loop {
+                let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+                    break;
+                };
+                if !dataflow.started || dataflow.stop_sent || !dataflow.all_nodes_idle() {
+                    break;
+                }
+                let Some(lockstep) = &mut dataflow.lockstep else {
+                    break;
+                };
+                match lockstep.next_event() {
+                    Some((timestamp, event)) => {
+                        self.deliver_lockstep_event(dataflow_id, timestamp, event)
+                            .await?;
+                    }
+                    None => {
+                        tracing::info!(
+                            "deterministic dataflow `{dataflow_id}` has no pending events \
+                            left -> stopping it"
+                        );
+                        dataflow
+                            .stop_all(&mut self.coordinator_connection, &self.clock, None)
+                            .await?;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn deliver_lockstep_event(
+        &mut self,
+        dataflow_id: DataflowId,
+        timestamp: uhlc::Timestamp,
+        event: LockstepEvent,
+    ) -> eyre::Result<()> {
+        let event = match event {
+            LockstepEvent::Output {
+                output_id: OutputId(node_id, output_id),
+                metadata,
+                data,
+            } => {
+                let metadata =
+                    Metadata::from_parameters(timestamp, metadata.type_info, metadata.parameters);
+                return self
+                    .deliver_output(
+                        dataflow_id,
+                        node_id,
+                        output_id,
+                        metadata,
+                        data.map(DataMessage::Vec),
+                    )
+                    .await;
+            }
+            LockstepEvent::OutputClosed { output_id } => {
+                let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+                    return Ok(());
+                };
+                let receivers: Vec<_> = dataflow
+                    .mappings
+                    .get(&output_id)
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect();
+                for (receiver_id, input_id) in receivers {
+                    close_input(dataflow, &receiver_id, &input_id, &self.clock);
+                }
+                return Ok(());
+            }
+            LockstepEvent::Timer { interval } => {
+                if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                    if dataflow
+                        .timers
+                        .get(&interval)
+                        .map_or(true, |r| r.is_empty())
+                    {
+                        // all receivers were removed, so the timer would tick
+                        // forever without making progress
+                        if let Some(lockstep) = &mut dataflow.lockstep {
+                            lockstep.remove_timer(interval);
+                        }
+                        return Ok(());
+                    }
+                }
+                DoraEvent::Timer {
+                    dataflow_id,
+                    interval,
+                    metadata: Metadata::from_parameters(
+                        timestamp,
+                        ArrowTypeInfo::empty(),
+                        MetadataParameters::default(),
+                    ),
+                }
+            }
+            LockstepEvent::InjectedInput { input_id, data } => {
+                let (type_info, data) = array_to_data_message(&data);
+                DoraEvent::InjectedInput {
+                    dataflow_id,
+                    input_id,
+                    metadata: Metadata::new(timestamp, type_info),
+                    data,
+                }
+            }
+            LockstepEvent::InjectionDone { input_id } => DoraEvent::InjectionDone {
+                dataflow_id,
+                input_id,
+            },
+        };
+        // timer and injection events never cause the daemon to exit
+        let _ = self.handle_dora_event(event).await?;
+        Ok(())
+    }
+
     async fn send_log_message(&mut self, message: LogMessage) -> eyre::Result<()> {
         if let Some(connection) = &mut self.coordinator_connection {
-            let msg = serde_json::to_vec(&Timestamped {
-                inner: CoordinatorRequest::Event {
-                    machine_id: self.machine_id.clone(),
-                    event: DaemonEvent::Log(message),
-                },
-                timestamp: self.clock.new_timestamp(),
-            })?;
-            socket_stream_send(connection, &msg)
+            connection
+                .send(Timestamped {
+                    inner: CoordinatorRequest::Event {
+                        machine_id: self.machine_id.clone(),
+                        event: DaemonEvent::Log(message),
+                    },
+                    timestamp: self.clock.new_timestamp(),
+                })
                 .await
-                .wrap_err("failed to send watchdog message to dora-coordinator")?;
+                .wrap_err("failed to send log message to dora-coordinator")?;
+        }
+        Ok(())
+    }
+
+    /// Starts a background task that reconnects to the coordinator and
+    /// registers the daemon again.
+    ///
+    /// Running dataflows are kept alive while the coordinator is unreachable.
+    fn reconnect_to_coordinator(&mut self) {
+        let Some(connection) = &mut self.coordinator_connection else {
+            return;
+        };
+        if !connection.start_reconnect() {
+            return;
+        }
+        // no new dataflows are spawned while the coordinator is unreachable,
+        // so this set stays valid for the whole reconnect
+        let running_dataflows = self
+            .running
+            .keys()
+            .copied()
+            .chain(connection.unreported_dataflows())
+            .collect();
+        let reconnect = connection.reconnect(self.machine_id.clone(), running_dataflows);
+        let events_tx = self.events_tx.clone();
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            let reconnected = reconnect.await;
+            let event = Timestamped {
+                inner: Event::CoordinatorReconnected(reconnected),
+                timestamp: clock.new_timestamp(),
+            };
+            let _ = events_tx.send(event).await;
+        });
+    }
 
-            if self.last_coordinator_heartbeat.elapsed() > Duration::from_secs(20) {
-                bail!("lost connection to coordinator")
+    /// Switches to the new coordinator connection after the daemon registered
+    /// again and sends all events that were queued while disconnected.
+    async fn handle_coordinator_reconnected(&mut self, reconnected: coordinator::Reconnected) {
+        let Some(connection) = &mut self.coordinator_connection else {
+            return;
+        };
+        match connection.reconnected(reconnected.event_stream).await {
+            Ok(()) => {
+                tracing::info!("reconnected to dora-coordinator");
+                self.last_coordinator_heartbeat = Instant::now();
+                let mut events = coordinator_event_stream(
+                    reconnected.events,
+                    connection.generation(),
+                    self.clock.clone(),
+                );
+                let events_tx = self.events_tx.clone();
+                tokio::spawn(async move {
+                    while let Some(event) = events.next().await {
+                        if events_tx.send(event).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            Err(err) => {
+                tracing::warn!("{err:?}");
+                connection.reconnect_failed();
+                self.reconnect_to_coordinator();
             }
         }
-        Ok(())
     }
 
     async fn handle_coordinator_event(
@@ -369,17 +774,22 @@ impl Daemon {
                 machine_listen_ports,
                 dataflow_descriptor,
             }) => {
-                match dataflow_descriptor.communication.remote {
-                    dora_core::config::RemoteCommunicationConfig::Tcp => {}
-                }
                 for (machine_id, socket) in machine_listen_ports {
-                    match self.inter_daemon_connections.entry(machine_id) {
+                    let connection = || {
+                        InterDaemonConnection::new(
+                            machine_id.clone(),
+                            socket,
+                            self.inter_daemon_config,
+                            self.clock.clone(),
+                        )
+                    };
+                    match self.inter_daemon_connections.entry(machine_id.clone()) {
                         std::collections::btree_map::Entry::Vacant(entry) => {
-                            entry.insert(InterDaemonConnection::new(socket));
+                            entry.insert(connection());
                         }
                         std::collections::btree_map::Entry::Occupied(mut entry) => {
                             if entry.get().socket() != socket {
-                                entry.insert(InterDaemonConnection::new(socket));
+                                entry.insert(connection());
                             }
                         }
                     }
@@ -471,6 +881,16 @@ impl Daemon {
                 }
                 RunStatus::Continue
             }
+            DaemonCoordinatorEvent::Stats { dataflow_id } => {
+                let stats = match self.running.get(&dataflow_id) {
+                    Some(dataflow) => Ok(dataflow.stats.clone()),
+                    None => Err(format!("no running dataflow with ID `{dataflow_id}`")),
+                };
+                let _ = reply_tx
+                    .send(Some(DaemonCoordinatorReply::Stats(stats)))
+                    .map_err(|_| error!("could not send stats reply from daemon to coordinator"));
+                RunStatus::Continue
+            }
             DaemonCoordinatorEvent::ReloadDataflow {
                 dataflow_id,
                 node_id,
@@ -484,6 +904,50 @@ impl Daemon {
                     .map_err(|_| error!("could not send reload reply from daemon to coordinator"));
                 RunStatus::Continue
             }
+            DaemonCoordinatorEvent::AddNodes {
+                dataflow_id,
+                nodes,
+                added,
+                dataflow_descriptor,
+            } => {
+                let routes = self.running.get(&dataflow_id).map(RunningDataflow::routes);
+                let result = self
+                    .add_nodes(dataflow_id, nodes, added.clone(), dataflow_descriptor)
+                    .await;
+                if let Err(err) = &result {
+                    tracing::error!("{err:?}");
+                    if let (Some(dataflow), Some(routes)) =
+                        (self.running.get_mut(&dataflow_id), routes)
+                    {
+                        dataflow.undo_add_nodes(&added, routes, &self.clock);
+                    }
+                }
+                let reply = DaemonCoordinatorReply::AddNodesResult(
+                    result.map_err(|err| format!("{err:?}")),
+                );
+                let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                    error!("could not send `AddNodesResult` reply from daemon to coordinator")
+                });
+                RunStatus::Continue
+            }
+            DaemonCoordinatorEvent::RemoveNodes {
+                dataflow_id,
+                nodes,
+                grace_duration,
+            } => {
+                let result = match self.running.get_mut(&dataflow_id) {
+                    Some(dataflow) => {
+                        dataflow.remove_nodes(&nodes, &self.clock, grace_duration);
+                        Ok(())
+                    }
+                    None => Err(format!("no running dataflow with ID `{dataflow_id}`")),
+                };
+                let reply = DaemonCoordinatorReply::RemoveNodesResult(result);
+                let _ = reply_tx.send(Some(reply)).map_err(|_| {
+                    error!("could not send `RemoveNodesResult` reply from daemon to coordinator")
+                });
+                RunStatus::Continue
+            }
             DaemonCoordinatorEvent::StopDataflow {
                 dataflow_id,
                 grace_duration,
@@ -507,6 +971,28 @@ impl Daemon {
                     .await?;
                 RunStatus::Continue
             }
+            DaemonCoordinatorEvent::MachineLost {
+                dataflow_id,
+                machine_id,
+                nodes,
+            } => {
+                match self.running.get_mut(&dataflow_id) {
+                    Some(dataflow) => {
+                        tracing::warn!(
+                            "machine `{machine_id}` of dataflow `{dataflow_id}` was lost, \
+                            closing inputs from its nodes"
+                        );
+                        dataflow.machine_lost(&machine_id, &nodes, &self.clock);
+                    }
+                    None => {
+                        tracing::warn!(
+                            "received MachineLost for unknown dataflow (ID `{dataflow_id}`)"
+                        );
+                    }
+                }
+                let _ = reply_tx.send(None);
+                RunStatus::Continue
+            }
             DaemonCoordinatorEvent::Destroy => {
                 tracing::info!("received destroy command -> exiting");
                 let (notify_tx, notify_rx) = oneshot::channel();
@@ -540,17 +1026,49 @@ impl Daemon {
                 output_id,
                 metadata,
                 data,
+                compression,
             } => {
                 let inner = async {
                     let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
                         format!("send out failed: no running dataflow with ID `{dataflow_id}`")
                     })?;
+                    let remote_output = OutputId(node_id.clone(), output_id.clone());
+                    if dataflow.is_stale_latched(&remote_output, &metadata) {
+                        // replayed latched message that was already received
+                        // through the subscription, or vice versa
+                        return Ok(());
+                    }
+                    let data = match (data, compression) {
+                        (Some(data), Some(compression)) => {
+                            let stats = dataflow
+                                .compression_stats(&OutputId(node_id.clone(), output_id.clone()));
+                            Some(compression::decompress(
+                                compression,
+                                &data,
+                                &metadata.type_info,
+                                stats,
+                            )?)
+                        }
+                        (data, _) => data,
+                    };
+                    let is_clock_source = dataflow
+                        .simulated_clock
+                        .as_ref()
+                        .and_then(|c| c.external_source())
+                        .is_some_and(|OutputId(node, output)| {
+                            node == &node_id && output == &output_id
+                        });
+                    if is_clock_source {
+                        advance_external_clock(dataflow, &metadata, data.as_ref(), &self.clock);
+                    }
+                    dataflow.retain_latched(&remote_output, &metadata, &data);
                     send_output_to_local_receivers(
                         node_id.clone(),
                         output_id.clone(),
                         dataflow,
                         &metadata,
                         data.map(DataMessage::Vec),
+                        false,
                         &self.clock,
                     )
                     .await?;
@@ -586,6 +1104,34 @@ impl Daemon {
                 }
                 Ok(())
             }
+            InterDaemonEvent::ConnectionGap { lost } => {
+                for ((dataflow_id, node_id, output_id), count) in lost {
+                    tracing::warn!(
+                        "{count} messages of output `{node_id}/{output_id}` were lost \
+                        because the connection to the sending machine was interrupted"
+                    );
+                    let Some(dataflow) = self.running.get(&dataflow_id) else {
+                        continue;
+                    };
+                    let Some(receivers) = dataflow.mappings.get(&OutputId(node_id, output_id))
+                    else {
+                        continue;
+                    };
+                    for (receiver_id, input_id) in receivers {
+                        if let Some(channel) = dataflow.subscribe_channels.get(receiver_id) {
+                            let _ = send_with_timestamp(
+                                channel,
+                                daemon_messages::NodeEvent::InputGap {
+                                    id: input_id.clone(),
+                                    lost: count,
+                                },
+                                &self.clock,
+                            );
+                        }
+                    }
+                }
+                Ok(())
+            }
         }
     }
 
@@ -596,7 +1142,26 @@ impl Daemon {
         nodes: Vec<ResolvedNode>,
         dataflow_descriptor: Descriptor,
     ) -> eyre::Result<()> {
-        let dataflow = RunningDataflow::new(dataflow_id, self.machine_id.clone());
+        let mut dataflow = RunningDataflow::new(
+            dataflow_id,
+            self.machine_id.clone(),
+            self.shmem_registry.clone(),
+        );
+        dataflow.injections = self.taps.inputs.clone();
+        dataflow.lockstep = self
+            .deterministic
+            .as_ref()
+            .map(|options| Lockstep::new(options.seed, self.virtual_timeout));
+        // a `clock` is rejected in deterministic mode when the dataflow is checked
+        dataflow.simulated_clock = SimulatedClock::new(&dataflow_descriptor);
+        dataflow.output_taps = self
+            .taps
+            .outputs
+            .iter()
+            .map(|((node_id, output_id), tap)| {
+                (OutputId(node_id.clone(), output_id.clone()), tap.clone())
+            })
+            .collect();
         let dataflow = match self.running.entry(dataflow_id) {
             std::collections::hash_map::Entry::Vacant(entry) => {
                 self.working_dir.insert(dataflow_id, working_dir.clone());
@@ -607,94 +1172,88 @@ impl Daemon {
             }
         };
 
-        let mut log_messages = Vec::new();
-        for node in nodes {
+        dataflow.descriptor = Some(dataflow_descriptor.clone());
+        dataflow.remote_nodes = nodes
+            .iter()
+            .filter(|node| node.deploy.machine != self.machine_id)
+            .map(|node| node.id.clone())
+            .collect();
+        dataflow
+            .open_zenoh(
+                &dataflow_descriptor,
+                self.inter_daemon_config,
+                self.clock.clone(),
+            )
+            .await?;
+
+        for node in &nodes {
             let local = node.deploy.machine == self.machine_id;
+            dataflow.register_latched_outputs(node);
+            for (input_id, input) in node_inputs(node) {
+                dataflow.register_input(node, input_id, input, local, &dataflow_descriptor);
+            }
+        }
 
-            let inputs = node_inputs(&node);
-            for (input_id, input) in inputs {
-                if local {
-                    dataflow
-                        .open_inputs
-                        .entry(node.id.clone())
-                        .or_default()
-                        .insert(input_id.clone());
-                    match input.mapping {
-                        InputMapping::User(mapping) => {
-                            dataflow
-                                .mappings
-                                .entry(OutputId(mapping.source, mapping.output))
-                                .or_default()
-                                .insert((node.id.clone(), input_id));
-                        }
-                        InputMapping::Timer { interval } => {
-                            dataflow
-                                .timers
-                                .entry(interval)
-                                .or_default()
-                                .insert((node.id.clone(), input_id));
-                        }
-                    }
-                } else if let InputMapping::User(mapping) = input.mapping {
-                    dataflow
-                        .open_external_mappings
-                        .entry(OutputId(mapping.source, mapping.output))
-                        .or_default()
-                        .entry(node.deploy.machine.clone())
-                        .or_default()
-                        .insert((node.id.clone(), input_id));
-                }
+        if let Some(zenoh) = &mut dataflow.zenoh {
+            // subscribe before spawning the local nodes, so that the remote
+            // outputs are received once the dataflow is reported as ready.
+            // Local timers are derived from the external clock, if configured.
+            let clock_source = dataflow
+                .simulated_clock
+                .as_ref()
+                .and_then(|c| c.external_source())
+                .filter(|source| {
+                    !dataflow.timers.is_empty() && !dataflow.mappings.contains_key(*source)
+                });
+            let remote_outputs = dataflow
+                .mappings
+                .keys()
+                .chain(clock_source)
+                .filter(|OutputId(source, _)| dataflow.remote_nodes.contains(source));
+            for output_id in remote_outputs {
+                zenoh.subscribe(output_id, self.events_tx.clone()).await?;
             }
+        }
+
+        let mut log_messages = Vec::new();
+        for node in nodes {
+            let local = node.deploy.machine == self.machine_id;
             if local {
                 dataflow.pending_nodes.insert(node.id.clone());
 
-                if node.kind.dynamic() {
-                    dataflow.dynamic_nodes.insert(node.id.clone());
-                }
-
                 let node_id = node.id.clone();
-                let node_stderr_most_recent = dataflow
-                    .node_stderr_most_recent
-                    .entry(node.id.clone())
-                    .or_insert_with(|| Arc::new(ArrayQueue::new(STDERR_LOG_LINES)))
-                    .clone();
-                match spawn::spawn_node(
-                    dataflow_id,
-                    &working_dir,
-                    node,
-                    self.events_tx.clone(),
-                    dataflow_descriptor.clone(),
-                    self.clock.clone(),
-                    node_stderr_most_recent,
-                )
-                .await
-                .wrap_err_with(|| format!("failed to spawn node `{node_id}`"))
-                {
-                    Ok(running_node) => {
-                        dataflow.running_nodes.insert(node_id, running_node);
-                    }
-                    Err(err) => {
-                        log_messages.push(LogMessage {
-                            dataflow_id,
-                            node_id: Some(node_id.clone()),
-                            level: Level::Error,
-                            target: None,
-                            module_path: None,
-                            file: None,
-                            line: None,
-                            message: format!("{err:?}"),
-                        });
-                        let messages = dataflow
-                            .pending_nodes
-                            .handle_node_stop(
-                                &node_id,
-                                &mut self.coordinator_connection,
-                                &self.clock,
-                                &mut dataflow.cascading_error_causes,
-                            )
-                            .await?;
-                        log_messages.extend(messages);
-                    }
+                let result = dataflow
+                    .spawn_node(
+                        &working_dir,
+                        node,
+                        self.events_tx.clone(),
+                        dataflow_descriptor.clone(),
+                        self.clock.clone(),
+                        self.print_node_output,
+                        &self.shmem_registry,
+                    )
+                    .await;
+                if let Err(err) = result {
+                    log_messages.push(LogMessage {
+                        dataflow_id,
+                        node_id: Some(node_id.clone()),
+                        level: Level::Error,
+                        target: None,
+                        module_path: None,
+                        file: None,
+                        line: None,
+                        message: format!("{err:?}"),
+                    });
+                    let messages = dataflow
+                        .pending_nodes
+                        .handle_node_stop(
+                            &node_id,
+                            &mut self.coordinator_connection,
+                            &self.clock,
+                            &mut dataflow.cascading_error_causes,
+                        )
+                        .await?;
+                    log_messages.extend(messages);
                 }
             } else {
                 dataflow.pending_nodes.set_external_nodes(true);
@@ -708,6 +1267,182 @@ impl Daemon {
         Ok(())
     }
 
+    /// Adds the given nodes to a running dataflow.
+    ///
+    /// The `nodes` contain all nodes of the dataflow. Only the inputs of the
+    /// `added` nodes and the inputs that are fed by them are registered.
+    async fn add_nodes(
+        &mut self,
+        dataflow_id: Uuid,
+        nodes: Vec<ResolvedNode>,
+        added: BTreeSet<NodeId>,
+        dataflow_descriptor: Descriptor,
+    ) -> eyre::Result<()> {
+        let working_dir = self
+            .working_dir
+            .get(&dataflow_id)
+            .wrap_err_with(|| format!("no working dir for dataflow `{dataflow_id}`"))?
+            .clone();
+        let dataflow = self
+            .running
+            .get_mut(&dataflow_id)
+            .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
+        if !dataflow.started {
+            bail!("dataflow `{dataflow_id}` is not started yet");
+        }
+        if let Some(node_id) = added
+            .iter()
+            .find(|id| dataflow.running_nodes.contains_key(*id))
+        {
+            bail!("node `{node_id}` is still running");
+        }
+
+        dataflow.remote_nodes.extend(
+            nodes
+                .iter()
+                .filter(|node| node.deploy.machine != self.machine_id)
+                .map(|node| node.id.clone()),
+        );
+        dataflow
+            .open_zenoh(
+                &dataflow_descriptor,
+                self.inter_daemon_config,
+                self.clock.clone(),
+            )
+            .await?;
+
+        dataflow.removed_nodes.retain(|id| !added.contains(id));
+
+        let previous_timers: BTreeSet<_> = dataflow.timers.keys().copied().collect();
+        let previous_machines: HashMap<_, BTreeSet<_>> = dataflow
+            .open_external_mappings
+            .iter()
+            .map(|(output_id, machines)| (output_id.clone(), machines.keys().cloned().collect()))
+            .collect();
+
+        let mut local_nodes = Vec::new();
+        for node in nodes {
+            let local = node.deploy.machine == self.machine_id;
+            let is_added = added.contains(&node.id);
+            if is_added {
+                dataflow.register_latched_outputs(&node);
+            }
+            for (input_id, input) in node_inputs(&node) {
+                let fed_by_added = match &input.mapping {
+                    InputMapping::User(mapping) => added.contains(&mapping.source),
+                    InputMapping::Timer { .. } => false,
+                };
+                if is_added || fed_by_added {
+                    // reopens inputs that were closed when a node with the same ID was removed
+                    dataflow.register_input(&node, input_id, input, local, &dataflow_descriptor);
+                }
+            }
+            if local && is_added {
+                local_nodes.push(node);
+            }
+        }
+
+        let new_timers: Vec<_> = dataflow
+            .timers
+            .keys()
+            .copied()
+            .filter(|interval| !previous_timers.contains(interval))
+            .collect();
+        for interval in new_timers {
+            dataflow.start_timer(interval, &self.events_tx, &self.clock);
+        }
+        // retained messages of latched outputs for machines that did not
+        // receive them yet
+        let mut forward = Vec::new();
+        for (output_id, message) in &dataflow.latched_messages {
+            if dataflow.remote_nodes.contains(&output_id.0) {
+                continue;
+            }
+            let new_machines: Vec<_> = dataflow
+                .open_external_mappings
+                .get(output_id)
+                .into_iter()
+                .flat_map(|machines| machines.keys())
+                .filter(|machine| {
+                    !previous_machines
+                        .get(output_id)
+                        .is_some_and(|previous| previous.contains(*machine))
+                })
+                .cloned()
+                .collect();
+            if !new_machines.is_empty() {
+                forward.push((
+                    output_id.clone(),
+                    message.metadata.clone(),
+                    message.data.clone(),
+                    new_machines,
+                ));
+            }
+        }
+        let mut latched_events = Vec::new();
+        for (output_id, metadata, data, machines) in forward {
+            let event =
+                dataflow.remote_output_event(&output_id, metadata, data, None, &self.clock)?;
+            latched_events.push((output_id, event, machines));
+        }
+
+        if let Some(zenoh) = &mut dataflow.zenoh {
+            // the new machines query the retained messages after subscribing
+            for (output_id, event, _) in &latched_events {
+                zenoh
+                    .retain_latched(output_id, event)
+                    .await
+                    .wrap_err("failed to retain latched output for remote receivers")?;
+            }
+            // subscriptions of removed nodes end when their inputs are closed,
+            // so nodes that are added again with the same ID need a new one
+            let remote_outputs: Vec<_> = dataflow
+                .mappings
+                .keys()
+                .filter(|output_id| {
+                    dataflow.remote_nodes.contains(&output_id.0) && !zenoh.is_subscribed(output_id)
+                })
+                .cloned()
+                .collect();
+            for output_id in &remote_outputs {
+                zenoh.subscribe(output_id, self.events_tx.clone()).await?;
+                if dataflow.latched_outputs.contains(output_id) {
+                    zenoh
+                        .query_latched(output_id, self.events_tx.clone())
+                        .await?;
+                }
+            }
+        } else {
+            for (_, event, machines) in latched_events {
+                inter_daemon::send_inter_daemon_event(
+                    &machines,
+                    &mut self.inter_daemon_connections,
+                    &event,
+                )
+                .wrap_err("failed to forward latched output to remote receivers")?;
+            }
+        }
+
+        for node in local_nodes {
+            let node_id = node.id.clone();
+            dataflow
+                .spawn_node(
+                    &working_dir,
+                    node,
+                    self.events_tx.clone(),
+                    dataflow_descriptor.clone(),
+                    self.clock.clone(),
+                    self.print_node_output,
+                    &self.shmem_registry,
+                )
+                .await?;
+            tracing::info!("added node `{node_id}` to dataflow `{dataflow_id}`");
+        }
+        dataflow.descriptor = Some(dataflow_descriptor);
+
+        Ok(())
+    }
+
     async fn handle_dynamic_node_event(
         &mut self,
         event: DynamicNodeEventWrapper,
@@ -769,7 +1504,132 @@ impl Daemon {
                 });
                 Ok(())
             }
+            DynamicNodeEventWrapper {
+                event:
+                    DynamicNodeEvent::SubscribeOutputs {
+                        dataflow_id,
+                        outputs,
+                    },
+                reply_tx,
+            } => {
+                let result = self
+                    .subscribe_outputs(dataflow_id, outputs)
+                    .await
+                    .map_err(|err| format!("{err:?}"));
+                let _ = reply_tx
+                    .send(Some(DaemonReply::NodeConfig { result }))
+                    .map_err(|_| error!("could not send ad-hoc subscriber reply"));
+                Ok(())
+            }
+        }
+    }
+
+    /// Registers an ad-hoc subscriber for the given outputs with a generated node ID.
+    ///
+    /// Ad-hoc subscribers are read-only and not part of the dataflow descriptor.
+    /// They always connect over TCP and receive copies of shared memory outputs,
+    /// so that they never hold drop tokens of the sender. Subscribers that don't
+    /// subscribe within [`AD_HOC_CONNECT_TIMEOUT`] are removed again.
+    async fn subscribe_outputs(
+        &mut self,
+        dataflow_id: Option<DataflowId>,
+        outputs: BTreeSet<(NodeId, DataId)>,
+    ) -> eyre::Result<NodeConfig> {
+        if outputs.is_empty() {
+            bail!("no outputs to subscribe to");
+        }
+        let dataflow = match dataflow_id {
+            Some(dataflow_id) => self
+                .running
+                .get_mut(&dataflow_id)
+                .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?,
+            None => {
+                let mut running = self.running.values_mut();
+                match (running.next(), running.next()) {
+                    (Some(dataflow), None) => dataflow,
+                    (None, _) => bail!("no running dataflow"),
+                    (Some(_), Some(_)) => {
+                        bail!("multiple dataflows are running, please specify a dataflow ID")
+                    }
+                }
+            }
+        };
+        if !dataflow.started {
+            bail!("dataflow `{}` is not started yet", dataflow.id);
+        }
+        let dataflow_descriptor = dataflow
+            .descriptor
+            .clone()
+            .context("dataflow descriptor is not known")?;
+
+        let mut inputs = BTreeMap::new();
+        for (source, output) in outputs {
+            // outputs of remote nodes are only available if a local node receives them
+            let available = dataflow
+                .running_nodes
+                .get(&source)
+                .is_some_and(|n| n.node_config.run_config.outputs.contains(&output))
+                || dataflow
+                    .mappings
+                    .get(&OutputId(source.clone(), output.clone()))
+                    .is_some_and(|receivers| !receivers.is_empty());
+            if !available {
+                bail!("output `{source}/{output}` is not available on this machine");
+            }
+            inputs.insert(
+                DataId::from(format!("{source}/{output}")),
+                Input {
+                    mapping: InputMapping::User(UserInputMapping { source, output }),
+                    queue_size: None,
+                    compression: None,
+                    notify_dropped: false,
+                },
+            );
         }
+
+        let node_id = NodeId::from(format!("ad-hoc-{}", Uuid::new_v4()));
+        let daemon_communication = node_communication::spawn_listener_loop(
+            &dataflow.id,
+            &node_id,
+            &self.events_tx,
+            LocalCommunicationConfig::Tcp,
+            node_communication::ListenerOptions {
+                queue_sizes: inputs.keys().map(|id| (id.clone(), 10)).collect(),
+                report_idle: false,
+            },
+            self.clock.clone(),
+            &self.shmem_registry,
+        )
+        .await?;
+        dataflow.add_ad_hoc_subscriber(&node_id, &inputs);
+        tracing::info!(
+            "registered ad-hoc subscriber `{node_id}` for dataflow `{}`",
+            dataflow.id
+        );
+        let timeout = DoraEvent::AdHocSubscriberConnectTimeout {
+            dataflow_id: dataflow.id,
+            node_id: node_id.clone(),
+        };
+        send_after(
+            &self.events_tx,
+            AD_HOC_CONNECT_TIMEOUT,
+            timeout,
+            self.clock.clone(),
+        );
+
+        Ok(NodeConfig {
+            dataflow_id: dataflow.id,
+            node_id,
+            run_config: NodeRunConfig {
+                inputs,
+                outputs: BTreeSet::new(),
+                latched_outputs: BTreeSet::new(),
+            },
+            daemon_communication,
+            dataflow_descriptor,
+            dynamic: true,
+            deterministic: false,
+        })
     }
 
     async fn handle_node_event(
@@ -791,10 +1651,46 @@ impl Daemon {
                     Err(err) => {
                         let _ = reply_sender.send(DaemonReply::Result(Err(err)));
                     }
+                    Ok(dataflow) if dataflow.ad_hoc_subscribers.contains(&node_id) => {
+                        // ad-hoc subscribers are not known to the coordinator
+                        let closed = event_sender.clone();
+                        let events_tx = self.events_tx.clone();
+                        let clock = self.clock.clone();
+                        let disconnected = DoraEvent::AdHocSubscriberDisconnected {
+                            dataflow_id,
+                            node_id: node_id.clone(),
+                        };
+                        tokio::spawn(async move {
+                            // the event channel closes when the connection breaks
+                            closed.closed().await;
+                            let event = Timestamped {
+                                inner: disconnected.into(),
+                                timestamp: clock.new_timestamp(),
+                            };
+                            let _ = events_tx.send(event).await;
+                        });
+                        Self::subscribe(dataflow, node_id, event_sender, &self.clock).await;
+                        let _ = reply_sender.send(DaemonReply::Result(Ok(())));
+                    }
                     Ok(dataflow) => {
                         tracing::debug!("node `{node_id}` is ready");
                         Self::subscribe(dataflow, node_id.clone(), event_sender, &self.clock).await;
+                        send_to_coordinator(
+                            &mut self.coordinator_connection,
+                            &self.machine_id,
+                            DaemonEvent::NodeReady {
+                                dataflow_id,
+                                node_id: node_id.clone(),
+                            },
+                            &self.clock,
+                        )
+                        .await?;
 
+                        if dataflow.started {
+                            // the node was added to the running dataflow
+                            let _ = reply_sender.send(DaemonReply::Result(Ok(())));
+                            return Ok(());
+                        }
                         let status = dataflow
                             .pending_nodes
                             .handle_node_subscription(
@@ -891,10 +1787,27 @@ impl Daemon {
                                 );
                             }
                         }
+                        // the node dropped a leased segment without sending it
+                        None if dataflow.shmem_pool.release_unsent(&node_id, &token) => {}
                         None => tracing::warn!("unknown drop token `{token:?}`"),
                     }
                 }
             }
+            DaemonNodeEvent::InputsDropped { counts } => {
+                let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
+                    format!(
+                        "failed to record dropped inputs: \
+                        no running dataflow with ID `{dataflow_id}`"
+                    )
+                })?;
+                for (input_id, count) in counts {
+                    *dataflow
+                        .stats
+                        .dropped_inputs
+                        .entry(format!("{node_id}/{input_id}"))
+                        .or_default() += count;
+                }
+            }
             DaemonNodeEvent::EventStreamDropped { reply_sender } => {
                 let inner = async {
                     let dataflow = self
@@ -902,12 +1815,33 @@ impl Daemon {
                         .get_mut(&dataflow_id)
                         .wrap_err_with(|| format!("no running dataflow with ID `{dataflow_id}`"))?;
                     dataflow.subscribe_channels.remove(&node_id);
+                    if dataflow.ad_hoc_subscribers.contains(&node_id) {
+                        dataflow.remove_ad_hoc_subscriber(&node_id);
+                    }
                     Result::<_, eyre::Error>::Ok(())
                 };
 
                 let reply = inner.await.map_err(|err| format!("{err:?}"));
                 let _ = reply_sender.send(DaemonReply::Result(reply));
             }
+            DaemonNodeEvent::AllocateSharedMemory { len, reply_sender } => {
+                let result = match self.running.get_mut(&dataflow_id) {
+                    Some(dataflow) => dataflow.shmem_pool.lease(&node_id, len),
+                    None => Err(eyre!("no running dataflow with ID `{dataflow_id}`")),
+                };
+                let _ = reply_sender.send(DaemonReply::SharedMemory {
+                    result: result.map_err(|err| format!("{err:?}")),
+                });
+            }
+            DaemonNodeEvent::Idle { received } => {
+                if let Some(lockstep) = self
+                    .running
+                    .get_mut(&dataflow_id)
+                    .and_then(|d| d.lockstep.as_mut())
+                {
+                    lockstep.report_idle(node_id, received);
+                }
+            }
         }
         Ok(())
     }
@@ -947,40 +1881,149 @@ impl Daemon {
         let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
             format!("send out failed: no running dataflow with ID `{dataflow_id}`")
         })?;
+        if dataflow.lockstep.is_some() {
+            // the output is delivered in `advance_lockstep` once it's its turn, so
+            // copy out the data to release the shared memory right away
+            let data = release_output_data(&node_id, dataflow, data, true, &self.clock).await?;
+            if let Some(lockstep) = &mut dataflow.lockstep {
+                lockstep.push_output(OutputId(node_id, output_id), metadata, data);
+            }
+            return Ok(());
+        }
+        let Some(simulated_clock) = &dataflow.simulated_clock else {
+            return self
+                .deliver_output(dataflow_id, node_id, output_id, metadata, data)
+                .await;
+        };
+
+        let output = OutputId(node_id, output_id);
+        let mut data = data;
+        if simulated_clock.external_source() == Some(&output) {
+            // copy out the data to read the published time
+            let bytes = release_output_data(&output.0, dataflow, data, true, &self.clock).await?;
+            advance_external_clock(dataflow, &metadata, bytes.as_ref(), &self.clock);
+            data = bytes.map(DataMessage::Vec);
+        }
+        let metadata = match &dataflow.simulated_clock {
+            Some(clock) => clock.restamp(metadata),
+            None => metadata,
+        };
+        let OutputId(node_id, output_id) = output;
+        self.deliver_output(dataflow_id, node_id, output_id, metadata, data)
+            .await
+    }
+
+    /// Sends the given output to all local and remote receivers.
+    async fn deliver_output(
+        &mut self,
+        dataflow_id: Uuid,
+        node_id: NodeId,
+        output_id: DataId,
+        metadata: dora_core::message::Metadata,
+        data: Option<DataMessage>,
+    ) -> Result<(), eyre::ErrReport> {
+        let dataflow = self.running.get_mut(&dataflow_id).wrap_err_with(|| {
+            format!("send out failed: no running dataflow with ID `{dataflow_id}`")
+        })?;
+        // the data bytes are only needed for taps, latched outputs, and remote receivers
+        let copy_shared_memory = {
+            let output_id = OutputId(node_id.clone(), output_id.clone());
+            dataflow.output_taps.contains_key(&output_id)
+                || dataflow.latched_outputs.contains(&output_id)
+                || dataflow
+                    .open_external_mappings
+                    .get(&output_id)
+                    .is_some_and(|m| !m.is_empty())
+        };
         let data_bytes = send_output_to_local_receivers(
             node_id.clone(),
             output_id.clone(),
             dataflow,
             &metadata,
             data,
+            copy_shared_memory,
             &self.clock,
         )
         .await?;
 
         let output_id = OutputId(node_id, output_id);
+        dataflow.retain_latched(&output_id, &metadata, &data_bytes);
+        if let Some(tap) = dataflow.output_taps.get(&output_id) {
+            let received = if dataflow.lockstep.is_some() || dataflow.simulated_clock.is_some() {
+                // the output is delivered at its virtual or simulated timestamp
+                metadata.timestamp()
+            } else {
+                self.clock.new_timestamp()
+            };
+            let _ = tap.send(CapturedOutput {
+                metadata: metadata.clone(),
+                data: data_bytes.clone(),
+                received,
+            });
+        }
         let remote_receivers: Vec<_> = dataflow
             .open_external_mappings
             .get(&output_id)
             .map(|m| m.keys().cloned().collect())
             .unwrap_or_default();
         if !remote_receivers.is_empty() {
-            let event = Timestamped {
-                inner: InterDaemonEvent::Output {
-                    dataflow_id,
-                    node_id: output_id.0,
-                    output_id: output_id.1,
-                    metadata,
-                    data: data_bytes,
-                },
-                timestamp: self.clock.new_timestamp(),
+            let compression_of = |machine: &String| {
+                dataflow
+                    .remote_compression
+                    .get(&output_id)
+                    .and_then(|c| c.get(machine))
+                    .copied()
             };
-            inter_daemon::send_inter_daemon_event(
-                &remote_receivers,
-                &mut self.inter_daemon_connections,
-                &event,
-            )
-            .await
-            .wrap_err("failed to forward output to remote receivers")?;
+            if dataflow.zenoh.is_some() {
+                // the output is published only once, so compress it if any
+                // receiving machine requests it (validation ensures that all
+                // requested compressions are identical)
+                let compression = remote_receivers.iter().find_map(compression_of);
+                let event = dataflow.remote_output_event(
+                    &output_id,
+                    metadata,
+                    data_bytes,
+                    compression,
+                    &self.clock,
+                )?;
+                let latched = dataflow.latched_outputs.contains(&output_id);
+                if let Some(zenoh) = &mut dataflow.zenoh {
+                    zenoh
+                        .publish(&output_id, &event, latched)
+                        .await
+                        .wrap_err("failed to queue output for remote receivers")?;
+                }
+            } else {
+                let mut groups: Vec<(Option<Compression>, Vec<String>)> = Vec::new();
+                for machine in remote_receivers {
+                    let compression = compression_of(&machine);
+                    match groups.iter_mut().find(|(c, _)| *c == compression) {
+                        Some((_, machines)) => machines.push(machine),
+                        None => groups.push((compression, vec![machine])),
+                    }
+                }
+                let mut data_bytes = data_bytes;
+                while let Some((compression, machines)) = groups.pop() {
+                    let data = if groups.is_empty() {
+                        data_bytes.take()
+                    } else {
+                        data_bytes.clone()
+                    };
+                    let event = dataflow.remote_output_event(
+                        &output_id,
+                        metadata.clone(),
+                        data,
+                        compression,
+                        &self.clock,
+                    )?;
+                    inter_daemon::send_inter_daemon_event(
+                        &machines,
+                        &mut self.inter_daemon_connections,
+                        &event,
+                    )
+                    .wrap_err("failed to forward output to remote receivers")?;
+                }
+            }
         }
 
         Ok(())
@@ -992,6 +2035,26 @@ impl Daemon {
         event_sender: UnboundedSender<Timestamped<daemon_messages::NodeEvent>>,
         clock: &HLC,
     ) {
+        let event_sender = NodeEventSender::new(event_sender);
+
+        // deliver the last message of latched outputs that were sent before
+        for (output_id, receivers) in &dataflow.mappings {
+            let Some(message) = dataflow.latched_messages.get(output_id) else {
+                continue;
+            };
+            for (_, input_id) in receivers.iter().filter(|(node, _)| node == &node_id) {
+                let _ = send_with_timestamp(
+                    &event_sender,
+                    daemon_messages::NodeEvent::Input {
+                        id: input_id.clone(),
+                        metadata: message.metadata.clone(),
+                        data: message.data.clone().map(DataMessage::Vec),
+                    },
+                    clock,
+                );
+            }
+        }
+
         // some inputs might have been closed already -> report those events
         let closed_inputs = dataflow
             .mappings
@@ -1073,6 +2136,13 @@ impl Daemon {
         )
         .await?;
 
+        dataflow.shmem_pool.reclaim(node_id);
+        if let Err(err) = self.shmem_registry.remove_node(dataflow_id, node_id) {
+            tracing::warn!(
+                "{:?}",
+                err.wrap_err("failed to remove shared memory of node")
+            );
+        }
         dataflow.running_nodes.remove(node_id);
         if dataflow
             .running_nodes
@@ -1093,21 +2163,33 @@ impl Daemon {
                 self.machine_id
             );
             if let Some(connection) = &mut self.coordinator_connection {
-                let msg = serde_json::to_vec(&Timestamped {
-                    inner: CoordinatorRequest::Event {
-                        machine_id: self.machine_id.clone(),
-                        event: DaemonEvent::AllNodesFinished {
-                            dataflow_id,
-                            result,
+                connection
+                    .send(Timestamped {
+                        inner: CoordinatorRequest::Event {
+                            machine_id: self.machine_id.clone(),
+                            event: DaemonEvent::AllNodesFinished {
+                                dataflow_id,
+                                result,
+                            },
                         },
-                    },
-                    timestamp: self.clock.new_timestamp(),
-                })?;
-                socket_stream_send(connection, &msg)
+                        timestamp: self.clock.new_timestamp(),
+                    })
                     .await
                     .wrap_err("failed to report dataflow finish to dora-coordinator")?;
             }
-            self.running.remove(&dataflow_id);
+            if let Some(zenoh) = self
+                .running
+                .remove(&dataflow_id)
+                .and_then(|dataflow| dataflow.zenoh)
+            {
+                self.close_zenoh(zenoh);
+            }
+            if let Err(err) = self.shmem_registry.remove_dataflow(dataflow_id) {
+                tracing::warn!(
+                    "{:?}",
+                    err.wrap_err("failed to remove shared memory of dataflow")
+                );
+            }
         }
 
         for log_message in log_messages {
@@ -1128,36 +2210,11 @@ impl Daemon {
                     tracing::warn!("Timer event for unknown dataflow `{dataflow_id}`");
                     return Ok(RunStatus::Continue);
                 };
-
-                let Some(subscribers) = dataflow.timers.get(&interval) else {
-                    return Ok(RunStatus::Continue);
+                let metadata = match &dataflow.simulated_clock {
+                    Some(clock) => clock.restamp(metadata),
+                    None => metadata,
                 };
-
-                let mut closed = Vec::new();
-                for (receiver_id, input_id) in subscribers {
-                    let Some(channel) = dataflow.subscribe_channels.get(receiver_id) else {
-                        continue;
-                    };
-
-                    let send_result = send_with_timestamp(
-                        channel,
-                        daemon_messages::NodeEvent::Input {
-                            id: input_id.clone(),
-                            metadata: metadata.clone(),
-                            data: None,
-                        },
-                        &self.clock,
-                    );
-                    match send_result {
-                        Ok(()) => {}
-                        Err(_) => {
-                            closed.push(receiver_id);
-                        }
-                    }
-                }
-                for id in closed {
-                    dataflow.subscribe_channels.remove(id);
-                }
+                send_timer_tick(dataflow, interval, metadata, &self.clock);
             }
             DoraEvent::Logs {
                 dataflow_id,
@@ -1169,6 +2226,10 @@ impl Daemon {
                     tracing::warn!("Logs event for unknown dataflow `{dataflow_id}`");
                     return Ok(RunStatus::Continue);
                 };
+                let metadata = match &dataflow.simulated_clock {
+                    Some(clock) => clock.restamp(metadata),
+                    None => metadata,
+                };
 
                 let Some(subscribers) = dataflow.mappings.get(&output_id) else {
                     tracing::warn!(
@@ -1206,21 +2267,112 @@ impl Daemon {
                     dataflow.subscribe_channels.remove(id);
                 }
             }
-            DoraEvent::SpawnedNodeResult {
+            DoraEvent::InjectedInput {
                 dataflow_id,
-                node_id,
-                exit_status,
+                input_id: (node_id, input_id),
+                metadata,
+                data,
             } => {
-                let node_result = match exit_status {
-                    NodeExitStatus::Success => {
-                        tracing::info!("node {dataflow_id}/{node_id} finished successfully");
-                        Ok(())
-                    }
-                    exit_status => {
-                        let dataflow = self.running.get(&dataflow_id);
-                        let caused_by_node = dataflow
-                            .and_then(|dataflow| {
-                                dataflow.cascading_error_causes.error_caused_by(&node_id)
+                let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+                    tracing::warn!("InjectedInput event for unknown dataflow `{dataflow_id}`");
+                    return Ok(RunStatus::Continue);
+                };
+                let metadata = match &dataflow.simulated_clock {
+                    Some(clock) => clock.restamp(metadata),
+                    None => metadata,
+                };
+                let Some(channel) = dataflow.subscribe_channels.get(&node_id) else {
+                    return Ok(RunStatus::Continue);
+                };
+                let send_result = send_with_timestamp(
+                    channel,
+                    daemon_messages::NodeEvent::Input {
+                        id: input_id,
+                        metadata,
+                        data: Some(data),
+                    },
+                    &self.clock,
+                );
+                if send_result.is_err() {
+                    dataflow.subscribe_channels.remove(&node_id);
+                }
+            }
+            DoraEvent::InjectionDone {
+                dataflow_id,
+                input_id: (node_id, input_id),
+            } => {
+                let Some(dataflow) = self.running.get_mut(&dataflow_id) else {
+                    tracing::warn!("InjectionDone event for unknown dataflow `{dataflow_id}`");
+                    return Ok(RunStatus::Continue);
+                };
+                close_input(dataflow, &node_id, &input_id, &self.clock);
+            }
+            DoraEvent::NodeLog(message) => {
+                self.send_log_message(message).await?;
+            }
+            DoraEvent::AdHocSubscriberConnectTimeout {
+                dataflow_id,
+                node_id,
+            } => {
+                if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                    if dataflow.remove_unconnected_ad_hoc_subscriber(&node_id) {
+                        tracing::warn!("ad-hoc subscriber `{node_id}` did not connect in time");
+                    }
+                }
+            }
+            DoraEvent::AdHocSubscriberDisconnected {
+                dataflow_id,
+                node_id,
+            } => {
+                if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                    if dataflow.ad_hoc_subscribers.contains(&node_id) {
+                        dataflow.remove_ad_hoc_subscriber(&node_id);
+                    }
+                }
+            }
+            DoraEvent::NodeOutput {
+                dataflow_id,
+                node_id,
+                output,
+            } => {
+                send_to_coordinator(
+                    &mut self.coordinator_connection,
+                    &self.machine_id,
+                    DaemonEvent::NodeLogs {
+                        dataflow_id,
+                        node_id,
+                        output,
+                    },
+                    &self.clock,
+                )
+                .await?;
+            }
+            DoraEvent::SpawnedNodeResult {
+                dataflow_id,
+                node_id,
+                exit_status,
+            } => {
+                let node_result = match exit_status {
+                    NodeExitStatus::Success => {
+                        tracing::info!("node {dataflow_id}/{node_id} finished successfully");
+                        Ok(())
+                    }
+                    exit_status
+                        if self
+                            .running
+                            .get(&dataflow_id)
+                            .is_some_and(|d| d.removed_nodes.contains(&node_id)) =>
+                    {
+                        tracing::info!(
+                            "removed node {dataflow_id}/{node_id} exited with {exit_status:?}"
+                        );
+                        Ok(())
+                    }
+                    exit_status => {
+                        let dataflow = self.running.get(&dataflow_id);
+                        let caused_by_node = dataflow
+                            .and_then(|dataflow| {
+                                dataflow.cascading_error_causes.error_caused_by(&node_id)
                             })
                             .cloned();
                         let grace_duration_kill = dataflow
@@ -1277,6 +2429,18 @@ impl Daemon {
                 })
                 .await?;
 
+                send_to_coordinator(
+                    &mut self.coordinator_connection,
+                    &self.machine_id,
+                    DaemonEvent::NodeExited {
+                        dataflow_id,
+                        node_id: node_id.clone(),
+                        result: node_result.clone(),
+                    },
+                    &self.clock,
+                )
+                .await?;
+
                 self.dataflow_node_results
                     .entry(dataflow_id)
                     .or_default()
@@ -1284,6 +2448,15 @@ impl Daemon {
 
                 self.handle_node_stop(dataflow_id, &node_id).await?;
 
+                if self.stop_after.as_ref() == Some(&node_id) {
+                    if let Some(dataflow) = self.running.get_mut(&dataflow_id) {
+                        tracing::info!("node `{node_id}` exited -> stopping dataflow");
+                        dataflow
+                            .stop_all(&mut self.coordinator_connection, &self.clock, None)
+                            .await?;
+                    }
+                }
+
                 if let Some(exit_when_done) = &mut self.exit_when_done {
                     exit_when_done.remove(&(dataflow_id, node_id));
                     if exit_when_done.is_empty() {
@@ -1299,33 +2472,69 @@ impl Daemon {
     }
 }
 
+/// Sends the given output to all local receivers.
+///
+/// Returns the data bytes of the output. Outputs in shared memory are only
+/// copied out if `copy_shared_memory` is set.
 async fn send_output_to_local_receivers(
     node_id: NodeId,
     output_id: DataId,
     dataflow: &mut RunningDataflow,
     metadata: &dora_core::message::Metadata,
     data: Option<DataMessage>,
+    copy_shared_memory: bool,
     clock: &HLC,
 ) -> Result<Option<AVec<u8, ConstAlign<128>>>, eyre::ErrReport> {
-    let timestamp = metadata.timestamp();
+    // simulated timestamps might be ahead of the wall clock, so they must not be
+    // used to update the HLCs of the nodes
+    let timestamp = match &dataflow.simulated_clock {
+        Some(_) => clock.new_timestamp(),
+        None => metadata.timestamp(),
+    };
     let empty_set = BTreeSet::new();
     let output_id = OutputId(node_id, output_id);
     let local_receivers = dataflow.mappings.get(&output_id).unwrap_or(&empty_set);
     let OutputId(node_id, _) = output_id;
+    // ad-hoc subscribers get their own copy of shared memory data, so that they
+    // never delay the drop token of the sender
+    let ad_hoc_data = match &data {
+        Some(DataMessage::SharedMemory {
+            shared_memory_id,
+            len,
+            ..
+        }) if local_receivers
+            .iter()
+            .any(|(receiver_id, _)| dataflow.ad_hoc_subscribers.contains(receiver_id)) =>
+        {
+            let bytes = match dataflow.shmem_pool.get(shared_memory_id, *len) {
+                Some(data) => AVec::from_slice(1, data),
+                None => read_shared_memory(shared_memory_id, *len)?,
+            };
+            Some(DataMessage::Vec(bytes))
+        }
+        _ => None,
+    };
     let mut closed = Vec::new();
     for (receiver_id, input_id) in local_receivers {
         if let Some(channel) = dataflow.subscribe_channels.get(receiver_id) {
+            let receiver_data = match &ad_hoc_data {
+                Some(copy) if dataflow.ad_hoc_subscribers.contains(receiver_id) => {
+                    Some(copy.clone())
+                }
+                _ => data.clone(),
+            };
+            let drop_token = receiver_data.as_ref().and_then(|d| d.drop_token());
             let item = daemon_messages::NodeEvent::Input {
                 id: input_id.clone(),
                 metadata: metadata.clone(),
-                data: data.clone(),
+                data: receiver_data,
             };
             match channel.send(Timestamped {
                 inner: item,
                 timestamp,
             }) {
                 Ok(()) => {
-                    if let Some(token) = data.as_ref().and_then(|d| d.drop_token()) {
+                    if let Some(token) = drop_token {
                         dataflow
                             .pending_drop_tokens
                             .entry(token)
@@ -1343,9 +2552,93 @@ async fn send_output_to_local_receivers(
             }
         }
     }
+    let closed: Vec<_> = closed.into_iter().cloned().collect();
     for id in closed {
-        dataflow.subscribe_channels.remove(id);
+        if dataflow.ad_hoc_subscribers.contains(&id) {
+            dataflow.remove_ad_hoc_subscriber(&id);
+        } else {
+            dataflow.subscribe_channels.remove(&id);
+        }
     }
+    release_output_data(&node_id, dataflow, data, copy_shared_memory, clock).await
+}
+
+/// Sends a tick of the timer with the given interval to all its receivers.
+fn send_timer_tick(
+    dataflow: &mut RunningDataflow,
+    interval: Duration,
+    metadata: dora_core::message::Metadata,
+    clock: &HLC,
+) {
+    let Some(subscribers) = dataflow.timers.get(&interval) else {
+        return;
+    };
+
+    let mut closed = Vec::new();
+    for (receiver_id, input_id) in subscribers {
+        let Some(channel) = dataflow.subscribe_channels.get(receiver_id) else {
+            continue;
+        };
+
+        let send_result = send_with_timestamp(
+            channel,
+            daemon_messages::NodeEvent::Input {
+                id: input_id.clone(),
+                metadata: metadata.clone(),
+                data: None,
+            },
+            clock,
+        );
+        match send_result {
+            Ok(()) => {}
+            Err(_) => {
+                closed.push(receiver_id);
+            }
+        }
+    }
+    let closed: Vec<_> = closed.into_iter().cloned().collect();
+    for id in closed {
+        dataflow.subscribe_channels.remove(&id);
+    }
+}
+
+/// Advances the simulated clock to the time of a message of the external clock
+/// source and sends the timer ticks that are due.
+fn advance_external_clock(
+    dataflow: &mut RunningDataflow,
+    metadata: &dora_core::message::Metadata,
+    data: Option<&AVec<u8, ConstAlign<128>>>,
+    clock: &HLC,
+) {
+    let Some(simulated_clock) = &mut dataflow.simulated_clock else {
+        return;
+    };
+    match simulated_clock.update(&metadata.type_info, data) {
+        Ok(ticks) => {
+            for (interval, timestamp) in ticks {
+                let metadata = Metadata::from_parameters(
+                    timestamp,
+                    ArrowTypeInfo::empty(),
+                    MetadataParameters::default(),
+                );
+                send_timer_tick(dataflow, interval, metadata, clock);
+            }
+        }
+        Err(err) => tracing::warn!("failed to read time of external clock: {err:?}"),
+    }
+}
+
+/// Returns the data bytes of an output and releases its shared memory once all
+/// local receivers are done with it.
+///
+/// Outputs in shared memory are only copied out if `copy_shared_memory` is set.
+async fn release_output_data(
+    node_id: &NodeId,
+    dataflow: &mut RunningDataflow,
+    data: Option<DataMessage>,
+    copy_shared_memory: bool,
+    clock: &HLC,
+) -> eyre::Result<Option<AVec<u8, ConstAlign<128>>>> {
     let (data_bytes, drop_token) = match data {
         None => (None, None),
         Some(DataMessage::SharedMemory {
@@ -1353,11 +2646,11 @@ async fn send_output_to_local_receivers(
             len,
             drop_token,
         }) => {
-            let memory = ShmemConf::new()
-                .os_id(shared_memory_id)
-                .open()
-                .wrap_err("failed to map shared memory output")?;
-            let data = Some(AVec::from_slice(1, &unsafe { memory.as_slice() }[..len]));
+            let data = match dataflow.shmem_pool.send(&shared_memory_id, drop_token, len) {
+                Some(data) => copy_shared_memory.then(|| AVec::from_slice(1, data)),
+                None if copy_shared_memory => Some(read_shared_memory(&shared_memory_id, len)?),
+                None => None,
+            };
             (data, Some(drop_token))
         }
         Some(DataMessage::Vec(v)) => (Some(v), None),
@@ -1377,6 +2670,26 @@ async fn send_output_to_local_receivers(
     Ok(data_bytes)
 }
 
+/// Copies the given array into a new sample.
+fn array_to_data_message(array: &ArrayData) -> (ArrowTypeInfo, DataMessage) {
+    let total_len = required_data_size(array);
+    let mut sample: AVec<u8, ConstAlign<128>> = AVec::__from_elem(128, 0, total_len);
+    let type_info = copy_array_into_sample(&mut sample, array);
+    (type_info, DataMessage::Vec(sample))
+}
+
+/// Copies the first `len` bytes of the given shared memory region.
+fn read_shared_memory(
+    shared_memory_id: &str,
+    len: usize,
+) -> eyre::Result<AVec<u8, ConstAlign<128>>> {
+    let memory = ShmemConf::new()
+        .os_id(shared_memory_id)
+        .open()
+        .wrap_err("failed to map shared memory output")?;
+    Ok(AVec::from_slice(1, &unsafe { memory.as_slice() }[..len]))
+}
+
 fn node_inputs(node: &ResolvedNode) -> BTreeMap<DataId, Input> {
     match &node.kind {
         CoreNodeKind::Custom(n) => n.run_config.inputs.clone(),
@@ -1393,17 +2706,54 @@ async fn send_input_closed_events<F>(
 where
     F: FnMut(&OutputId) -> bool,
 {
-    let local_node_inputs: BTreeSet<_> = dataflow
+    if let Some(lockstep) = &mut dataflow.lockstep {
+        // close the inputs after all queued outputs were delivered
+        for output_id in dataflow.mappings.keys().filter(|o| filter(o)) {
+            lockstep.push_output_closed(output_id.clone());
+        }
+        return Ok(());
+    }
+
+    let mut local_node_inputs: BTreeSet<_> = dataflow
         .mappings
         .iter()
         .filter(|(k, _)| filter(k))
         .flat_map(|(_, v)| v)
         .cloned()
         .collect();
+    let clock_closed = dataflow
+        .simulated_clock
+        .as_ref()
+        .and_then(|c| c.external_source())
+        .is_some_and(&mut filter);
+    if clock_closed {
+        // timers don't tick anymore without the external clock
+        local_node_inputs.extend(dataflow.timers.values().flatten().cloned());
+    }
     for (receiver_id, input_id) in &local_node_inputs {
         close_input(dataflow, receiver_id, input_id, clock);
     }
 
+    if let Some(zenoh) = &mut dataflow.zenoh {
+        // all remote receivers are subscribed to the topic of the output
+        for (output_id, mapping) in &mut dataflow.open_external_mappings {
+            if filter(output_id) && !mapping.is_empty() {
+                let event = Timestamped {
+                    inner: InterDaemonEvent::InputsClosed {
+                        dataflow_id: dataflow.id,
+                        inputs: std::mem::take(mapping).into_values().flatten().collect(),
+                    },
+                    timestamp: clock.new_timestamp(),
+                };
+                zenoh
+                    .publish(output_id, &event, false)
+                    .await
+                    .wrap_err("failed to queue InputsClosed event")?;
+            }
+        }
+        return Ok(());
+    }
+
     let mut external_node_inputs = BTreeMap::new();
     for (output_id, mapping) in &mut dataflow.open_external_mappings {
         if filter(output_id) {
@@ -1424,7 +2774,6 @@ where
                 inter_daemon_connections,
                 &event,
             )
-            .await
             .wrap_err("failed to sent InputClosed event to remote receiver")?;
         }
     }
@@ -1458,6 +2807,47 @@ fn close_input(
     }
 }
 
+/// Kills the given nodes if they are still running after the grace duration.
+///
+/// The killed nodes are recorded in `grace_duration_kills`, if given.
+fn kill_after_grace_duration(
+    nodes: BTreeMap<NodeId, RunningNode>,
+    grace_duration_kills: Option<Arc<crossbeam_skiplist::SkipSet<NodeId>>>,
+    grace_duration: Option<Duration>,
+) {
+    tokio::spawn(async move {
+        let duration = grace_duration.unwrap_or(Duration::from_millis(2000));
+        tokio::time::sleep(duration).await;
+        let mut system = sysinfo::System::new();
+        system.refresh_processes();
+
+        for (node, node_details) in nodes.iter() {
+            if let Some(pid) = node_details.pid {
+                if let Some(process) = system.process(Pid::from(pid as usize)) {
+                    if let Some(kills) = &grace_duration_kills {
+                        kills.insert(node.clone());
+                    }
+                    process.kill();
+                    warn!(
+                        "{node} was killed due to not stopping within the {:#?} grace period",
+                        duration
+                    )
+                }
+            }
+        }
+    });
+}
+
+/// Routing state of a running dataflow.
+struct Routes {
+    mappings: HashMap<OutputId, BTreeSet<InputId>>,
+    timers: BTreeMap<Duration, BTreeSet<InputId>>,
+    open_inputs: BTreeMap<NodeId, BTreeSet<DataId>>,
+    open_external_mappings: HashMap<OutputId, BTreeMap<String, BTreeSet<InputId>>>,
+    remote_nodes: BTreeSet<NodeId>,
+    latched_outputs: HashSet<OutputId>,
+}
+
 #[derive(Debug, Clone)]
 struct RunningNode {
     pid: Option<u32>,
@@ -1469,7 +2859,7 @@ pub struct RunningDataflow {
     /// Local nodes that are not started yet
     pending_nodes: PendingNodes,
 
-    subscribe_channels: HashMap<NodeId, UnboundedSender<Timestamped<daemon_messages::NodeEvent>>>,
+    subscribe_channels: HashMap<NodeId, NodeEventSender>,
     drop_channels: HashMap<NodeId, UnboundedSender<Timestamped<daemon_messages::NodeDropEvent>>>,
     mappings: HashMap<OutputId, BTreeSet<InputId>>,
     timers: BTreeMap<Duration, BTreeSet<InputId>>,
@@ -1485,8 +2875,19 @@ pub struct RunningDataflow {
     open_external_mappings: HashMap<OutputId, BTreeMap<String, BTreeSet<InputId>>>,
 
     pending_drop_tokens: HashMap<DropToken, DropTokenInformation>,
+    /// Shared memory segments that are leased to the nodes for sending outputs.
+    shmem_pool: ShmemPool,
+
+    /// Outputs that are declared as `latched` in the dataflow.
+    latched_outputs: HashSet<OutputId>,
+    /// The last message of every latched output, delivered to nodes that subscribe later.
+    latched_messages: HashMap<OutputId, LatchedMessage>,
+
+    /// Messages to inject into node inputs once the dataflow is started.
+    injections: BTreeMap<InputId, Vec<InjectedMessage>>,
+    output_taps: HashMap<OutputId, UnboundedSender<CapturedOutput>>,
 
-    /// Keep handles to all timer tasks of this dataflow to cancel them on drop.
+    /// Keep handles to all timer and injection tasks of this dataflow to cancel them on drop.
     _timer_handles: Vec<futures::future::RemoteHandle<()>>,
     stop_sent: bool,
 
@@ -1500,10 +2901,437 @@ pub struct RunningDataflow {
     grace_duration_kills: Arc<crossbeam_skiplist::SkipSet<NodeId>>,
 
     node_stderr_most_recent: BTreeMap<NodeId, Arc<ArrayQueue<String>>>,
+
+    /// Read-only subscribers that are not part of the dataflow descriptor.
+    ///
+    /// They receive copies of shared memory outputs instead of drop tokens.
+    ad_hoc_subscribers: BTreeSet<NodeId>,
+    /// The descriptor of the dataflow, including added nodes.
+    descriptor: Option<Descriptor>,
+    /// Nodes of the dataflow that run on other machines.
+    remote_nodes: BTreeSet<NodeId>,
+    /// Nodes that were stopped because they were removed from the dataflow.
+    ///
+    /// Their exit is not reported as an error, even if they had to be killed.
+    removed_nodes: BTreeSet<NodeId>,
+    /// Whether all nodes were ready and the timers were started.
+    started: bool,
+    /// Zenoh session for sending outputs to remote machines, if configured.
+    zenoh: Option<ZenohConnection>,
+    /// Compression of local outputs that are sent to other machines, by target machine.
+    remote_compression: HashMap<OutputId, BTreeMap<String, Compression>>,
+    stats: DataflowDaemonStats,
+    /// Event queue and virtual clock, if the dataflow runs in deterministic mode.
+    lockstep: Option<Lockstep>,
+    /// Time source for timers and message timestamps, unless the dataflow
+    /// follows the wall clock.
+    simulated_clock: Option<SimulatedClock>,
 }
 
 impl RunningDataflow {
-    fn new(dataflow_id: Uuid, machine_id: String) -> RunningDataflow {
+    /// Records the given input of a node in the mappings of the dataflow.
+    ///
+    /// Inputs of local nodes are added to the local mappings, inputs of
+    /// remote nodes to the external mappings of their source.
+    fn register_input(
+        &mut self,
+        node: &ResolvedNode,
+        input_id: DataId,
+        input: Input,
+        local: bool,
+        dataflow_descriptor: &Descriptor,
+    ) {
+        if local {
+            self.open_inputs
+                .entry(node.id.clone())
+                .or_default()
+                .insert(input_id.clone());
+            if self
+                .injections
+                .contains_key(&(node.id.clone(), input_id.clone()))
+            {
+                // fed by an injection tap instead of the mapped source
+                return;
+            }
+            match input.mapping {
+                InputMapping::User(mapping) => {
+                    self.mappings
+                        .entry(OutputId(mapping.source, mapping.output))
+                        .or_default()
+                        .insert((node.id.clone(), input_id));
+                }
+                InputMapping::Timer { interval } => {
+                    self.timers
+                        .entry(interval)
+                        .or_default()
+                        .insert((node.id.clone(), input_id));
+                }
+            }
+        } else {
+            let output_id = match input.mapping {
+                InputMapping::User(mapping) => OutputId(mapping.source, mapping.output),
+                // timers are derived from the external clock, if configured, so
+                // its messages need to be forwarded to the machine of the node
+                InputMapping::Timer { .. } => {
+                    match self
+                        .simulated_clock
+                        .as_ref()
+                        .and_then(|c| c.external_source())
+                    {
+                        Some(source) => source.clone(),
+                        None => return,
+                    }
+                }
+            };
+            let compression = input
+                .compression
+                .or(dataflow_descriptor.communication.compression);
+            if let Some(compression) = compression {
+                // inputs on the same machine share the transferred message; the
+                // dataflow validation ensures that all inputs of an output use
+                // the same compression, so inputs without compression receive
+                // the compressed message too
+                self.remote_compression
+                    .entry(output_id.clone())
+                    .or_default()
+                    .entry(node.deploy.machine.clone())
+                    .or_insert(compression);
+            }
+            self.open_external_mappings
+                .entry(output_id)
+                .or_default()
+                .entry(node.deploy.machine.clone())
+                .or_default()
+                .insert((node.id.clone(), input_id));
+        }
+    }
+
+    /// Opens the zenoh session if it is configured and the dataflow has remote nodes.
+    async fn open_zenoh(
+        &mut self,
+        dataflow_descriptor: &Descriptor,
+        config: InterDaemonConfig,
+        clock: Arc<HLC>,
+    ) -> eyre::Result<()> {
+        if let RemoteCommunicationConfig::Zenoh {
+            config: zenoh_config,
+            prefix,
+        } = &dataflow_descriptor.communication.remote
+        {
+            if self.zenoh.is_none() && !self.remote_nodes.is_empty() {
+                let zenoh = ZenohConnection::open(
+                    self.id,
+                    zenoh_config.clone(),
+                    prefix,
+                    config.buffer_size,
+                    clock,
+                )
+                .await
+                .wrap_err("failed to open zenoh session for remote communication")?;
+                self.zenoh = Some(zenoh);
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn spawn_node(
+        &mut self,
+        working_dir: &Path,
+        node: ResolvedNode,
+        events_tx: mpsc::Sender<Timestamped<Event>>,
+        dataflow_descriptor: Descriptor,
+        clock: Arc<HLC>,
+        print_node_output: bool,
+        shmem_registry: &ShmemRegistry,
+    ) -> eyre::Result<()> {
+        if node.kind.dynamic() {
+            self.dynamic_nodes.insert(node.id.clone());
+        }
+
+        let node_id = node.id.clone();
+        let node_stderr_most_recent = self
+            .node_stderr_most_recent
+            .entry(node.id.clone())
+            .or_insert_with(|| Arc::new(ArrayQueue::new(STDERR_LOG_LINES)))
+            .clone();
+        let running_node = spawn::spawn_node(
+            self.id,
+            working_dir,
+            node,
+            events_tx,
+            dataflow_descriptor,
+            clock,
+            node_stderr_most_recent,
+            print_node_output,
+            self.lockstep.is_some(),
+            shmem_registry,
+        )
+        .await
+        .wrap_err_with(|| format!("failed to spawn node `{node_id}`"))?;
+        self.running_nodes.insert(node_id, running_node);
+        Ok(())
+    }
+
+    /// Stops the given nodes and removes their inputs from the mappings.
+    ///
+    /// The inputs that are fed by the removed nodes are closed when the nodes exit.
+    fn remove_nodes(
+        &mut self,
+        nodes: &BTreeSet<NodeId>,
+        clock: &HLC,
+        grace_duration: Option<Duration>,
+    ) {
+        let mut stopped = BTreeMap::new();
+        for node_id in nodes {
+            if let Some(channel) = self.subscribe_channels.remove(node_id) {
+                let _ = send_with_timestamp(&channel, daemon_messages::NodeEvent::Stop, clock);
+            }
+            if let Some(node) = self.running_nodes.get(node_id) {
+                stopped.insert(node_id.clone(), node.clone());
+            }
+            self.open_inputs.remove(node_id);
+        }
+        self.removed_nodes.extend(nodes.iter().cloned());
+        let is_removed = |(receiver_id, _): &InputId| nodes.contains(receiver_id);
+        for receivers in self.mappings.values_mut() {
+            receivers.retain(|input| !is_removed(input));
+        }
+        // remote outputs without local receivers are no longer needed
+        let unused: Vec<_> = self
+            .mappings
+            .iter()
+            .filter(|(OutputId(source, _), receivers)| {
+                receivers.is_empty() && self.remote_nodes.contains(source)
+            })
+            .map(|(output_id, _)| output_id.clone())
+            .collect();
+        for output_id in unused {
+            self.mappings.remove(&output_id);
+            if let Some(zenoh) = &mut self.zenoh {
+                zenoh.unsubscribe(&output_id);
+            }
+        }
+        for receivers in self.timers.values_mut() {
+            receivers.retain(|input| !is_removed(input));
+        }
+        for machines in self.open_external_mappings.values_mut() {
+            for receivers in machines.values_mut() {
+                receivers.retain(|input| !is_removed(input));
+            }
+            machines.retain(|_, receivers| !receivers.is_empty());
+        }
+
+        // removed nodes that need to be killed are not a failure of the dataflow
+        kill_after_grace_duration(stopped, None, grace_duration);
+    }
+
+    /// Returns a copy of the routing state, to undo a failed `add_nodes` call.
+    fn routes(&self) -> Routes {
+        Routes {
+            mappings: self.mappings.clone(),
+            timers: self.timers.clone(),
+            open_inputs: self.open_inputs.clone(),
+            open_external_mappings: self.open_external_mappings.clone(),
+            remote_nodes: self.remote_nodes.clone(),
+            latched_outputs: self.latched_outputs.clone(),
+        }
+    }
+
+    /// Stops the already spawned nodes of a failed `add_nodes` call and
+    /// restores the routing state from before the call.
+    fn undo_add_nodes(&mut self, added: &BTreeSet<NodeId>, mut routes: Routes, clock: &HLC) {
+        self.remove_nodes(added, clock, None);
+        // only spawned nodes report their exit, so the others must not be
+        // remembered in case they are added again later
+        self.removed_nodes
+            .retain(|id| !added.contains(id) || self.running_nodes.contains_key(id));
+        if let Some(zenoh) = &mut self.zenoh {
+            for output_id in self.mappings.keys() {
+                if !routes.mappings.contains_key(output_id) {
+                    zenoh.unsubscribe(output_id);
+                }
+            }
+        }
+        // timers that were started already must not be started again
+        for interval in self.timers.keys() {
+            routes.timers.entry(*interval).or_default();
+        }
+        self.mappings = routes.mappings;
+        self.timers = routes.timers;
+        self.open_inputs = routes.open_inputs;
+        self.open_external_mappings = routes.open_external_mappings;
+        self.remote_nodes = routes.remote_nodes;
+        self.latched_outputs = routes.latched_outputs;
+    }
+
+    /// Records the `latched_outputs` of the given node.
+    fn register_latched_outputs(&mut self, node: &ResolvedNode) {
+        let latched = node
+            .kind
+            .run_config()
+            .latched_outputs
+            .into_iter()
+            .map(|output_id| OutputId(node.id.clone(), output_id));
+        self.latched_outputs.extend(latched);
+    }
+
+    /// Keeps the given message if it was sent on a latched output.
+    fn retain_latched(
+        &mut self,
+        output_id: &OutputId,
+        metadata: &Metadata,
+        data: &Option<AVec<u8, ConstAlign<128>>>,
+    ) {
+        if self.latched_outputs.contains(output_id) {
+            self.latched_messages.insert(
+                output_id.clone(),
+                LatchedMessage {
+                    metadata: metadata.clone(),
+                    data: data.clone(),
+                },
+            );
+        }
+    }
+
+    /// Whether the given message of a latched output is not newer than the
+    /// retained one.
+    ///
+    /// Latched messages of remote outputs can be received twice when they are
+    /// replayed to added nodes.
+    fn is_stale_latched(&self, output_id: &OutputId, metadata: &Metadata) -> bool {
+        self.latched_messages
+            .get(output_id)
+            .is_some_and(|retained| metadata.timestamp() <= retained.metadata.timestamp())
+    }
+
+    /// Registers an ad-hoc subscriber with the given inputs.
+    fn add_ad_hoc_subscriber(&mut self, node_id: &NodeId, inputs: &BTreeMap<DataId, Input>) {
+        for (input_id, input) in inputs {
+            if let InputMapping::User(mapping) = &input.mapping {
+                self.mappings
+                    .entry(OutputId(mapping.source.clone(), mapping.output.clone()))
+                    .or_default()
+                    .insert((node_id.clone(), input_id.clone()));
+            }
+        }
+        self.open_inputs
+            .insert(node_id.clone(), inputs.keys().cloned().collect());
+        self.ad_hoc_subscribers.insert(node_id.clone());
+    }
+
+    /// Unregisters the given ad-hoc subscriber if it did not subscribe yet.
+    ///
+    /// Returns whether the subscriber was removed.
+    fn remove_unconnected_ad_hoc_subscriber(&mut self, node_id: &NodeId) -> bool {
+        let unconnected = self.ad_hoc_subscribers.contains(node_id)
+            && !self.subscribe_channels.contains_key(node_id);
+        if unconnected {
+            self.remove_ad_hoc_subscriber(node_id);
+        }
+        unconnected
+    }
+
+    /// Unregisters the given ad-hoc subscriber after it disconnected.
+    fn remove_ad_hoc_subscriber(&mut self, node_id: &NodeId) {
+        self.ad_hoc_subscribers.remove(node_id);
+        self.subscribe_channels.remove(node_id);
+        self.drop_channels.remove(node_id);
+        self.open_inputs.remove(node_id);
+        let mut unused = Vec::new();
+        for (output_id, receivers) in &mut self.mappings {
+            let subscribed = receivers
+                .iter()
+                .any(|(receiver_id, _)| receiver_id == node_id);
+            receivers.retain(|(receiver_id, _)| receiver_id != node_id);
+            if subscribed && receivers.is_empty() {
+                unused.push(output_id.clone());
+            }
+        }
+        // drop the mappings that are only left over from the subscriber
+        for output_id in unused {
+            self.mappings.remove(&output_id);
+            if let Some(zenoh) = &mut self.zenoh {
+                zenoh.unsubscribe(&output_id);
+            }
+        }
+        tracing::info!(
+            "removed ad-hoc subscriber `{node_id}` from dataflow `{}`",
+            self.id
+        );
+    }
+
+    /// Closes all local inputs that are fed by the given nodes of a lost machine.
+    fn machine_lost(&mut self, machine_id: &str, nodes: &BTreeSet<NodeId>, clock: &HLC) {
+        // the lost machine is unreachable, so don't try to send anything to it
+        for receivers in self.open_external_mappings.values_mut() {
+            receivers.remove(machine_id);
+        }
+        let inputs: BTreeSet<_> = self
+            .mappings
+            .iter()
+            .filter(|(OutputId(source, _), _)| nodes.contains(source))
+            .flat_map(|(_, inputs)| inputs)
+            .cloned()
+            .collect();
+        for (receiver_id, input_id) in &inputs {
+            close_input(self, receiver_id, input_id, clock);
+        }
+    }
+
+    fn compression_stats(
+        &mut self,
+        OutputId(node_id, output_id): &OutputId,
+    ) -> &mut CompressionStats {
+        self.stats
+            .compression
+            .entry(format!("{node_id}/{output_id}"))
+            .or_default()
+    }
+
+    /// Creates an `Output` event for remote receivers, compressing the data
+    /// with the given algorithm.
+    fn remote_output_event(
+        &mut self,
+        output_id: &OutputId,
+        metadata: Metadata,
+        data: Option<AVec<u8, ConstAlign<128>>>,
+        compression: Option<Compression>,
+        clock: &HLC,
+    ) -> eyre::Result<Timestamped<InterDaemonEvent>> {
+        let (data, compression) = match (data, compression) {
+            (Some(data), Some(compression)) => {
+                let stats = self.compression_stats(output_id);
+                let compressed =
+                    compression::compress(compression, &data, &metadata.type_info, stats)
+                        .wrap_err_with(|| {
+                            format!(
+                                "failed to compress output `{}/{}`",
+                                output_id.0, output_id.1
+                            )
+                        })?;
+                (Some(compressed), Some(compression))
+            }
+            (data, _) => (data, None),
+        };
+        Ok(Timestamped {
+            inner: InterDaemonEvent::Output {
+                dataflow_id: self.id,
+                node_id: output_id.0.clone(),
+                output_id: output_id.1.clone(),
+                metadata,
+                data,
+                compression,
+            },
+            timestamp: clock.new_timestamp(),
+        })
+    }
+
+    fn new(
+        dataflow_id: Uuid,
+        machine_id: String,
+        shmem_registry: ShmemRegistry,
+    ) -> RunningDataflow {
         Self {
             id: dataflow_id,
             pending_nodes: PendingNodes::new(dataflow_id, machine_id),
@@ -1516,12 +3344,27 @@ impl RunningDataflow {
             dynamic_nodes: BTreeSet::new(),
             open_external_mappings: HashMap::new(),
             pending_drop_tokens: HashMap::new(),
+            shmem_pool: ShmemPool::new(dataflow_id, shmem_registry),
+            latched_outputs: HashSet::new(),
+            latched_messages: HashMap::new(),
+            injections: BTreeMap::new(),
+            output_taps: HashMap::new(),
             _timer_handles: Vec::new(),
             stop_sent: false,
             empty_set: BTreeSet::new(),
             cascading_error_causes: Default::default(),
             grace_duration_kills: Default::default(),
             node_stderr_most_recent: BTreeMap::new(),
+            ad_hoc_subscribers: BTreeSet::new(),
+            descriptor: None,
+            remote_nodes: BTreeSet::new(),
+            removed_nodes: BTreeSet::new(),
+            started: false,
+            zenoh: None,
+            remote_compression: HashMap::new(),
+            stats: DataflowDaemonStats::default(),
+            lockstep: None,
+            simulated_clock: None,
         }
     }
 
@@ -1530,45 +3373,65 @@ impl RunningDataflow {
         events_tx: &mpsc::Sender<Timestamped<Event>>,
         clock: &Arc<HLC>,
     ) -> eyre::Result<()> {
-        for interval in self.timers.keys().copied() {
+        self.started = true;
+        let intervals: Vec<_> = self.timers.keys().copied().collect();
+        for interval in intervals {
+            self.start_timer(interval, events_tx, clock);
+        }
+
+        if let Some(lockstep) = &mut self.lockstep {
+            for (input_id, messages) in std::mem::take(&mut self.injections) {
+                lockstep.add_injection(input_id, messages);
+            }
+            return Ok(());
+        }
+
+        let factor = self
+            .simulated_clock
+            .as_ref()
+            .and_then(|c| c.factor())
+            .unwrap_or(1.0);
+        for (input_id, messages) in std::mem::take(&mut self.injections) {
             let events_tx = events_tx.clone();
             let dataflow_id = self.id;
             let clock = clock.clone();
             let task = async move {
-                let mut interval_stream = tokio::time::interval(interval);
-                let hlc = HLC::default();
-                loop {
-                    interval_stream.tick().await;
-
-                    let span = tracing::span!(tracing::Level::TRACE, "tick");
-                    let _ = span.enter();
-
-                    let metadata = dora_core::message::Metadata::from_parameters(
-                        hlc.new_timestamp(),
-                        ArrowTypeInfo::empty(),
-                        MetadataParameters {
-                            watermark: 0,
-                            deadline: 0,
-                            #[cfg(feature = "telemetry")]
-                            open_telemetry_context: serialize_context(&span.context()),
-                            #[cfg(not(feature = "telemetry"))]
-                            open_telemetry_context: "".into(),
-                        },
-                    );
+                let start = tokio::time::Instant::now();
+                for message in messages {
+                    let Some(deadline) = simulated_clock::wall_duration(message.offset, factor)
+                        .and_then(|offset| start.checked_add(offset))
+                    else {
+                        tracing::warn!(
+                            "offset of injected message is too large, stopping injection"
+                        );
+                        break;
+                    };
+                    tokio::time::sleep_until(deadline).await;
 
+                    let (type_info, data) = array_to_data_message(&message.data);
                     let event = Timestamped {
-                        inner: DoraEvent::Timer {
+                        inner: DoraEvent::InjectedInput {
                             dataflow_id,
-                            interval,
-                            metadata,
+                            input_id: input_id.clone(),
+                            metadata: Metadata::new(clock.new_timestamp(), type_info),
+                            data,
                         }
                         .into(),
                         timestamp: clock.new_timestamp(),
                     };
                     if events_tx.send(event).await.is_err() {
-                        break;
+                        return;
                     }
                 }
+                let event = Timestamped {
+                    inner: DoraEvent::InjectionDone {
+                        dataflow_id,
+                        input_id,
+                    }
+                    .into(),
+                    timestamp: clock.new_timestamp(),
+                };
+                let _ = events_tx.send(event).await;
             };
             let (task, handle) = task.remote_handle();
             tokio::spawn(task);
@@ -1578,9 +3441,81 @@ impl RunningDataflow {
         Ok(())
     }
 
+    fn start_timer(
+        &mut self,
+        interval: Duration,
+        events_tx: &mpsc::Sender<Timestamped<Event>>,
+        clock: &Arc<HLC>,
+    ) {
+        if let Some(lockstep) = &mut self.lockstep {
+            lockstep.add_timer(interval);
+            return;
+        }
+        let period = match &mut self.simulated_clock {
+            Some(simulated_clock) => match simulated_clock.factor() {
+                Some(factor) => match simulated_clock::wall_duration(interval, factor) {
+                    Some(period) => period.max(Duration::from_nanos(1)),
+                    None => {
+                        tracing::warn!("ignoring timer with too large interval {interval:?}");
+                        return;
+                    }
+                },
+                None => {
+                    // ticks are derived from the external clock
+                    simulated_clock.add_timer(interval);
+                    return;
+                }
+            },
+            None => interval,
+        };
+
+        let events_tx = events_tx.clone();
+        let dataflow_id = self.id;
+        let clock = clock.clone();
+        let task = async move {
+            let mut interval_stream = tokio::time::interval(period);
+            let hlc = HLC::default();
+            loop {
+                interval_stream.tick().await;
+
+                let span = tracing::span!(tracing::Level::TRACE, "tick");
+                let _ = span.enter();
+
+                let metadata = dora_core::message::Metadata::from_parameters(
+                    hlc.new_timestamp(),
+                    ArrowTypeInfo::empty(),
+                    MetadataParameters {
+                        watermark: 0,
+                        deadline: 0,
+                        #[cfg(feature = "telemetry")]
+                        open_telemetry_context: serialize_context(&span.context()),
+                        #[cfg(not(feature = "telemetry"))]
+                        open_telemetry_context: "".into(),
+                    },
+                );
+
+                let event = Timestamped {
+                    inner: DoraEvent::Timer {
+                        dataflow_id,
+                        interval,
+                        metadata,
+                    }
+                    .into(),
+                    timestamp: clock.new_timestamp(),
+                };
+                if events_tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let (task, handle) = task.remote_handle();
+        tokio::spawn(task);
+        self._timer_handles.push(handle);
+    }
+
     async fn stop_all(
         &mut self,
-        coordinator_connection: &mut Option<TcpStream>,
+        coordinator_connection: &mut Option<CoordinatorConnection>,
         clock: &HLC,
         grace_duration: Option<Duration>,
     ) -> eyre::Result<()> {
@@ -1597,31 +3532,33 @@ impl RunningDataflow {
             let _ = send_with_timestamp(&channel, daemon_messages::NodeEvent::Stop, clock);
         }
 
-        let running_nodes = self.running_nodes.clone();
-        let grace_duration_kills = self.grace_duration_kills.clone();
-        tokio::spawn(async move {
-            let duration = grace_duration.unwrap_or(Duration::from_millis(2000));
-            tokio::time::sleep(duration).await;
-            let mut system = sysinfo::System::new();
-            system.refresh_processes();
-
-            for (node, node_details) in running_nodes.iter() {
-                if let Some(pid) = node_details.pid {
-                    if let Some(process) = system.process(Pid::from(pid as usize)) {
-                        grace_duration_kills.insert(node.clone());
-                        process.kill();
-                        warn!(
-                            "{node} was killed due to not stopping within the {:#?} grace period",
-                            duration
-                        )
-                    }
-                }
-            }
-        });
+        kill_after_grace_duration(
+            self.running_nodes.clone(),
+            Some(self.grace_duration_kills.clone()),
+            grace_duration,
+        );
         self.stop_sent = true;
         Ok(())
     }
 
+    /// Whether all local nodes wait for their next event and received all
+    /// events that were sent to them.
+    ///
+    /// Always `false` for dataflows that don't run in deterministic mode.
+    fn all_nodes_idle(&self) -> bool {
+        let Some(lockstep) = &self.lockstep else {
+            return false;
+        };
+        self.running_nodes.iter().all(|(node_id, node)| {
+            match self.subscribe_channels.get(node_id) {
+                Some(channel) => lockstep.is_idle(node_id, channel.sent()),
+                // dynamic nodes might not be connected; other nodes only have no
+                // channel after dropping their event stream, i.e. while exiting
+                None => node.node_config.dynamic,
+            }
+        })
+    }
+
     fn open_inputs(&self, node_id: &NodeId) -> &BTreeSet<DataId> {
         self.open_inputs.get(node_id).unwrap_or(&self.empty_set)
     }
@@ -1631,6 +3568,7 @@ impl RunningDataflow {
             std::collections::hash_map::Entry::Occupied(entry) => {
                 if entry.get().pending_nodes.is_empty() {
                     let (drop_token, info) = entry.remove_entry();
+                    self.shmem_pool.release(&drop_token);
                     let result = match self.drop_channels.get_mut(&info.owner) {
                         Some(channel) => send_with_timestamp(
                             channel,
@@ -1663,6 +3601,13 @@ impl RunningDataflow {
 pub struct OutputId(NodeId, DataId);
 type InputId = (NodeId, DataId);
 
+/// The last message of a latched output.
+struct LatchedMessage {
+    metadata: Metadata,
+    /// Daemon-owned copy of the data, so that the sender's shared memory can be reused.
+    data: Option<AVec<u8, ConstAlign<128>>>,
+}
+
 struct DropTokenInformation {
     /// The node that created the associated drop token.
     owner: NodeId,
@@ -1684,6 +3629,13 @@ pub enum Event {
     DynamicNode(DynamicNodeEventWrapper),
     HeartbeatInterval,
     CtrlC,
+    Timeout,
+    /// The event stream of the coordinator connection with the given generation ended.
+    CoordinatorDisconnected {
+        generation: u64,
+    },
+    /// The daemon registered at the coordinator again after a disconnect.
+    CoordinatorReconnected(coordinator::Reconnected),
 }
 
 impl From<DoraEvent> for Event {
@@ -1717,9 +3669,21 @@ pub enum DaemonNodeEvent {
     ReportDrop {
         tokens: Vec<DropToken>,
     },
+    InputsDropped {
+        counts: BTreeMap<DataId, u64>,
+    },
     EventStreamDropped {
         reply_sender: oneshot::Sender<DaemonReply>,
     },
+    AllocateSharedMemory {
+        len: usize,
+        reply_sender: oneshot::Sender<DaemonReply>,
+    },
+    /// The node waits for its next event after receiving the given number of
+    /// events (only reported in deterministic mode).
+    Idle {
+        received: u64,
+    },
 }
 
 #[derive(Debug)]
@@ -1735,11 +3699,39 @@ pub enum DoraEvent {
         message: DataMessage,
         metadata: Metadata,
     },
+    InjectedInput {
+        dataflow_id: DataflowId,
+        input_id: InputId,
+        metadata: Metadata,
+        data: DataMessage,
+    },
+    InjectionDone {
+        dataflow_id: DataflowId,
+        input_id: InputId,
+    },
     SpawnedNodeResult {
         dataflow_id: DataflowId,
         node_id: NodeId,
         exit_status: NodeExitStatus,
     },
+    /// A structured log record that a node printed on stdout or stderr.
+    NodeLog(LogMessage),
+    /// Output of a node on stdout or stderr.
+    NodeOutput {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output: String,
+    },
+    /// Removes the given ad-hoc subscriber if it did not subscribe yet.
+    AdHocSubscriberConnectTimeout {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+    },
+    /// The event stream of the given ad-hoc subscriber was closed.
+    AdHocSubscriberDisconnected {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+    },
 }
 
 #[must_use]
@@ -1749,44 +3741,166 @@ enum RunStatus {
 }
 
 fn send_with_timestamp<T>(
-    sender: &UnboundedSender<Timestamped<T>>,
+    sender: &impl TimestampedSender<T>,
     event: T,
     clock: &HLC,
 ) -> Result<(), mpsc::error::SendError<Timestamped<T>>> {
-    sender.send(Timestamped {
+    sender.send_timestamped(Timestamped {
         inner: event,
         timestamp: clock.new_timestamp(),
     })
 }
 
-fn set_up_ctrlc_handler(
-    clock: Arc<HLC>,
-) -> Result<impl Stream<Item = Timestamped<Event>>, eyre::ErrReport> {
-    let (ctrlc_tx, ctrlc_rx) = mpsc::channel(1);
+trait TimestampedSender<T> {
+    fn send_timestamped(
+        &self,
+        message: Timestamped<T>,
+    ) -> Result<(), mpsc::error::SendError<Timestamped<T>>>;
+}
 
-    let mut ctrlc_sent = false;
-    ctrlc::set_handler(move || {
-        if ctrlc_sent {
-            tracing::warn!("received second ctrlc signal -> aborting immediately");
-            std::process::abort();
-        } else {
-            tracing::info!("received ctrlc signal");
-            if ctrlc_tx
-                .blocking_send(Timestamped {
-                    inner: Event::CtrlC,
-                    timestamp: clock.new_timestamp(),
-                })
-                .is_err()
-            {
-                tracing::error!("failed to report ctrl-c event to dora-coordinator");
-            }
+impl<T> TimestampedSender<T> for UnboundedSender<Timestamped<T>> {
+    fn send_timestamped(
+        &self,
+        message: Timestamped<T>,
+    ) -> Result<(), mpsc::error::SendError<Timestamped<T>>> {
+        self.send(message)
+    }
+}
+
+/// Sends events to a subscribed node and counts them.
+///
+/// The count is compared with the idle reports of the node in deterministic mode.
+struct NodeEventSender {
+    channel: UnboundedSender<Timestamped<daemon_messages::NodeEvent>>,
+    sent: AtomicU64,
+}
 
-            ctrlc_sent = true;
+impl NodeEventSender {
+    fn new(channel: UnboundedSender<Timestamped<daemon_messages::NodeEvent>>) -> Self {
+        Self {
+            channel,
+            sent: AtomicU64::new(0),
         }
-    })
-    .wrap_err("failed to set ctrl-c handler")?;
+    }
+
+    fn send(
+        &self,
+        event: Timestamped<daemon_messages::NodeEvent>,
+    ) -> Result<(), mpsc::error::SendError<Timestamped<daemon_messages::NodeEvent>>> {
+        self.channel.send(event)?;
+        self.sent.fetch_add(1, atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Number of events that were sent successfully.
+    fn sent(&self) -> u64 {
+        self.sent.load(atomic::Ordering::Relaxed)
+    }
+}
+
+impl TimestampedSender<daemon_messages::NodeEvent> for NodeEventSender {
+    fn send_timestamped(
+        &self,
+        message: Timestamped<daemon_messages::NodeEvent>,
+    ) -> Result<(), mpsc::error::SendError<Timestamped<daemon_messages::NodeEvent>>> {
+        self.send(message)
+    }
+}
+
+/// Wraps the events of a coordinator connection and appends a
+/// [`Event::CoordinatorDisconnected`] event once the connection is closed.
+fn coordinator_event_stream(
+    events: impl Stream<Item = Timestamped<CoordinatorEvent>> + Send + 'static,
+    generation: u64,
+    clock: Arc<HLC>,
+) -> impl Stream<Item = Timestamped<Event>> + Unpin + Send {
+    let events = events.map(|Timestamped { inner, timestamp }| Timestamped {
+        inner: Event::Coordinator(inner),
+        timestamp,
+    });
+    let disconnected = stream::once(async move {
+        Timestamped {
+            inner: Event::CoordinatorDisconnected { generation },
+            timestamp: clock.new_timestamp(),
+        }
+    });
+    Box::pin(events.chain(disconnected))
+}
+
+/// Sends the given event to the coordinator, if the daemon is connected to one.
+/// Sends the given event to the daemon after the given duration.
+fn send_after(
+    events_tx: &mpsc::Sender<Timestamped<Event>>,
+    duration: Duration,
+    event: DoraEvent,
+    clock: Arc<HLC>,
+) {
+    let events_tx = events_tx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(duration).await;
+        let event = Timestamped {
+            inner: event.into(),
+            timestamp: clock.new_timestamp(),
+        };
+        let _ = events_tx.send(event).await;
+    });
+}
+
+async fn send_to_coordinator(
+    coordinator_connection: &mut Option<CoordinatorConnection>,
+    machine_id: &str,
+    event: DaemonEvent,
+    clock: &HLC,
+) -> eyre::Result<()> {
+    if let Some(connection) = coordinator_connection {
+        connection
+            .send(Timestamped {
+                inner: CoordinatorRequest::Event {
+                    machine_id: machine_id.to_owned(),
+                    event,
+                },
+                timestamp: clock.new_timestamp(),
+            })
+            .await
+            .wrap_err("failed to send event to dora-coordinator")?;
+    }
+    Ok(())
+}
 
-    Ok(ReceiverStream::new(ctrlc_rx))
+/// Labels that are detected automatically and sent to the coordinator on register.
+fn default_labels() -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    labels.insert("arch".to_owned(), std::env::consts::ARCH.to_owned());
+    labels.insert("os".to_owned(), std::env::consts::OS.to_owned());
+    if let Ok(cpus) = std::thread::available_parallelism() {
+        labels.insert("cpus".to_owned(), cpus.to_string());
+    }
+    labels
+}
+
+/// Subscribes to ctrl-c signals.
+///
+/// The subscription ends when the returned stream is dropped. This way,
+/// `Daemon::run_dataflow` can be called multiple times in the same process,
+/// also concurrently.
+fn set_up_ctrlc_handler(
+    clock: Arc<HLC>,
+) -> Result<impl Stream<Item = Timestamped<Event>>, eyre::ErrReport> {
+    let (ctrlc_tx, ctrlc_rx) = mpsc::channel(1);
+    let subscription = ctrlc_handler::subscribe_ctrlc(move || {
+        ctrlc_tx
+            .try_send(Timestamped {
+                inner: Event::CtrlC,
+                timestamp: clock.new_timestamp(),
+            })
+            .map_err(|_| eyre!("failed to report ctrl-c event to dora-daemon"))
+    })?;
+
+    Ok(ReceiverStream::new(ctrlc_rx).map(move |event| {
+        // keep the subscription as long as the stream
+        let _ = &subscription;
+        event
+    }))
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -1808,3 +3922,216 @@ impl CascadingErrorCauses {
         self.caused_by.entry(affected_node).or_insert(causing_node);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a dataflow with a single shell node to `dataflow.yml` in a new
+    /// temporary directory.
+    fn write_shell_dataflow(command: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let dataflow = format!(
+            "nodes:\n  - id: shell\n    custom:\n      source: shell\n      args: {command}\n"
+        );
+        std::fs::write(dir.path().join("dataflow.yml"), dataflow).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn run_dataflow_rejects_unknown_stop_after_node() {
+        let dir = write_shell_dataflow("exit 0");
+        let path = dir.path().join("dataflow.yml");
+        let options = RunDataflowOptions {
+            stop_after: Some(NodeId::from("missing".to_string())),
+            ..Default::default()
+        };
+        let err = Daemon::run_dataflow(&path, options).await.unwrap_err();
+        assert!(format!("{err:?}").contains("no node with ID `missing`"));
+    }
+
+    #[tokio::test]
+    async fn run_dataflow_returns_when_nodes_exit() {
+        let dir = write_shell_dataflow("exit 0");
+        let path = dir.path().join("dataflow.yml");
+        let result = tokio::time::timeout(
+            Duration::from_secs(30),
+            Daemon::run_dataflow(&path, RunDataflowOptions::default()),
+        )
+        .await
+        .expect("dataflow did not finish")
+        .unwrap();
+        assert!(result
+            .node_results
+            .contains_key(&NodeId::from("shell".to_string())));
+    }
+    /// Returns a dataflow with a `source` node on machine `B` that feeds a
+    /// `sink` and an `added` node on machine `A`.
+    fn remote_source_dataflow() -> (Descriptor, Vec<ResolvedNode>) {
+        let descriptor: Descriptor = serde_yaml::from_str(
+            r#"
+            nodes:
+              - id: source
+                path: dynamic
+                _unstable_deploy:
+                  machine: B
+                outputs: [out]
+              - id: sink
+                path: dynamic
+                _unstable_deploy:
+                  machine: A
+                inputs:
+                  in: source/out
+              - id: added
+                path: dynamic
+                _unstable_deploy:
+                  machine: A
+                inputs:
+                  in: source/out
+                  tick: dora/timer/millis/100
+            "#,
+        )
+        .unwrap();
+        let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+        (descriptor, nodes)
+    }
+
+    fn running_dataflow(descriptor: &Descriptor, nodes: &[ResolvedNode]) -> RunningDataflow {
+        let mut dataflow =
+            RunningDataflow::new(Uuid::now_v7(), "A".into(), ShmemRegistry::open().unwrap());
+        dataflow.remote_nodes.insert("source".to_string().into());
+        let sink = nodes.iter().find(|n| n.id.as_ref() == "sink").unwrap();
+        for (input_id, input) in node_inputs(sink) {
+            dataflow.register_input(sink, input_id, input, true, descriptor);
+        }
+        dataflow
+    }
+
+    #[tokio::test]
+    async fn undo_add_nodes_restores_the_routes() {
+        let (descriptor, nodes) = remote_source_dataflow();
+        let mut dataflow = running_dataflow(&descriptor, &nodes);
+        let routes = dataflow.routes();
+
+        let added = nodes.iter().find(|n| n.id.as_ref() == "added").unwrap();
+        for (input_id, input) in node_inputs(added) {
+            dataflow.register_input(added, input_id, input, true, &descriptor);
+        }
+        let added_ids = BTreeSet::from([added.id.clone()]);
+        dataflow.undo_add_nodes(&added_ids, routes, &HLC::default());
+
+        let source_out = OutputId("source".to_string().into(), "out".to_string().into());
+        assert_eq!(
+            dataflow.mappings[&source_out],
+            BTreeSet::from([("sink".to_string().into(), "in".to_string().into())])
+        );
+        assert!(!dataflow.open_inputs.contains_key(&added.id));
+        // the timer might already run, so it is kept without receivers
+        assert_eq!(
+            dataflow.timers.get(&Duration::from_millis(100)),
+            Some(&BTreeSet::new())
+        );
+        // the added node was never spawned
+        assert!(!dataflow.removed_nodes.contains(&added.id));
+    }
+
+    #[test]
+    fn replayed_latched_messages_are_received_once() {
+        let descriptor: Descriptor = serde_yaml::from_str(
+            r#"
+            nodes:
+              - id: source
+                path: dynamic
+                outputs: [out, config]
+                latched_outputs: [config]
+            "#,
+        )
+        .unwrap();
+        let nodes = descriptor.resolve_aliases_and_set_defaults().unwrap();
+        let mut dataflow =
+            RunningDataflow::new(Uuid::now_v7(), "A".into(), ShmemRegistry::open().unwrap());
+        dataflow.register_latched_outputs(&nodes[0]);
+        let config = OutputId("source".to_string().into(), "config".to_string().into());
+        let out = OutputId("source".to_string().into(), "out".to_string().into());
+        assert!(dataflow.latched_outputs.contains(&config));
+        assert!(!dataflow.latched_outputs.contains(&out));
+
+        let clock = HLC::default();
+        let older = Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+        let newer = Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty());
+        assert!(!dataflow.is_stale_latched(&config, &newer));
+        dataflow.retain_latched(&config, &newer, &None);
+        // received again through the subscription or the replay
+        assert!(dataflow.is_stale_latched(&config, &newer));
+        assert!(dataflow.is_stale_latched(&config, &older));
+        // messages of other outputs are never dropped
+        dataflow.retain_latched(&out, &newer, &None);
+        assert!(!dataflow.is_stale_latched(&out, &newer));
+    }
+
+    fn ad_hoc_inputs(outputs: &[(&str, &str)]) -> BTreeMap<DataId, Input> {
+        outputs
+            .iter()
+            .map(|(source, output)| {
+                let input = Input {
+                    mapping: InputMapping::User(UserInputMapping {
+                        source: source.to_string().into(),
+                        output: output.to_string().into(),
+                    }),
+                    queue_size: None,
+                    compression: None,
+                    notify_dropped: false,
+                };
+                (DataId::from(format!("{source}/{output}")), input)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn unconnected_ad_hoc_subscribers_are_removed() {
+        let (descriptor, nodes) = remote_source_dataflow();
+        let mut dataflow = running_dataflow(&descriptor, &nodes);
+        let node_id = NodeId::from("ad-hoc".to_string());
+        dataflow.add_ad_hoc_subscriber(&node_id, &ad_hoc_inputs(&[("source", "out")]));
+        assert_eq!(dataflow.mappings.values().flatten().count(), 2);
+
+        assert!(dataflow.remove_unconnected_ad_hoc_subscriber(&node_id));
+        assert!(!dataflow.ad_hoc_subscribers.contains(&node_id));
+        assert!(!dataflow.open_inputs.contains_key(&node_id));
+        // the mapping is still needed for the `sink` node
+        let source_out = OutputId("source".to_string().into(), "out".to_string().into());
+        assert_eq!(dataflow.mappings[&source_out].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn connected_ad_hoc_subscribers_are_kept() {
+        let (descriptor, nodes) = remote_source_dataflow();
+        let mut dataflow = running_dataflow(&descriptor, &nodes);
+        let node_id = NodeId::from("ad-hoc".to_string());
+        dataflow.add_ad_hoc_subscriber(&node_id, &ad_hoc_inputs(&[("sink", "status")]));
+        let (events_tx, events_rx) = tokio::sync::mpsc::unbounded_channel();
+        dataflow
+            .subscribe_channels
+            .insert(node_id.clone(), NodeEventSender::new(events_tx));
+        assert!(!dataflow.remove_unconnected_ad_hoc_subscriber(&node_id));
+
+        // disconnect
+        drop(events_rx);
+        dataflow.remove_ad_hoc_subscriber(&node_id);
+        let sink_status = OutputId("sink".to_string().into(), "status".to_string().into());
+        assert!(!dataflow.mappings.contains_key(&sink_status));
+        assert!(!dataflow.subscribe_channels.contains_key(&node_id));
+    }
+
+    #[tokio::test]
+    async fn removing_the_last_receiver_drops_the_remote_output() {
+        let (descriptor, nodes) = remote_source_dataflow();
+        let mut dataflow = running_dataflow(&descriptor, &nodes);
+        let sink_ids = BTreeSet::from(["sink".to_string().into()]);
+        dataflow.remove_nodes(&sink_ids, &HLC::default(), None);
+
+        assert!(dataflow.mappings.is_empty());
+        assert!(dataflow.open_inputs.is_empty());
+        assert_eq!(dataflow.removed_nodes, sink_ids);
+    }
+}
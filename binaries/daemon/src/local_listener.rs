@@ -66,53 +66,66 @@ async fn handle_connection_loop(
     }
 
     loop {
-        match receive_message(&mut connection).await {
+        let (event, timestamp) = match receive_message(&mut connection).await {
             Ok(Some(Timestamped {
                 inner: DaemonRequest::NodeConfig { node_id },
                 timestamp,
-            })) => {
-                let (reply_tx, reply_rx) = oneshot::channel();
-                if events_tx
-                    .send_async(Timestamped {
-                        inner: DynamicNodeEventWrapper {
-                            event: DynamicNodeEvent::NodeConfig { node_id },
-                            reply_tx,
-                        },
-                        timestamp,
-                    })
-                    .await
-                    .is_err()
-                {
-                    break;
-                }
-                let Ok(reply) = reply_rx.await else {
-                    tracing::warn!("daemon sent no reply");
-                    continue;
-                };
-                if let Some(reply) = reply {
-                    let serialized = match serde_json::to_vec(&reply)
-                        .wrap_err("failed to serialize DaemonReply")
-                    {
-                        Ok(r) => r,
-                        Err(err) => {
-                            tracing::error!("{err:?}");
-                            continue;
-                        }
-                    };
-                    if let Err(err) = socket_stream_send(&mut connection, &serialized).await {
-                        tracing::warn!("failed to send reply: {err}");
-                        continue;
-                    };
-                }
-            }
+            })) => (DynamicNodeEvent::NodeConfig { node_id }, timestamp),
+            Ok(Some(Timestamped {
+                inner:
+                    DaemonRequest::SubscribeOutputs {
+                        dataflow_id,
+                        outputs,
+                    },
+                timestamp,
+            })) => (
+                DynamicNodeEvent::SubscribeOutputs {
+                    dataflow_id,
+                    outputs,
+                },
+                timestamp,
+            ),
             Ok(None) => break,
             Err(err) => {
                 tracing::warn!("{err:?}");
                 break;
             }
-            _ => tracing::warn!(
-                "Unexpected Daemon Request that is not yet by Additional local listener controls"
-            ),
+            _ => {
+                tracing::warn!(
+                    "Unexpected Daemon Request that is not yet by Additional local listener controls"
+                );
+                continue;
+            }
+        };
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if events_tx
+            .send_async(Timestamped {
+                inner: DynamicNodeEventWrapper { event, reply_tx },
+                timestamp,
+            })
+            .await
+            .is_err()
+        {
+            break;
+        }
+        let Ok(reply) = reply_rx.await else {
+            tracing::warn!("daemon sent no reply");
+            continue;
+        };
+        if let Some(reply) = reply {
+            let serialized =
+                match serde_json::to_vec(&reply).wrap_err("failed to serialize DaemonReply") {
+                    Ok(r) => r,
+                    Err(err) => {
+                        tracing::error!("{err:?}");
+                        continue;
+                    }
+                };
+            if let Err(err) = socket_stream_send(&mut connection, &serialized).await {
+                tracing::warn!("failed to send reply: {err}");
+                continue;
+            };
         }
     }
 }
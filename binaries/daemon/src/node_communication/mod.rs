@@ -1,4 +1,4 @@
-use crate::{DaemonNodeEvent, Event};
+use crate::{shmem_registry::ShmemRegistry, DaemonNodeEvent, Event};
 use dora_core::{
     config::{DataId, LocalCommunicationConfig, NodeId},
     daemon_messages::{
@@ -33,13 +33,24 @@ pub mod tcp;
 #[cfg(unix)]
 pub mod unix_domain;
 
+/// Settings for the listener of a node.
+#[derive(Debug, Clone)]
+pub struct ListenerOptions {
+    /// Maximum number of queued events per input.
+    pub queue_sizes: BTreeMap<DataId, usize>,
+    /// Report to the daemon when the node waits for an event while no events
+    /// are queued (used in deterministic mode).
+    pub report_idle: bool,
+}
+
 pub async fn spawn_listener_loop(
     dataflow_id: &DataflowId,
     node_id: &NodeId,
     daemon_tx: &mpsc::Sender<Timestamped<Event>>,
     config: LocalCommunicationConfig,
-    queue_sizes: BTreeMap<DataId, usize>,
+    options: ListenerOptions,
     clock: Arc<uhlc::HLC>,
+    shmem_registry: &ShmemRegistry,
 ) -> eyre::Result<DaemonCommunication> {
     match config {
         LocalCommunicationConfig::Tcp => {
@@ -58,7 +69,7 @@ pub async fn spawn_listener_loop(
             let event_loop_node_id = format!("{dataflow_id}/{node_id}");
             let daemon_tx = daemon_tx.clone();
             tokio::spawn(async move {
-                tcp::listener_loop(socket, daemon_tx, queue_sizes, clock).await;
+                tcp::listener_loop(socket, daemon_tx, options, clock).await;
                 tracing::debug!("event listener loop finished for `{event_loop_node_id}`");
             });
 
@@ -85,14 +96,22 @@ pub async fn spawn_listener_loop(
             let daemon_events_region_id = daemon_events_region.get_os_id().to_owned();
             let daemon_drop_region_id = daemon_drop_region.get_os_id().to_owned();
             let daemon_events_close_region_id = daemon_events_close_region.get_os_id().to_owned();
+            for id in [
+                &daemon_control_region_id,
+                &daemon_events_region_id,
+                &daemon_drop_region_id,
+                &daemon_events_close_region_id,
+            ] {
+                shmem_registry.register(id.clone(), *dataflow_id, Some(node_id.clone()))?;
+            }
 
             {
                 let server = unsafe { ShmemServer::new(daemon_control_region) }
                     .wrap_err("failed to create control server")?;
                 let daemon_tx = daemon_tx.clone();
-                let queue_sizes = queue_sizes.clone();
+                let options = options.clone();
                 let clock = clock.clone();
-                tokio::spawn(shmem::listener_loop(server, daemon_tx, queue_sizes, clock));
+                tokio::spawn(shmem::listener_loop(server, daemon_tx, options, clock));
             }
 
             {
@@ -100,10 +119,10 @@ pub async fn spawn_listener_loop(
                     .wrap_err("failed to create events server")?;
                 let event_loop_node_id = format!("{dataflow_id}/{node_id}");
                 let daemon_tx = daemon_tx.clone();
-                let queue_sizes = queue_sizes.clone();
+                let options = options.clone();
                 let clock = clock.clone();
                 tokio::task::spawn(async move {
-                    shmem::listener_loop(server, daemon_tx, queue_sizes, clock).await;
+                    shmem::listener_loop(server, daemon_tx, options, clock).await;
                     tracing::debug!("event listener loop finished for `{event_loop_node_id}`");
                 });
             }
@@ -113,10 +132,10 @@ pub async fn spawn_listener_loop(
                     .wrap_err("failed to create drop server")?;
                 let drop_loop_node_id = format!("{dataflow_id}/{node_id}");
                 let daemon_tx = daemon_tx.clone();
-                let queue_sizes = queue_sizes.clone();
+                let options = options.clone();
                 let clock = clock.clone();
                 tokio::task::spawn(async move {
-                    shmem::listener_loop(server, daemon_tx, queue_sizes, clock).await;
+                    shmem::listener_loop(server, daemon_tx, options, clock).await;
                     tracing::debug!("drop listener loop finished for `{drop_loop_node_id}`");
                 });
             }
@@ -128,7 +147,7 @@ pub async fn spawn_listener_loop(
                 let daemon_tx = daemon_tx.clone();
                 let clock = clock.clone();
                 tokio::task::spawn(async move {
-                    shmem::listener_loop(server, daemon_tx, queue_sizes, clock).await;
+                    shmem::listener_loop(server, daemon_tx, options, clock).await;
                     tracing::debug!(
                         "events close listener loop finished for `{drop_loop_node_id}`"
                     );
@@ -162,7 +181,7 @@ pub async fn spawn_listener_loop(
             let event_loop_node_id = format!("{dataflow_id}/{node_id}");
             let daemon_tx = daemon_tx.clone();
             tokio::spawn(async move {
-                unix_domain::listener_loop(socket, daemon_tx, queue_sizes, clock).await;
+                unix_domain::listener_loop(socket, daemon_tx, options, clock).await;
                 tracing::debug!("event listener loop finished for `{event_loop_node_id}`");
             });
 
@@ -183,6 +202,9 @@ struct Listener {
     subscribed_drop_events: Option<UnboundedReceiver<Timestamped<NodeDropEvent>>>,
     queue: VecDeque<Box<Option<Timestamped<NodeEvent>>>>,
     queue_sizes: BTreeMap<DataId, usize>,
+    report_idle: bool,
+    /// Number of events taken from `subscribed_events` so far.
+    received: u64,
     clock: Arc<uhlc::HLC>,
 }
 
@@ -190,7 +212,7 @@ impl Listener {
     pub(crate) async fn run<C: Connection>(
         mut connection: C,
         daemon_tx: mpsc::Sender<Timestamped<Event>>,
-        queue_sizes: BTreeMap<DataId, usize>,
+        options: ListenerOptions,
         hlc: Arc<uhlc::HLC>,
     ) {
         // receive the first message
@@ -241,7 +263,9 @@ impl Listener {
                             daemon_tx,
                             subscribed_events: None,
                             subscribed_drop_events: None,
-                            queue_sizes,
+                            queue_sizes: options.queue_sizes,
+                            report_idle: options.report_idle,
+                            received: 0,
                             queue: VecDeque::new(),
                             clock: hlc.clone(),
                         };
@@ -290,6 +314,7 @@ impl Listener {
                     }
                     future::Either::Right((message, _)) => break message,
                 };
+                self.received += 1;
 
                 self.queue.push_back(Box::new(Some(event)));
                 self.handle_events().await?;
@@ -315,6 +340,7 @@ impl Listener {
     async fn handle_events(&mut self) -> eyre::Result<()> {
         if let Some(events) = &mut self.subscribed_events {
             while let Ok(event) = events.try_recv() {
+                self.received += 1;
                 self.queue.push_back(Box::new(Some(event)));
             }
 
@@ -327,7 +353,7 @@ impl Listener {
     #[tracing::instrument(skip(self), fields(%self.node_id), level = "trace")]
     async fn drop_oldest_inputs(&mut self) -> Result<(), eyre::ErrReport> {
         let mut queue_size_remaining = self.queue_sizes.clone();
-        let mut dropped = 0;
+        let mut dropped: BTreeMap<DataId, u64> = BTreeMap::new();
         let mut drop_tokens = Vec::new();
 
         // iterate over queued events, newest first
@@ -341,7 +367,7 @@ impl Listener {
             };
             match queue_size_remaining.get_mut(id) {
                 Some(0) => {
-                    dropped += 1;
+                    *dropped.entry(id.clone()).or_default() += 1;
                     if let Some(drop_token) = data.as_ref().and_then(|d| d.drop_token()) {
                         drop_tokens.push(drop_token);
                     }
@@ -357,15 +383,63 @@ impl Listener {
         }
         self.report_drop_tokens(drop_tokens).await?;
 
-        if dropped > 0 {
+        if !dropped.is_empty() {
             tracing::debug!(
-                "dropped {dropped} inputs of node `{}` because event queue was too full",
+                "dropped {} inputs of node `{}` because event queue was too full",
+                dropped.values().sum::<u64>(),
                 self.node_id
             );
+            self.notify_dropped_inputs(dropped).await?;
         }
         Ok(())
     }
 
+    /// Informs the node and the daemon about dropped inputs.
+    ///
+    /// Merges the counts into already queued `InputDropped` events so that the
+    /// node receives at most one such event per input.
+    async fn notify_dropped_inputs(&mut self, dropped: BTreeMap<DataId, u64>) -> eyre::Result<()> {
+        for (id, dropped_count) in &dropped {
+            let queued = self
+                .queue
+                .iter_mut()
+                .find_map(|event| match event.as_mut() {
+                    Some(Timestamped {
+                        inner:
+                            NodeEvent::InputDropped {
+                                id: queued_id,
+                                count,
+                            },
+                        ..
+                    }) if queued_id == id => Some(count),
+                    _ => None,
+                });
+            match queued {
+                Some(count) => *count += dropped_count,
+                None => self.queue.push_back(Box::new(Some(Timestamped {
+                    inner: NodeEvent::InputDropped {
+                        id: id.clone(),
+                        count: *dropped_count,
+                    },
+                    timestamp: self.clock.new_timestamp(),
+                }))),
+            }
+        }
+
+        let event = Timestamped {
+            inner: Event::Node {
+                dataflow_id: self.dataflow_id,
+                node_id: self.node_id.clone(),
+                event: DaemonNodeEvent::InputsDropped { counts: dropped },
+            },
+            timestamp: self.clock.new_timestamp(),
+        };
+        self.daemon_tx
+            .send(event)
+            .await
+            .map_err(|_| eyre!("failed to report dropped inputs to daemon"))
+    }
+
     #[tracing::instrument(skip(self, connection), fields(%self.dataflow_id, %self.node_id), level = "trace")]
     async fn handle_message<C: Connection>(
         &mut self,
@@ -383,7 +457,7 @@ impl Listener {
                     .await
                     .wrap_err("failed to send register reply")?;
             }
-            DaemonRequest::NodeConfig { .. } => {
+            DaemonRequest::NodeConfig { .. } | DaemonRequest::SubscribeOutputs { .. } => {
                 let reply = DaemonReply::Result(Err("unexpected node config message".into()));
                 self.send_reply(reply, connection)
                     .await
@@ -459,10 +533,16 @@ impl Listener {
                     .filter_map(|e| *e)
                     .collect();
                 let reply = if queued_events.is_empty() {
+                    if self.report_idle && self.subscribed_events.is_some() {
+                        self.report_idle().await?;
+                    }
                     match self.subscribed_events.as_mut() {
                         // wait for next event
                         Some(events) => match events.recv().await {
-                            Some(event) => DaemonReply::NextEvents(vec![event]),
+                            Some(event) => {
+                                self.received += 1;
+                                DaemonReply::NextEvents(vec![event])
+                            }
                             None => DaemonReply::NextEvents(vec![]),
                         },
                         None => {
@@ -486,6 +566,24 @@ impl Listener {
                     .await
                     .wrap_err("failed to send ReportDropTokens reply")?;
             }
+            DaemonRequest::ReportDroppedInputs { counts } => {
+                let event = Timestamped {
+                    inner: Event::Node {
+                        dataflow_id: self.dataflow_id,
+                        node_id: self.node_id.clone(),
+                        event: DaemonNodeEvent::InputsDropped { counts },
+                    },
+                    timestamp: self.clock.new_timestamp(),
+                };
+                self.daemon_tx
+                    .send(event)
+                    .await
+                    .map_err(|_| eyre!("failed to report dropped inputs to daemon"))?;
+
+                self.send_reply(DaemonReply::Empty, connection)
+                    .await
+                    .wrap_err("failed to send ReportDroppedInputs reply")?;
+            }
             DaemonRequest::NextFinishedDropTokens => {
                 let reply = match self.subscribed_drop_events.as_mut() {
                     // wait for next event
@@ -504,6 +602,21 @@ impl Listener {
                         format!("failed to send NextFinishedDropTokens reply: {reply:?}")
                     })?;
             }
+            DaemonRequest::Barrier => {
+                // all previous requests were passed to the daemon already
+                self.send_reply(DaemonReply::Result(Ok(())), connection)
+                    .await
+                    .wrap_err("failed to send Barrier reply")?;
+            }
+            DaemonRequest::AllocateSharedMemory { len } => {
+                let (reply_sender, reply) = oneshot::channel();
+                self.process_daemon_event(
+                    DaemonNodeEvent::AllocateSharedMemory { len, reply_sender },
+                    Some(reply),
+                    connection,
+                )
+                .await?;
+            }
             DaemonRequest::EventStreamDropped => {
                 let (reply_sender, reply) = oneshot::channel();
                 self.process_daemon_event(
@@ -541,6 +654,24 @@ impl Listener {
         Ok(())
     }
 
+    /// Tells the daemon that the node waits for its next event.
+    async fn report_idle(&mut self) -> eyre::Result<()> {
+        let event = Timestamped {
+            inner: Event::Node {
+                dataflow_id: self.dataflow_id,
+                node_id: self.node_id.clone(),
+                event: DaemonNodeEvent::Idle {
+                    received: self.received,
+                },
+            },
+            timestamp: self.clock.new_timestamp(),
+        };
+        self.daemon_tx
+            .send(event)
+            .await
+            .map_err(|_| eyre!("failed to report idle node to daemon"))
+    }
+
     async fn process_daemon_event<C: Connection>(
         &mut self,
         event: DaemonNodeEvent,
@@ -1,9 +1,8 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::sync::Arc;
 
-use super::{Connection, Listener};
+use super::{Connection, Listener, ListenerOptions};
 use crate::Event;
 use dora_core::{
-    config::DataId,
     daemon_messages::{DaemonReply, DaemonRequest, Timestamped},
     message::uhlc::HLC,
 };
@@ -15,7 +14,7 @@ use tokio::sync::{mpsc, oneshot};
 pub async fn listener_loop(
     mut server: ShmemServer<Timestamped<DaemonRequest>, DaemonReply>,
     daemon_tx: mpsc::Sender<Timestamped<Event>>,
-    queue_sizes: BTreeMap<DataId, usize>,
+    options: ListenerOptions,
     clock: Arc<HLC>,
 ) {
     let (tx, rx) = flume::bounded(0);
@@ -40,7 +39,7 @@ pub async fn listener_loop(
         }
     });
     let connection = ShmemConnection(tx);
-    Listener::run(connection, daemon_tx, queue_sizes, clock).await
+    Listener::run(connection, daemon_tx, options, clock).await
 }
 
 enum Operation {
@@ -1,12 +1,11 @@
-use std::{collections::BTreeMap, io::ErrorKind, sync::Arc};
+use std::{io::ErrorKind, sync::Arc};
 
-use super::{Connection, Listener};
+use super::{Connection, Listener, ListenerOptions};
 use crate::{
     socket_stream_utils::{socket_stream_receive, socket_stream_send},
     Event,
 };
 use dora_core::{
-    config::DataId,
     daemon_messages::{DaemonReply, DaemonRequest, Timestamped},
     message::uhlc::HLC,
 };
@@ -20,7 +19,7 @@ use tokio::{
 pub async fn listener_loop(
     listener: TcpListener,
     daemon_tx: mpsc::Sender<Timestamped<Event>>,
-    queue_sizes: BTreeMap<DataId, usize>,
+    options: ListenerOptions,
     clock: Arc<HLC>,
 ) {
     loop {
@@ -36,7 +35,7 @@ pub async fn listener_loop(
                 tokio::spawn(handle_connection_loop(
                     connection,
                     daemon_tx.clone(),
-                    queue_sizes.clone(),
+                    options.clone(),
                     clock.clone(),
                 ));
             }
@@ -48,14 +47,14 @@ pub async fn listener_loop(
 async fn handle_connection_loop(
     connection: TcpStream,
     daemon_tx: mpsc::Sender<Timestamped<Event>>,
-    queue_sizes: BTreeMap<DataId, usize>,
+    options: ListenerOptions,
     clock: Arc<HLC>,
 ) {
     if let Err(err) = connection.set_nodelay(true) {
         tracing::warn!("failed to set nodelay for connection: {err}");
     }
 
-    Listener::run(TcpConnection(connection), daemon_tx, queue_sizes, clock).await
+    Listener::run(TcpConnection(connection), daemon_tx, options, clock).await
 }
 
 struct TcpConnection(TcpStream);
@@ -1,7 +1,6 @@
-use std::{collections::BTreeMap, io::ErrorKind, sync::Arc};
+use std::{io::ErrorKind, sync::Arc};
 
 use dora_core::{
-    config::DataId,
     daemon_messages::{DaemonReply, DaemonRequest, Timestamped},
     message::uhlc::HLC,
 };
@@ -16,13 +15,13 @@ use crate::{
     Event,
 };
 
-use super::{Connection, Listener};
+use super::{Connection, Listener, ListenerOptions};
 
 #[tracing::instrument(skip(listener, daemon_tx, clock), level = "trace")]
 pub async fn listener_loop(
     listener: UnixListener,
     daemon_tx: mpsc::Sender<Timestamped<Event>>,
-    queue_sizes: BTreeMap<DataId, usize>,
+    options: ListenerOptions,
     clock: Arc<HLC>,
 ) {
     loop {
@@ -38,7 +37,7 @@ pub async fn listener_loop(
                 tokio::spawn(handle_connection_loop(
                     connection,
                     daemon_tx.clone(),
-                    queue_sizes.clone(),
+                    options.clone(),
                     clock.clone(),
                 ));
             }
@@ -50,10 +49,10 @@ pub async fn listener_loop(
 async fn handle_connection_loop(
     connection: UnixStream,
     daemon_tx: mpsc::Sender<Timestamped<Event>>,
-    queue_sizes: BTreeMap<DataId, usize>,
+    options: ListenerOptions,
     clock: Arc<HLC>,
 ) {
-    Listener::run(UnixConnection(connection), daemon_tx, queue_sizes, clock).await
+    Listener::run(UnixConnection(connection), daemon_tx, options, clock).await
 }
 
 struct UnixConnection(UnixStream);
@@ -7,9 +7,9 @@ use dora_core::{
     message::uhlc::{Timestamp, HLC},
 };
 use eyre::{bail, Context};
-use tokio::{net::TcpStream, sync::oneshot};
+use tokio::sync::oneshot;
 
-use crate::{socket_stream_utils::socket_stream_send, CascadingErrorCauses};
+use crate::{coordinator::CoordinatorConnection, CascadingErrorCauses};
 
 pub struct PendingNodes {
     dataflow_id: DataflowId,
@@ -59,7 +59,7 @@ impl PendingNodes {
         &mut self,
         node_id: NodeId,
         reply_sender: oneshot::Sender<DaemonReply>,
-        coordinator_connection: &mut Option<TcpStream>,
+        coordinator_connection: &mut Option<CoordinatorConnection>,
         clock: &HLC,
         cascading_errors: &mut CascadingErrorCauses,
     ) -> eyre::Result<DataflowStatus> {
@@ -74,7 +74,7 @@ impl PendingNodes {
     pub async fn handle_node_stop(
         &mut self,
         node_id: &NodeId,
-        coordinator_connection: &mut Option<TcpStream>,
+        coordinator_connection: &mut Option<CoordinatorConnection>,
         clock: &HLC,
         cascading_errors: &mut CascadingErrorCauses,
     ) -> eyre::Result<Vec<LogMessage>> {
@@ -99,7 +99,7 @@ impl PendingNodes {
 
     pub async fn handle_dataflow_stop(
         &mut self,
-        coordinator_connection: &mut Option<TcpStream>,
+        coordinator_connection: &mut Option<CoordinatorConnection>,
         clock: &HLC,
         cascading_errors: &mut CascadingErrorCauses,
         dynamic_nodes: &BTreeSet<NodeId>,
@@ -132,7 +132,7 @@ impl PendingNodes {
 
     async fn update_dataflow_status(
         &mut self,
-        coordinator_connection: &mut Option<TcpStream>,
+        coordinator_connection: &mut Option<CoordinatorConnection>,
         clock: &HLC,
         cascading_errors: &mut CascadingErrorCauses,
     ) -> eyre::Result<DataflowStatus> {
@@ -188,7 +188,7 @@ impl PendingNodes {
 
     async fn report_nodes_ready(
         &self,
-        coordinator_connection: &mut Option<TcpStream>,
+        coordinator_connection: &mut Option<CoordinatorConnection>,
         timestamp: Timestamp,
     ) -> eyre::Result<()> {
         let Some(connection) = coordinator_connection else {
@@ -200,17 +200,17 @@ impl PendingNodes {
             self.exited_before_subscribe
         );
 
-        let msg = serde_json::to_vec(&Timestamped {
-            inner: CoordinatorRequest::Event {
-                machine_id: self.machine_id.clone(),
-                event: DaemonEvent::AllNodesReady {
-                    dataflow_id: self.dataflow_id,
-                    exited_before_subscribe: self.exited_before_subscribe.clone(),
+        connection
+            .send(Timestamped {
+                inner: CoordinatorRequest::Event {
+                    machine_id: self.machine_id.clone(),
+                    event: DaemonEvent::AllNodesReady {
+                        dataflow_id: self.dataflow_id,
+                        exited_before_subscribe: self.exited_before_subscribe.clone(),
+                    },
                 },
-            },
-            timestamp,
-        })?;
-        socket_stream_send(connection, &msg)
+                timestamp,
+            })
             .await
             .wrap_err("failed to send AllNodesReady message to dora-coordinator")?;
         Ok(())
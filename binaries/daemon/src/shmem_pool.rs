@@ -0,0 +1,251 @@
+//! Pool of shared memory segments that nodes use for sending large outputs.
+//!
+//! Segments are grouped into power-of-two size classes. A node leases a
+//! segment together with a drop token when it allocates an output sample and
+//! the segment returns to the pool once all receivers reported the drop token
+//! of the output. If the node drops the sample without sending it, it reports
+//! the drop token itself. This way, high-rate outputs reuse already mapped
+//! segments instead of creating and mapping a new shared memory region for
+//! every message.
+
+use crate::shmem_registry::ShmemRegistry;
+use dora_core::{
+    config::NodeId,
+    daemon_messages::{DataflowId, DropToken, SharedMemoryId},
+};
+use eyre::Context;
+use shared_memory_server::{Shmem, ShmemConf};
+use std::collections::{BTreeMap, HashMap};
+
+/// Size of the smallest segment class.
+const MIN_SEGMENT_SIZE: usize = 4096;
+/// Number of unused segments that are kept mapped per size class.
+const MAX_FREE_SEGMENTS_PER_CLASS: usize = 8;
+
+pub struct ShmemPool {
+    dataflow_id: DataflowId,
+    registry: ShmemRegistry,
+    /// Unused segments, by size class.
+    free: BTreeMap<usize, Vec<Segment>>,
+    /// Segments that are leased to a node, but were not sent out yet.
+    leased: HashMap<SharedMemoryId, Lease>,
+    /// Segments that were sent out and are still in use by receivers.
+    in_flight: HashMap<DropToken, Segment>,
+}
+
+impl ShmemPool {
+    pub fn new(dataflow_id: DataflowId, registry: ShmemRegistry) -> Self {
+        Self {
+            dataflow_id,
+            registry,
+            free: BTreeMap::new(),
+            leased: HashMap::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Leases a segment of at least `len` bytes to the given node.
+    ///
+    /// The returned drop token is used when the segment is sent out. The node
+    /// reports the token directly if it drops the segment without sending it.
+    pub fn lease(
+        &mut self,
+        node_id: &NodeId,
+        len: usize,
+    ) -> eyre::Result<(SharedMemoryId, DropToken)> {
+        let class = len.max(MIN_SEGMENT_SIZE).next_power_of_two();
+        let segment = match self.free.get_mut(&class).and_then(|s| s.pop()) {
+            Some(segment) => segment,
+            None => {
+                let segment = Segment(Box::new(
+                    ShmemConf::new()
+                        .size(class)
+                        .writable(true)
+                        .create()
+                        .wrap_err("failed to allocate shared memory segment")?,
+                ));
+                self.registry
+                    .register(segment.0.get_os_id().to_owned(), self.dataflow_id, None)?;
+                segment
+            }
+        };
+        let id = segment.0.get_os_id().to_owned();
+        let drop_token = DropToken::generate();
+        self.leased.insert(
+            id.clone(),
+            Lease {
+                owner: node_id.clone(),
+                drop_token,
+                segment,
+            },
+        );
+        Ok((id, drop_token))
+    }
+
+    /// Returns the first `len` bytes of the given leased segment, or `None` if
+    /// the segment is not leased from the pool.
+    pub fn get(&self, shared_memory_id: &str, len: usize) -> Option<&[u8]> {
+        let lease = self.leased.get(shared_memory_id)?;
+        unsafe { lease.segment.0.as_slice() }.get(..len)
+    }
+
+    /// Marks the given leased segment as sent out with the given drop token.
+    ///
+    /// Returns the first `len` bytes of the segment, or `None` if the segment
+    /// is not part of the pool.
+    pub fn send(
+        &mut self,
+        shared_memory_id: &str,
+        drop_token: DropToken,
+        len: usize,
+    ) -> Option<&[u8]> {
+        let lease = self.leased.remove(shared_memory_id)?;
+        let segment = self.in_flight.entry(drop_token).or_insert(lease.segment);
+        unsafe { segment.0.as_slice() }.get(..len)
+    }
+
+    /// Returns the segment that was leased to the given node with the given
+    /// drop token to the pool, if the node dropped it without sending it.
+    ///
+    /// Returns `false` if the node has no such lease.
+    pub fn release_unsent(&mut self, node_id: &NodeId, drop_token: &DropToken) -> bool {
+        let id = self
+            .leased
+            .iter()
+            .find(|(_, lease)| &lease.owner == node_id && &lease.drop_token == drop_token)
+            .map(|(id, _)| id.clone());
+        match id.and_then(|id| self.leased.remove(&id)) {
+            Some(lease) => {
+                self.add_free(lease.segment);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the segment that was sent out with the given drop token to the pool.
+    ///
+    /// Returns `false` if no segment of the pool belongs to the token.
+    pub fn release(&mut self, drop_token: &DropToken) -> bool {
+        match self.in_flight.remove(drop_token) {
+            Some(segment) => {
+                self.add_free(segment);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns all segments that are leased to the given node, but were not sent.
+    pub fn reclaim(&mut self, node_id: &NodeId) {
+        let ids: Vec<_> = self
+            .leased
+            .iter()
+            .filter(|(_, lease)| &lease.owner == node_id)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in ids {
+            if let Some(lease) = self.leased.remove(&id) {
+                self.add_free(lease.segment);
+            }
+        }
+    }
+
+    fn add_free(&mut self, segment: Segment) {
+        let free = self.free.entry(segment.0.len()).or_default();
+        if free.len() < MAX_FREE_SEGMENTS_PER_CLASS {
+            free.push(segment);
+        } else if let Err(err) = self.registry.unregister(segment.0.get_os_id()) {
+            tracing::warn!("{err:?}");
+        }
+    }
+}
+
+struct Lease {
+    owner: NodeId,
+    drop_token: DropToken,
+    segment: Segment,
+}
+
+struct Segment(Box<Shmem>);
+
+// The raw pointer of the mapping is only accessed through the pool, which
+// requires `&mut self` for all modifications.
+unsafe impl Send for Segment {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    /// The registry directory must be kept until the pool is dropped.
+    fn pool() -> (ShmemPool, tempfile::TempDir) {
+        let registry_dir = tempfile::tempdir().unwrap();
+        let registry = ShmemRegistry::open_in(registry_dir.path()).unwrap();
+        (ShmemPool::new(Uuid::now_v7(), registry), registry_dir)
+    }
+
+    fn node(id: &str) -> NodeId {
+        id.to_owned().into()
+    }
+
+    #[test]
+    fn unsent_lease_returns_to_pool() {
+        let (mut pool, _registry_dir) = pool();
+        let (id, drop_token) = pool.lease(&node("a"), 100).unwrap();
+        assert!(pool.get(&id, 100).is_some());
+
+        // only the owner can return the lease
+        assert!(!pool.release_unsent(&node("b"), &drop_token));
+        assert!(!pool.release_unsent(&node("a"), &DropToken::generate()));
+        assert!(pool.release_unsent(&node("a"), &drop_token));
+        assert!(pool.get(&id, 100).is_none());
+
+        let (reused, new_token) = pool.lease(&node("b"), 200).unwrap();
+        assert_eq!(reused, id);
+        assert_ne!(new_token, drop_token);
+    }
+
+    #[test]
+    fn sent_segment_returns_after_drop_token() {
+        let (mut pool, _registry_dir) = pool();
+        let (id, drop_token) = pool.lease(&node("a"), 5000).unwrap();
+        assert_eq!(
+            pool.send(&id, drop_token, 5000).map(|d| d.len()),
+            Some(5000)
+        );
+        // sent segments can no longer be returned as unsent
+        assert!(!pool.release_unsent(&node("a"), &drop_token));
+
+        let (other, _) = pool.lease(&node("a"), 5000).unwrap();
+        assert_ne!(other, id);
+
+        assert!(pool.release(&drop_token));
+        assert!(!pool.release(&drop_token));
+        let (reused, _) = pool.lease(&node("a"), 5000).unwrap();
+        assert_eq!(reused, id);
+    }
+
+    #[test]
+    fn size_classes_are_separate() {
+        let (mut pool, _registry_dir) = pool();
+        let (small, drop_token) = pool.lease(&node("a"), 10).unwrap();
+        assert!(pool.release_unsent(&node("a"), &drop_token));
+        let (large, _) = pool.lease(&node("a"), MIN_SEGMENT_SIZE * 2).unwrap();
+        assert_ne!(small, large);
+        let (reused, _) = pool.lease(&node("a"), MIN_SEGMENT_SIZE).unwrap();
+        assert_eq!(reused, small);
+    }
+
+    #[test]
+    fn leases_of_exited_nodes_are_reclaimed() {
+        let (mut pool, _registry_dir) = pool();
+        let (id_a, _) = pool.lease(&node("a"), 100).unwrap();
+        let (id_b, _) = pool.lease(&node("b"), 100).unwrap();
+        pool.reclaim(&node("a"));
+        assert!(pool.get(&id_a, 100).is_none());
+        assert!(pool.get(&id_b, 100).is_some());
+        let (reused, _) = pool.lease(&node("c"), 100).unwrap();
+        assert_eq!(reused, id_a);
+    }
+}
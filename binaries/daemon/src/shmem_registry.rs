@@ -0,0 +1,419 @@
+//! Tracking of the shared memory segments that the daemon creates.
+//!
+//! Shared memory segments are not removed by the OS when the process that
+//! created them is killed. To clean them up, every daemon records its
+//! segments in a file in the registry directory, tagged with the dataflow and
+//! node that they belong to. The segments of a node are removed when it
+//! exits, the segments of a dataflow when it finishes. Segments of daemons
+//! that are no longer running are removed on daemon startup and by
+//! `dora destroy --cleanup`.
+//!
+//! Registry files are named `<pid>-<start time>-<uuid>.json`. The start time
+//! of the process is compared too, so that a new process that reuses the PID
+//! of a crashed daemon does not keep its segments alive. The files are
+//! written by a background thread to not block the daemon.
+
+use dora_core::{
+    config::NodeId,
+    daemon_messages::{DataflowId, SharedMemoryId},
+};
+use eyre::{Context, ContextCompat};
+use shared_memory_server::ShmemConf;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+};
+use sysinfo::{Pid, ProcessStatus, System};
+use uuid::Uuid;
+
+/// Returns the directory in which the daemons record their shared memory segments.
+pub fn registry_dir() -> PathBuf {
+    std::env::temp_dir().join("dora-shmem")
+}
+
+/// Removes the shared memory segments of all daemons that are no longer running.
+///
+/// Returns the number of removed segments.
+pub fn cleanup_shared_memory() -> eyre::Result<usize> {
+    cleanup_dir(&registry_dir())
+}
+
+#[derive(Clone)]
+pub struct ShmemRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl ShmemRegistry {
+    /// Creates a new registry file for this daemon.
+    ///
+    /// Removes the leftover segments of daemons that are no longer running first.
+    pub fn open() -> eyre::Result<Self> {
+        Self::open_in(&registry_dir())
+    }
+
+    /// Like [`open`](Self::open), but uses the given registry directory.
+    pub(crate) fn open_in(dir: &Path) -> eyre::Result<Self> {
+        std::fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("failed to create `{}`", dir.display()))?;
+        match cleanup_dir(dir) {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!("removed {removed} leaked shared memory segments"),
+            Err(err) => tracing::warn!("{:?}", err.wrap_err("failed to clean up shared memory")),
+        }
+
+        let pid = std::process::id();
+        let start_time = process_start_time(&mut System::new(), Pid::from_u32(pid))
+            .wrap_err("failed to determine the start time of the daemon process")?;
+        let file = dir.join(format!("{pid}-{start_time}-{}.json", Uuid::new_v4()));
+        let segments = BTreeMap::new();
+        write_file(&file, &serialize(&segments)?)?;
+
+        let (snapshots_tx, snapshots_rx) = mpsc::channel();
+        let writer = std::thread::Builder::new()
+            .name("shmem-registry".into())
+            .spawn({
+                let file = file.clone();
+                move || write_loop(&file, snapshots_rx)
+            })
+            .wrap_err("failed to spawn shared memory registry thread")?;
+        let registry = Registry {
+            file,
+            segments,
+            writer: Some((snapshots_tx, writer)),
+        };
+        Ok(Self {
+            inner: Arc::new(Mutex::new(registry)),
+        })
+    }
+
+    /// Records a new segment that belongs to the given dataflow and node.
+    pub fn register(
+        &self,
+        id: SharedMemoryId,
+        dataflow_id: DataflowId,
+        node_id: Option<NodeId>,
+    ) -> eyre::Result<()> {
+        let mut registry = self.inner.lock().unwrap();
+        registry.segments.insert(
+            id,
+            SegmentOwner {
+                dataflow_id,
+                node_id,
+            },
+        );
+        registry.persist()
+    }
+
+    /// Removes the given segment from the registry, without deleting it.
+    pub fn unregister(&self, id: &str) -> eyre::Result<()> {
+        let mut registry = self.inner.lock().unwrap();
+        if registry.segments.remove(id).is_some() {
+            registry.persist()?;
+        }
+        Ok(())
+    }
+
+    /// Deletes all segments of the given node.
+    pub fn remove_node(&self, dataflow_id: DataflowId, node_id: &NodeId) -> eyre::Result<()> {
+        self.remove(|owner| {
+            owner.dataflow_id == dataflow_id && owner.node_id.as_ref() == Some(node_id)
+        })
+    }
+
+    /// Deletes all segments of the given dataflow.
+    pub fn remove_dataflow(&self, dataflow_id: DataflowId) -> eyre::Result<()> {
+        self.remove(|owner| owner.dataflow_id == dataflow_id)
+    }
+
+    fn remove(&self, filter: impl Fn(&SegmentOwner) -> bool) -> eyre::Result<()> {
+        let mut registry = self.inner.lock().unwrap();
+        let len = registry.segments.len();
+        registry.segments.retain(|id, owner| {
+            if filter(owner) {
+                unlink(id);
+                false
+            } else {
+                true
+            }
+        });
+        if registry.segments.len() != len {
+            registry.persist()?;
+        }
+        Ok(())
+    }
+}
+
+struct Registry {
+    file: PathBuf,
+    segments: BTreeMap<SharedMemoryId, SegmentOwner>,
+    /// Sends new snapshots of `segments` to the thread that writes the file.
+    writer: Option<(mpsc::Sender<Vec<u8>>, JoinHandle<()>)>,
+}
+
+impl Registry {
+    fn persist(&self) -> eyre::Result<()> {
+        let serialized = serialize(&self.segments)?;
+        if let Some((snapshots, _)) = &self.writer {
+            if snapshots.send(serialized).is_err() {
+                tracing::warn!("shared memory registry thread stopped");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Registry {
+    fn drop(&mut self) {
+        // wait for pending writes, so that the file is not created again
+        if let Some((snapshots, writer)) = self.writer.take() {
+            drop(snapshots);
+            let _ = writer.join();
+        }
+        for id in self.segments.keys() {
+            unlink(id);
+        }
+        if let Err(err) = std::fs::remove_file(&self.file) {
+            tracing::warn!("failed to remove `{}`: {err}", self.file.display());
+        }
+    }
+}
+
+fn serialize(segments: &BTreeMap<SharedMemoryId, SegmentOwner>) -> eyre::Result<Vec<u8>> {
+    serde_json::to_vec(segments).wrap_err("failed to serialize segments")
+}
+
+fn write_loop(file: &Path, snapshots: mpsc::Receiver<Vec<u8>>) {
+    while let Ok(snapshot) = snapshots.recv() {
+        // only the latest snapshot needs to be written
+        let snapshot = snapshots.try_iter().last().unwrap_or(snapshot);
+        if let Err(err) = write_file(file, &snapshot) {
+            tracing::warn!("{err:?}");
+        }
+    }
+}
+
+fn write_file(file: &Path, content: &[u8]) -> eyre::Result<()> {
+    // write to a temporary file first to never leave a partial file behind
+    let tmp = file.with_extension("tmp");
+    std::fs::write(&tmp, content)
+        .wrap_err_with(|| format!("failed to write `{}`", tmp.display()))?;
+    std::fs::rename(&tmp, file).wrap_err_with(|| format!("failed to write `{}`", file.display()))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SegmentOwner {
+    dataflow_id: DataflowId,
+    node_id: Option<NodeId>,
+}
+
+/// Removes the segments of all registry files in `dir` whose daemon is no
+/// longer running.
+fn cleanup_dir(dir: &Path) -> eyre::Result<usize> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => {
+            return Err(err).wrap_err_with(|| format!("failed to read `{}`", dir.display()))
+        }
+    };
+    let mut system = System::new();
+    let mut removed = 0;
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => {
+                tracing::warn!("failed to read entry of `{}`: {err}", dir.display());
+                continue;
+            }
+        };
+        let Some((pid, start_time)) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(parse_file_stem)
+        else {
+            continue;
+        };
+        if process_start_time(&mut system, pid) == Some(start_time) {
+            continue;
+        }
+        if path.extension().is_some_and(|e| e == "json") {
+            let segments: BTreeMap<SharedMemoryId, SegmentOwner> =
+                match std::fs::read(&path).map(|raw| serde_json::from_slice(&raw)) {
+                    Ok(Ok(segments)) => segments,
+                    Ok(Err(err)) => {
+                        tracing::warn!("failed to parse `{}`: {err}", path.display());
+                        BTreeMap::new()
+                    }
+                    Err(err) => {
+                        tracing::warn!("failed to read `{}`: {err}", path.display());
+                        continue;
+                    }
+                };
+            for (id, owner) in &segments {
+                if unlink(id) {
+                    tracing::debug!(
+                        "removed leaked shared memory segment `{id}` of dataflow `{}`",
+                        owner.dataflow_id
+                    );
+                    removed += 1;
+                }
+            }
+        }
+        if let Err(err) = std::fs::remove_file(&path) {
+            tracing::warn!("failed to remove `{}`: {err}", path.display());
+        }
+    }
+    Ok(removed)
+}
+
+/// Parses the PID and process start time of a registry file name.
+fn parse_file_stem(stem: &str) -> Option<(Pid, u64)> {
+    let mut parts = stem.splitn(3, '-');
+    let pid = parts.next()?.parse::<u32>().ok()?;
+    let start_time = parts.next()?.parse().ok()?;
+    Some((Pid::from_u32(pid), start_time))
+}
+
+/// Returns the start time of the given process, or `None` if it is not running.
+fn process_start_time(system: &mut System, pid: Pid) -> Option<u64> {
+    if !system.refresh_process(pid) {
+        return None;
+    }
+    system
+        .process(pid)
+        .filter(|p| !matches!(p.status(), ProcessStatus::Zombie | ProcessStatus::Dead))
+        .map(|p| p.start_time())
+}
+
+/// Deletes the segment with the given ID, if it still exists.
+///
+/// Existing mappings of the segment stay valid.
+fn unlink(id: &str) -> bool {
+    match ShmemConf::new().os_id(id).open() {
+        Ok(mut segment) => {
+            // the owner deletes the segment on drop
+            segment.set_owner(true);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Creates a segment that outlives its handle.
+    fn leaked_segment() -> SharedMemoryId {
+        let mut segment = ShmemConf::new().size(4096).create().unwrap();
+        segment.set_owner(false);
+        segment.get_os_id().to_owned()
+    }
+
+    fn exists(id: &str) -> bool {
+        ShmemConf::new().os_id(id).open().is_ok()
+    }
+
+    fn write_registry(dir: &Path, pid: u32, start_time: u64, segments: &[&SharedMemoryId]) {
+        let segments: BTreeMap<_, _> = segments
+            .iter()
+            .map(|id| {
+                let owner = SegmentOwner {
+                    dataflow_id: Uuid::now_v7(),
+                    node_id: None,
+                };
+                ((*id).clone(), owner)
+            })
+            .collect();
+        let file = dir.join(format!("{pid}-{start_time}-{}.json", Uuid::now_v7()));
+        std::fs::write(file, serde_json::to_vec(&segments).unwrap()).unwrap();
+    }
+
+    fn wait_for_file(dir: &Path, condition: impl Fn(&str) -> bool) {
+        let start = Instant::now();
+        loop {
+            let content = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|e| e == "json"))
+                .map(|path| std::fs::read_to_string(path).unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            if condition(&content) {
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "content: {content}"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn cleanup_removes_segments_of_stopped_daemons() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let pid = std::process::id();
+        let start_time = process_start_time(&mut System::new(), Pid::from_u32(pid)).unwrap();
+
+        let running = leaked_segment();
+        let reused_pid = leaked_segment();
+        let stopped = leaked_segment();
+        write_registry(dir, pid, start_time, &[&running]);
+        // a different process with the same PID
+        write_registry(dir, pid, start_time.saturating_sub(100), &[&reused_pid]);
+        write_registry(dir, u32::MAX - 1, start_time, &[&stopped]);
+        // entries that can't be removed don't stop the cleanup
+        std::fs::create_dir(dir.join(format!("{}-1-{}.tmp", u32::MAX - 1, Uuid::now_v7())))
+            .unwrap();
+        std::fs::write(dir.join("unrelated.txt"), "").unwrap();
+
+        assert_eq!(cleanup_dir(dir).unwrap(), 2);
+        assert!(exists(&running));
+        assert!(!exists(&reused_pid));
+        assert!(!exists(&stopped));
+        assert!(dir.join("unrelated.txt").exists());
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 3);
+
+        unlink(&running);
+    }
+
+    #[test]
+    fn registry_file_tracks_segments() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let registry = ShmemRegistry::open_in(dir).unwrap();
+        let dataflow_id = Uuid::now_v7();
+        let node_id: NodeId = "node".to_owned().into();
+
+        let first = leaked_segment();
+        let second = leaked_segment();
+        registry
+            .register(first.clone(), dataflow_id, Some(node_id.clone()))
+            .unwrap();
+        registry
+            .register(second.clone(), dataflow_id, None)
+            .unwrap();
+        wait_for_file(dir, |content| {
+            content.contains(&first) && content.contains(&second)
+        });
+
+        // the registry of a running daemon is kept
+        assert_eq!(cleanup_dir(dir).unwrap(), 0);
+
+        registry.remove_node(dataflow_id, &node_id).unwrap();
+        assert!(!exists(&first));
+        wait_for_file(dir, |content| {
+            !content.contains(&first) && content.contains(&second)
+        });
+
+        // remaining segments are removed together with the registry
+        drop(registry);
+        assert!(!exists(&second));
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,419 @@
+//! Simulated time for dataflows whose `clock` is not the wall clock.
+//!
+//! In `scaled` mode, the simulated time starts at the current wall-clock time
+//! and then runs `factor` times as fast. In `external` mode, it follows the
+//! time that a node publishes. The daemon stamps the metadata of forwarded
+//! messages with the simulated time and derives the `dora/timer/*` ticks from
+//! it.
+
+use crate::OutputId;
+use aligned_vec::{AVec, ConstAlign};
+use dora_core::{
+    config::ClockConfig,
+    descriptor::Descriptor,
+    message::{
+        uhlc::{self, Timestamp, HLC, NTP64},
+        ArrowTypeInfo, Metadata,
+    },
+};
+use dora_node_api::{
+    arrow::{
+        array::{make_array, Array, AsArray},
+        compute::cast,
+        datatypes::{DataType, Int64Type, TimeUnit, UInt64Type},
+    },
+    RawData,
+};
+use eyre::{bail, ContextCompat};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Emit at most this many ticks per timer when the external clock jumps ahead.
+const MAX_TICKS_PER_UPDATE: u32 = 100;
+
+/// Latest time that fits into an HLC timestamp, which stores the seconds since
+/// the UNIX epoch in 32 bits.
+const MAX_TIME: Duration = Duration::from_secs(u32::MAX as u64);
+
+/// Creates an HLC that doesn't follow the wall clock, but only advances when
+/// it's updated with a newer timestamp.
+pub fn manual_clock() -> HLC {
+    let id = uhlc::ID::try_from([1]).expect("1 is a valid HLC ID");
+    uhlc::HLCBuilder::new()
+        .with_id(id)
+        .with_clock(uhlc::zero_clock)
+        // the clock is moved forward explicitly, possibly by large steps
+        .with_max_delta(Duration::from_secs(u32::MAX.into()))
+        .build()
+}
+
+pub struct SimulatedClock {
+    clock: HLC,
+    source: TimeSource,
+}
+
+enum TimeSource {
+    Scaled {
+        factor: f64,
+        origin: Duration,
+        start: Instant,
+    },
+    External {
+        output_id: OutputId,
+        /// The last published time, or `None` before the first one.
+        now: Option<Duration>,
+        /// The next tick of each timer, or `None` if the timer was added before
+        /// the first published time.
+        timers: BTreeMap<Duration, Option<Duration>>,
+    },
+}
+
+impl SimulatedClock {
+    /// Returns `None` if the dataflow follows the wall clock.
+    pub fn new(descriptor: &Descriptor) -> Option<Self> {
+        let source = match &descriptor.clock {
+            ClockConfig::Real => return None,
+            ClockConfig::Scaled { factor } => TimeSource::Scaled {
+                factor: *factor,
+                origin: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+                start: Instant::now(),
+            },
+            ClockConfig::External { .. } => {
+                let source = descriptor.clock_source()?;
+                TimeSource::External {
+                    output_id: OutputId(source.source, source.output),
+                    now: None,
+                    timers: BTreeMap::new(),
+                }
+            }
+        };
+        Some(Self {
+            clock: manual_clock(),
+            source,
+        })
+    }
+
+    /// The speedup factor in `scaled` mode.
+    pub fn factor(&self) -> Option<f64> {
+        match &self.source {
+            TimeSource::Scaled { factor, .. } => Some(*factor),
+            TimeSource::External { .. } => None,
+        }
+    }
+
+    /// The output that publishes the time in `external` mode.
+    pub fn external_source(&self) -> Option<&OutputId> {
+        match &self.source {
+            TimeSource::Scaled { .. } => None,
+            TimeSource::External { output_id, .. } => Some(output_id),
+        }
+    }
+
+    pub fn new_timestamp(&self) -> Timestamp {
+        if let TimeSource::Scaled {
+            factor,
+            origin,
+            start,
+        } = &self.source
+        {
+            let now = Duration::try_from_secs_f64(start.elapsed().as_secs_f64() * factor)
+                .ok()
+                .and_then(|elapsed| origin.checked_add(elapsed))
+                .filter(|now| *now <= MAX_TIME);
+            match now {
+                Some(now) => {
+                    let timestamp = Timestamp::new(NTP64::from(now), *self.clock.get_id());
+                    if let Err(err) = self.clock.update_with_timestamp(&timestamp) {
+                        tracing::warn!("failed to advance simulated clock: {err}");
+                    }
+                }
+                None => tracing::warn!("simulated time exceeds the range of timestamps"),
+            }
+        }
+        self.clock.new_timestamp()
+    }
+
+    /// Replaces the timestamp of the given metadata with the simulated time.
+    pub fn restamp(&self, metadata: Metadata) -> Metadata {
+        Metadata::from_parameters(
+            self.new_timestamp(),
+            metadata.type_info,
+            metadata.parameters,
+        )
+    }
+
+    /// Starts to derive ticks for the given timer interval from the published
+    /// time in `external` mode.
+    ///
+    /// The first tick is at the next published time.
+    pub fn add_timer(&mut self, interval: Duration) {
+        if let TimeSource::External { timers, .. } = &mut self.source {
+            if interval.is_zero() {
+                tracing::warn!("ignoring timer with zero interval");
+                return;
+            }
+            timers.entry(interval).or_insert(None);
+        }
+    }
+
+    /// Advances the clock to the time of a message of the external clock
+    /// source.
+    ///
+    /// Returns the timer ticks that are due, as pairs of interval and tick
+    /// timestamp.
+    pub fn update(
+        &mut self,
+        type_info: &ArrowTypeInfo,
+        data: Option<&AVec<u8, ConstAlign<128>>>,
+    ) -> eyre::Result<Vec<(Duration, Timestamp)>> {
+        let TimeSource::External { now, timers, .. } = &mut self.source else {
+            return Ok(Vec::new());
+        };
+        let time = published_time(type_info, data)?;
+        if time > MAX_TIME {
+            tracing::warn!("ignoring external clock value that exceeds the range of timestamps");
+            return Ok(Vec::new());
+        }
+        if now.is_some_and(|now| time < now) {
+            tracing::warn!("ignoring external clock value that moved backwards");
+            return Ok(Vec::new());
+        }
+        *now = Some(time);
+        let id = *self.clock.get_id();
+        if let Err(err) = self
+            .clock
+            .update_with_timestamp(&Timestamp::new(time.into(), id))
+        {
+            tracing::warn!("failed to advance simulated clock: {err}");
+        }
+
+        let mut ticks = Vec::new();
+        for (interval, next_tick) in timers {
+            let next = next_tick.get_or_insert(time);
+            if *next > time {
+                continue;
+            }
+            let missed = (time - *next).as_nanos() / interval.as_nanos();
+            if missed >= u128::from(MAX_TICKS_PER_UPDATE) {
+                tracing::warn!(
+                    "external clock jumped by {missed} ticks of the {interval:?} timer, \
+                    skipping all but the last {MAX_TICKS_PER_UPDATE}"
+                );
+                let skipped = missed - u128::from(MAX_TICKS_PER_UPDATE - 1);
+                *next += *interval * u32::try_from(skipped).unwrap_or(u32::MAX);
+            }
+            while *next <= time {
+                ticks.push((*interval, Timestamp::new((*next).into(), id)));
+                *next += *interval;
+            }
+        }
+        ticks.sort_by_key(|(_, timestamp)| *timestamp);
+        Ok(ticks)
+    }
+}
+
+/// Converts a duration in simulated time to wall-clock time for the given
+/// `scaled` mode factor.
+///
+/// Returns `None` if the result doesn't fit into a `Duration`.
+pub fn wall_duration(simulated: Duration, factor: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(simulated.as_secs_f64() / factor).ok()
+}
+
+/// Reads the time from a message of the external clock source.
+///
+/// The message must contain an unsigned integer with the nanoseconds since the
+/// UNIX epoch or an Arrow timestamp. If it contains multiple values, the last
+/// one is used.
+fn published_time(
+    type_info: &ArrowTypeInfo,
+    data: Option<&AVec<u8, ConstAlign<128>>>,
+) -> eyre::Result<Duration> {
+    let raw = match data {
+        Some(data) => RawData::Vec(data.clone()),
+        None => RawData::Empty,
+    };
+    let array = make_array(raw.into_arrow_array(type_info)?);
+    let nanos = match array.data_type() {
+        DataType::UInt64 => array.as_primitive::<UInt64Type>().iter().last(),
+        DataType::Timestamp(_, _) => {
+            let array = cast(&array, &DataType::Timestamp(TimeUnit::Nanosecond, None))?;
+            let array = cast(&array, &DataType::Int64)?;
+            let value = array.as_primitive::<Int64Type>().iter().last();
+            value.map(|v| v.and_then(|v| u64::try_from(v).ok()))
+        }
+        other => bail!("external clock must be a `UInt64` or `Timestamp` value (got `{other}`)"),
+    };
+    let nanos = nanos
+        .flatten()
+        .context("external clock message has no value")?;
+    Ok(Duration::from_nanos(nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_node_api::{
+        arrow::array::{ArrayData, StringArray, TimestampMillisecondArray, UInt64Array},
+        arrow_utils::{copy_array_into_sample, required_data_size},
+    };
+
+    fn sample(array: ArrayData) -> (ArrowTypeInfo, AVec<u8, ConstAlign<128>>) {
+        let mut data = AVec::__from_elem(128, 0, required_data_size(&array));
+        let type_info = copy_array_into_sample(&mut data, &array);
+        (type_info, data)
+    }
+
+    fn external_clock() -> SimulatedClock {
+        let descriptor: Descriptor = serde_yaml::from_str(
+            "
+nodes:
+  - id: replay
+    path: dynamic
+    outputs: [time]
+clock:
+  mode: external
+  source: replay/time
+",
+        )
+        .unwrap();
+        SimulatedClock::new(&descriptor).unwrap()
+    }
+
+    fn update(clock: &mut SimulatedClock, nanos: u64) -> Vec<(Duration, Duration)> {
+        let (type_info, data) = sample(UInt64Array::from(vec![nanos]).into_data());
+        clock
+            .update(&type_info, Some(&data))
+            .unwrap()
+            .into_iter()
+            .map(|(interval, timestamp)| (interval, timestamp.get_time().to_duration()))
+            .collect()
+    }
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn timers_follow_the_published_time() {
+        let mut clock = external_clock();
+        assert_eq!(
+            clock.external_source(),
+            Some(&OutputId(
+                "replay".to_string().into(),
+                "time".to_string().into()
+            ))
+        );
+        clock.add_timer(millis(10));
+        clock.add_timer(millis(25));
+
+        // the first tick is at the first published time
+        assert_eq!(
+            update(&mut clock, 1_000_000_000),
+            [(millis(10), millis(1000)), (millis(25), millis(1000))]
+        );
+        assert_eq!(update(&mut clock, 1_005_000_000), []);
+        assert_eq!(
+            update(&mut clock, 1_030_000_000),
+            [
+                (millis(10), millis(1010)),
+                (millis(10), millis(1020)),
+                (millis(25), millis(1025)),
+                (millis(10), millis(1030)),
+            ]
+        );
+        assert!(clock.new_timestamp().get_time().to_duration() >= millis(1030));
+
+        // time that moves backwards is ignored
+        assert_eq!(update(&mut clock, 1_000_000_000), []);
+        assert_eq!(
+            update(&mut clock, 1_040_000_000),
+            [(millis(10), millis(1040))]
+        );
+    }
+
+    #[test]
+    fn large_jumps_emit_a_limited_number_of_ticks() {
+        let mut clock = external_clock();
+        clock.add_timer(millis(1));
+        update(&mut clock, 0);
+
+        let ticks = update(&mut clock, 10_000_000_000);
+        assert_eq!(ticks.len(), MAX_TICKS_PER_UPDATE as usize);
+        // the last ticks are kept, up to the published time
+        assert_eq!(ticks.last(), Some(&(millis(1), millis(10_000))));
+        assert_eq!(ticks.first(), Some(&(millis(1), millis(9_901))));
+
+        // the timer continues normally afterwards
+        assert_eq!(
+            update(&mut clock, 10_001_000_000),
+            [(millis(1), millis(10_001))]
+        );
+    }
+
+    #[test]
+    fn out_of_range_published_time_is_ignored() {
+        let mut clock = external_clock();
+        clock.add_timer(millis(10));
+        assert_eq!(update(&mut clock, u64::MAX), []);
+
+        // the clock still works afterwards
+        assert_eq!(
+            update(&mut clock, 1_000_000_000),
+            [(millis(10), millis(1000))]
+        );
+    }
+
+    #[test]
+    fn scaled_time_beyond_the_timestamp_range_is_ignored() {
+        for (factor, origin) in [(f64::MAX, Duration::ZERO), (1.0, MAX_TIME)] {
+            let clock = SimulatedClock {
+                clock: manual_clock(),
+                source: TimeSource::Scaled {
+                    factor,
+                    origin,
+                    start: Instant::now() - Duration::from_secs(1),
+                },
+            };
+            assert!(clock.new_timestamp().get_time().to_duration() < MAX_TIME);
+        }
+    }
+
+    #[test]
+    fn wall_durations_are_checked() {
+        assert_eq!(wall_duration(millis(1000), 2.0), Some(millis(500)));
+        assert_eq!(wall_duration(millis(1000), 1e-30), None);
+    }
+
+    #[test]
+    fn published_time_accepts_integers_and_timestamps() {
+        let (type_info, data) = sample(UInt64Array::from(vec![1, 2, 3]).into_data());
+        assert_eq!(
+            published_time(&type_info, Some(&data)).unwrap(),
+            Duration::from_nanos(3)
+        );
+
+        let (type_info, data) = sample(TimestampMillisecondArray::from(vec![1_500]).into_data());
+        assert_eq!(
+            published_time(&type_info, Some(&data)).unwrap(),
+            millis(1_500)
+        );
+    }
+
+    #[test]
+    fn published_time_rejects_other_messages() {
+        let (type_info, data) = sample(StringArray::from(vec!["1"]).into_data());
+        let err = published_time(&type_info, Some(&data)).unwrap_err();
+        assert!(err.to_string().contains("`UInt64` or `Timestamp`"), "{err}");
+
+        let (type_info, data) = sample(UInt64Array::from(Vec::<u64>::new()).into_data());
+        assert!(published_time(&type_info, Some(&data)).is_err());
+
+        let (type_info, data) = sample(TimestampMillisecondArray::from(vec![-1]).into_data());
+        assert!(published_time(&type_info, Some(&data)).is_err());
+    }
+}
@@ -1,12 +1,16 @@
 use crate::{
-    log, node_communication::spawn_listener_loop, node_inputs, DoraEvent, Event, NodeExitStatus,
-    OutputId, RunningNode,
+    log,
+    node_communication::{spawn_listener_loop, ListenerOptions},
+    node_inputs,
+    shmem_registry::ShmemRegistry,
+    DoraEvent, Event, NodeExitStatus, OutputId, RunningNode,
 };
 use aligned_vec::{AVec, ConstAlign};
 use crossbeam::queue::ArrayQueue;
 use dora_arrow_convert::IntoArrow;
 use dora_core::{
     config::DataId,
+    coordinator_messages::LogMessage,
     daemon_messages::{DataMessage, DataflowId, NodeConfig, RuntimeConfig, Timestamped},
     descriptor::{
         resolve_path, source_is_url, Descriptor, OperatorDefinition, OperatorSource, PythonSource,
@@ -36,6 +40,7 @@ use tokio::{
 use tracing::error;
 
 /// clock is required for generating timestamps when dropping messages early because queue is full
+#[allow(clippy::too_many_arguments)]
 pub async fn spawn_node(
     dataflow_id: DataflowId,
     working_dir: &Path,
@@ -44,6 +49,9 @@ pub async fn spawn_node(
     dataflow_descriptor: Descriptor,
     clock: Arc<HLC>,
     node_stderr_most_recent: Arc<ArrayQueue<String>>,
+    print_node_output: bool,
+    deterministic: bool,
+    shmem_registry: &ShmemRegistry,
 ) -> eyre::Result<RunningNode> {
     let node_id = node.id.clone();
     tracing::debug!("Spawning node `{dataflow_id}/{node_id}`");
@@ -57,8 +65,12 @@ pub async fn spawn_node(
         &node_id,
         &daemon_tx,
         dataflow_descriptor.communication.local,
-        queue_sizes,
+        ListenerOptions {
+            queue_sizes,
+            report_idle: deterministic,
+        },
         clock.clone(),
+        shmem_registry,
     )
     .await?;
     let send_stdout_to = node
@@ -72,6 +84,7 @@ pub async fn spawn_node(
         daemon_communication,
         dataflow_descriptor,
         dynamic: node.kind.dynamic(),
+        deterministic,
     };
 
     let mut child = match node.kind {
@@ -312,11 +325,14 @@ pub async fn spawn_node(
                 }
             };
 
-            if buffer.contains("TRACE")
-                || buffer.contains("INFO")
-                || buffer.contains("DEBUG")
-                || buffer.contains("WARN")
-                || buffer.contains("ERROR")
+            // JSON log records are always on a single line
+            let json_log = buffer.trim_start().starts_with('{');
+            if !json_log
+                && (buffer.contains("TRACE")
+                    || buffer.contains("INFO")
+                    || buffer.contains("DEBUG")
+                    || buffer.contains("WARN")
+                    || buffer.contains("ERROR"))
             {
                 // tracing output, potentially multi-line -> keep reading following lines
                 // until double-newline
@@ -437,6 +453,12 @@ pub async fn spawn_node(
                 let _ = daemon_tx_log.send(event).await;
             }
 
+            if print_node_output {
+                for line in message.lines() {
+                    println!("{}: {line}", node.id);
+                }
+            }
+
             let _ = file
                 .write_all(message.as_bytes())
                 .await
@@ -453,6 +475,32 @@ pub async fn spawn_node(
                 .sync_all()
                 .await
                 .map_err(|err| error!("Could not sync logs to file due to {err}"));
+
+            // turn structured log lines into log messages
+            for line in message.lines() {
+                if let Some(log_message) =
+                    LogMessage::from_json_line(dataflow_id, Some(node.id.clone()), line)
+                {
+                    let event = Timestamped {
+                        inner: DoraEvent::NodeLog(log_message).into(),
+                        timestamp: uhlc.new_timestamp(),
+                    };
+                    let _ = daemon_tx_log.send(event).await;
+                }
+            }
+
+            // forward the output to the coordinator, which stores it too
+            let event = DoraEvent::NodeOutput {
+                dataflow_id,
+                node_id: node.id.clone(),
+                output: message,
+            }
+            .into();
+            let event = Timestamped {
+                inner: event,
+                timestamp: uhlc.new_timestamp(),
+            };
+            let _ = daemon_tx_log.send(event).await;
         }
         let _ = log_finish_tx
             .send(())
@@ -0,0 +1,263 @@
+//! Conversions between JSON values, Arrow arrays and `dora-record` recordings.
+
+use crate::{CapturedOutput, InjectedMessage};
+use dora_node_api::{
+    arrow::{
+        array::{make_array, Array, ArrayData, ArrayRef, ListArray, TimestampMillisecondArray},
+        datatypes::{DataType, Field, Schema},
+        error::ArrowError,
+        json::{reader::infer_json_schema_from_iterator, ArrayWriter, ReaderBuilder},
+        record_batch::RecordBatch,
+    },
+    RawData,
+};
+use eyre::{Context, ContextCompat};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::{json, Value};
+use std::{path::Path, sync::Arc, time::Duration};
+
+/// Columns that `dora-record` writes in addition to the data column.
+const RECORDING_METADATA_COLUMNS: &[&str] =
+    &["trace_id", "span_id", "timestamp_uhlc", "timestamp_utc"];
+
+/// Converts every value into an Arrow array of the given data type.
+///
+/// The data type is inferred from the values if not set.
+pub fn values_to_arrays(
+    values: &[Value],
+    data_type: Option<DataType>,
+) -> eyre::Result<Vec<ArrayData>> {
+    let values: Vec<_> = values.iter().map(normalize_value).collect();
+    let data_type = match data_type {
+        Some(data_type) => data_type,
+        None => {
+            let schema = infer_json_schema_from_iterator(
+                values.iter().flatten().map(|v| Ok(json!({ "v": v }))),
+            )
+            .context("failed to infer data type of values")?;
+            schema
+                .field_with_name("v")
+                .map(|f| f.data_type().clone())
+                .unwrap_or(DataType::Null)
+        }
+    };
+
+    let schema = Arc::new(Schema::new(vec![Field::new("v", data_type.clone(), true)]));
+    values
+        .iter()
+        .map(|message| {
+            let mut decoder = ReaderBuilder::new(schema.clone())
+                .with_batch_size(message.len().max(1))
+                .build_decoder()?;
+            let rows: Vec<_> = message.iter().map(|v| json!({ "v": v })).collect();
+            decoder.serialize(&rows)?;
+            let array = match decoder.flush()? {
+                Some(batch) => batch.column(0).to_data(),
+                None => ArrayData::new_empty(&data_type),
+            };
+            Ok(array)
+        })
+        .collect::<Result<_, ArrowError>>()
+        .with_context(|| format!("failed to convert values to `{data_type}`"))
+}
+
+/// Reads the messages of a `dora-record` parquet file.
+///
+/// The message offsets are based on the recorded `timestamp_utc`.
+pub fn read_recording(path: &Path) -> eyre::Result<Vec<InjectedMessage>> {
+    let file = std::fs::File::open(path)
+        .wrap_err_with(|| format!("failed to open recording `{}`", path.display()))?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+        .and_then(|builder| builder.build())
+        .wrap_err_with(|| format!("failed to read recording `{}`", path.display()))?;
+
+    let mut messages = Vec::new();
+    let mut first_timestamp = None;
+    for batch in reader {
+        let batch = batch.context("failed to read record batch")?;
+        let timestamps = batch
+            .column_by_name("timestamp_utc")
+            .and_then(|c| c.as_any().downcast_ref::<TimestampMillisecondArray>())
+            .context("recording has no valid `timestamp_utc` column")?;
+        let schema = batch.schema();
+        let data_column = schema
+            .fields()
+            .iter()
+            .position(|f| !RECORDING_METADATA_COLUMNS.contains(&f.name().as_str()))
+            .context("recording has no data column")?;
+        let data = batch
+            .column(data_column)
+            .as_any()
+            .downcast_ref::<ListArray>()
+            .context("recording data column is not a list")?;
+
+        for row in 0..batch.num_rows() {
+            let timestamp = timestamps.value(row);
+            let first = *first_timestamp.get_or_insert(timestamp);
+            let offset = u64::try_from(timestamp - first).unwrap_or_default();
+            messages.push(InjectedMessage {
+                offset: Duration::from_millis(offset),
+                data: data.value(row).to_data(),
+            });
+        }
+    }
+    Ok(messages)
+}
+
+/// Converts the data of a captured output into a list of JSON values.
+pub fn output_to_values(message: &CapturedOutput) -> eyre::Result<Vec<Value>> {
+    let raw = match &message.data {
+        Some(data) => RawData::Vec(data.clone()),
+        None => RawData::Empty,
+    };
+    let array = make_array(raw.into_arrow_array(&message.metadata.type_info)?);
+    array_to_values(array)
+}
+
+fn array_to_values(array: ArrayRef) -> eyre::Result<Vec<Value>> {
+    if array.data_type() == &DataType::Null {
+        return Ok(vec![Value::Null; array.len()]);
+    }
+    let batch = RecordBatch::try_from_iter([("v", array)])?;
+    let mut writer = ArrayWriter::new(Vec::new());
+    writer.write(&batch)?;
+    writer.finish()?;
+    let json = writer.into_inner();
+    if json.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rows: Vec<serde_json::Map<String, Value>> = serde_json::from_slice(&json)?;
+    Ok(rows
+        .into_iter()
+        .map(|mut row| row.remove("v").unwrap_or(Value::Null))
+        .collect())
+}
+
+/// Every message is an array, so scalars are treated as single-element lists.
+pub fn normalize_value(value: &Value) -> Vec<Value> {
+    match value {
+        Value::Array(values) => values.clone(),
+        other => vec![other.clone()],
+    }
+}
+
+/// Compares two lists of values, treating numbers as equal if their `f64` values match.
+pub fn values_equal(a: &[Value], b: &[Value]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| value_equal(a, b))
+}
+
+fn value_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (Value::Array(a), Value::Array(b)) => values_equal(a, b),
+        (a, b) => a == b,
+    }
+}
+
+pub fn format_values(values: &[Value]) -> String {
+    match values {
+        [value] => value.to_string(),
+        values => Value::from(values.to_vec()).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::array_to_data_message;
+    use dora_core::{daemon_messages::DataMessage, message::uhlc::HLC};
+    use dora_node_api::{
+        arrow::{
+            array::{Int64Array, ListArray, StringArray},
+            datatypes::Int64Type,
+        },
+        Metadata,
+    };
+    use parquet::arrow::ArrowWriter;
+
+    fn captured(array: &ArrayData) -> CapturedOutput {
+        let (type_info, data) = array_to_data_message(array);
+        let DataMessage::Vec(data) = data else {
+            unreachable!()
+        };
+        let clock = HLC::default();
+        CapturedOutput {
+            metadata: Metadata::new(clock.new_timestamp(), type_info),
+            data: Some(data),
+            received: clock.new_timestamp(),
+        }
+    }
+
+    #[test]
+    fn values_roundtrip() {
+        let values = vec![json!(1), json!([2, 3]), json!([])];
+        let arrays = values_to_arrays(&values, Some(DataType::UInt64)).unwrap();
+        assert_eq!(arrays.len(), 3);
+        assert!(arrays.iter().all(|a| a.data_type() == &DataType::UInt64));
+
+        let received: Vec<_> = arrays
+            .iter()
+            .map(|a| output_to_values(&captured(a)).unwrap())
+            .collect();
+        assert_eq!(
+            received,
+            vec![vec![json!(1)], vec![json!(2), json!(3)], vec![]]
+        );
+    }
+
+    #[test]
+    fn data_type_is_inferred() {
+        let arrays = values_to_arrays(&[json!("a"), json!(["b", "c"])], None).unwrap();
+        assert!(arrays.iter().all(|a| a.data_type() == &DataType::Utf8));
+        let strings = StringArray::from(arrays[1].clone());
+        assert_eq!(strings.value(1), "c");
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(values_to_arrays(&[json!("not a number")], Some(DataType::UInt8)).is_err());
+    }
+
+    #[test]
+    fn numbers_are_compared_by_value() {
+        assert!(values_equal(&[json!(1)], &[json!(1.0)]));
+        assert!(values_equal(&[json!([1, 2])], &[json!([1.0, 2.0])]));
+        assert!(!values_equal(&[json!(1)], &[json!(2)]));
+        assert!(!values_equal(&[json!(1)], &[json!(1), json!(1)]));
+        assert_eq!(normalize_value(&json!(5)), vec![json!(5)]);
+        assert_eq!(format_values(&[json!(5)]), "5");
+        assert_eq!(format_values(&[json!(1), json!(2)]), "[1,2]");
+    }
+
+    #[test]
+    fn read_recording_uses_relative_timestamps() {
+        let data = ListArray::from_iter_primitive::<Int64Type, _, _>([
+            Some(vec![Some(1)]),
+            Some(vec![Some(2), Some(3)]),
+        ]);
+        let timestamps = TimestampMillisecondArray::from(vec![1_000, 1_250]);
+        let batch = RecordBatch::try_from_iter([
+            (
+                "trace_id",
+                Arc::new(StringArray::from(vec!["", ""])) as ArrayRef,
+            ),
+            ("random", Arc::new(data) as ArrayRef),
+            ("timestamp_utc", Arc::new(timestamps) as ArrayRef),
+        ])
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.parquet");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let messages = read_recording(&path).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].offset, Duration::ZERO);
+        assert_eq!(messages[1].offset, Duration::from_millis(250));
+        let values = Int64Array::from(messages[1].data.clone());
+        assert_eq!(values.values(), &[2, 3]);
+    }
+}
@@ -0,0 +1,105 @@
+use super::TestReport;
+use std::io::{self, Write};
+
+/// Writes the given test reports as JUnit XML, using one test suite per report.
+pub fn write_junit(reports: &[TestReport], mut writer: impl Write) -> io::Result<()> {
+    let tests: usize = reports.iter().map(|r| r.cases.len()).sum();
+    let failures = count_failures(reports.iter().flat_map(|r| &r.cases));
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<testsuites tests="{tests}" failures="{failures}">"#
+    )?;
+    for report in reports {
+        let name = escape(&report.name);
+        writeln!(
+            writer,
+            r#"  <testsuite name="{name}" tests="{}" failures="{}" time="{:.3}">"#,
+            report.cases.len(),
+            count_failures(&report.cases),
+            report.duration.as_secs_f64(),
+        )?;
+        for case in &report.cases {
+            let case_name = escape(&case.name);
+            match &case.failure {
+                None => writeln!(
+                    writer,
+                    r#"    <testcase name="{case_name}" classname="{name}"/>"#
+                )?,
+                Some(failure) => {
+                    let message = escape(failure.lines().next().unwrap_or_default());
+                    writeln!(
+                        writer,
+                        r#"    <testcase name="{case_name}" classname="{name}">"#
+                    )?;
+                    writeln!(
+                        writer,
+                        r#"      <failure message="{message}">{}</failure>"#,
+                        escape(failure)
+                    )?;
+                    writeln!(writer, "    </testcase>")?;
+                }
+            }
+        }
+        writeln!(writer, "  </testsuite>")?;
+    }
+    writeln!(writer, "</testsuites>")?;
+    Ok(())
+}
+
+fn count_failures<'a>(cases: impl IntoIterator<Item = &'a super::TestCase>) -> usize {
+    cases
+        .into_iter()
+        .filter(|case| case.failure.is_some())
+        .count()
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCase;
+    use std::time::Duration;
+
+    #[test]
+    fn failures_are_escaped() {
+        let report = TestReport {
+            name: "spec <1>".into(),
+            duration: Duration::from_millis(1500),
+            cases: vec![
+                TestCase {
+                    name: "dataflow".into(),
+                    failure: None,
+                },
+                TestCase {
+                    name: "node/output".into(),
+                    failure: Some("expected \"a\" & \"b\"\nsecond line".into()),
+                },
+            ],
+        };
+        let mut xml = Vec::new();
+        write_junit(&[report], &mut xml).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+
+        assert!(xml.contains(r#"<testsuites tests="2" failures="1">"#));
+        assert!(xml
+            .contains(r#"<testsuite name="spec &lt;1&gt;" tests="2" failures="1" time="1.500">"#));
+        assert!(xml.contains(r#"<testcase name="dataflow" classname="spec &lt;1&gt;"/>"#));
+        assert!(xml.contains(r#"<failure message="expected &quot;a&quot; &amp; &quot;b&quot;">"#));
+    }
+}
@@ -0,0 +1,598 @@
+//! Integration test harness for whole dataflows.
+//!
+//! A test spec is a YAML file that names a dataflow, the inputs that should be
+//! injected into its nodes, and assertions on the outputs of its nodes:
+//!
+//! ```yaml
+//! name: status node
+//! dataflow: dataflow.yml
+//! timeout: 20s
+//! inputs:
+//!   - target: rust-status-node/random
+//!     values: [1, 2, 3]
+//!     data_type: UInt64
+//!     interval: 100ms
+//! outputs:
+//!   - source: rust-status-node/status
+//!     count: 3
+//!     max_latency: 1s
+//! ```
+//!
+//! Injected inputs are disconnected from their mapped source. Their messages
+//! are either given as literal `values` or read from a `recording` that was
+//! written by the `dora-record` node. The input is closed after the last
+//! message was sent.
+//!
+//! The dataflow runs until all of its nodes exited or until the spec `timeout`
+//! is reached. Afterwards, the messages captured from the tapped outputs are
+//! checked against the assertions.
+//!
+//! Specs that set `deterministic: true` run the dataflow in lockstep mode (see
+//! [`DeterministicOptions`]), optionally with a `seed`. All timeouts are
+//! measured in virtual time then, so the same spec gives the same outputs on
+//! every run.
+//!
+//! Specs are run by `dora test`, or from Rust (e.g. inside `cargo test`) through
+//! [`run_test_spec`]. This module requires the `testing` feature of
+//! `dora-daemon`:
+//!
+//! ```no_run
+//! # async fn run() -> eyre::Result<()> {
+//! let report = dora_daemon::testing::run_test_spec("dataflow.test.yml".as_ref()).await?;
+//! report.into_result()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    CapturedOutput, Daemon, DataflowTaps, DeterministicOptions, InjectedMessage, RunDataflowOptions,
+};
+use dora_core::config::{DataId, NodeId};
+use duration_str::deserialize_option_duration;
+use eyre::{bail, Context, ContextCompat};
+use serde::Deserialize;
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::mpsc;
+
+pub use junit::write_junit;
+
+mod data;
+mod junit;
+
+/// Timeout that is used if the spec doesn't specify one.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestSpec {
+    /// Name of the test suite. Defaults to the file name of the spec.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Path to the dataflow, relative to the spec file.
+    pub dataflow: PathBuf,
+    /// Stop the dataflow after this duration.
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    pub timeout: Option<Duration>,
+    #[serde(default)]
+    pub inputs: Vec<InputSpec>,
+    #[serde(default)]
+    pub outputs: Vec<OutputSpec>,
+    /// Run the dataflow in deterministic lockstep mode.
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Seed for ordering simultaneous events in deterministic mode.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputSpec {
+    /// The input to inject messages into, in the format `<node>/<input>`.
+    pub target: String,
+    /// Literal messages to send. Lists are sent as a single array message.
+    #[serde(default)]
+    pub values: Option<Vec<serde_json::Value>>,
+    /// Arrow data type of the `values`, e.g. `UInt64`. Inferred if not set.
+    #[serde(default)]
+    pub data_type: Option<String>,
+    /// Time between two `values` messages.
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    pub interval: Option<Duration>,
+    /// Parquet file written by `dora-record`, relative to the spec file.
+    ///
+    /// The messages are replayed with their original timing.
+    #[serde(default)]
+    pub recording: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputSpec {
+    /// The output to check, in the format `<node>/<output>`.
+    pub source: String,
+    /// Expected number of messages.
+    #[serde(default)]
+    pub count: Option<usize>,
+    /// Expected message values. Scalars match single-element arrays.
+    #[serde(default)]
+    pub values: Option<Vec<serde_json::Value>>,
+    /// Whether the `values` must be received in the given order.
+    #[serde(default = "default_ordered")]
+    pub ordered: bool,
+    /// Maximum time between the creation of a message and its arrival at the daemon.
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    pub max_latency: Option<Duration>,
+    /// All expected messages must arrive within this duration after the
+    /// dataflow was started. If neither `count` nor `values` is set, at least
+    /// one message must arrive.
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    pub timeout: Option<Duration>,
+}
+
+fn default_ordered() -> bool {
+    true
+}
+
+/// Result of running a [`TestSpec`].
+#[derive(Debug, Clone)]
+pub struct TestReport {
+    pub name: String,
+    pub duration: Duration,
+    pub cases: Vec<TestCase>,
+}
+
+/// A single check of a test spec.
+///
+/// Every output assertion becomes a test case. An additional `dataflow` test
+/// case checks that all nodes exited successfully.
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub failure: Option<String>,
+}
+
+impl TestReport {
+    pub fn is_ok(&self) -> bool {
+        self.cases.iter().all(|c| c.failure.is_none())
+    }
+
+    /// Returns an error that lists all failed test cases.
+    pub fn into_result(self) -> eyre::Result<()> {
+        let mut message = String::new();
+        for case in &self.cases {
+            if let Some(failure) = &case.failure {
+                writeln!(message, "{}: {failure}", case.name)?;
+            }
+        }
+        if message.is_empty() {
+            Ok(())
+        } else {
+            bail!("dataflow test `{}` failed:\n{message}", self.name)
+        }
+    }
+}
+
+/// Reads the test spec at the given path and runs it.
+pub async fn run_test_spec(spec_path: &Path) -> eyre::Result<TestReport> {
+    let spec = TestSpec::read(spec_path)?;
+    let base_dir = spec_path
+        .parent()
+        .context("spec path has no parent")?
+        .to_owned();
+    spec.run(&base_dir).await
+}
+
+impl TestSpec {
+    pub fn read(path: &Path) -> eyre::Result<Self> {
+        let file = std::fs::File::open(path)
+            .wrap_err_with(|| format!("failed to open test spec `{}`", path.display()))?;
+        let mut spec: Self = serde_yaml::from_reader(file)
+            .wrap_err_with(|| format!("failed to parse test spec `{}`", path.display()))?;
+        if spec.name.is_none() {
+            spec.name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned());
+        }
+        Ok(spec)
+    }
+
+    /// Runs the test, resolving relative paths against `base_dir`.
+    pub async fn run(&self, base_dir: &Path) -> eyre::Result<TestReport> {
+        let mut taps = DataflowTaps::default();
+        for input in &self.inputs {
+            let target = parse_io_id(&input.target)?;
+            let messages = input
+                .messages(base_dir)
+                .wrap_err_with(|| format!("failed to load messages for `{}`", input.target))?;
+            if taps.inputs.insert(target, messages).is_some() {
+                bail!("input `{}` is injected multiple times", input.target);
+            }
+        }
+        let mut captures = Vec::new();
+        for output in &self.outputs {
+            let source = parse_io_id(&output.source)?;
+            let (tx, rx) = mpsc::unbounded_channel();
+            if taps.outputs.insert(source, tx).is_some() {
+                bail!("multiple assertions for output `{}`", output.source);
+            }
+            captures.push((output, rx));
+        }
+
+        if self.seed.is_some() && !self.deterministic {
+            bail!("`seed` requires `deterministic: true`");
+        }
+        let deterministic = self.deterministic.then(|| DeterministicOptions {
+            seed: self.seed.unwrap_or_default(),
+        });
+        let options = RunDataflowOptions {
+            timeout: Some(self.timeout.unwrap_or(DEFAULT_TIMEOUT)),
+            taps,
+            deterministic,
+            ..Default::default()
+        };
+        let start_time = if self.deterministic {
+            // the virtual clock starts at zero
+            SystemTime::UNIX_EPOCH
+        } else {
+            SystemTime::now()
+        };
+        let start = Instant::now();
+        let result = Daemon::run_dataflow(&base_dir.join(&self.dataflow), options).await?;
+        let duration = start.elapsed();
+
+        let mut cases = Vec::new();
+        let node_failures: Vec<_> = result
+            .node_results
+            .iter()
+            .filter_map(|(node_id, result)| result.as_ref().err().map(|err| (node_id, err)))
+            .map(|(node_id, err)| format!("node `{node_id}` failed: {err}"))
+            .collect();
+        cases.push(TestCase {
+            name: "dataflow".into(),
+            failure: (!node_failures.is_empty()).then(|| node_failures.join("\n")),
+        });
+        for (output, mut rx) in captures {
+            let mut messages = Vec::new();
+            while let Ok(message) = rx.try_recv() {
+                messages.push(message);
+            }
+            let failures = output.check(start_time, &messages);
+            cases.push(TestCase {
+                name: output.source.clone(),
+                failure: (!failures.is_empty()).then(|| failures.join("\n")),
+            });
+        }
+
+        Ok(TestReport {
+            name: self.name.clone().unwrap_or_else(|| "dataflow test".into()),
+            duration,
+            cases,
+        })
+    }
+}
+
+impl InputSpec {
+    fn messages(&self, base_dir: &Path) -> eyre::Result<Vec<InjectedMessage>> {
+        match (&self.values, &self.recording) {
+            (Some(values), None) => {
+                let data_type = self
+                    .data_type
+                    .as_deref()
+                    .map(|t| t.parse())
+                    .transpose()
+                    .context("invalid `data_type`")?;
+                let interval = self.interval.unwrap_or_default();
+                data::values_to_arrays(values, data_type)?
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, data)| {
+                        Ok(InjectedMessage {
+                            offset: interval * u32::try_from(i)?,
+                            data,
+                        })
+                    })
+                    .collect()
+            }
+            (None, Some(recording)) => {
+                if self.data_type.is_some() || self.interval.is_some() {
+                    bail!("`data_type` and `interval` are not supported for recordings");
+                }
+                data::read_recording(&base_dir.join(recording))
+            }
+            (Some(_), Some(_)) => bail!("`recording` cannot be combined with `values`"),
+            (None, None) => bail!("either `values` or `recording` must be set"),
+        }
+    }
+}
+
+impl OutputSpec {
+    /// Checks the captured messages, returning a list of failure descriptions.
+    fn check(&self, start: SystemTime, messages: &[CapturedOutput]) -> Vec<String> {
+        let mut failures = Vec::new();
+
+        if let Some(count) = self.count {
+            if messages.len() != count {
+                failures.push(format!(
+                    "expected {count} messages, received {}",
+                    messages.len()
+                ));
+            }
+        }
+
+        if let Some(expected) = &self.values {
+            match messages.iter().map(data::output_to_values).collect() {
+                Ok(received) => failures.extend(check_values(expected, received, self.ordered)),
+                Err(err) => failures.push(format!("failed to decode received messages: {err}")),
+            }
+        }
+
+        if let Some(max_latency) = self.max_latency {
+            for (i, message) in messages.iter().enumerate() {
+                let latency = message
+                    .received
+                    .get_diff_duration(&message.metadata.timestamp());
+                if latency > max_latency {
+                    failures.push(format!(
+                        "message {i} had a latency of {latency:?} (max: {max_latency:?})"
+                    ));
+                }
+            }
+        }
+
+        if let Some(timeout) = self.timeout {
+            let expected = self
+                .count
+                .or(self.values.as_ref().map(|v| v.len()))
+                .unwrap_or(1);
+            let late = messages
+                .iter()
+                .take(expected)
+                .enumerate()
+                .find_map(|(i, m)| {
+                    let received = m.received.get_time().to_system_time();
+                    let elapsed = received.duration_since(start).unwrap_or_default();
+                    (elapsed > timeout).then_some((i, elapsed))
+                });
+            if let Some((i, elapsed)) = late {
+                failures.push(format!(
+                    "message {i} arrived after {elapsed:?} (timeout: {timeout:?})"
+                ));
+            } else if messages.len() < expected {
+                failures.push(format!(
+                    "expected {expected} messages within {timeout:?}, received {}",
+                    messages.len()
+                ));
+            }
+        }
+
+        failures
+    }
+}
+
+fn check_values(
+    expected: &[serde_json::Value],
+    received: Vec<Vec<serde_json::Value>>,
+    ordered: bool,
+) -> Option<String> {
+    let expected: Vec<_> = expected.iter().map(data::normalize_value).collect();
+    if expected.len() != received.len() {
+        return Some(format!(
+            "expected {} values, received {}",
+            expected.len(),
+            received.len(),
+        ));
+    }
+    if ordered {
+        let mismatch = expected
+            .iter()
+            .zip(&received)
+            .enumerate()
+            .find(|(_, (e, r))| !data::values_equal(e, r));
+        mismatch.map(|(i, (e, r))| {
+            format!(
+                "message {i}: expected {}, received {}",
+                data::format_values(e),
+                data::format_values(r)
+            )
+        })
+    } else {
+        let mut remaining = received;
+        for e in &expected {
+            match remaining.iter().position(|r| data::values_equal(e, r)) {
+                Some(index) => {
+                    remaining.remove(index);
+                }
+                None => {
+                    return Some(format!(
+                        "expected value {} was not received",
+                        data::format_values(e)
+                    ))
+                }
+            }
+        }
+        None
+    }
+}
+
+fn parse_io_id(id: &str) -> eyre::Result<(NodeId, DataId)> {
+    let (node_id, data_id) = id
+        .split_once('/')
+        .with_context(|| format!("invalid id `{id}`, expected `<node>/<data>`"))?;
+    Ok((node_id.to_owned().into(), data_id.to_owned().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::array_to_data_message;
+    use dora_core::{
+        daemon_messages::DataMessage,
+        message::{
+            uhlc::{Timestamp, ID, NTP64},
+            Metadata,
+        },
+    };
+    use dora_node_api::arrow::array::{Array, UInt64Array};
+    use std::time::UNIX_EPOCH;
+
+    fn timestamp(time: SystemTime) -> Timestamp {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap();
+        Timestamp::new(NTP64::from(since_epoch), ID::try_from([1]).unwrap())
+    }
+
+    /// A message with the given value that was sent at `sent` and received
+    /// `latency` later.
+    fn message(value: u64, sent: SystemTime, latency: Duration) -> CapturedOutput {
+        let (type_info, data) = array_to_data_message(&UInt64Array::from(vec![value]).to_data());
+        let DataMessage::Vec(data) = data else {
+            unreachable!()
+        };
+        CapturedOutput {
+            metadata: Metadata::new(timestamp(sent), type_info),
+            data: Some(data),
+            received: timestamp(sent + latency),
+        }
+    }
+
+    fn output_spec(yaml: &str) -> OutputSpec {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn parse_spec() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+            dataflow: dataflow.yml
+            timeout: 20s
+            inputs:
+              - target: node/tick
+                values: [1, 2]
+                interval: 100ms
+            outputs:
+              - source: node/status
+                count: 2
+                max_latency: 1s
+            "#,
+        )
+        .unwrap();
+        assert_eq!(spec.timeout, Some(Duration::from_secs(20)));
+        assert_eq!(spec.inputs[0].interval, Some(Duration::from_millis(100)));
+        assert!(spec.outputs[0].ordered);
+        assert_eq!(spec.outputs[0].max_latency, Some(Duration::from_secs(1)));
+
+        let unknown_field = serde_yaml::from_str::<TestSpec>("dataflow: a.yml\nfoo: 1");
+        assert!(unknown_field.is_err());
+    }
+
+    #[test]
+    fn input_values_are_spaced_by_interval() {
+        let input: InputSpec = serde_yaml::from_str(
+            "target: node/tick\nvalues: [1, [2, 3]]\ndata_type: UInt64\ninterval: 50ms",
+        )
+        .unwrap();
+        let messages = input.messages(Path::new(".")).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].offset, Duration::ZERO);
+        assert_eq!(messages[1].offset, Duration::from_millis(50));
+        assert_eq!(messages[1].data.len(), 2);
+
+        let invalid: InputSpec =
+            serde_yaml::from_str("target: node/tick\nvalues: [1]\nrecording: rec.parquet").unwrap();
+        assert!(invalid.messages(Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn check_count_and_values() {
+        let start = SystemTime::now();
+        let messages = [
+            message(1, start, Duration::ZERO),
+            message(2, start, Duration::ZERO),
+        ];
+
+        assert!(output_spec("source: a/b\ncount: 2\nvalues: [1, 2]")
+            .check(start, &messages)
+            .is_empty());
+        assert_eq!(
+            output_spec("source: a/b\ncount: 3").check(start, &messages),
+            vec!["expected 3 messages, received 2"]
+        );
+        assert_eq!(
+            output_spec("source: a/b\nvalues: [2, 1]").check(start, &messages),
+            vec!["message 0: expected 2, received 1"]
+        );
+        assert!(output_spec("source: a/b\nvalues: [2, 1]\nordered: false")
+            .check(start, &messages)
+            .is_empty());
+        assert_eq!(
+            output_spec("source: a/b\nvalues: [2, 3]\nordered: false").check(start, &messages),
+            vec!["expected value 3 was not received"]
+        );
+    }
+
+    #[test]
+    fn check_latency_and_timeout() {
+        let start = SystemTime::now();
+        let messages = [
+            message(1, start, Duration::from_millis(10)),
+            message(
+                2,
+                start + Duration::from_secs(2),
+                Duration::from_millis(500),
+            ),
+        ];
+
+        assert_eq!(
+            output_spec("source: a/b\nmax_latency: 100ms").check(start, &messages),
+            vec!["message 1 had a latency of 500ms (max: 100ms)"]
+        );
+        assert!(output_spec("source: a/b\ncount: 2\ntimeout: 3s")
+            .check(start, &messages)
+            .is_empty());
+        assert_eq!(
+            output_spec("source: a/b\ncount: 2\ntimeout: 1s").check(start, &messages)[0],
+            "message 1 arrived after 2.5s (timeout: 1s)"
+        );
+        assert_eq!(
+            output_spec("source: a/b\ncount: 2\ntimeout: 1s").check(start, &messages[..1]),
+            vec![
+                "expected 2 messages, received 1",
+                "expected 2 messages within 1s, received 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn report_lists_failed_cases() {
+        let report = TestReport {
+            name: "spec".into(),
+            duration: Duration::ZERO,
+            cases: vec![
+                TestCase {
+                    name: "dataflow".into(),
+                    failure: None,
+                },
+                TestCase {
+                    name: "a/b".into(),
+                    failure: Some("expected 3 messages, received 2".into()),
+                },
+            ],
+        };
+        assert!(!report.is_ok());
+        let err = report.into_result().unwrap_err().to_string();
+        assert!(err.contains("a/b: expected 3 messages, received 2"));
+        assert!(!err.contains("dataflow:"));
+    }
+
+    #[test]
+    fn parse_io_ids() {
+        let (node, data) = parse_io_id("node/output").unwrap();
+        assert_eq!(node.to_string(), "node");
+        assert_eq!(data.to_string(), "output");
+        assert!(parse_io_id("output").is_err());
+    }
+}
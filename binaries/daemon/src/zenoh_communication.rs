@@ -0,0 +1,737 @@
+//! Forwarding of outputs between daemons through Zenoh.
+//!
+//! Every output that is received by remote nodes is published on its own
+//! topic. Daemons subscribe to the topics of all remote outputs that feed
+//! their local nodes, so each output is sent only once, independent of the
+//! number of receiving machines.
+//!
+//! The last message of latched outputs is kept in a queryable, so that
+//! daemons that subscribe later can query it.
+//!
+//! All Zenoh operations use the async API to avoid blocking the event loop of
+//! the daemon. Messages are published by a background task, like the messages
+//! of [`InterDaemonConnection`](crate::inter_daemon::InterDaemonConnection),
+//! so a congested receiver never stalls the daemon.
+
+use crate::{Event, OutputId};
+use dora_core::{
+    daemon_messages::{DataflowId, InterDaemonEvent, Timestamped},
+    message::uhlc::HLC,
+};
+use eyre::{eyre, Context};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, Notify},
+    task::JoinHandle,
+};
+use zenoh::{
+    prelude::{
+        r#async::{AsyncResolve, Priority, SessionDeclarations, SplitBuffer},
+        Sample,
+    },
+    publication::{CongestionControl, Publisher},
+    queryable::{Query, Queryable},
+    subscriber::Subscriber,
+    Session,
+};
+
+/// Maximum time for publishing the queued messages when the connection is closed.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct ZenohConnection {
+    session: Arc<Session>,
+    /// Topic prefix of the dataflow.
+    prefix: String,
+    /// Tasks that forward the events of the subscribed remote outputs.
+    subscriptions: HashMap<OutputId, JoinHandle<()>>,
+    /// The last serialized event of every latched output, answered to queries.
+    latched: Arc<Mutex<HashMap<OutputId, Vec<u8>>>>,
+    queryables: HashMap<OutputId, Queryable<'static, ()>>,
+    queue: Arc<Mutex<PublishQueue>>,
+    notify: Arc<Notify>,
+    /// Maximum number of queued outputs.
+    output_limit: usize,
+    publish_task: Option<JoinHandle<()>>,
+}
+
+/// Messages that wait for being published.
+#[derive(Default)]
+struct PublishQueue {
+    messages: VecDeque<QueuedMessage>,
+    /// Number of queued messages that belong to an output.
+    queued_outputs: usize,
+    /// Number of outputs that were dropped because the queue was full.
+    lost: HashMap<OutputId, u64>,
+    /// Set when the connection is closed; the publish task finishes once all
+    /// queued messages are published.
+    closed: bool,
+}
+
+struct QueuedMessage {
+    output_id: OutputId,
+    message: Vec<u8>,
+    is_output: bool,
+}
+
+impl ZenohConnection {
+    /// Opens a new Zenoh session for the given dataflow.
+    ///
+    /// The given config is deserialized into a Zenoh config. The default
+    /// config is used if it is `None`. At most `buffer_size` outputs are
+    /// queued for publishing, further outputs are dropped and reported to the
+    /// receivers.
+    pub async fn open<'de, D>(
+        dataflow_id: DataflowId,
+        config: Option<D>,
+        prefix: &str,
+        buffer_size: usize,
+        clock: Arc<HLC>,
+    ) -> eyre::Result<Self>
+    where
+        D: serde::Deserializer<'de>,
+        D::Error: Send + Sync + 'static,
+    {
+        let config = match config {
+            Some(config) => {
+                zenoh::config::Config::deserialize(config).wrap_err("invalid zenoh config")?
+            }
+            None => zenoh::config::Config::default(),
+        };
+        let session = zenoh::open(config)
+            .res()
+            .await
+            .map_err(|err| eyre!(err))
+            .wrap_err("failed to open zenoh session")?;
+        let session = session.into_arc();
+        let prefix = format!("{prefix}/{dataflow_id}");
+        let queue = Arc::new(Mutex::new(PublishQueue::default()));
+        let notify = Arc::new(Notify::new());
+        let publish_task = tokio::spawn(publish_loop(
+            session.clone(),
+            prefix.clone(),
+            dataflow_id,
+            queue.clone(),
+            notify.clone(),
+            clock,
+        ));
+        Ok(Self {
+            session,
+            prefix,
+            subscriptions: HashMap::new(),
+            latched: Default::default(),
+            queryables: HashMap::new(),
+            queue,
+            notify,
+            output_limit: buffer_size.max(1),
+            publish_task: Some(publish_task),
+        })
+    }
+
+    /// Forwards the events published for the given remote output to the daemon.
+    ///
+    /// The subscription is declared when this function returns. It ends once
+    /// the inputs of the output are closed.
+    pub async fn subscribe(
+        &mut self,
+        output_id: &OutputId,
+        events_tx: mpsc::Sender<Timestamped<Event>>,
+    ) -> eyre::Result<()> {
+        let topic = self.topic(output_id);
+        let subscriber = self
+            .session
+            .declare_subscriber(topic.clone())
+            .reliable()
+            .res()
+            .await
+            .map_err(|err| eyre!(err))
+            .wrap_err_with(|| format!("failed to subscribe to `{topic}`"))?;
+        let task = tokio::spawn(receive_loop(subscriber, events_tx, topic));
+        if let Some(previous) = self.subscriptions.insert(output_id.clone(), task) {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    /// Stops forwarding the events of the given remote output.
+    pub fn unsubscribe(&mut self, output_id: &OutputId) {
+        if let Some(task) = self.subscriptions.remove(output_id) {
+            task.abort();
+        }
+    }
+
+    /// Whether the events of the given remote output are still forwarded.
+    ///
+    /// Subscriptions end when the inputs of the output are closed.
+    pub fn is_subscribed(&self, output_id: &OutputId) -> bool {
+        self.subscriptions
+            .get(output_id)
+            .is_some_and(|task| !task.is_finished())
+    }
+
+    /// Queries the last message of the given latched remote output and
+    /// forwards it to the daemon.
+    ///
+    /// Should be called after subscribing to the output, so that no message is
+    /// missed. Messages might be received twice, through the subscription and
+    /// through the query.
+    pub async fn query_latched(
+        &self,
+        output_id: &OutputId,
+        events_tx: mpsc::Sender<Timestamped<Event>>,
+    ) -> eyre::Result<()> {
+        let topic = self.topic(output_id);
+        let replies = self
+            .session
+            .get(&topic)
+            .res()
+            .await
+            .map_err(|err| eyre!(err))
+            .wrap_err_with(|| format!("failed to query `{topic}`"))?;
+        tokio::spawn(async move {
+            while let Ok(reply) = replies.recv_async().await {
+                let Ok(sample) = reply.sample else {
+                    continue;
+                };
+                let Some(event) = deserialize(&sample, &topic) else {
+                    continue;
+                };
+                if events_tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Keeps the given event of a latched output to answer queries of daemons
+    /// that subscribe later.
+    pub async fn retain_latched(
+        &mut self,
+        output_id: &OutputId,
+        event: &Timestamped<InterDaemonEvent>,
+    ) -> eyre::Result<()> {
+        let message = bincode::serialize(event).wrap_err("failed to serialize InterDaemonEvent")?;
+        self.retain_serialized(output_id, message).await
+    }
+
+    async fn retain_serialized(
+        &mut self,
+        output_id: &OutputId,
+        message: Vec<u8>,
+    ) -> eyre::Result<()> {
+        self.latched
+            .lock()
+            .unwrap()
+            .insert(output_id.clone(), message);
+        if !self.queryables.contains_key(output_id) {
+            let topic = self.topic(output_id);
+            let latched = self.latched.clone();
+            let queried = output_id.clone();
+            let queryable = self
+                .session
+                .declare_queryable(topic.clone())
+                .callback(move |query: Query| {
+                    let message = latched.lock().unwrap().get(&queried).cloned();
+                    if let Some(message) = message {
+                        let sample = Sample::new(query.key_expr().clone(), message);
+                        // the callback is synchronous, so the reply can't be awaited
+                        if let Err(err) =
+                            zenoh::prelude::sync::SyncResolve::res_sync(query.reply(Ok(sample)))
+                        {
+                            tracing::warn!("failed to reply to query: {err}");
+                        }
+                    }
+                })
+                .res()
+                .await
+                .map_err(|err| eyre!(err))
+                .wrap_err_with(|| format!("failed to create queryable for `{topic}`"))?;
+            self.queryables.insert(output_id.clone(), queryable);
+        }
+        Ok(())
+    }
+
+    /// Queues the given event for publishing to all daemons that subscribed to
+    /// the output, without waiting for the receivers.
+    ///
+    /// Events of `latched` outputs are retained for daemons that subscribe later.
+    /// Outputs are dropped if too many outputs are queued already. Other events
+    /// are never dropped.
+    pub async fn publish(
+        &mut self,
+        output_id: &OutputId,
+        event: &Timestamped<InterDaemonEvent>,
+        latched: bool,
+    ) -> eyre::Result<()> {
+        let message = bincode::serialize(event).wrap_err("failed to serialize InterDaemonEvent")?;
+        if latched {
+            self.retain_serialized(output_id, message.clone()).await?;
+        }
+        let is_output = matches!(event.inner, InterDaemonEvent::Output { .. });
+        let mut queue = self.queue.lock().unwrap();
+        if is_output && queue.queued_outputs >= self.output_limit {
+            tracing::trace!("zenoh publish queue is full, dropping output");
+            *queue.lost.entry(output_id.clone()).or_default() += 1;
+        } else {
+            queue.queued_outputs += usize::from(is_output);
+            queue.messages.push_back(QueuedMessage {
+                output_id: output_id.clone(),
+                message,
+                is_output,
+            });
+        }
+        drop(queue);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Publishes the remaining queued messages and closes the session.
+    ///
+    /// Messages that are not published within [`CLOSE_TIMEOUT`], e.g. because
+    /// a receiver is congested, are dropped.
+    pub async fn close(mut self) {
+        for (_, task) in self.subscriptions.drain() {
+            task.abort();
+            let _ = task.await;
+        }
+        self.queue.lock().unwrap().closed = true;
+        self.notify.notify_one();
+        if let Some(mut task) = self.publish_task.take() {
+            if tokio::time::timeout(CLOSE_TIMEOUT, &mut task)
+                .await
+                .is_err()
+            {
+                tracing::warn!("failed to publish the remaining messages before closing zenoh");
+                task.abort();
+                let _ = task.await;
+            }
+        }
+        self.queryables.clear();
+
+        // the session can only be closed once all publishers, subscribers,
+        // and queryables are dropped
+        let session = self.session.clone();
+        drop(self);
+        match Arc::try_unwrap(session) {
+            Ok(session) => {
+                if let Err(err) = session.close().res().await {
+                    tracing::warn!("failed to close zenoh session: {err}");
+                }
+            }
+            Err(_) => tracing::debug!("zenoh session is still in use, closing it on drop"),
+        }
+    }
+
+    fn topic(&self, output_id: &OutputId) -> String {
+        topic(&self.prefix, output_id)
+    }
+}
+
+impl Drop for ZenohConnection {
+    fn drop(&mut self) {
+        for (_, task) in self.subscriptions.drain() {
+            task.abort();
+        }
+        if let Some(task) = self.publish_task.take() {
+            task.abort();
+        }
+    }
+}
+
+fn topic(prefix: &str, OutputId(node_id, output_id): &OutputId) -> String {
+    format!("{prefix}/{node_id}/{output_id}")
+}
+
+async fn publish_loop(
+    session: Arc<Session>,
+    prefix: String,
+    dataflow_id: DataflowId,
+    queue: Arc<Mutex<PublishQueue>>,
+    notify: Arc<Notify>,
+    clock: Arc<HLC>,
+) {
+    let mut publishers: HashMap<OutputId, Publisher<'static>> = HashMap::new();
+    loop {
+        let next = {
+            let mut queue = queue.lock().unwrap();
+            // report lost messages before the next message of the output
+            let lost = queue.lost.keys().next().cloned();
+            if let Some((output_id, count)) =
+                lost.and_then(|output_id| queue.lost.remove_entry(&output_id))
+            {
+                let event = Timestamped {
+                    inner: InterDaemonEvent::ConnectionGap {
+                        lost: [(
+                            (dataflow_id, output_id.0.clone(), output_id.1.clone()),
+                            count,
+                        )]
+                        .into(),
+                    },
+                    timestamp: clock.new_timestamp(),
+                };
+                match bincode::serialize(&event) {
+                    Ok(gap) => Some((output_id, gap)),
+                    Err(err) => {
+                        tracing::warn!("failed to serialize ConnectionGap: {err}");
+                        continue;
+                    }
+                }
+            } else if let Some(queued) = queue.messages.pop_front() {
+                queue.queued_outputs -= usize::from(queued.is_output);
+                Some((queued.output_id, queued.message))
+            } else if queue.closed {
+                break;
+            } else {
+                None
+            }
+        };
+        let Some((output_id, message)) = next else {
+            notify.notified().await;
+            continue;
+        };
+
+        let publisher = match publishers.entry(output_id) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let topic = topic(&prefix, entry.key());
+                let publisher = session
+                    .declare_publisher(topic.clone())
+                    .congestion_control(CongestionControl::Block)
+                    .priority(Priority::RealTime)
+                    .res()
+                    .await;
+                match publisher {
+                    Ok(publisher) => entry.insert(publisher),
+                    Err(err) => {
+                        tracing::warn!("failed to create publisher for `{topic}`: {err}");
+                        continue;
+                    }
+                }
+            }
+        };
+        if let Err(err) = publisher.put(message).res().await {
+            tracing::warn!("failed to publish event: {err}");
+        }
+    }
+}
+
+async fn receive_loop(
+    subscriber: Subscriber<'static, flume::Receiver<zenoh::sample::Sample>>,
+    events_tx: mpsc::Sender<Timestamped<Event>>,
+    topic: String,
+) {
+    while let Ok(sample) = subscriber.recv_async().await {
+        let Some(event) = deserialize(&sample, &topic) else {
+            continue;
+        };
+        let closed = matches!(
+            event.inner,
+            Event::Daemon(InterDaemonEvent::InputsClosed { .. })
+        );
+        if events_tx.send(event).await.is_err() || closed {
+            // no further events are published after the inputs were closed
+            break;
+        }
+    }
+    tracing::debug!("zenoh subscription to `{topic}` finished");
+}
+
+fn deserialize(sample: &Sample, topic: &str) -> Option<Timestamped<Event>> {
+    match bincode::deserialize::<Timestamped<InterDaemonEvent>>(&sample.value.payload.contiguous())
+    {
+        Ok(event) => Some(Timestamped {
+            inner: Event::Daemon(event.inner),
+            timestamp: event.timestamp,
+        }),
+        Err(err) => {
+            tracing::warn!("failed to deserialize InterDaemonEvent on `{topic}`: {err}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_core::{
+        config::{DataId, NodeId},
+        message::{uhlc::HLC, ArrowTypeInfo, Metadata},
+    };
+    use std::path::Path;
+    use uuid::Uuid;
+
+    /// Sessions communicate through a Unix socket in the given directory, which
+    /// avoids races for free TCP ports.
+    fn peer_config(listen: Option<&Path>, connect: Option<&Path>) -> serde_json::Value {
+        let endpoints = |dir: Option<&Path>| {
+            dir.map(|dir| format!("unixsock-stream/{}", dir.join("zenoh.sock").display()))
+                .into_iter()
+        };
+        serde_json::json!({
+            "mode": "peer",
+            "listen": { "endpoints": endpoints(listen).collect::<Vec<_>>() },
+            "connect": { "endpoints": endpoints(connect).collect::<Vec<_>>() },
+            "scouting": { "multicast": { "enabled": false } },
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn forwards_outputs_between_two_sessions() {
+        let dataflow_id = Uuid::now_v7();
+        let prefix = format!("dora-test-{}", Uuid::now_v7());
+        let socket_dir = tempfile::tempdir().unwrap();
+        let mut sender = ZenohConnection::open(
+            dataflow_id,
+            Some(peer_config(Some(socket_dir.path()), None)),
+            &prefix,
+            100,
+            Arc::new(HLC::default()),
+        )
+        .await
+        .unwrap();
+        let mut receiver = ZenohConnection::open(
+            dataflow_id,
+            Some(peer_config(None, Some(socket_dir.path()))),
+            &prefix,
+            100,
+            Arc::new(HLC::default()),
+        )
+        .await
+        .unwrap();
+
+        let node_id: NodeId = "source".to_owned().into();
+        let data_id: DataId = "out".to_owned().into();
+        let output_id = OutputId(node_id.clone(), data_id.clone());
+        let (events_tx, mut events_rx) = mpsc::channel(100);
+        receiver.subscribe(&output_id, events_tx).await.unwrap();
+
+        let clock = HLC::default();
+        let output = Timestamped {
+            inner: InterDaemonEvent::Output {
+                dataflow_id,
+                node_id: node_id.clone(),
+                output_id: data_id.clone(),
+                metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty()),
+                data: None,
+                compression: None,
+            },
+            timestamp: clock.new_timestamp(),
+        };
+        // the subscription might take a moment to propagate to the other session
+        let received = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                sender.publish(&output_id, &output, false).await.unwrap();
+                tokio::select! {
+                    event = events_rx.recv() => break event,
+                    _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        })
+        .await
+        .expect("output was not forwarded");
+        assert!(matches!(
+            received.map(|e| e.inner),
+            Some(Event::Daemon(InterDaemonEvent::Output { node_id: n, output_id: o, .. }))
+                if n == node_id && o == data_id
+        ));
+
+        let closed = Timestamped {
+            inner: InterDaemonEvent::InputsClosed {
+                dataflow_id,
+                inputs: [(node_id.clone(), data_id.clone())].into(),
+            },
+            timestamp: clock.new_timestamp(),
+        };
+        sender.publish(&output_id, &closed, false).await.unwrap();
+        // queued messages are published before the session is closed
+        sender.close().await;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match events_rx.recv().await.map(|e| e.inner) {
+                    Some(Event::Daemon(InterDaemonEvent::InputsClosed { .. })) => break,
+                    Some(_) => continue,
+                    None => panic!("subscription ended before the inputs were closed"),
+                }
+            }
+        })
+        .await
+        .expect("inputs closed event was not forwarded");
+
+        // the subscription ends after the inputs were closed
+        let finished = tokio::time::timeout(Duration::from_secs(5), events_rx.recv())
+            .await
+            .expect("subscription did not finish");
+        assert!(finished.is_none());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while receiver.is_subscribed(&output_id) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("subscription task did not finish");
+        receiver.close().await;
+    }
+
+    #[tokio::test]
+    async fn outputs_are_dropped_when_the_queue_is_full() {
+        let dataflow_id = Uuid::now_v7();
+        let socket_dir = tempfile::tempdir().unwrap();
+        let prefix = format!("dora-test-{}", Uuid::now_v7());
+        let clock = Arc::new(HLC::default());
+        let mut connection = ZenohConnection::open(
+            dataflow_id,
+            Some(peer_config(Some(socket_dir.path()), None)),
+            &prefix,
+            2,
+            clock.clone(),
+        )
+        .await
+        .unwrap();
+
+        let output_id = OutputId("source".to_owned().into(), "out".to_owned().into());
+        let output = Timestamped {
+            inner: InterDaemonEvent::Output {
+                dataflow_id,
+                node_id: output_id.0.clone(),
+                output_id: output_id.1.clone(),
+                metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty()),
+                data: None,
+                compression: None,
+            },
+            timestamp: clock.new_timestamp(),
+        };
+        let closed = Timestamped {
+            inner: InterDaemonEvent::InputsClosed {
+                dataflow_id,
+                inputs: Default::default(),
+            },
+            timestamp: clock.new_timestamp(),
+        };
+        // the publish task can't run in between on the single-threaded runtime
+        for _ in 0..4 {
+            connection
+                .publish(&output_id, &output, false)
+                .await
+                .unwrap();
+        }
+        connection
+            .publish(&output_id, &closed, false)
+            .await
+            .unwrap();
+        {
+            let queue = connection.queue.lock().unwrap();
+            assert_eq!(queue.queued_outputs, 2);
+            // other events are never dropped
+            assert_eq!(queue.messages.len(), 3);
+            assert_eq!(queue.lost.get(&output_id), Some(&2));
+        }
+
+        tokio::time::timeout(Duration::from_secs(10), connection.close())
+            .await
+            .expect("connection was not closed");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unsubscribe_stops_forwarding() {
+        let socket_dir = tempfile::tempdir().unwrap();
+        let prefix = format!("dora-test-{}", Uuid::now_v7());
+        let mut connection = ZenohConnection::open(
+            Uuid::now_v7(),
+            Some(peer_config(Some(socket_dir.path()), None)),
+            &prefix,
+            100,
+            Arc::new(HLC::default()),
+        )
+        .await
+        .unwrap();
+        let output_id = OutputId("source".to_owned().into(), "out".to_owned().into());
+        let (events_tx, mut events_rx) = mpsc::channel(100);
+        connection.subscribe(&output_id, events_tx).await.unwrap();
+        assert!(connection.is_subscribed(&output_id));
+
+        connection.unsubscribe(&output_id);
+        assert!(!connection.is_subscribed(&output_id));
+        let finished = tokio::time::timeout(Duration::from_secs(5), events_rx.recv())
+            .await
+            .expect("subscription did not finish");
+        assert!(finished.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn latched_outputs_can_be_queried_later() {
+        let dataflow_id = Uuid::now_v7();
+        let prefix = format!("dora-test-{}", Uuid::now_v7());
+        let socket_dir = tempfile::tempdir().unwrap();
+        let mut sender = ZenohConnection::open(
+            dataflow_id,
+            Some(peer_config(Some(socket_dir.path()), None)),
+            &prefix,
+            100,
+            Arc::new(HLC::default()),
+        )
+        .await
+        .unwrap();
+        let receiver = ZenohConnection::open(
+            dataflow_id,
+            Some(peer_config(None, Some(socket_dir.path()))),
+            &prefix,
+            100,
+            Arc::new(HLC::default()),
+        )
+        .await
+        .unwrap();
+
+        let output_id = OutputId("source".to_owned().into(), "out".to_owned().into());
+        let clock = HLC::default();
+        let output = |timestamp| Timestamped {
+            inner: InterDaemonEvent::Output {
+                dataflow_id,
+                node_id: output_id.0.clone(),
+                output_id: output_id.1.clone(),
+                metadata: Metadata::new(timestamp, ArrowTypeInfo::empty()),
+                data: None,
+                compression: None,
+            },
+            timestamp: clock.new_timestamp(),
+        };
+        // published before anyone subscribed
+        let first = clock.new_timestamp();
+        sender
+            .publish(&output_id, &output(first), true)
+            .await
+            .unwrap();
+        let latest = clock.new_timestamp();
+        sender
+            .retain_latched(&output_id, &output(latest))
+            .await
+            .unwrap();
+
+        // the queryable might take a moment to propagate to the other session
+        let (events_tx, mut events_rx) = mpsc::channel(100);
+        let received = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                receiver
+                    .query_latched(&output_id, events_tx.clone())
+                    .await
+                    .unwrap();
+                tokio::select! {
+                    event = events_rx.recv() => break event,
+                    _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        })
+        .await
+        .expect("latched output was not replayed");
+        // only the latest message is replayed
+        assert!(matches!(
+            received.map(|e| e.inner),
+            Some(Event::Daemon(InterDaemonEvent::Output { metadata, .. }))
+                if metadata.timestamp() == latest
+        ));
+    }
+}
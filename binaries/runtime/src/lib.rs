@@ -37,7 +37,7 @@ pub fn main() -> eyre::Result<()> {
     } = config;
     let node_id = config.node_id.clone();
     #[cfg(feature = "tracing")]
-    set_up_tracing(&node_id.to_string()).context("failed to set up tracing subscriber")?;
+    set_up_tracing(node_id.as_ref()).context("failed to set up tracing subscriber")?;
 
     let dataflow_descriptor = config.dataflow_descriptor.clone();
 
@@ -51,11 +51,17 @@ pub fn main() -> eyre::Result<()> {
     };
 
     let (operator_events_tx, events) = mpsc::channel(1);
+    let (dropped_tx, dropped) = flume::unbounded();
     let operator_id = operator_definition.id.clone();
-    let operator_events = ReceiverStream::new(events).map(move |event| RuntimeEvent::Operator {
-        id: operator_id.clone(),
-        event,
-    });
+    let dropped = dropped
+        .into_stream()
+        .map(|counts| OperatorEvent::InputsDropped { counts });
+    let operator_events = (ReceiverStream::new(events), dropped)
+        .merge()
+        .map(move |event| RuntimeEvent::Operator {
+            id: operator_id.clone(),
+            event,
+        });
 
     let tokio_runtime = Builder::new_current_thread()
         .enable_all()
@@ -64,8 +70,13 @@ pub fn main() -> eyre::Result<()> {
 
     let mut operator_channels = HashMap::new();
     let queue_sizes = queue_sizes(&operator_definition.config);
-    let (operator_channel, incoming_events) =
-        operator::channel::channel(tokio_runtime.handle(), queue_sizes);
+    let notify_dropped = notify_dropped(&operator_definition.config);
+    let (operator_channel, incoming_events) = operator::channel::channel(
+        tokio_runtime.handle(),
+        queue_sizes,
+        notify_dropped,
+        dropped_tx,
+    );
     operator_channels.insert(operator_definition.id.clone(), operator_channel);
 
     tracing::info!("spawning main task");
@@ -114,6 +125,15 @@ fn queue_sizes(config: &OperatorConfig) -> std::collections::BTreeMap<DataId, us
     sizes
 }
 
+fn notify_dropped(config: &OperatorConfig) -> BTreeSet<DataId> {
+    config
+        .inputs
+        .iter()
+        .filter(|(_, input)| input.notify_dropped)
+        .map(|(input_id, _)| input_id.clone())
+        .collect()
+}
+
 #[tracing::instrument(skip(operator_events, operator_channels), level = "trace")]
 async fn run(
     operators: HashMap<OperatorId, OperatorConfig>,
@@ -203,6 +223,17 @@ async fn run(
                             break;
                         }
                     }
+                    OperatorEvent::InputsDropped { counts } => {
+                        let counts = counts
+                            .into_iter()
+                            .map(|(input_id, count)| {
+                                (operator_output_id(&operator_id, &input_id), count)
+                            })
+                            .collect();
+                        if let Err(err) = node.report_dropped_inputs(counts) {
+                            tracing::warn!("{err:?}");
+                        }
+                    }
                     OperatorEvent::AllocateOutputSample { len, sample: tx } => {
                         let sample = node.allocate_data_sample(len);
                         if tx.send(sample).is_err() {
@@ -310,6 +341,58 @@ async fn run(
                     }
                 }
             }
+            RuntimeEvent::Event(Event::InputGap { id, lost }) => {
+                let Some((operator_id, input_id)) = id.as_str().split_once('/') else {
+                    tracing::warn!("received InputGap event for non-operator input {id}");
+                    continue;
+                };
+                let operator_id = OperatorId::from(operator_id.to_owned());
+                let input_id = DataId::from(input_id.to_owned());
+
+                let Some(operator_channel) = operator_channels.get(&operator_id) else {
+                    tracing::warn!("received input {id} for unknown operator");
+                    continue;
+                };
+                if let Err(err) = operator_channel
+                    .send_async(Event::InputGap {
+                        id: input_id.clone(),
+                        lost,
+                    })
+                    .await
+                    .wrap_err_with(|| {
+                        format!("failed to send InputGap({input_id}) to operator `{operator_id}`")
+                    })
+                {
+                    tracing::warn!("{err}");
+                }
+            }
+            RuntimeEvent::Event(Event::InputDropped { id, count }) => {
+                let Some((operator_id, input_id)) = id.as_str().split_once('/') else {
+                    tracing::warn!("received InputDropped event for non-operator input {id}");
+                    continue;
+                };
+                let operator_id = OperatorId::from(operator_id.to_owned());
+                let input_id = DataId::from(input_id.to_owned());
+
+                let Some(operator_channel) = operator_channels.get(&operator_id) else {
+                    tracing::warn!("received input {id} for unknown operator");
+                    continue;
+                };
+                if let Err(err) = operator_channel
+                    .send_async(Event::InputDropped {
+                        id: input_id.clone(),
+                        count,
+                    })
+                    .await
+                    .wrap_err_with(|| {
+                        format!(
+                            "failed to send InputDropped({input_id}) to operator `{operator_id}`"
+                        )
+                    })
+                {
+                    tracing::warn!("{err}");
+                }
+            }
             RuntimeEvent::Event(Event::Error(err)) => eyre::bail!("received error event: {err}"),
             RuntimeEvent::Event(other) => {
                 tracing::warn!("received unknown event `{other:?}`");
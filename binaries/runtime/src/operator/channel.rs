@@ -4,17 +4,23 @@ use futures::{
     future::{self, FusedFuture},
     FutureExt,
 };
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
+/// Creates a queue for the events of an operator.
+///
+/// The number of inputs that are dropped because their queue is full are sent
+/// to `dropped_tx`, so that they can be reported to the daemon.
 pub fn channel(
     runtime: &tokio::runtime::Handle,
     queue_sizes: BTreeMap<DataId, usize>,
+    notify_dropped: BTreeSet<DataId>,
+    dropped_tx: flume::Sender<BTreeMap<DataId, u64>>,
 ) -> (flume::Sender<Event>, flume::Receiver<Event>) {
     let (incoming_tx, incoming_rx) = flume::bounded(10);
     let (outgoing_tx, outgoing_rx) = flume::bounded(0);
 
     runtime.spawn(async {
-        let mut buffer = InputBuffer::new(queue_sizes);
+        let mut buffer = InputBuffer::new(queue_sizes, notify_dropped, dropped_tx);
         buffer.run(incoming_rx, outgoing_tx).await;
     });
 
@@ -24,13 +30,22 @@ pub fn channel(
 struct InputBuffer {
     queue: VecDeque<Option<Event>>,
     queue_sizes: BTreeMap<DataId, usize>,
+    /// Inputs for which an `InputDropped` event is sent to the operator.
+    notify_dropped: BTreeSet<DataId>,
+    dropped_tx: flume::Sender<BTreeMap<DataId, u64>>,
 }
 
 impl InputBuffer {
-    pub fn new(queue_sizes: BTreeMap<DataId, usize>) -> Self {
+    pub fn new(
+        queue_sizes: BTreeMap<DataId, usize>,
+        notify_dropped: BTreeSet<DataId>,
+        dropped_tx: flume::Sender<BTreeMap<DataId, u64>>,
+    ) -> Self {
         Self {
             queue: VecDeque::new(),
             queue_sizes,
+            notify_dropped,
+            dropped_tx,
         }
     }
 
@@ -92,15 +107,36 @@ impl InputBuffer {
     }
 
     fn add_event(&mut self, event: Event) {
-        self.queue.push_back(Some(event));
+        match event {
+            Event::InputDropped { id, count } => self.add_dropped(id, count),
+            event => self.queue.push_back(Some(event)),
+        }
 
         // drop oldest input events to maintain max queue length queue
         self.drop_oldest_inputs();
     }
 
+    /// Queues an `InputDropped` event, merging it into an already queued one
+    /// for the same input.
+    fn add_dropped(&mut self, id: DataId, count: u64) {
+        let queued = self.queue.iter_mut().find_map(|event| match event {
+            Some(Event::InputDropped {
+                id: queued_id,
+                count,
+            }) if *queued_id == id => Some(count),
+            _ => None,
+        });
+        match queued {
+            Some(queued_count) => *queued_count += count,
+            None => self
+                .queue
+                .push_back(Some(Event::InputDropped { id, count })),
+        }
+    }
+
     fn drop_oldest_inputs(&mut self) {
         let mut queue_size_remaining = self.queue_sizes.clone();
-        let mut dropped = 0;
+        let mut dropped: BTreeMap<DataId, u64> = BTreeMap::new();
 
         // iterate over queued events, newest first
         for event in self.queue.iter_mut().rev() {
@@ -109,7 +145,7 @@ impl InputBuffer {
             };
             match queue_size_remaining.get_mut(input_id) {
                 Some(0) => {
-                    dropped += 1;
+                    *dropped.entry(input_id.clone()).or_default() += 1;
                     *event = None;
                 }
                 Some(size_remaining) => {
@@ -121,8 +157,84 @@ impl InputBuffer {
             }
         }
 
-        if dropped > 0 {
-            tracing::debug!("dropped {dropped} operator inputs because event queue was too full");
+        if !dropped.is_empty() {
+            tracing::debug!(
+                "dropped {} operator inputs because event queue was too full",
+                dropped.values().sum::<u64>()
+            );
+            if self.dropped_tx.send(dropped.clone()).is_err() {
+                tracing::debug!("failed to report dropped operator inputs: runtime stopped");
+            }
+        }
+        for (id, count) in dropped {
+            if self.notify_dropped.contains(&id) {
+                self.add_dropped(id, count);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dora_node_api::{
+        arrow::array::UInt8Array,
+        dora_core::message::{uhlc::HLC, ArrowTypeInfo, Metadata},
+        ArrowData,
+    };
+    use std::{sync::Arc, time::Duration};
+
+    fn input(id: &str, value: u8, clock: &HLC) -> Event {
+        Event::Input {
+            id: id.to_owned().into(),
+            metadata: Metadata::new(clock.new_timestamp(), ArrowTypeInfo::empty()),
+            data: ArrowData(Arc::new(UInt8Array::from(vec![value]))),
+        }
+    }
+
+    #[test]
+    fn dropped_inputs_are_reported() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let clock = HLC::default();
+        let (dropped_tx, dropped_rx) = flume::unbounded();
+        let queue_sizes = [("a".to_owned().into(), 1), ("b".to_owned().into(), 1)].into();
+        let notify_dropped = ["a".to_owned().into()].into();
+        let (events_tx, events_rx) =
+            channel(runtime.handle(), queue_sizes, notify_dropped, dropped_tx);
+
+        for i in 0..4 {
+            events_tx.send(input("a", i, &clock)).unwrap();
+            events_tx.send(input("b", i, &clock)).unwrap();
+        }
+        drop(events_tx);
+        // the operator does not receive any events until all inputs are queued
+        std::thread::sleep(Duration::from_millis(200));
+
+        let received: Vec<_> = events_rx
+            .iter()
+            .map(|event| match event {
+                Event::Input { id, data, .. } => {
+                    let data: &UInt8Array = data.as_any().downcast_ref().unwrap();
+                    format!("{id}={}", data.value(0))
+                }
+                Event::InputDropped { id, count } => format!("{id} dropped {count}"),
+                other => panic!("unexpected event {other:?}"),
+            })
+            .collect();
+        // the first input is already passed to the operator
+        assert_eq!(received, ["a=0", "a dropped 2", "a=3", "b=3"]);
+
+        let mut dropped: BTreeMap<DataId, u64> = BTreeMap::new();
+        for counts in dropped_rx.try_iter() {
+            for (id, count) in counts {
+                *dropped.entry(id).or_default() += count;
+            }
         }
+        let expected: BTreeMap<DataId, u64> =
+            [("a".to_owned().into(), 2), ("b".to_owned().into(), 3)].into();
+        assert_eq!(dropped, expected);
     }
 }
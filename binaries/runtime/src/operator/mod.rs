@@ -5,13 +5,14 @@ use dora_core::{
 };
 use dora_node_api::{DataSample, Event};
 use eyre::{Context, Result};
-use std::any::Any;
+use std::{any::Any, collections::BTreeMap};
 use tokio::sync::{mpsc::Sender, oneshot};
 
 pub mod channel;
 #[cfg(feature = "python")]
 mod python;
 mod shared_lib;
+mod stdout;
 
 #[allow(unused_variables)]
 pub fn run_operator(
@@ -31,6 +32,7 @@ pub fn run_operator(
                 events_tx,
                 incoming_events,
                 init_done,
+                operator_definition.config.send_stdout_as.as_deref(),
             )
             .wrap_err_with(|| {
                 format!(
@@ -50,6 +52,7 @@ pub fn run_operator(
                 incoming_events,
                 init_done,
                 dataflow_descriptor,
+                operator_definition.config.send_stdout_as.as_deref(),
             )
             .wrap_err_with(|| {
                 format!(
@@ -82,6 +85,10 @@ pub enum OperatorEvent {
         parameters: MetadataParameters,
         data: Option<DataSample>,
     },
+    /// Inputs that were dropped by the event queue of the operator.
+    InputsDropped {
+        counts: BTreeMap<DataId, u64>,
+    },
     Error(eyre::Error),
     Panic(Box<dyn Any + Send>),
     Finished {
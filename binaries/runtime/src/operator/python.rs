@@ -1,8 +1,8 @@
 #![allow(clippy::borrow_deref_ref)] // clippy warns about code generated by #[pymethods]
 
-use super::{OperatorEvent, StopReason};
+use super::{stdout::StdoutSender, OperatorEvent, StopReason};
 use dora_core::{
-    config::{NodeId, OperatorId},
+    config::{DataId, NodeId, OperatorId},
     descriptor::{source_is_url, Descriptor, PythonSource},
 };
 use dora_download::download_file;
@@ -12,8 +12,8 @@ use dora_operator_api_types::DoraStatus;
 use eyre::{bail, eyre, Context, Result};
 use pyo3::{
     pyclass,
-    types::{IntoPyDict, PyAnyMethods, PyDict, PyTracebackMethods},
-    Py, PyAny, Python,
+    types::{IntoPyDict, PyAnyMethods, PyDict, PyModule, PyTracebackMethods},
+    Bound, Py, PyAny, Python,
 };
 use std::{
     panic::{catch_unwind, AssertUnwindSafe},
@@ -31,6 +31,7 @@ fn traceback(err: pyo3::PyErr) -> eyre::Report {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(skip(events_tx, incoming_events), level = "trace")]
 pub fn run(
     node_id: &NodeId,
@@ -40,6 +41,7 @@ pub fn run(
     incoming_events: flume::Receiver<Event>,
     init_done: oneshot::Sender<Result<()>>,
     dataflow_descriptor: &Descriptor,
+    send_stdout_as: Option<&str>,
 ) -> eyre::Result<()> {
     let path = if source_is_url(&python_source.source) {
         let target_path = Path::new("build")
@@ -72,8 +74,18 @@ pub fn run(
     let send_output = SendOutputCallback {
         events_tx: events_tx.clone(),
     };
+    let stdout_writer = send_stdout_as.map(|output_id| StdoutWriter {
+        sender: StdoutSender::new(events_tx.clone(), DataId::from(output_id.to_owned())),
+        buffer: String::new(),
+    });
 
     let init_operator = move |py: Python| {
+        if let Some(writer) = stdout_writer {
+            stdout_dispatcher(py)?
+                .call_method1("register", (writer,))
+                .wrap_err("failed to redirect stdout of operator")?;
+        }
+
         if let Some(parent_path) = path_parent {
             let parent_path = parent_path
                 .to_str()
@@ -254,7 +266,18 @@ pub fn run(
         python_runner().wrap_err_with(|| format!("error in Python module at {}", path.display()))
     });
 
-    match catch_unwind(closure) {
+    let result = catch_unwind(closure);
+    if send_stdout_as.is_some() {
+        // send all remaining stdout before reporting that the operator finished
+        let unregistered = Python::with_gil(|py| -> Result<()> {
+            stdout_dispatcher(py)?.call_method0("unregister")?;
+            Ok(())
+        });
+        if let Err(err) = unregistered {
+            warn!("failed to restore stdout of operator: {err:?}");
+        }
+    }
+    match result {
         Ok(Ok(reason)) => {
             let _ = events_tx.blocking_send(OperatorEvent::Finished { reason });
         }
@@ -275,12 +298,72 @@ struct SendOutputCallback {
     events_tx: Sender<OperatorEvent>,
 }
 
+/// Replaces `sys.stdout` with a dispatcher that forwards everything to the
+/// original stdout and additionally to the writer registered for the current
+/// thread. This way, each operator thread can capture its own output.
+const STDOUT_DISPATCHER: &str = r#"
+import sys
+import threading
+
+
+class StdoutDispatcher:
+    def __init__(self, inner):
+        self.inner = inner
+        self.dora_writers = {}
+
+    def write(self, text):
+        writer = self.dora_writers.get(threading.get_ident())
+        if writer is not None:
+            writer.write(text)
+        return self.inner.write(text)
+
+    def flush(self):
+        self.inner.flush()
+
+    def __getattr__(self, name):
+        return getattr(self.inner, name)
+
+
+def register(writer):
+    if not hasattr(sys.stdout, "dora_writers"):
+        sys.stdout = StdoutDispatcher(sys.stdout)
+    sys.stdout.dora_writers[threading.get_ident()] = writer
+
+
+def unregister():
+    writers = getattr(sys.stdout, "dora_writers", {})
+    writer = writers.pop(threading.get_ident(), None)
+    if writer is not None:
+        writer.flush()
+"#;
+
+fn stdout_dispatcher(py: Python) -> Result<Bound<'_, PyModule>> {
+    let module = match py.import_bound("dora_stdout_dispatcher") {
+        Ok(module) => module,
+        Err(_) => PyModule::from_code_bound(
+            py,
+            STDOUT_DISPATCHER,
+            "dora_stdout_dispatcher.py",
+            "dora_stdout_dispatcher",
+        )
+        .wrap_err("failed to load stdout dispatcher")?,
+    };
+    Ok(module)
+}
+
+/// Sends the lines written to it as outputs of the operator.
+#[pyclass]
+struct StdoutWriter {
+    sender: StdoutSender,
+    buffer: String,
+}
+
 #[allow(unsafe_op_in_unsafe_fn)]
 mod callback_impl {
 
     use crate::operator::OperatorEvent;
 
-    use super::SendOutputCallback;
+    use super::{SendOutputCallback, StdoutWriter};
     use aligned_vec::{AVec, ConstAlign};
     use arrow::{array::ArrayData, pyarrow::FromPyArrow};
     use dora_core::message::ArrowTypeInfo;
@@ -378,4 +461,30 @@ mod callback_impl {
             Ok(())
         }
     }
+
+    #[pymethods]
+    impl StdoutWriter {
+        fn write(&mut self, text: &str, py: Python) {
+            self.buffer.push_str(text);
+            while let Some(end) = self.buffer.find('\n') {
+                let line: String = self.buffer.drain(..=end).collect();
+                self.send(&line[..end], py);
+            }
+        }
+
+        fn flush(&mut self, py: Python) {
+            if !self.buffer.is_empty() {
+                let line = std::mem::take(&mut self.buffer);
+                self.send(&line, py);
+            }
+        }
+    }
+
+    impl StdoutWriter {
+        fn send(&self, line: &str, py: Python) {
+            if let Err(err) = py.allow_threads(|| self.sender.send_line(line)) {
+                tracing::warn!("{err}");
+            }
+        }
+    }
 }
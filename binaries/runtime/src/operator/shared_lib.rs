@@ -1,4 +1,7 @@
-use super::{OperatorEvent, StopReason};
+use super::{
+    stdout::{capture_thread_stdout, StdoutSender},
+    OperatorEvent, StopReason,
+};
 use aligned_vec::{AVec, ConstAlign};
 use dora_core::{
     adjust_shared_library_path,
@@ -32,6 +35,7 @@ pub fn run(
     events_tx: Sender<OperatorEvent>,
     incoming_events: flume::Receiver<Event>,
     init_done: oneshot::Sender<Result<()>>,
+    send_stdout_as: Option<&str>,
 ) -> eyre::Result<()> {
     let path = if source_is_url(source) {
         let target_path = adjust_shared_library_path(
@@ -55,6 +59,14 @@ pub fn run(
             .wrap_err_with(|| format!("failed to load shared library at `{}`", path.display()))?
     };
 
+    let stdout_capture = send_stdout_as
+        .map(|output_id| {
+            let sender = StdoutSender::new(events_tx.clone(), DataId::from(output_id.to_owned()));
+            capture_thread_stdout(sender)
+        })
+        .transpose()
+        .wrap_err("failed to capture stdout of operator")?;
+
     let closure = AssertUnwindSafe(|| {
         let bindings = Bindings::init(&library).context("failed to init operator")?;
 
@@ -66,7 +78,10 @@ pub fn run(
 
         operator.run(init_done)
     });
-    match catch_unwind(closure) {
+    let result = catch_unwind(closure);
+    // send all captured stdout lines before reporting that the operator finished
+    drop(stdout_capture);
+    match result {
         Ok(Ok(reason)) => {
             let _ = events_tx.blocking_send(OperatorEvent::Finished { reason });
         }
@@ -186,6 +201,7 @@ impl<'lib> SharedLibraryOperator<'lib> {
                     input_closed: None,
                     stop: true,
                     error: None,
+                    input_dropped: None,
                 },
                 Event::Input {
                     id: input_id,
@@ -210,6 +226,7 @@ impl<'lib> SharedLibraryOperator<'lib> {
                         input_closed: None,
                         stop: false,
                         error: None,
+                        input_dropped: None,
                     }
                 }
                 Event::InputClosed { id: input_id } => dora_operator_api_types::RawEvent {
@@ -217,16 +234,31 @@ impl<'lib> SharedLibraryOperator<'lib> {
                     input: None,
                     stop: false,
                     error: None,
+                    input_dropped: None,
                 },
                 Event::Reload { .. } => {
                     // Reloading shared lib operator is not supported. See: https://github.com/dora-rs/dora/pull/239#discussion_r1154313139
                     continue;
                 }
+                Event::InputDropped { id, count } => dora_operator_api_types::RawEvent {
+                    input_dropped: Some(
+                        Box::new(dora_operator_api_types::InputDropped {
+                            id: id.to_string().into(),
+                            count,
+                        })
+                        .into(),
+                    ),
+                    input: None,
+                    input_closed: None,
+                    stop: false,
+                    error: None,
+                },
                 Event::Error(err) => dora_operator_api_types::RawEvent {
                     error: Some(err.into()),
                     input_closed: None,
                     input: None,
                     stop: false,
+                    input_dropped: None,
                 },
                 other => {
                     tracing::warn!("unexpected event: {other:?}");
@@ -0,0 +1,263 @@
+//! Per-operator stdout capturing for the `send_stdout_as` operator option.
+//!
+//! Each captured line is echoed to the real stdout of the runtime (so that it
+//! still shows up in the node logs) and sent as a string output of the operator
+//! that printed it.
+
+use super::OperatorEvent;
+use aligned_vec::{AVec, ConstAlign};
+use arrow::array::{ArrayData, StringArray};
+use dora_core::config::DataId;
+use dora_node_api::{
+    arrow_utils::{copy_array_into_sample, required_data_size},
+    MetadataParameters,
+};
+use eyre::eyre;
+use tokio::sync::mpsc::Sender;
+
+#[derive(Clone)]
+pub struct StdoutSender {
+    events_tx: Sender<OperatorEvent>,
+    output_id: DataId,
+}
+
+impl StdoutSender {
+    pub fn new(events_tx: Sender<OperatorEvent>, output_id: DataId) -> Self {
+        Self {
+            events_tx,
+            output_id,
+        }
+    }
+
+    /// Sends the given line as an output of the operator.
+    ///
+    /// Must not be called from within an async context.
+    pub fn send_line(&self, line: &str) -> eyre::Result<()> {
+        let array: ArrayData = StringArray::from(vec![line]).into();
+        let total_len = required_data_size(&array);
+        let mut sample: AVec<u8, ConstAlign<128>> = AVec::__from_elem(128, 0, total_len);
+        let type_info = copy_array_into_sample(&mut sample, &array);
+
+        let event = OperatorEvent::Output {
+            output_id: self.output_id.clone(),
+            type_info,
+            parameters: MetadataParameters::default(),
+            data: Some(sample.into()),
+        };
+        self.events_tx
+            .blocking_send(event)
+            .map_err(|_| eyre!("failed to send stdout line to runtime"))
+    }
+}
+
+#[cfg(unix)]
+pub use fd::capture_thread_stdout;
+
+/// Stdout capturing is not supported on this platform, so this only warns.
+#[cfg(not(unix))]
+pub fn capture_thread_stdout(sender: StdoutSender) -> eyre::Result<CaptureGuard> {
+    tracing::warn!(
+        "`send_stdout_as` is not supported for shared library operators on this platform \
+        (output `{}` will stay empty)",
+        sender.output_id
+    );
+    Ok(CaptureGuard {})
+}
+
+#[cfg(not(unix))]
+pub struct CaptureGuard {}
+
+#[cfg(unix)]
+mod fd {
+    use super::StdoutSender;
+    use eyre::Context;
+    use std::{
+        fs::File,
+        io::{BufRead, BufReader, Write},
+        os::fd::{FromRawFd, RawFd},
+        thread::JoinHandle,
+    };
+
+    /// Redirects the stdout of the current thread into a pipe until the returned
+    /// guard is dropped.
+    ///
+    /// On Linux, `unshare(CLONE_FILES)` first gives the current thread a private
+    /// copy of the file descriptor table, so that redirecting file descriptor 1
+    /// does not affect other threads. The thread keeps its private table after
+    /// the guard is dropped. Note that only the file descriptor is per thread:
+    /// the buffer of C's `stdout` stream is shared by the whole process, so
+    /// buffered `printf` output is written to the file descriptor of whichever
+    /// thread flushes it. Operators should flush C stdio before returning from
+    /// their callbacks to get all of their output.
+    ///
+    /// Other platforms don't support private file descriptor tables, so the
+    /// redirection applies to the whole process there. For this reason, only a
+    /// single operator per runtime may use `send_stdout_as` on these platforms.
+    ///
+    /// The guard must be dropped on the same thread.
+    pub fn capture_thread_stdout(sender: StdoutSender) -> eyre::Result<CaptureGuard> {
+        #[cfg(target_os = "linux")]
+        if unsafe { libc::unshare(libc::CLONE_FILES) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .wrap_err("failed to unshare file descriptor table");
+        }
+
+        let _ = std::io::stdout().flush();
+        let original = cvt(unsafe { libc::dup(libc::STDOUT_FILENO) })
+            .wrap_err("failed to duplicate stdout")?;
+        let mut fds = [0 as RawFd; 2];
+        if let Err(err) = cvt(unsafe { libc::pipe(fds.as_mut_ptr()) }) {
+            unsafe { libc::close(original) };
+            return Err(err).wrap_err("failed to create stdout pipe");
+        }
+        let [read_fd, write_fd] = fds;
+        let redirected = cvt(unsafe { libc::dup2(write_fd, libc::STDOUT_FILENO) });
+        unsafe { libc::close(write_fd) };
+        if let Err(err) = redirected {
+            unsafe {
+                libc::close(read_fd);
+                libc::close(original);
+            }
+            return Err(err).wrap_err("failed to redirect stdout");
+        }
+
+        let reader = unsafe { File::from_raw_fd(read_fd) };
+        let mut echo = match cvt(unsafe { libc::dup(original) }) {
+            Ok(fd) => unsafe { File::from_raw_fd(fd) },
+            Err(err) => {
+                unsafe { libc::dup2(original, libc::STDOUT_FILENO) };
+                unsafe { libc::close(original) };
+                return Err(err).wrap_err("failed to duplicate stdout");
+            }
+        };
+        let reader_thread = std::thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            let mut send = true;
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(err) => {
+                        tracing::warn!("failed to read captured stdout: {err}");
+                        break;
+                    }
+                }
+                let _ = echo.write_all(&line);
+                if send {
+                    let text = String::from_utf8_lossy(&line);
+                    let text = text.strip_suffix('\n').unwrap_or(&text);
+                    if let Err(err) = sender.send_line(text) {
+                        tracing::warn!("{err}");
+                        send = false;
+                    }
+                }
+            }
+        });
+
+        Ok(CaptureGuard {
+            original,
+            reader_thread: Some(reader_thread),
+        })
+    }
+
+    /// Restores the original stdout when dropped and waits until all captured
+    /// lines were sent.
+    pub struct CaptureGuard {
+        original: RawFd,
+        reader_thread: Option<JoinHandle<()>>,
+    }
+
+    impl Drop for CaptureGuard {
+        fn drop(&mut self) {
+            let _ = std::io::stdout().flush();
+            // replacing the pipe closes its last write end, so the reader thread
+            // sees an EOF after reading all remaining lines
+            if let Err(err) = cvt(unsafe { libc::dup2(self.original, libc::STDOUT_FILENO) }) {
+                tracing::warn!("failed to restore stdout: {err}");
+                return;
+            }
+            unsafe { libc::close(self.original) };
+            if let Some(thread) = self.reader_thread.take() {
+                if thread.join().is_err() {
+                    tracing::warn!("stdout capture thread panicked");
+                }
+            }
+        }
+    }
+
+    fn cvt(ret: libc::c_int) -> std::io::Result<libc::c_int> {
+        if ret < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    fn captured_lines(events_rx: &mut mpsc::Receiver<OperatorEvent>) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        while let Ok(event) = events_rx.try_recv() {
+            if let OperatorEvent::Output {
+                data: Some(data), ..
+            } = event
+            {
+                lines.push(data.to_vec());
+            }
+        }
+        lines
+    }
+
+    fn contains(data: &[u8], text: &str) -> bool {
+        data.windows(text.len()).any(|w| w == text.as_bytes())
+    }
+
+    #[test]
+    fn operators_get_separate_stdout() {
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+        let threads: Vec<_> = ["first", "second"]
+            .into_iter()
+            .map(|name| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    let (events_tx, mut events_rx) = mpsc::channel(100);
+                    let sender = StdoutSender::new(events_tx, DataId::from("stdout".to_owned()));
+                    let guard = capture_thread_stdout(sender).unwrap();
+                    // both threads are capturing at the same time
+                    barrier.wait();
+                    for i in 0..3 {
+                        let line = format!("{name} {i}\n");
+                        let written = unsafe {
+                            libc::write(libc::STDOUT_FILENO, line.as_ptr().cast(), line.len())
+                        };
+                        assert_eq!(written, line.len() as isize);
+                    }
+                    barrier.wait();
+                    drop(guard);
+                    captured_lines(&mut events_rx)
+                })
+            })
+            .collect();
+        let [first, second]: [Vec<Vec<u8>>; 2] = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+
+        assert_eq!(first.len(), 3);
+        assert_eq!(second.len(), 3);
+        for (i, (first, second)) in first.iter().zip(&second).enumerate() {
+            assert!(contains(first, &format!("first {i}")));
+            assert!(contains(second, &format!("second {i}")));
+            assert!(!contains(first, "second"));
+            assert!(!contains(second, "first"));
+        }
+    }
+}
@@ -1,6 +1,7 @@
-use dora_coordinator::{ControlEvent, Event};
+use dora_coordinator::{ControlEvent, Event, HeartbeatConfig, LogConfig, StateConfig};
 use dora_core::{
     descriptor::Descriptor,
+    security::SecurityConfig,
     topics::{
         ControlRequest, ControlRequestReply, DataflowId, DORA_COORDINATOR_PORT_CONTROL_DEFAULT,
         DORA_COORDINATOR_PORT_DEFAULT,
@@ -48,6 +49,11 @@ async fn main() -> eyre::Result<()> {
     let (coordinator_port, coordinator) = dora_coordinator::start(
         coordinator_bind,
         coordinator_control_bind,
+        None,
+        StateConfig::default(),
+        SecurityConfig::default(),
+        HeartbeatConfig::default(),
+        LogConfig::default(),
         ReceiverStream::new(coordinator_events_rx),
     )
     .await?;
@@ -134,6 +140,7 @@ async fn start_dataflow(
                 dataflow: dataflow_descriptor,
                 local_working_dir: working_dir,
                 name: None,
+                dry_run: false,
             },
             reply_sender,
         }))
@@ -94,11 +94,14 @@ impl RequestReplyLayer for TcpLayer {
     }
 }
 
-pub struct TcpConnection {
-    pub stream: TcpStream,
+/// Length-prefixed request/reply connection over a TCP stream.
+///
+/// The stream type can be changed to wrap the TCP stream, e.g. for encryption.
+pub struct TcpConnection<S = TcpStream> {
+    pub stream: S,
 }
 
-impl ListenConnection for TcpConnection {
+impl<S: Read + Write + Send + Sync> ListenConnection for TcpConnection<S> {
     type RequestData = Vec<u8>;
     type ReplyData = Vec<u8>;
     type Error = std::io::Error;
@@ -114,7 +117,7 @@ impl ListenConnection for TcpConnection {
     }
 }
 
-impl RequestReplyConnection for TcpConnection {
+impl<S: Read + Write + Send + Sync> RequestReplyConnection for TcpConnection<S> {
     type RequestData = Vec<u8>;
     type ReplyData = Vec<u8>;
     type Error = std::io::Error;
@@ -127,7 +130,7 @@ impl RequestReplyConnection for TcpConnection {
     }
 }
 
-impl TcpConnection {
+impl<S: Read + Write> TcpConnection<S> {
     pub fn send(&mut self, request: &[u8]) -> std::io::Result<()> {
         let len_raw = (request.len() as u64).to_le_bytes();
         self.stream.write_all(&len_raw)?;
@@ -272,6 +272,14 @@ pub struct NodeRunConfig {
     ///  - output_2
     #[serde(default)]
     pub outputs: BTreeSet<DataId>,
+    /// Outputs whose last message is kept by the daemon and delivered to
+    /// nodes that subscribe later.
+    ///
+    /// Every latched output must be listed in `outputs` too. This is a
+    /// separate set, so that the type of `outputs` stays the same for the
+    /// node APIs and operator runtimes that use it.
+    #[serde(default)]
+    pub latched_outputs: BTreeSet<DataId>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
@@ -279,6 +287,16 @@ pub struct NodeRunConfig {
 pub struct Input {
     pub mapping: InputMapping,
     pub queue_size: Option<usize>,
+    /// Compression of the messages for this input if they are sent from
+    /// another machine.
+    ///
+    /// Overrides the `compression` setting of the dataflow. All inputs that
+    /// receive the same output must use the same compression, or none.
+    #[schemars(with = "Option<String>")]
+    pub compression: Option<Compression>,
+    /// Send an `InputDropped` event to the node when messages of this input
+    /// are dropped because its queue is full.
+    pub notify_dropped: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -288,6 +306,10 @@ pub enum InputDef {
     WithOptions {
         source: InputMapping,
         queue_size: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<Compression>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        notify_dropped: bool,
     },
 }
 
@@ -297,13 +319,19 @@ impl From<Input> for InputDef {
             Input {
                 mapping,
                 queue_size: None,
+                compression: None,
+                notify_dropped: false,
             } => Self::MappingOnly(mapping),
             Input {
                 mapping,
                 queue_size,
+                compression,
+                notify_dropped,
             } => Self::WithOptions {
                 source: mapping,
                 queue_size,
+                compression,
+                notify_dropped,
             },
         }
     }
@@ -315,15 +343,88 @@ impl From<InputDef> for Input {
             InputDef::MappingOnly(mapping) => Self {
                 mapping,
                 queue_size: None,
+                compression: None,
+                notify_dropped: false,
             },
-            InputDef::WithOptions { source, queue_size } => Self {
+            InputDef::WithOptions {
+                source,
+                queue_size,
+                compression,
+                notify_dropped,
+            } => Self {
                 mapping: source,
                 queue_size,
+                compression,
+                notify_dropped,
             },
         }
     }
 }
 
+/// Compression algorithm for messages that are sent between machines.
+///
+/// Written as `lz4`, `zstd`, or `zstd(<level>)` in dataflow descriptors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Compression {
+    Lz4,
+    /// Zstandard compression with the given level.
+    Zstd(i32),
+}
+
+impl Compression {
+    pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+}
+
+impl FromStr for Compression {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd(Self::DEFAULT_ZSTD_LEVEL)),
+            other => {
+                let level = other
+                    .strip_prefix("zstd(")
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .ok_or_else(|| {
+                        eyre::eyre!(
+                            "unknown compression `{other}`, expected `lz4`, `zstd`, or `zstd(<level>)`"
+                        )
+                    })?;
+                let level = level
+                    .trim()
+                    .parse()
+                    .map_err(|_| eyre::eyre!("invalid zstd compression level `{level}`"))?;
+                Ok(Self::Zstd(level))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Compression {
+    type Error = eyre::Report;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd(level) => write!(f, "zstd({level})"),
+        }
+    }
+}
+
+impl From<Compression> for String {
+    fn from(compression: Compression) -> Self {
+        compression.to_string()
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, JsonSchema, Clone)]
 #[serde(deny_unknown_fields, rename_all = "lowercase")]
 pub struct CommunicationConfig {
@@ -342,6 +443,12 @@ pub struct CommunicationConfig {
     )]
     #[schemars(with = "String")]
     pub remote: RemoteCommunicationConfig,
+    /// Compression of the messages that are sent between machines.
+    ///
+    /// Can be overridden per input.
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub compression: Option<Compression>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -360,12 +467,25 @@ impl Default for LocalCommunicationConfig {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(deny_unknown_fields, rename_all = "lowercase")]
 pub enum RemoteCommunicationConfig {
+    /// Send outputs to every receiving machine over a separate TCP connection.
     Tcp,
-    // TODO:a
-    // Zenoh {
-    //     config: Option<serde_yaml::Value>,
-    //     prefix: String,
-    // },
+    /// Publish outputs through a Zenoh session, with one topic per output.
+    ///
+    /// Receiving daemons subscribe to the outputs they need, so an output is
+    /// published only once even if multiple machines receive it. Zenoh peers
+    /// discover each other automatically through multicast scouting.
+    Zenoh {
+        /// Zenoh configuration, e.g. to set the `listen` or `connect` endpoints.
+        #[serde(default)]
+        config: Option<serde_yaml::Value>,
+        /// Prefix for all topic names.
+        #[serde(default = "default_zenoh_prefix")]
+        prefix: String,
+    },
+}
+
+fn default_zenoh_prefix() -> String {
+    "dora".into()
 }
 
 impl Default for RemoteCommunicationConfig {
@@ -373,3 +493,45 @@ impl Default for RemoteCommunicationConfig {
         Self::Tcp
     }
 }
+
+/// Time source for the `dora/timer/*` inputs and the message timestamps of a
+/// dataflow.
+///
+/// e.g.
+///
+/// clock:
+///
+///   mode: scaled
+///
+///   factor: 10
+///
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum ClockConfig {
+    /// Follow the wall clock.
+    #[default]
+    Real,
+    /// Run `factor` times as fast as the wall clock, e.g. `0.5` for half speed.
+    ///
+    /// The factor must be between `0.000001` and `1000000`.
+    Scaled { factor: f64 },
+    /// Follow the time that a node publishes on the given output, e.g. for
+    /// replaying a recording.
+    ///
+    /// The output must send a `UInt64` array with the nanoseconds since the
+    /// UNIX epoch or an Arrow `Timestamp` array. If a message contains multiple
+    /// values, the last one is used. Messages of other types are ignored with
+    /// a warning, as the type of an output is only known at runtime. Timers
+    /// only tick when the published time advances, and they are closed
+    /// together with the output.
+    ///
+    /// Not supported in deterministic mode, which uses its own virtual clock.
+    External { source: InputMapping },
+}
+
+impl ClockConfig {
+    /// Smallest supported factor of the `scaled` mode.
+    pub const MIN_FACTOR: f64 = 1e-6;
+    /// Largest supported factor of the `scaled` mode.
+    pub const MAX_FACTOR: f64 = 1e6;
+}
@@ -1,6 +1,11 @@
-use crate::{config::NodeId, daemon_messages::DataflowId, topics::DataflowDaemonResult};
+use crate::{
+    config::NodeId,
+    daemon_messages::DataflowId,
+    topics::{DataflowDaemonResult, NodeError},
+};
 use eyre::eyre;
 pub use log::Level;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum CoordinatorRequest {
@@ -8,11 +13,24 @@ pub enum CoordinatorRequest {
         dora_version: String,
         machine_id: String,
         listen_port: u16,
+        /// Dataflows that are currently running on the daemon.
+        ///
+        /// Used by the coordinator to reconcile its state after a restart.
+        #[serde(default)]
+        running_dataflows: BTreeSet<DataflowId>,
+        /// Access token, if the coordinator requires token authentication.
+        #[serde(default)]
+        token: Option<String>,
+        /// Labels of the machine, used for placing nodes automatically.
+        #[serde(default)]
+        labels: BTreeMap<String, String>,
     },
     Event {
         machine_id: String,
         event: DaemonEvent,
     },
+    /// Authenticates an event connection, must be sent before any events.
+    Authenticate { token: String },
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -28,6 +46,75 @@ pub struct LogMessage {
     pub message: String,
 }
 
+impl LogMessage {
+    /// Parses a log line that a node printed in a JSON format.
+    ///
+    /// Supports the JSON format of `tracing-subscriber` and the usual field
+    /// names of JSON formatters for Python's `logging` module. Returns `None`
+    /// if the line is not a JSON object with a known log level.
+    pub fn from_json_line(
+        dataflow_id: DataflowId,
+        node_id: Option<NodeId>,
+        line: &str,
+    ) -> Option<Self> {
+        let line = line.trim();
+        if !line.starts_with('{') {
+            return None;
+        }
+        let serde_json::Value::Object(mut record) = serde_json::from_str(line).ok()? else {
+            return None;
+        };
+        let mut take_string = |keys: &[&str]| {
+            keys.iter().find_map(|key| match record.remove(*key)? {
+                serde_json::Value::String(s) => Some(s),
+                _ => None,
+            })
+        };
+
+        let level = match take_string(&["level", "levelname"])?
+            .to_ascii_uppercase()
+            .as_str()
+        {
+            "TRACE" => Level::Trace,
+            "DEBUG" => Level::Debug,
+            "INFO" => Level::Info,
+            "WARN" | "WARNING" => Level::Warn,
+            "ERROR" | "CRITICAL" | "FATAL" => Level::Error,
+            _ => return None,
+        };
+        let target = take_string(&["target", "name"]);
+        let module_path = take_string(&["module_path", "module"]);
+        let file = take_string(&["filename", "pathname", "file"]);
+        let mut message = take_string(&["message", "msg"]).unwrap_or_default();
+        let line = ["line_number", "lineno", "line"]
+            .iter()
+            .find_map(|key| record.get(*key)?.as_u64())
+            .and_then(|line| u32::try_from(line).ok());
+
+        // `tracing-subscriber` puts the message and other fields of the event
+        // into a nested object
+        if let Some(serde_json::Value::Object(mut fields)) = record.remove("fields") {
+            if let Some(serde_json::Value::String(m)) = fields.remove("message") {
+                message = m;
+            }
+            for (key, value) in fields {
+                message.push_str(&format!(" {key}={value}"));
+            }
+        }
+
+        Some(Self {
+            dataflow_id,
+            node_id,
+            level,
+            target,
+            module_path,
+            file,
+            line,
+            message,
+        })
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum DaemonEvent {
     AllNodesReady {
@@ -38,8 +125,27 @@ pub enum DaemonEvent {
         dataflow_id: DataflowId,
         result: DataflowDaemonResult,
     },
+    /// A node subscribed to its events.
+    NodeReady {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+    },
+    NodeExited {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        result: Result<(), NodeError>,
+    },
     Heartbeat,
     Log(LogMessage),
+    /// Output of a node on stdout or stderr, which is stored by the coordinator.
+    ///
+    /// This event is not queued while the coordinator is unreachable, so output
+    /// that is produced during a disconnect is only stored on the daemon machine.
+    NodeLogs {
+        dataflow_id: DataflowId,
+        node_id: NodeId,
+        output: String,
+    },
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -56,3 +162,65 @@ impl RegisterResult {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn parse(line: &str) -> Option<LogMessage> {
+        LogMessage::from_json_line(Uuid::now_v7(), Some("node".to_string().into()), line)
+    }
+
+    #[test]
+    fn tracing_subscriber_records_are_parsed() {
+        let message = parse(
+            r#"{"timestamp":"2024-01-01T00:00:00Z","level":"WARN","fields":{"message":"slow","frame":3},"target":"camera","filename":"src/main.rs","line_number":12}"#,
+        )
+        .unwrap();
+        assert_eq!(message.level, Level::Warn);
+        assert_eq!(message.message, "slow frame=3");
+        assert_eq!(message.target.as_deref(), Some("camera"));
+        assert_eq!(message.file.as_deref(), Some("src/main.rs"));
+        assert_eq!(message.line, Some(12));
+        assert_eq!(message.node_id, Some("node".to_string().into()));
+    }
+
+    #[test]
+    fn python_logging_records_are_parsed() {
+        let message = parse(
+            r#"  {"levelname": "critical", "name": "root", "module": "main", "pathname": "main.py", "lineno": 7, "msg": "failed"}"#,
+        )
+        .unwrap();
+        assert_eq!(message.level, Level::Error);
+        assert_eq!(message.message, "failed");
+        assert_eq!(message.target.as_deref(), Some("root"));
+        assert_eq!(message.module_path.as_deref(), Some("main"));
+        assert_eq!(message.file.as_deref(), Some("main.py"));
+        assert_eq!(message.line, Some(7));
+    }
+
+    #[test]
+    fn missing_optional_fields_are_allowed() {
+        let message = parse(r#"{"level":"info"}"#).unwrap();
+        assert_eq!(message.level, Level::Info);
+        assert_eq!(message.message, "");
+        assert_eq!(message.target, None);
+        assert_eq!(message.line, None);
+    }
+
+    #[test]
+    fn lines_without_known_level_are_rejected() {
+        assert!(parse(r#"{"level":"verbose","message":"hi"}"#).is_none());
+        assert!(parse(r#"{"level":3,"message":"hi"}"#).is_none());
+        assert!(parse(r#"{"message":"hi"}"#).is_none());
+    }
+
+    #[test]
+    fn non_json_lines_are_rejected() {
+        assert!(parse("INFO starting").is_none());
+        assert!(parse("{ not json").is_none());
+        assert!(parse(r#"["level", "INFO"]"#).is_none());
+        assert!(parse("").is_none());
+    }
+}
@@ -7,8 +7,9 @@ use std::{
 };
 
 use crate::{
-    config::{DataId, NodeId, NodeRunConfig, OperatorId},
+    config::{Compression, DataId, NodeId, NodeRunConfig, OperatorId},
     descriptor::{Descriptor, OperatorDefinition, ResolvedNode},
+    topics::DataflowDaemonStats,
 };
 use aligned_vec::{AVec, ConstAlign};
 use dora_message::{uhlc, Metadata};
@@ -22,6 +23,13 @@ pub struct NodeConfig {
     pub daemon_communication: DaemonCommunication,
     pub dataflow_descriptor: Descriptor,
     pub dynamic: bool,
+    /// The dataflow runs in deterministic lockstep mode.
+    ///
+    /// The node must only request its next event from the daemon once the
+    /// previous one was fully processed, so that the daemon can tell when the
+    /// node is idle.
+    #[serde(default)]
+    pub deterministic: bool,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -70,12 +78,44 @@ pub enum DaemonRequest {
     ReportDropTokens {
         drop_tokens: Vec<DropToken>,
     },
+    /// Reports inputs that the node dropped itself, e.g. in the operator
+    /// queues of the dora runtime, by input ID.
+    ReportDroppedInputs {
+        counts: BTreeMap<DataId, u64>,
+    },
     SubscribeDrop,
     NextFinishedDropTokens,
     EventStreamDropped,
     NodeConfig {
         node_id: NodeId,
     },
+    /// Registers an ad-hoc subscriber for the given outputs of a running dataflow.
+    ///
+    /// The subscriber is not part of the dataflow descriptor. The daemon assigns
+    /// a generated node ID and replies with its node config, which has one input
+    /// named `<node>/<output>` per subscribed output. If no dataflow ID is given,
+    /// the only running dataflow of the daemon is used.
+    SubscribeOutputs {
+        dataflow_id: Option<DataflowId>,
+        outputs: BTreeSet<(NodeId, DataId)>,
+    },
+    /// Leases a shared memory segment of at least the given size from the
+    /// daemon's pool.
+    ///
+    /// The reply contains the drop token that must be used for sending the
+    /// segment. The segment returns to the pool once all receivers of the
+    /// output that it is sent on reported this drop token. If the segment is
+    /// not sent, the node reports the drop token itself (through
+    /// [`DaemonRequest::ReportDropTokens`]).
+    AllocateSharedMemory {
+        len: usize,
+    },
+    /// Replies once all previous requests on the same connection were passed
+    /// to the daemon.
+    ///
+    /// Used in deterministic mode to ensure that sent outputs are known to the
+    /// daemon before the node asks for its next event.
+    Barrier,
 }
 
 impl DaemonRequest {
@@ -84,7 +124,9 @@ impl DaemonRequest {
         match self {
             DaemonRequest::SendMessage { .. }
             | DaemonRequest::NodeConfig { .. }
-            | DaemonRequest::ReportDropTokens { .. } => false,
+            | DaemonRequest::SubscribeOutputs { .. }
+            | DaemonRequest::ReportDropTokens { .. }
+            | DaemonRequest::ReportDroppedInputs { .. } => false,
             DaemonRequest::Register { .. }
             | DaemonRequest::Subscribe
             | DaemonRequest::CloseOutputs(_)
@@ -92,14 +134,16 @@ impl DaemonRequest {
             | DaemonRequest::NextEvent { .. }
             | DaemonRequest::SubscribeDrop
             | DaemonRequest::NextFinishedDropTokens
-            | DaemonRequest::EventStreamDropped => true,
+            | DaemonRequest::EventStreamDropped
+            | DaemonRequest::AllocateSharedMemory { .. }
+            | DaemonRequest::Barrier => true,
         }
     }
 
     pub fn expects_tcp_json_reply(&self) -> bool {
         #[allow(clippy::match_like_matches_macro)]
         match self {
-            DaemonRequest::NodeConfig { .. } => true,
+            DaemonRequest::NodeConfig { .. } | DaemonRequest::SubscribeOutputs { .. } => true,
             DaemonRequest::Register { .. }
             | DaemonRequest::Subscribe
             | DaemonRequest::CloseOutputs(_)
@@ -108,8 +152,11 @@ impl DaemonRequest {
             | DaemonRequest::SubscribeDrop
             | DaemonRequest::NextFinishedDropTokens
             | DaemonRequest::ReportDropTokens { .. }
+            | DaemonRequest::ReportDroppedInputs { .. }
             | DaemonRequest::SendMessage { .. }
-            | DaemonRequest::EventStreamDropped => false,
+            | DaemonRequest::EventStreamDropped
+            | DaemonRequest::AllocateSharedMemory { .. }
+            | DaemonRequest::Barrier => false,
         }
     }
 }
@@ -154,16 +201,23 @@ impl fmt::Debug for DataMessage {
     }
 }
 
-type SharedMemoryId = String;
+pub type SharedMemoryId = String;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[must_use]
 pub enum DaemonReply {
     Result(Result<(), String>),
-    PreparedMessage { shared_memory_id: SharedMemoryId },
+    PreparedMessage {
+        shared_memory_id: SharedMemoryId,
+    },
     NextEvents(Vec<Timestamped<NodeEvent>>),
     NextDropEvents(Vec<Timestamped<NodeDropEvent>>),
-    NodeConfig { result: Result<NodeConfig, String> },
+    NodeConfig {
+        result: Result<NodeConfig, String>,
+    },
+    SharedMemory {
+        result: Result<(SharedMemoryId, DropToken), String>,
+    },
     Empty,
 }
 
@@ -187,6 +241,17 @@ pub enum NodeEvent {
     InputClosed {
         id: DataId,
     },
+    /// Some messages of the input were lost because the connection to the
+    /// sending machine was interrupted.
+    InputGap {
+        id: DataId,
+        lost: u64,
+    },
+    /// Messages of the input were dropped because its queue was full.
+    InputDropped {
+        id: DataId,
+        count: u64,
+    },
     AllInputsClosed,
 }
 
@@ -253,13 +318,43 @@ pub enum DaemonCoordinatorEvent {
         dataflow_id: DataflowId,
         node_id: NodeId,
     },
+    Stats {
+        dataflow_id: DataflowId,
+    },
+    /// Adds nodes to a running dataflow.
+    AddNodes {
+        dataflow_id: DataflowId,
+        /// All nodes of the dataflow after the change, including the added ones.
+        nodes: Vec<ResolvedNode>,
+        added: BTreeSet<NodeId>,
+        dataflow_descriptor: Descriptor,
+    },
+    /// Stops the given nodes of a running dataflow and removes them.
+    RemoveNodes {
+        dataflow_id: DataflowId,
+        nodes: BTreeSet<NodeId>,
+        grace_duration: Option<Duration>,
+    },
+    /// Another machine of the dataflow stopped responding, so the inputs that
+    /// are fed by its nodes should be closed.
+    MachineLost {
+        dataflow_id: DataflowId,
+        machine_id: String,
+        nodes: BTreeSet<NodeId>,
+    },
     Destroy,
     Heartbeat,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub enum DynamicNodeEvent {
-    NodeConfig { node_id: NodeId },
+    NodeConfig {
+        node_id: NodeId,
+    },
+    SubscribeOutputs {
+        dataflow_id: Option<DataflowId>,
+        outputs: BTreeSet<(NodeId, DataId)>,
+    },
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -270,11 +365,18 @@ pub enum InterDaemonEvent {
         output_id: DataId,
         metadata: Metadata,
         data: Option<AVec<u8, ConstAlign<128>>>,
+        /// The algorithm that `data` is compressed with, if any.
+        compression: Option<Compression>,
     },
     InputsClosed {
         dataflow_id: DataflowId,
         inputs: BTreeSet<(NodeId, DataId)>,
     },
+    /// Number of outputs that were dropped while the connection was
+    /// interrupted, by dataflow and output.
+    ConnectionGap {
+        lost: BTreeMap<(DataflowId, NodeId, DataId), u64>,
+    },
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -282,12 +384,15 @@ pub enum DaemonCoordinatorReply {
     SpawnResult(Result<(), String>),
     ReloadResult(Result<(), String>),
     StopResult(Result<(), String>),
+    AddNodesResult(Result<(), String>),
+    RemoveNodesResult(Result<(), String>),
     DestroyResult {
         result: Result<(), String>,
         #[serde(skip)]
         notify: Option<tokio::sync::oneshot::Sender<()>>,
     },
     Logs(Result<Vec<u8>, String>),
+    Stats(Result<DataflowDaemonStats, String>),
 }
 
 pub type DataflowId = Uuid;
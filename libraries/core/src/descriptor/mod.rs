@@ -1,7 +1,8 @@
 use crate::config::{
-    CommunicationConfig, DataId, Input, InputMapping, NodeId, NodeRunConfig, OperatorId,
+    ClockConfig, CommunicationConfig, DataId, Input, InputMapping, NodeId, NodeRunConfig,
+    OperatorId, UserInputMapping,
 };
-use eyre::{bail, eyre, Context, OptionExt, Result};
+use eyre::{bail, Context, OptionExt, Result};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with_expand_env::with_expand_envs;
@@ -11,7 +12,6 @@ use std::{
     fmt,
     path::{Path, PathBuf},
 };
-use tracing::warn;
 pub use visualize::collect_dora_timers;
 mod validate;
 mod visualize;
@@ -27,8 +27,11 @@ pub struct Descriptor {
     #[serde(default)]
     pub communication: CommunicationConfig,
     #[schemars(skip)]
-    #[serde(default, rename = "_unstable_deploy")]
+    #[serde(default, rename = "_unstable_deploy", alias = "deploy")]
     pub deploy: Deploy,
+    /// Time source for timers and message timestamps.
+    #[serde(default)]
+    pub clock: ClockConfig,
     pub nodes: Vec<Node>,
 }
 
@@ -84,6 +87,7 @@ impl Descriptor {
                     run_config: NodeRunConfig {
                         inputs: node.inputs,
                         outputs: node.outputs,
+                        latched_outputs: node.latched_outputs,
                     },
                     envs: None,
                 }),
@@ -110,6 +114,37 @@ impl Descriptor {
         Ok(resolved)
     }
 
+    /// Returns the output that publishes the clock if the `clock` is in
+    /// `external` mode.
+    ///
+    /// Like input mappings, the output of a single-operator node is resolved
+    /// to the output of its operator.
+    pub fn clock_source(&self) -> Option<UserInputMapping> {
+        let ClockConfig::External {
+            source: InputMapping::User(mapping),
+        } = &self.clock
+        else {
+            return None;
+        };
+        let operator = self
+            .nodes
+            .iter()
+            .find(|n| n.id == mapping.source)
+            .and_then(|n| n.operator.as_ref());
+        let output = match operator {
+            Some(op) => {
+                let op_name = op.id.as_ref().map(|id| id.to_string());
+                let op_name = op_name.as_deref().unwrap_or(SINGLE_OPERATOR_DEFAULT_ID);
+                DataId::from(format!("{op_name}/{}", mapping.output))
+            }
+            None => mapping.output.clone(),
+        };
+        Some(UserInputMapping {
+            source: mapping.source.clone(),
+            output,
+        })
+    }
+
     pub fn visualize_as_mermaid(&self) -> eyre::Result<String> {
         let resolved = self.resolve_aliases_and_set_defaults()?;
         let flowchart = visualize::visualize_nodes(&resolved);
@@ -138,6 +173,11 @@ impl Descriptor {
             .wrap_err("Dataflow could not be validated.")
     }
 
+    /// Checks that the dataflow can run in deterministic lockstep mode.
+    pub fn check_deterministic(&self) -> eyre::Result<()> {
+        validate::check_deterministic(self).wrap_err("Dataflow cannot run in deterministic mode.")
+    }
+
     pub fn check_in_daemon(
         &self,
         working_dir: &Path,
@@ -158,6 +198,47 @@ impl Descriptor {
 #[serde(deny_unknown_fields)]
 pub struct Deploy {
     pub machine: Option<String>,
+    /// Only place the node on machines that have all of the given labels.
+    ///
+    /// Ignored if `machine` is set explicitly.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_labels",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub selector: BTreeMap<String, String>,
+    /// Prefer machines with the given labels if multiple machines match the selector.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_labels",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub prefer: BTreeMap<String, String>,
+    /// Number of CPUs that the node reserves on its machine.
+    ///
+    /// Nodes are only placed on machines whose `cpus` label is large enough.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<u32>,
+}
+
+impl Deploy {
+    /// Whether the node should be placed automatically by the coordinator.
+    pub fn needs_placement(&self) -> bool {
+        self.machine.is_none()
+            && (!self.selector.is_empty() || !self.prefer.is_empty() || self.cpus.is_some())
+    }
+}
+
+/// Deserializes label values that YAML parses as booleans or numbers to strings.
+fn deserialize_labels<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let labels: BTreeMap<String, EnvValue> = Deserialize::deserialize(deserializer)?;
+    Ok(labels
+        .into_iter()
+        .map(|(key, value)| (key, value.to_string()))
+        .collect())
 }
 
 /// Dora Node
@@ -175,7 +256,7 @@ pub struct Node {
 
     /// Unstable machine deployment configuration
     #[schemars(skip)]
-    #[serde(default, rename = "_unstable_deploy")]
+    #[serde(default, rename = "_unstable_deploy", alias = "deploy")]
     pub deploy: Deploy,
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -197,6 +278,15 @@ pub struct Node {
     pub inputs: BTreeMap<DataId, Input>,
     #[serde(default)]
     pub outputs: BTreeSet<DataId>,
+    /// Outputs whose last message is delivered to nodes that subscribe later,
+    /// e.g. nodes that are added to the running dataflow.
+    ///
+    /// Each latched output must be listed in `outputs` too. Latching is
+    /// configured in this separate list instead of a per-output
+    /// `{ id, latched: true }` entry, so that `outputs` stays a plain list of
+    /// IDs for existing dataflows and for tools that read it.
+    #[serde(default)]
+    pub latched_outputs: BTreeSet<DataId>,
 }
 
 impl Node {
@@ -286,26 +376,30 @@ pub struct ResolvedNode {
 }
 
 impl ResolvedNode {
+    /// The output that the stdout of the node should be sent to.
+    ///
+    /// Runtime nodes capture the stdout of each operator separately and send
+    /// it to the operator's own `send_stdout_as` output, so this is always
+    /// `None` for them.
+    ///
+    /// Separate capturing is only supported on Linux. On other platforms, the
+    /// capturing applies to the whole runtime, so only a single operator per
+    /// runtime may use `send_stdout_as` there.
     pub fn send_stdout_as(&self) -> Result<Option<String>> {
         match &self.kind {
-            // TODO: Split stdout between operators
             CoreNodeKind::Runtime(n) => {
                 let count = n
                     .operators
                     .iter()
                     .filter(|op| op.config.send_stdout_as.is_some())
                     .count();
-                if count == 1 && n.operators.len() > 1 {
-                    warn!("All stdout from all operators of a runtime are going to be sent in the selected `send_stdout_as` operator.")
-                } else if count > 1 {
-                    return Err(eyre!("More than one `send_stdout_as` entries for a runtime node. Please only use one `send_stdout_as` per runtime."));
+                if cfg!(not(target_os = "linux")) && count > 1 {
+                    bail!(
+                        "More than one `send_stdout_as` entries for a runtime node. \
+                        Please only use one `send_stdout_as` per runtime on this platform."
+                    );
                 }
-                Ok(n.operators.iter().find_map(|op| {
-                    op.config
-                        .send_stdout_as
-                        .clone()
-                        .map(|stdout| format!("{}/{}", op.id, stdout))
-                }))
+                Ok(None)
             }
             CoreNodeKind::Custom(n) => Ok(n.send_stdout_as.clone()),
         }
@@ -363,12 +457,26 @@ fn runtime_node_outputs(n: &RuntimeNode) -> BTreeSet<DataId> {
         .collect()
 }
 
+fn runtime_node_latched_outputs(n: &RuntimeNode) -> BTreeSet<DataId> {
+    n.operators
+        .iter()
+        .flat_map(|operator| {
+            operator
+                .config
+                .latched_outputs
+                .iter()
+                .map(|output_id| DataId::from(format!("{}/{output_id}", operator.id)))
+        })
+        .collect()
+}
+
 impl CoreNodeKind {
     pub fn run_config(&self) -> NodeRunConfig {
         match self {
             CoreNodeKind::Runtime(n) => NodeRunConfig {
                 inputs: runtime_node_inputs(n),
                 outputs: runtime_node_outputs(n),
+                latched_outputs: runtime_node_latched_outputs(n),
             },
             CoreNodeKind::Custom(n) => n.run_config.clone(),
         }
@@ -412,6 +520,11 @@ pub struct OperatorConfig {
     pub inputs: BTreeMap<DataId, Input>,
     #[serde(default)]
     pub outputs: BTreeSet<DataId>,
+    /// Outputs of the operator whose last message is delivered to nodes that
+    /// subscribe later. They must be listed in `outputs` too, see the
+    /// `latched_outputs` field of nodes.
+    #[serde(default)]
+    pub latched_outputs: BTreeSet<DataId>,
 
     #[serde(flatten)]
     pub source: OperatorSource,
@@ -1,12 +1,14 @@
 use crate::{
     adjust_shared_library_path,
-    config::{DataId, Input, InputMapping, OperatorId, UserInputMapping},
+    config::{
+        ClockConfig, Compression, DataId, Input, InputMapping, NodeId, OperatorId, UserInputMapping,
+    },
     descriptor::{self, source_is_url, CoreNodeKind, OperatorSource, EXE_EXTENSION},
     get_python_path,
 };
 
 use eyre::{bail, eyre, Context};
-use std::{path::Path, process::Command};
+use std::{collections::BTreeMap, path::Path, process::Command};
 use tracing::info;
 
 use super::{resolve_path, Descriptor, DYNAMIC_SOURCE, SHELL_SOURCE};
@@ -121,6 +123,48 @@ pub fn check_dataflow(
             .context("Could not resolve `send_stdout_as` configuration")?;
     }
 
+    // check that latched outputs are declared as outputs
+    for node in &nodes {
+        let run_config = node.kind.run_config();
+        if let Some(output) = run_config
+            .latched_outputs
+            .difference(&run_config.outputs)
+            .next()
+        {
+            bail!(
+                "latched output `{}/{output}` is not listed in `outputs`",
+                node.id
+            );
+        }
+    }
+
+    check_compression(dataflow, &nodes)?;
+
+    match &dataflow.clock {
+        ClockConfig::Real => {}
+        ClockConfig::Scaled { factor } => {
+            if !(ClockConfig::MIN_FACTOR..=ClockConfig::MAX_FACTOR).contains(factor) {
+                bail!(
+                    "clock factor must be between {} and {} (got `{factor}`)",
+                    ClockConfig::MIN_FACTOR,
+                    ClockConfig::MAX_FACTOR
+                );
+            }
+        }
+        ClockConfig::External { source } => {
+            let Some(source) = dataflow.clock_source() else {
+                bail!("clock source must be a node output (got `{source}`)");
+            };
+            let input = Input {
+                mapping: InputMapping::User(source),
+                queue_size: None,
+                compression: None,
+                notify_dropped: false,
+            };
+            check_input(&input, &nodes, "clock")?;
+        }
+    }
+
     if has_python_operator {
         check_python_runtime()?;
     }
@@ -177,6 +221,55 @@ fn check_input(
     Ok(())
 }
 
+/// Checks that all inputs that receive the same output request the same compression.
+///
+/// Remote outputs are compressed once per receiving machine (or only once with
+/// Zenoh), so conflicting settings could not be honored.
+fn check_compression(dataflow: &Descriptor, nodes: &[super::ResolvedNode]) -> eyre::Result<()> {
+    let mut compressions: BTreeMap<(NodeId, DataId), (Compression, String)> = BTreeMap::new();
+    for node in nodes {
+        let inputs: Vec<_> = match &node.kind {
+            CoreNodeKind::Custom(custom_node) => custom_node
+                .run_config
+                .inputs
+                .iter()
+                .map(|(input_id, input)| (format!("{}/{input_id}", node.id), input))
+                .collect(),
+            CoreNodeKind::Runtime(runtime_node) => runtime_node
+                .operators
+                .iter()
+                .flat_map(|operator| {
+                    operator.config.inputs.iter().map(|(input_id, input)| {
+                        (format!("{}/{}/{input_id}", node.id, operator.id), input)
+                    })
+                })
+                .collect(),
+        };
+        for (input_id_str, input) in inputs {
+            let InputMapping::User(UserInputMapping { source, output }) = &input.mapping else {
+                continue;
+            };
+            let Some(compression) = input.compression.or(dataflow.communication.compression) else {
+                continue;
+            };
+            match compressions.get(&(source.clone(), output.clone())) {
+                Some((other, other_input)) if *other != compression => bail!(
+                    "inputs `{other_input}` and `{input_id_str}` request different \
+                    compressions for output `{source}/{output}` (`{other}` and `{compression}`)"
+                ),
+                Some(_) => {}
+                None => {
+                    compressions.insert(
+                        (source.clone(), output.clone()),
+                        (compression, input_id_str),
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 fn check_python_runtime() -> eyre::Result<()> {
     // Check if python dora-rs is installed and match cli version
     let reinstall_command =
@@ -204,3 +297,189 @@ assert dora.__version__=='{VERSION}',  'Python dora-rs should be {VERSION}, but
 
     Ok(())
 }
+
+/// Checks that the dataflow can run in deterministic lockstep mode.
+///
+/// Runtime nodes report to be idle as soon as their operators received an
+/// event, not when the operator callback returned, so they are rejected. A
+/// `clock` is rejected too because deterministic mode uses its own virtual
+/// clock.
+pub fn check_deterministic(dataflow: &Descriptor) -> eyre::Result<()> {
+    if dataflow.clock != ClockConfig::Real {
+        bail!("a `clock` is not supported in deterministic mode");
+    }
+    let nodes = dataflow.resolve_aliases_and_set_defaults()?;
+    if let Some(node) = nodes
+        .iter()
+        .find(|node| matches!(node.kind, CoreNodeKind::Runtime(_)))
+    {
+        bail!(
+            "node `{}` has operators, which are not supported in deterministic mode",
+            node.id
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dataflow(default: Option<&str>, a: Option<&str>, b: Option<&str>) -> Descriptor {
+        let compression =
+            |c: Option<&str>| c.map(|c| format!("compression: {c}")).unwrap_or_default();
+        let yaml = format!(
+            "
+nodes:
+  - id: source
+    path: dynamic
+    outputs: [out]
+  - id: a
+    path: dynamic
+    inputs:
+      in:
+        source: source/out
+        {}
+  - id: b
+    path: dynamic
+    inputs:
+      in:
+        source: source/out
+        {}
+communication:
+  {}
+",
+            compression(a),
+            compression(b),
+            compression(default),
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    fn check(dataflow: &Descriptor) -> eyre::Result<()> {
+        check_dataflow(dataflow, Path::new("."), None, false)
+    }
+
+    #[test]
+    fn same_compression_is_accepted() {
+        check(&dataflow(None, Some("lz4"), Some("lz4"))).unwrap();
+        check(&dataflow(Some("zstd"), None, Some("zstd(3)"))).unwrap();
+        check(&dataflow(None, Some("lz4"), None)).unwrap();
+    }
+
+    #[test]
+    fn conflicting_compressions_are_rejected() {
+        let err = check(&dataflow(None, Some("lz4"), Some("zstd"))).unwrap_err();
+        assert!(
+            err.to_string().contains("`a/in` and `b/in`"),
+            "unexpected error: {err}"
+        );
+        let err = check(&dataflow(Some("lz4"), None, Some("zstd(7)"))).unwrap_err();
+        assert!(
+            err.to_string().contains("zstd(7)"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn latched_outputs_must_be_listed_as_outputs() {
+        let dataflow = |latched: &str| -> Descriptor {
+            let yaml = format!(
+                "
+nodes:
+  - id: source
+    path: dynamic
+    outputs: [out]
+    latched_outputs: [{latched}]
+"
+            );
+            serde_yaml::from_str(&yaml).unwrap()
+        };
+        check(&dataflow("out")).unwrap();
+        let err = check(&dataflow("other")).unwrap_err();
+        assert!(
+            err.to_string().contains("`source/other`"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn clock_factor_is_bounded() {
+        let mut dataflow = dataflow(None, None, None);
+        for factor in [ClockConfig::MIN_FACTOR, 1.0, ClockConfig::MAX_FACTOR] {
+            dataflow.clock = ClockConfig::Scaled { factor };
+            check(&dataflow).unwrap();
+        }
+        for factor in [0.0, -1.0, 1e-30, 1e20, f64::INFINITY, f64::NAN] {
+            dataflow.clock = ClockConfig::Scaled { factor };
+            let err = check(&dataflow).unwrap_err();
+            assert!(
+                err.to_string().contains("clock factor"),
+                "unexpected error: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn multiple_send_stdout_as_per_runtime_need_linux() {
+        let dataflow: Descriptor = serde_yaml::from_str(
+            "
+nodes:
+  - id: runtime
+    operators:
+      - id: a
+        shared-library: a
+        outputs: [stdout]
+        send_stdout_as: stdout
+      - id: b
+        shared-library: b
+        outputs: [stdout]
+        send_stdout_as: stdout
+",
+        )
+        .unwrap();
+        let nodes = dataflow.resolve_aliases_and_set_defaults().unwrap();
+        let result = nodes[0].send_stdout_as();
+        if cfg!(target_os = "linux") {
+            assert_eq!(result.unwrap(), None);
+        } else {
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn operators_are_rejected_in_deterministic_mode() {
+        let with_operator: Descriptor = serde_yaml::from_str(
+            "
+nodes:
+  - id: source
+    path: dynamic
+    outputs: [out]
+  - id: runtime
+    operator:
+      shared-library: op
+      inputs:
+        in: source/out
+",
+        )
+        .unwrap();
+        let err = check_deterministic(&with_operator).unwrap_err();
+        assert!(
+            err.to_string().contains("`runtime`"),
+            "unexpected error: {err}"
+        );
+
+        check_deterministic(&dataflow(None, None, None)).unwrap();
+    }
+
+    #[test]
+    fn clock_is_rejected_in_deterministic_mode() {
+        let mut dataflow = dataflow(None, None, None);
+        dataflow.clock = ClockConfig::Scaled { factor: 2.0 };
+        let err = check_deterministic(&dataflow).unwrap_err();
+        assert!(
+            err.to_string().contains("`clock`"),
+            "unexpected error: {err}"
+        );
+    }
+}
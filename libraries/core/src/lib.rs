@@ -11,6 +11,7 @@ pub mod config;
 pub mod coordinator_messages;
 pub mod daemon_messages;
 pub mod descriptor;
+pub mod security;
 pub mod topics;
 
 pub fn adjust_shared_library_path(path: &Path) -> Result<std::path::PathBuf, eyre::ErrReport> {
@@ -0,0 +1,210 @@
+//! Authentication and encryption settings for connections to the coordinator.
+//!
+//! The settings are read from a YAML file that is passed to the coordinator,
+//! the daemons, and the CLI:
+//!
+//! ```yaml
+//! # mutual TLS, all parties need a certificate signed by the given CA
+//! tls:
+//!   ca: certs/ca.pem
+//!   cert: certs/coordinator.pem
+//!   key: certs/coordinator.key
+//! # token that the CLI and daemons send to the coordinator
+//! token: 5f3c1b2e...
+//! # tokens that the coordinator accepts (only needed on the coordinator)
+//! access_tokens:
+//!   - token: 5f3c1b2e...
+//!   - token: 0a7dd9c1...
+//!     role: read-only
+//! ```
+//!
+//! Relative paths are resolved relative to the directory of the config file.
+
+use eyre::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use subtle::ConstantTimeEq;
+
+#[cfg(feature = "tls")]
+pub mod tls;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityConfig {
+    /// Enables mutual TLS on all connections to the coordinator.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Token that is sent to the coordinator for authentication.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Tokens that are accepted by the coordinator.
+    ///
+    /// Token authentication is disabled if no tokens are set.
+    #[serde(default)]
+    pub access_tokens: Vec<AccessToken>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Certificate of the CA that signed the certificates of all parties.
+    pub ca: PathBuf,
+    /// Certificate chain of this party.
+    pub cert: PathBuf,
+    /// Private key of this party.
+    pub key: PathBuf,
+    /// Name to verify the coordinator certificate against.
+    ///
+    /// Defaults to the IP address of the coordinator.
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessToken {
+    pub token: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Full access, including starting and stopping dataflows.
+    #[default]
+    Admin,
+    /// Only allowed to inspect dataflows, e.g. to list them or to read their logs.
+    ReadOnly,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Admin => write!(f, "admin"),
+            Role::ReadOnly => write!(f, "read-only"),
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Reads the config from the given path, or returns the default (insecure)
+    /// config if no path is given.
+    pub fn load(path: Option<&Path>) -> eyre::Result<Self> {
+        match path {
+            Some(path) => Self::read(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn read(path: &Path) -> eyre::Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read security config `{}`", path.display()))?;
+        let mut config: Self = serde_yaml::from_str(&raw)
+            .wrap_err_with(|| format!("failed to parse security config `{}`", path.display()))?;
+        if let (Some(tls), Some(base)) = (&mut config.tls, path.parent()) {
+            for path in [&mut tls.ca, &mut tls.cert, &mut tls.key] {
+                *path = base.join(&*path);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Whether connections to the coordinator need to authenticate with a token.
+    pub fn requires_token(&self) -> bool {
+        !self.access_tokens.is_empty()
+    }
+
+    /// Checks the given token against the accepted tokens and returns its role.
+    pub fn authenticate(&self, token: Option<&str>) -> eyre::Result<Role> {
+        if !self.requires_token() {
+            return Ok(Role::Admin);
+        }
+        let Some(token) = token else {
+            bail!("authentication required");
+        };
+        // compare all tokens in constant time to avoid leaking information
+        let mut role = None;
+        for accepted in &self.access_tokens {
+            if bool::from(accepted.token.as_bytes().ct_eq(token.as_bytes())) {
+                role = role.or(Some(accepted.role));
+            }
+        }
+        match role {
+            Some(role) => Ok(role),
+            None => bail!("invalid access token"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(tokens: &[(&str, Role)]) -> SecurityConfig {
+        SecurityConfig {
+            access_tokens: tokens
+                .iter()
+                .map(|(token, role)| AccessToken {
+                    token: token.to_string(),
+                    role: *role,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_access_tokens_grant_admin_access() {
+        let config = SecurityConfig::default();
+        assert!(!config.requires_token());
+        assert_eq!(config.authenticate(None).unwrap(), Role::Admin);
+        assert_eq!(config.authenticate(Some("anything")).unwrap(), Role::Admin);
+    }
+
+    #[test]
+    fn tokens_are_mapped_to_their_role() {
+        let config = config(&[("admin-token", Role::Admin), ("reader", Role::ReadOnly)]);
+        assert!(config.requires_token());
+        assert_eq!(
+            config.authenticate(Some("admin-token")).unwrap(),
+            Role::Admin
+        );
+        assert_eq!(config.authenticate(Some("reader")).unwrap(), Role::ReadOnly);
+    }
+
+    #[test]
+    fn missing_or_unknown_tokens_are_rejected() {
+        let config = config(&[("secret", Role::Admin)]);
+        assert!(config.authenticate(None).is_err());
+        assert!(config.authenticate(Some("")).is_err());
+        assert!(config.authenticate(Some("secre")).is_err());
+        assert!(config.authenticate(Some("secret2")).is_err());
+    }
+
+    #[test]
+    fn read_resolves_tls_paths_relative_to_the_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let path = dir.join("security.yml");
+        std::fs::write(
+            &path,
+            "tls:\n  ca: certs/ca.pem\n  cert: /abs/cert.pem\n  key: key.pem\n\
+             token: abc\n\
+             access_tokens:\n  - token: abc\n  - token: def\n    role: read-only\n",
+        )
+        .unwrap();
+
+        let config = SecurityConfig::read(&path).unwrap();
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.ca, dir.join("certs/ca.pem"));
+        assert_eq!(tls.cert, PathBuf::from("/abs/cert.pem"));
+        assert_eq!(tls.key, dir.join("key.pem"));
+        assert_eq!(config.token.as_deref(), Some("abc"));
+        assert_eq!(config.access_tokens[0].role, Role::Admin);
+        assert_eq!(config.access_tokens[1].role, Role::ReadOnly);
+
+        std::fs::write(&path, "tokens: [abc]\n").unwrap();
+        assert!(SecurityConfig::read(&path).is_err(), "unknown fields");
+    }
+}
@@ -0,0 +1,201 @@
+//! Mutual TLS for connections to the coordinator, based on `rustls`.
+
+use super::TlsConfig;
+use eyre::{bail, Context, ContextCompat};
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, StreamOwned,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+pub use rustls;
+
+/// Connects to the given address, using TLS if a connector is given.
+pub async fn connect(addr: SocketAddr, tls: Option<&TlsConnector>) -> io::Result<Connection> {
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    match tls {
+        Some(connector) => connector.connect(stream).await,
+        None => Ok(Connection::Tcp(stream)),
+    }
+}
+
+/// Creates encrypted connections to the coordinator.
+#[derive(Clone)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+    server_name: Option<String>,
+}
+
+impl TlsConnector {
+    pub fn new(config: &TlsConfig) -> eyre::Result<Self> {
+        let client_config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(root_store(&config.ca)?)
+            .with_client_auth_cert(load_certs(&config.cert)?, load_key(&config.key)?)
+            .wrap_err("invalid client certificate or key")?;
+        Ok(Self {
+            config: Arc::new(client_config),
+            server_name: config.server_name.clone(),
+        })
+    }
+
+    pub async fn connect(&self, stream: TcpStream) -> io::Result<Connection> {
+        let server_name = self.server_name(stream.peer_addr()?.ip())?;
+        let stream = tokio_rustls::TlsConnector::from(self.config.clone())
+            .connect(server_name, stream)
+            .await?;
+        Ok(Connection::Tls(Box::new(stream.into())))
+    }
+
+    /// Wraps the given blocking stream. The TLS handshake happens on first use.
+    pub fn connect_blocking(
+        &self,
+        stream: std::net::TcpStream,
+    ) -> io::Result<StreamOwned<ClientConnection, std::net::TcpStream>> {
+        let server_name = self.server_name(stream.peer_addr()?.ip())?;
+        let connection = ClientConnection::new(self.config.clone(), server_name)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+
+    fn server_name(&self, peer: IpAddr) -> io::Result<ServerName<'static>> {
+        match &self.server_name {
+            Some(name) => ServerName::try_from(name.clone())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err)),
+            None => Ok(ServerName::IpAddress(peer.into())),
+        }
+    }
+}
+
+/// Accepts encrypted connections that present a client certificate signed by the CA.
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: tokio_rustls::TlsAcceptor,
+}
+
+impl TlsAcceptor {
+    pub fn new(config: &TlsConfig) -> eyre::Result<Self> {
+        let verifier = WebPkiClientVerifier::builder_with_provider(
+            Arc::new(root_store(&config.ca)?),
+            provider(),
+        )
+        .build()
+        .wrap_err("failed to create client certificate verifier")?;
+        let server_config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(load_certs(&config.cert)?, load_key(&config.key)?)
+            .wrap_err("invalid server certificate or key")?;
+        Ok(Self {
+            acceptor: Arc::new(server_config).into(),
+        })
+    }
+
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<Connection> {
+        let stream = self.acceptor.accept(stream).await?;
+        Ok(Connection::Tls(Box::new(stream.into())))
+    }
+}
+
+/// A TCP connection that is optionally encrypted through TLS.
+#[derive(Debug)]
+pub enum Connection {
+    Tcp(TcpStream),
+    Tls(Box<tokio_rustls::TlsStream<TcpStream>>),
+}
+
+impl Connection {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Connection::Tcp(stream) => stream.peer_addr(),
+            Connection::Tls(stream) => stream.get_ref().0.peer_addr(),
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn root_store(ca: &Path) -> eyre::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca)? {
+        roots
+            .add(cert)
+            .wrap_err_with(|| format!("invalid CA certificate in `{}`", ca.display()))?;
+    }
+    Ok(roots)
+}
+
+fn load_certs(path: &Path) -> eyre::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).wrap_err_with(|| format!("failed to open `{}`", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .wrap_err_with(|| format!("failed to parse certificates in `{}`", path.display()))?;
+    if certs.is_empty() {
+        bail!("no certificates found in `{}`", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> eyre::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).wrap_err_with(|| format!("failed to open `{}`", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .wrap_err_with(|| format!("failed to parse private key in `{}`", path.display()))?
+        .wrap_err_with(|| format!("no private key found in `{}`", path.display()))
+}
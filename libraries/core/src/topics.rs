@@ -11,7 +11,9 @@ use uuid::Uuid;
 
 use crate::{
     config::{NodeId, OperatorId},
+    coordinator_messages::LogMessage,
     descriptor::Descriptor,
+    security::Role,
 };
 
 pub const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
@@ -29,6 +31,9 @@ pub enum ControlRequest {
         // TODO: remove this once we figure out deploying of node/operator
         // binaries from CLI to coordinator/daemon
         local_working_dir: PathBuf,
+        /// Only compute and validate the node placement, without starting the dataflow.
+        #[serde(default)]
+        dry_run: bool,
     },
     Reload {
         dataflow_id: Uuid,
@@ -50,6 +55,11 @@ pub enum ControlRequest {
         uuid: Option<Uuid>,
         name: Option<String>,
         node: String,
+        /// Only return structured log records of this level or more severe.
+        ///
+        /// Lines that are not structured log records are always returned.
+        #[serde(default)]
+        level: Option<log::LevelFilter>,
     },
     Destroy,
     List,
@@ -59,6 +69,72 @@ pub enum ControlRequest {
         dataflow_id: Uuid,
         level: log::LevelFilter,
     },
+    /// Streams [`LifecycleEvent`]s of all dataflows, or only of the given one.
+    ///
+    /// The events are sent as [`EventMessage`]s. A subscription for a single
+    /// dataflow is closed after its [`LifecycleEvent::DataflowFinished`] event.
+    SubscribeEvents {
+        dataflow_id: Option<Uuid>,
+    },
+    /// Returns details about a running or finished dataflow.
+    Inspect {
+        uuid: Option<Uuid>,
+        name: Option<String>,
+    },
+    /// Returns the statistics that the daemons collected about a running dataflow.
+    Stats {
+        uuid: Option<Uuid>,
+        name: Option<String>,
+    },
+    /// Adds the nodes of the given descriptor fragment to a running dataflow.
+    ///
+    /// Only the `nodes` of the fragment are used. Source paths are resolved
+    /// relative to the working directory of the dataflow.
+    AddNodes {
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        nodes: Descriptor,
+    },
+    /// Stops the given nodes of a running dataflow and removes them.
+    ///
+    /// Inputs that are fed by outputs of the removed nodes are closed.
+    RemoveNodes {
+        uuid: Option<Uuid>,
+        name: Option<String>,
+        nodes: BTreeSet<NodeId>,
+        grace_duration: Option<Duration>,
+    },
+    /// Authenticates the connection with the given access token.
+    ///
+    /// Must be the first request if the coordinator requires token authentication.
+    Authenticate {
+        token: String,
+    },
+}
+
+impl ControlRequest {
+    /// Whether the request is allowed for the [`Role::ReadOnly`] role.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            ControlRequest::Check { .. }
+            | ControlRequest::Logs { .. }
+            | ControlRequest::List
+            | ControlRequest::DaemonConnected
+            | ControlRequest::ConnectedMachines
+            | ControlRequest::Inspect { .. }
+            | ControlRequest::Stats { .. }
+            | ControlRequest::LogSubscribe { .. }
+            | ControlRequest::SubscribeEvents { .. }
+            | ControlRequest::Authenticate { .. } => true,
+            ControlRequest::Start { .. }
+            | ControlRequest::Reload { .. }
+            | ControlRequest::Stop { .. }
+            | ControlRequest::StopByName { .. }
+            | ControlRequest::AddNodes { .. }
+            | ControlRequest::RemoveNodes { .. }
+            | ControlRequest::Destroy => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -91,14 +167,120 @@ pub enum DataflowStatus {
 pub enum ControlRequestReply {
     Error(String),
     CoordinatorStopped,
-    DataflowStarted { uuid: Uuid },
-    DataflowReloaded { uuid: Uuid },
-    DataflowStopped { uuid: Uuid, result: DataflowResult },
+    DataflowStarted {
+        uuid: Uuid,
+    },
+    DataflowReloaded {
+        uuid: Uuid,
+    },
+    DataflowStopped {
+        uuid: Uuid,
+        result: DataflowResult,
+    },
     DataflowList(DataflowList),
     DestroyOk,
     DaemonConnected(bool),
     ConnectedMachines(BTreeSet<String>),
     Logs(Vec<u8>),
+    DataflowInfo(DataflowInfo),
+    /// The node placement that was computed for a [`ControlRequest::Start`] dry run.
+    DataflowPlan {
+        nodes: Vec<NodeInfo>,
+    },
+    Authenticated {
+        role: Role,
+    },
+    /// Statistics of a running dataflow, by machine.
+    DataflowStats(BTreeMap<String, DataflowDaemonStats>),
+    NodesAdded {
+        uuid: Uuid,
+        nodes: Vec<NodeInfo>,
+    },
+    NodesRemoved {
+        uuid: Uuid,
+    },
+}
+
+/// Details about a dataflow, returned for [`ControlRequest::Inspect`].
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct DataflowInfo {
+    pub id: DataflowId,
+    pub status: DataflowStatus,
+    pub nodes: Vec<NodeInfo>,
+    /// The result of the dataflow, if it finished already.
+    pub result: Option<DataflowResult>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct NodeInfo {
+    pub id: NodeId,
+    /// The machine that the node is deployed on.
+    pub machine: String,
+}
+
+/// Event in the lifecycle of a dataflow or of a daemon.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    DataflowStarted {
+        dataflow: DataflowId,
+    },
+    NodeSpawned {
+        dataflow: DataflowId,
+        node: NodeId,
+        machine: String,
+    },
+    /// A node was spawned again after it was removed from the running
+    /// dataflow, e.g. through `dora node add`.
+    NodeRestarted {
+        dataflow: DataflowId,
+        node: NodeId,
+        machine: String,
+    },
+    /// The node subscribed to its events.
+    NodeReady {
+        dataflow: DataflowId,
+        node: NodeId,
+    },
+    NodeExited {
+        dataflow: DataflowId,
+        node: NodeId,
+        result: Result<(), NodeError>,
+    },
+    DataflowFinished {
+        dataflow: DataflowId,
+        result: DataflowResult,
+    },
+    DaemonConnected {
+        machine: String,
+    },
+    /// The daemon stopped sending heartbeat messages.
+    DaemonLost {
+        machine: String,
+    },
+}
+
+impl LifecycleEvent {
+    /// The dataflow that the event belongs to, if any.
+    pub fn dataflow_uuid(&self) -> Option<Uuid> {
+        match self {
+            LifecycleEvent::DataflowStarted { dataflow }
+            | LifecycleEvent::NodeSpawned { dataflow, .. }
+            | LifecycleEvent::NodeRestarted { dataflow, .. }
+            | LifecycleEvent::NodeReady { dataflow, .. }
+            | LifecycleEvent::NodeExited { dataflow, .. }
+            | LifecycleEvent::DataflowFinished { dataflow, .. } => Some(dataflow.uuid),
+            LifecycleEvent::DaemonConnected { .. } | LifecycleEvent::DaemonLost { .. } => None,
+        }
+    }
+}
+
+/// Message that is streamed to event subscribers, serialized as JSON.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventMessage {
+    Log(LogMessage),
+    Lifecycle(LifecycleEvent),
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -150,6 +332,40 @@ impl DataflowDaemonResult {
     }
 }
 
+/// Statistics that a daemon collected about a running dataflow.
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+pub struct DataflowDaemonStats {
+    /// Compression of messages that were exchanged with other machines, by
+    /// output (`<node>/<output>`).
+    pub compression: BTreeMap<String, CompressionStats>,
+    /// Number of messages that were dropped because the input queue of the
+    /// receiver was full, by input (`<node>/<input>`).
+    #[serde(default)]
+    pub dropped_inputs: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+pub struct CompressionStats {
+    /// Number of compressed messages that were sent to other machines.
+    pub compressed_messages: u64,
+    /// Total size of the sent messages before compression.
+    pub uncompressed_bytes: u64,
+    /// Total size of the sent messages after compression.
+    pub compressed_bytes: u64,
+    pub compression_time: Duration,
+    /// Number of compressed messages that were received from other machines.
+    pub decompressed_messages: u64,
+    pub decompression_time: Duration,
+}
+
+impl CompressionStats {
+    /// The ratio between the uncompressed and compressed size of the sent messages.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.compressed_bytes > 0)
+            .then(|| self.uncompressed_bytes as f64 / self.compressed_bytes as f64)
+    }
+}
+
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct NodeError {
     pub timestamp: uhlc::Timestamp,
@@ -186,11 +402,18 @@ impl std::fmt::Display for NodeError {
                     write!(f, "exited because of signal {signal_str}")
                 }
             }
-            NodeExitStatus::Unknown => write!(f, "unknown exit status"),
+            NodeExitStatus::Unknown => match &self.cause {
+                NodeErrorCause::MachineLost { machine_id } => write!(
+                    f,
+                    "node was lost because machine `{machine_id}` stopped responding"
+                ),
+                _ => write!(f, "unknown exit status"),
+            },
         }?;
 
         match &self.cause {
             NodeErrorCause::GraceDuration => {}, // handled above
+            NodeErrorCause::MachineLost { .. } => {}, // handled above
             NodeErrorCause::Cascading { caused_by_node } => write!(
                 f,
                 ". This error occurred because node `{caused_by_node}` exited before connecting to dora."
@@ -214,6 +437,10 @@ pub enum NodeErrorCause {
     Cascading {
         caused_by_node: NodeId,
     },
+    /// The machine that ran the node stopped responding to heartbeat messages.
+    MachineLost {
+        machine_id: String,
+    },
     Other {
         stderr: String,
     },
@@ -251,3 +478,36 @@ impl From<Result<std::process::ExitStatus, std::io::Error>> for NodeExitStatus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_lost_error_names_the_machine() {
+        let error = NodeError {
+            timestamp: uhlc::HLC::default().new_timestamp(),
+            cause: NodeErrorCause::MachineLost {
+                machine_id: "robot".into(),
+            },
+            exit_status: NodeExitStatus::Unknown,
+        };
+        assert_eq!(
+            error.to_string(),
+            "node was lost because machine `robot` stopped responding"
+        );
+    }
+
+    #[test]
+    fn restarted_nodes_belong_to_their_dataflow() {
+        let uuid = Uuid::now_v7();
+        let event = LifecycleEvent::NodeRestarted {
+            dataflow: DataflowId { uuid, name: None },
+            node: NodeId::from("camera".to_string()),
+            machine: "robot".into(),
+        };
+        assert_eq!(event.dataflow_uuid(), Some(uuid));
+        let serialized = serde_json::to_value(&event).unwrap();
+        assert_eq!(serialized["node_restarted"]["node"], "camera");
+    }
+}
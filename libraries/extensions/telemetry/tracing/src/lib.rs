@@ -19,16 +19,41 @@ pub fn set_up_tracing(name: &str) -> eyre::Result<()> {
     set_up_tracing_opts(name, true, None)
 }
 
+/// Like [`set_up_tracing`], but prints the log output to stderr.
+///
+/// Useful for CLI commands whose stdout is parsed by other programs.
+pub fn set_up_tracing_stderr(name: &str) -> eyre::Result<()> {
+    set_up_tracing_layers(name, Some(Console::Stderr), None)
+}
+
 pub fn set_up_tracing_opts(name: &str, stdout: bool, filename: Option<&str>) -> eyre::Result<()> {
+    set_up_tracing_layers(name, stdout.then_some(Console::Stdout), filename)
+}
+
+enum Console {
+    Stdout,
+    Stderr,
+}
+
+fn set_up_tracing_layers(
+    name: &str,
+    console: Option<Console>,
+    filename: Option<&str>,
+) -> eyre::Result<()> {
     let mut layers = Vec::new();
 
-    if stdout {
+    if let Some(console) = console {
         // Filter log using `RUST_LOG`. More useful for CLI.
         let env_filter = EnvFilter::from_default_env().or(LevelFilter::WARN);
-        let layer = tracing_subscriber::fmt::layer()
-            .compact()
-            .with_filter(env_filter);
-        layers.push(layer.boxed());
+        let layer = tracing_subscriber::fmt::layer().compact();
+        let layer = match console {
+            Console::Stdout => layer.with_filter(env_filter).boxed(),
+            Console::Stderr => layer
+                .with_writer(std::io::stderr)
+                .with_filter(env_filter)
+                .boxed(),
+        };
+        layers.push(layer);
     }
 
     if let Some(filename) = filename {